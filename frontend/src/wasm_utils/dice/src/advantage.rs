@@ -0,0 +1,151 @@
+use serde::{Deserialize, Serialize};
+use tsify::Tsify;
+
+use crate::grammar::{Expr, ModifierOp, ModifierParam};
+use crate::number::Num;
+
+// ==========================================
+// 优势与劣势 (5e)
+// ==========================================
+
+// 掷骰的优势/劣势，与角色卡掷骰设置中的 'normal' | 'adv' | 'dis' 对应
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, Tsify, PartialEq)]
+#[tsify(into_wasm_abi, from_wasm_abi)]
+pub enum RollMode {
+    #[serde(rename = "normal")]
+    Normal,
+    #[serde(rename = "adv")]
+    Advantage,
+    #[serde(rename = "dis")]
+    Disadvantage,
+}
+
+impl RollMode {
+    // 5e 规则：同时具有优势与劣势时互相抵消，多个优势 (或劣势) 不叠加
+    pub fn from_flags(advantage: bool, disadvantage: bool) -> Self {
+        match (advantage, disadvantage) {
+            (true, false) => RollMode::Advantage,
+            (false, true) => RollMode::Disadvantage,
+            _ => RollMode::Normal,
+        }
+    }
+
+    pub fn combine(self, other: RollMode) -> Self {
+        use RollMode::*;
+        RollMode::from_flags(
+            self == Advantage || other == Advantage,
+            self == Disadvantage || other == Disadvantage,
+        )
+    }
+}
+
+// 给表达式加上优势/劣势，结果仍是普通的 kh1 / kl1 节点：
+// 单颗骰子 1d20adv -> 2d20kh1，其他表达式 (1d20ro1)dis -> {1d20ro1, 1d20ro1}kl1
+// 已经是优势/劣势写法 (2d20kh1、{1d20ro1, 1d20ro1}kh1) 的表达式按规则合并，抵消时还原为单次掷骰
+// 其他带 kh1 / kl1 的表达式 (如 4d6kh1) 不是优势/劣势，同样整体掷两次
+pub fn with_roll_mode(expr: Expr, mode: RollMode) -> Expr {
+    let (base, current) = split_roll_mode(&expr);
+    let combined = current.combine(mode);
+    if combined == current {
+        return expr;
+    }
+    let (op, base) = match combined {
+        RollMode::Normal => return base,
+        RollMode::Advantage => (ModifierOp::KeepHigh, base),
+        RollMode::Disadvantage => (ModifierOp::KeepLow, base),
+    };
+    let twice = match base {
+        Expr::Dice { side, .. } if is_single_die(&base) => Expr::Dice {
+            count: Box::new(Expr::Number(Num::int(2))),
+            side,
+        },
+        Expr::FateDice { .. } if is_single_die(&base) => Expr::FateDice {
+            count: Box::new(Expr::Number(Num::int(2))),
+        },
+        Expr::PercentileDice { .. } if is_single_die(&base) => Expr::PercentileDice {
+            count: Box::new(Expr::Number(Num::int(2))),
+        },
+        _ => Expr::Group(vec![base.clone(), base]),
+    };
+    Expr::Modifier {
+        lhs: Box::new(twice),
+        op,
+        param: Some(ModifierParam::Value(Box::new(Expr::Number(Num::ONE)))),
+    }
+}
+
+// 按掷骰设置给表达式中的每次 d20 掷骰 (1d20，可以带修饰符) 加上优势/劣势
+// 表达式中已有的优势/劣势与设置合并，如设置为优势时 1d20dis + 5 -> 1d20 + 5
+pub fn apply_roll_mode(expr: &Expr, mode: RollMode) -> Expr {
+    if mode == RollMode::Normal {
+        return expr.clone();
+    }
+    if is_d20_roll(&split_roll_mode(expr).0) {
+        return with_roll_mode(expr.clone(), mode);
+    }
+    let Ok(expr) = expr
+        .try_map_children(|child| Ok::<_, std::convert::Infallible>(apply_roll_mode(child, mode)));
+    expr
+}
+
+// ==========================================
+// 辅助处理函数
+// ==========================================
+
+// 识别 5e 的优势/劣势写法，返回单次掷骰的表达式与当前的优势/劣势
+// 只有 2d20kh1 与两个相同 d20 掷骰的骰组 {a, a}kh1 视为优势，kl1 视为劣势
+fn split_roll_mode(expr: &Expr) -> (Expr, RollMode) {
+    let Expr::Modifier {
+        lhs,
+        op,
+        param: Some(ModifierParam::Value(n)),
+    } = expr
+    else {
+        return (expr.clone(), RollMode::Normal);
+    };
+    let mode = match op {
+        ModifierOp::KeepHigh => RollMode::Advantage,
+        ModifierOp::KeepLow => RollMode::Disadvantage,
+        _ => return (expr.clone(), RollMode::Normal),
+    };
+    if **n != Expr::Number(Num::ONE) {
+        return (expr.clone(), RollMode::Normal);
+    }
+    let base = match lhs.as_ref() {
+        Expr::Dice { count, side }
+            if **count == Expr::Number(Num::int(2)) && **side == Expr::Number(Num::int(20)) =>
+        {
+            Expr::Dice {
+                count: Box::new(Expr::Number(Num::ONE)),
+                side: side.clone(),
+            }
+        }
+        Expr::Group(items)
+            if items.len() == 2 && items[0] == items[1] && is_d20_roll(&items[0]) =>
+        {
+            items[0].clone()
+        }
+        _ => return (expr.clone(), RollMode::Normal),
+    };
+    (base, mode)
+}
+
+fn is_single_die(expr: &Expr) -> bool {
+    match expr {
+        Expr::Dice { count, .. } | Expr::FateDice { count } | Expr::PercentileDice { count } => {
+            **count == Expr::Number(Num::ONE)
+        }
+        _ => false,
+    }
+}
+
+// 单颗 d20，可以带修饰符 (如 1d20ro1)
+fn is_d20_roll(expr: &Expr) -> bool {
+    match expr {
+        Expr::Dice { count, side } => {
+            **count == Expr::Number(Num::ONE) && **side == Expr::Number(Num::int(20))
+        }
+        Expr::Modifier { lhs, .. } => is_d20_roll(lhs),
+        _ => false,
+    }
+}
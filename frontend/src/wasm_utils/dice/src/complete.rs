@@ -0,0 +1,399 @@
+use pest::Parser;
+use pest::error::{ErrorVariant, InputLocation};
+use serde::{Deserialize, Serialize};
+use tsify::Tsify;
+
+use crate::explain::Language;
+use crate::functions::FunctionRegistry;
+use crate::grammar::{DiceGrammar, FunctionDef, ParamType, Rule};
+use crate::tokens::{TokenKind, tokenize};
+use crate::trace::Span;
+use crate::typecheck::BUILTIN_FUNCTIONS;
+
+// ==========================================
+// 公式编辑器的自动补全 (导出给前端)
+// ==========================================
+
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, Tsify, PartialEq)]
+#[tsify(into_wasm_abi)]
+pub enum CompletionKind {
+    Function, // 内置函数
+    Macro,    // 自定义函数
+    Modifier, // 后缀修饰符与优势/劣势
+    Dice,     // 骰子操作符 d 与骰面
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, Tsify, PartialEq)]
+#[tsify(into_wasm_abi)]
+pub struct Completion {
+    pub label: String,  // 显示的名称，如 "kh"
+    pub insert: String, // 替换 replace 范围的文本，如 "max("
+    pub kind: CompletionKind,
+    pub detail: String,      // 写法或签名，如 "kh<n>"
+    pub description: String, // 说明文字
+}
+
+// 光标所在的函数调用的签名，active 为光标所在的参数下标
+#[derive(Debug, Clone, Serialize, Deserialize, Tsify, PartialEq)]
+#[tsify(into_wasm_abi)]
+pub struct SignatureHelp {
+    pub name: String,
+    pub signatures: Vec<String>,
+    pub description: String,
+    pub active: usize,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, Tsify, PartialEq)]
+#[tsify(into_wasm_abi)]
+pub struct Completions {
+    pub items: Vec<Completion>,
+    pub replace: Span, // 选中补全时被替换的范围 (光标前正在输入的单词)，按 UTF-16 计算
+    pub signature: Option<SignatureHelp>,
+}
+
+// 计算光标处的补全候选，cursor 为按 UTF-16 计算的光标位置
+// 候选由语法在光标处期待的规则决定，如 1d20k 之后是修饰符 kh / kl，max( 之后是运算数
+// @ 之后列出所有自定义函数，选中后替换为函数调用，如 @sm -> smite(
+pub fn complete(
+    input: &str,
+    cursor: usize,
+    functions: &FunctionRegistry,
+    lang: Language,
+) -> Completions {
+    let cursor = byte_offset(input, cursor);
+    let before = &input[..cursor];
+    // 刚输入骰子操作符时 (如 1d) 补全骰面，而不是把 d 当作正在输入的单词
+    let word_start = if expected_rules(before).contains(&Rule::dice_side) {
+        before.len()
+    } else {
+        before
+            .trim_end_matches(|c: char| c.is_ascii_alphabetic() || c == '_')
+            .len()
+    };
+    let word = before[word_start..].to_lowercase();
+    let pick = |(en, zh): (&str, &str)| match lang {
+        Language::En => en.to_string(),
+        Language::ZhCn => zh.to_string(),
+    };
+
+    let (items, replace_start) = if before[..word_start].ends_with('@') {
+        let items = functions
+            .definitions()
+            .into_iter()
+            .filter(|def| def.name.to_lowercase().starts_with(&word))
+            .map(|def| macro_completion(def, &pick))
+            .collect();
+        (items, word_start - 1)
+    } else {
+        let mut items: Vec<Completion> = Vec::new();
+        for rule in expected_rules(&before[..word_start]) {
+            for item in candidates(rule, functions, &pick) {
+                if item.label.to_lowercase().starts_with(&word) && !items.contains(&item) {
+                    items.push(item);
+                }
+            }
+        }
+        (items, word_start)
+    };
+
+    Completions {
+        items,
+        replace: Span {
+            start: input[..replace_start].encode_utf16().count(),
+            end: before.encode_utf16().count(),
+        },
+        signature: signature_help(before, functions, &pick),
+    }
+}
+
+// ==========================================
+// 辅助处理函数
+// ==========================================
+
+// 后缀修饰符: 关键字、写法、说明 (英文, 中文)
+const MODIFIERS: [(&str, &str, (&str, &str)); 20] = [
+    (
+        "kh",
+        "kh<n>",
+        ("Keep the highest n dice", "保留最高的 n 颗骰子"),
+    ),
+    (
+        "kl",
+        "kl<n>",
+        ("Keep the lowest n dice", "保留最低的 n 颗骰子"),
+    ),
+    (
+        "dh",
+        "dh<n>",
+        ("Drop the highest n dice", "去掉最高的 n 颗骰子"),
+    ),
+    (
+        "dl",
+        "dl<n>",
+        ("Drop the lowest n dice", "去掉最低的 n 颗骰子"),
+    ),
+    (
+        "r",
+        "r<condition>",
+        ("Reroll dice that meet the condition", "重骰满足条件的骰子"),
+    ),
+    ("ro", "ro<condition>", ("Reroll once", "重骰一次")),
+    ("!", "!<condition>", ("Exploding dice", "爆骰")),
+    (
+        "!!",
+        "!!<condition>",
+        ("Compounding explosions", "复合爆骰"),
+    ),
+    (
+        "!p",
+        "!p<condition>",
+        ("Penetrating explosions", "穿透爆骰"),
+    ),
+    (
+        "l",
+        "l<n>",
+        ("Explode at most n times per die", "每颗骰子最多爆骰 n 次"),
+    ),
+    (
+        "mi",
+        "mi<n>",
+        ("Count dice below n as n", "小于 n 的骰子按 n 计"),
+    ),
+    (
+        "ma",
+        "ma<n>",
+        ("Count dice above n as n", "大于 n 的骰子按 n 计"),
+    ),
+    ("s", "s", ("Sort from lowest to highest", "按从小到大排序")),
+    (
+        "sd",
+        "sd",
+        ("Sort from highest to lowest", "按从大到小排序"),
+    ),
+    ("u", "u", ("Reroll duplicates", "重骰点数重复的骰子")),
+    (
+        "cnt",
+        "cnt<condition>",
+        (
+            "Count the dice that meet the condition",
+            "统计满足条件的骰子数量",
+        ),
+    ),
+    (
+        "cs",
+        "cs<condition>",
+        ("Critical success condition", "大成功的条件"),
+    ),
+    (
+        "cf",
+        "cf<condition>",
+        ("Critical failure condition", "大失败的条件"),
+    ),
+    (
+        "adv",
+        "adv",
+        (
+            "Advantage, roll twice and keep the higher",
+            "优势，掷两次取高",
+        ),
+    ),
+    (
+        "dis",
+        "dis",
+        (
+            "Disadvantage, roll twice and keep the lower",
+            "劣势，掷两次取低",
+        ),
+    ),
+];
+
+// 常用的骰面
+const DICE_SIDES: [(&str, (&str, &str)); 9] = [
+    ("4", ("Four-sided die", "4 面骰")),
+    ("6", ("Six-sided die", "6 面骰")),
+    ("8", ("Eight-sided die", "8 面骰")),
+    ("10", ("Ten-sided die", "10 面骰")),
+    ("12", ("Twelve-sided die", "12 面骰")),
+    ("20", ("Twenty-sided die", "20 面骰")),
+    ("100", ("Hundred-sided die", "100 面骰")),
+    (
+        "F",
+        (
+            "Fate dice, each rolls -1, 0 or +1",
+            "Fate 骰，结果为 -1、0、+1",
+        ),
+    ),
+    (
+        "%",
+        ("Percentile die, same as d100", "百分骰，与 d100 相同"),
+    ),
+];
+
+// 在文本末尾解析失败时语法期待的规则，文本在此之前已经出错时为空
+// 在末尾加上一个不可能出现的字符，使解析一定在此处失败
+fn expected_rules(text: &str) -> Vec<Rule> {
+    let probe = format!("{}\u{1}", text);
+    let Err(e) = DiceGrammar::parse(Rule::main, &probe) else {
+        return Vec::new();
+    };
+    match (e.location, e.variant) {
+        (InputLocation::Pos(pos), ErrorVariant::ParsingError { positives, .. })
+            if pos == text.len() =>
+        {
+            positives
+        }
+        _ => Vec::new(),
+    }
+}
+
+fn candidates(
+    rule: Rule,
+    functions: &FunctionRegistry,
+    pick: &impl Fn((&str, &str)) -> String,
+) -> Vec<Completion> {
+    match rule {
+        // 运算数的开头: 内置函数、自定义函数与省略数量的骰子 (如 d20)
+        Rule::expr | Rule::dice_expr | Rule::atom | Rule::neg | Rule::pos => BUILTIN_FUNCTIONS
+            .iter()
+            .map(|f| Completion {
+                label: f.name.to_string(),
+                insert: format!("{}(", f.name),
+                kind: CompletionKind::Function,
+                detail: f.signatures.join(" | "),
+                description: pick(f.description),
+            })
+            .chain(
+                functions
+                    .definitions()
+                    .into_iter()
+                    .map(|def| macro_completion(def, pick)),
+            )
+            .chain(candidates(Rule::dice_op, functions, pick))
+            .collect(),
+        Rule::modifier | Rule::roll_mode => MODIFIERS
+            .iter()
+            .filter(|(name, ..)| (rule == Rule::roll_mode) == matches!(*name, "adv" | "dis"))
+            .map(|(name, detail, description)| Completion {
+                label: name.to_string(),
+                insert: name.to_string(),
+                kind: CompletionKind::Modifier,
+                detail: detail.to_string(),
+                description: pick(*description),
+            })
+            .collect(),
+        Rule::dice_op => vec![Completion {
+            label: "d".to_string(),
+            insert: "d".to_string(),
+            kind: CompletionKind::Dice,
+            detail: "NdX".to_string(),
+            description: pick(("Roll N X-sided dice", "掷 N 颗 X 面骰")),
+        }],
+        Rule::dice_side => DICE_SIDES
+            .iter()
+            .map(|(side, description)| Completion {
+                label: side.to_string(),
+                insert: side.to_string(),
+                kind: CompletionKind::Dice,
+                detail: format!("d{}", side),
+                description: pick(*description),
+            })
+            .collect(),
+        _ => Vec::new(),
+    }
+}
+
+fn macro_completion(def: &FunctionDef, pick: &impl Fn((&str, &str)) -> String) -> Completion {
+    Completion {
+        label: def.name.clone(),
+        insert: format!("{}(", def.name),
+        kind: CompletionKind::Macro,
+        detail: macro_signature(def),
+        description: format!(
+            "{} {}",
+            pick(("Custom function:", "自定义函数：")),
+            def.body
+        ),
+    }
+}
+
+// 自定义函数的签名，如 smite(lv: int)
+fn macro_signature(def: &FunctionDef) -> String {
+    let params: Vec<String> = def
+        .params
+        .iter()
+        .map(|p| match p.param_type {
+            ParamType::Int => format!("{}: int", p.name),
+            ParamType::Num => p.name.clone(),
+            ParamType::List => format!("{}: list", p.name),
+        })
+        .collect();
+    format!("{}({})", def.name, params.join(", "))
+}
+
+// 找出光标所在的最内层函数调用，参数下标为该调用的括号内顶层逗号的个数
+fn signature_help(
+    before: &str,
+    functions: &FunctionRegistry,
+    pick: &impl Fn((&str, &str)) -> String,
+) -> Option<SignatureHelp> {
+    let tokens = tokenize(before);
+    // 未闭合的括号，以及每个括号内已经出现的逗号数
+    let mut open: Vec<(usize, usize)> = Vec::new();
+    for (i, token) in tokens.iter().enumerate() {
+        match token.text.as_str() {
+            "(" | "[" | "{" => open.push((i, 0)),
+            ")" | "]" | "}" => {
+                open.pop();
+            }
+            "," => {
+                if let Some(last) = open.last_mut() {
+                    last.1 += 1;
+                }
+            }
+            _ => {}
+        }
+    }
+    let (paren, active) = open
+        .into_iter()
+        .rev()
+        .find(|(i, _)| tokens[*i].text == "(" && *i > 0)?;
+    let name = &tokens[paren - 1];
+    match name.kind {
+        TokenKind::Function => {
+            let lower = name.text.to_lowercase();
+            let f = BUILTIN_FUNCTIONS.iter().find(|f| f.name == lower)?;
+            Some(SignatureHelp {
+                name: f.name.to_string(),
+                signatures: f.signatures.iter().map(|s| s.to_string()).collect(),
+                description: pick(f.description),
+                active,
+            })
+        }
+        TokenKind::Macro => {
+            let def = functions.get(&name.text)?;
+            Some(SignatureHelp {
+                name: def.name.clone(),
+                signatures: vec![macro_signature(def)],
+                description: format!(
+                    "{} {}",
+                    pick(("Custom function:", "自定义函数：")),
+                    def.body
+                ),
+                active,
+            })
+        }
+        _ => None,
+    }
+}
+
+// 按 UTF-16 计算的位置转换为字节位置，超出范围时为末尾
+fn byte_offset(input: &str, utf16: usize) -> usize {
+    let mut count = 0;
+    for (i, c) in input.char_indices() {
+        if count >= utf16 {
+            return i;
+        }
+        count += c.len_utf16();
+    }
+    input.len()
+}
@@ -0,0 +1,1092 @@
+use serde::{Deserialize, Serialize};
+use tsify::Tsify;
+
+use crate::grammar::{BinOp, CompareExpr, CompareOp, Expr, ModifierOp, ModifierParam};
+use crate::number::Num;
+use crate::tables::{MAX_TABLE_DEPTH, Table, TableRegistry};
+use crate::trace::{SourceSpans, TraceNode};
+use crate::typecheck::{
+    DiceItem, DicePoolType, DiceSide, MAX_REPEAT, NumberType, Type, VariableNumber, is_group,
+    median, resolve_index, resolve_slice, top_n_preserve_order, typecheck_expr,
+};
+
+// 单颗骰子最多爆骰、重骰的次数，防止 1d6!>0 这类表达式无限循环
+pub const MAX_EXPLOSIONS: usize = 100;
+pub const MAX_REROLLS: usize = 100;
+
+// rpdice 省略重复次数时的默认值 (暴击时骰子翻倍)
+pub const DEFAULT_REPEAT: i64 = 2;
+
+// ==========================================
+// 随机数来源
+// ==========================================
+
+// 骰子的随机数来源，测试时可以注入固定的序列
+pub trait DiceRng {
+    // 返回 [0, n) 之间均匀分布的整数
+    fn next_index(&mut self, n: usize) -> usize;
+}
+
+// SplitMix64 伪随机数生成器，种子由调用方提供
+pub struct SplitMix64 {
+    state: u64,
+}
+
+impl SplitMix64 {
+    pub fn new(seed: u64) -> Self {
+        SplitMix64 { state: seed }
+    }
+}
+
+impl DiceRng for SplitMix64 {
+    fn next_index(&mut self, n: usize) -> usize {
+        self.state = self.state.wrapping_add(0x9E37_79B9_7F4A_7C15);
+        let mut z = self.state;
+        z = (z ^ (z >> 30)).wrapping_mul(0xBF58_476D_1CE4_E5B9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94D0_49BB_1331_11EB);
+        z ^= z >> 31;
+        // 用乘法代替取模，避免取模偏差
+        ((z as u128 * n as u128) >> 64) as usize
+    }
+}
+
+// ==========================================
+// 结果类型定义 (导出给前端)
+// ==========================================
+
+// 单颗骰子的结果
+#[derive(Debug, Clone, Serialize, Deserialize, Tsify, PartialEq)]
+#[tsify(into_wasm_abi)]
+pub struct DieRoll {
+    pub face: i64,              // 掷出的骰面
+    pub value: i64,             // 计入结果的值，复合爆骰时为累加后的值，mi/ma 限制后的值
+    pub compound: Vec<i64>,     // 复合爆骰 (!!) 追加的骰面
+    pub valid: bool,            // 是否计入结果
+    pub dropped: bool,          // 被 kh/kl/dh/dl 丢弃
+    pub rerolled: bool,         // 被 r/ro 重骰替换
+    pub exploded: bool,         // 触发了爆骰
+    pub critical_success: bool, // 大成功，默认为掷出最大面，可用 cs 指定
+    pub critical_failure: bool, // 大失败，默认为掷出最小面，可用 cf 指定
+}
+
+// 一个骰池的结果
+#[derive(Debug, Clone, Serialize, Deserialize, Tsify, PartialEq)]
+#[tsify(into_wasm_abi)]
+pub struct RollGroup {
+    pub notation: String,      // 骰池对应的表达式，如 "4d6dl1"
+    pub dice: Vec<DieRoll>,    // 骰池中的每一颗骰子，按掷出的顺序排列 (使用 s/sd 时按结果排序)
+    pub value: Num,            // 该骰池计入结果的值
+    pub label: Option<String>, // 所在项的标签，如 2d6 [Sneak Attack]
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, Tsify, PartialEq)]
+#[tsify(into_wasm_abi)]
+#[serde(tag = "type", content = "value")]
+pub enum RollValue {
+    Number(Num),
+    List(Vec<Num>),
+    Nested(Vec<RollValue>), // 嵌套列表，每个元素都是列表
+}
+
+impl RollValue {
+    // 数值或列表所有元素的总和，嵌套列表按总和排序与比较，溢出时报错
+    pub fn total(&self) -> Result<Num, String> {
+        match self {
+            RollValue::Number(n) => Ok(*n),
+            RollValue::List(l) => Num::sum(l.iter().copied()),
+            RollValue::Nested(items) => {
+                let totals = items
+                    .iter()
+                    .map(RollValue::total)
+                    .collect::<Result<Vec<_>, _>>()?;
+                Num::sum(totals)
+            }
+        }
+    }
+}
+
+// 一次随机表查询的结果
+#[derive(Debug, Clone, Serialize, Deserialize, Tsify, PartialEq)]
+#[tsify(into_wasm_abi)]
+pub struct TableRoll {
+    pub table: String,        // 表名
+    pub roll: Num,            // 驱动掷骰的结果
+    pub entry: usize,         // 选中的表项下标
+    pub text: Option<String>, // 表项的文本
+    pub value: Num,           // 计入结果的值，表项有表达式时为其结果，否则为驱动掷骰的结果
+}
+
+// 骰组中一个子掷骰的结果
+#[derive(Debug, Clone, Serialize, Deserialize, Tsify, PartialEq)]
+#[tsify(into_wasm_abi)]
+pub struct GroupItem {
+    pub notation: String, // 子掷骰的表达式，如 "1d20 + 5"
+    pub total: Num,       // 子掷骰的总值
+    pub dropped: bool,    // 被 kh/kl/dh/dl 丢弃
+}
+
+// 一个骰组 {...} 的结果
+#[derive(Debug, Clone, Serialize, Deserialize, Tsify, PartialEq)]
+#[tsify(into_wasm_abi)]
+pub struct GroupRoll {
+    pub notation: String,      // 骰组对应的表达式，如 "{1d20 + 5, 1d20 + 3}kh1"
+    pub items: Vec<GroupItem>, // 按书写顺序排列的子掷骰
+    pub value: Num,            // 计入结果的值，成功检定时为成功数
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, Tsify, PartialEq)]
+#[tsify(into_wasm_abi)]
+pub struct RollOutput {
+    pub result: RollValue,             // 最终结果
+    pub groups: Vec<RollGroup>,        // 按求值顺序排列的所有骰池
+    pub group_rolls: Vec<GroupRoll>,   // 按求值顺序排列的骰组结果，嵌套的骰组排在外层骰组之前
+    pub tables: Vec<TableRoll>,        // 按查询顺序排列的随机表结果，嵌套表排在外层表之后
+    pub comment: Option<String>,       // 表达式末尾的注释，如 # stealth
+    pub trace: Option<Box<TraceNode>>, // 求值过程的记录，只在需要时生成
+}
+
+// ==========================================
+// 主求值函数
+// ==========================================
+
+// 对表达式求值，表达式需要先通过类型检查
+pub fn evaluate_expr<R: DiceRng>(expr: &Expr, rng: &mut R) -> Result<RollOutput, String> {
+    evaluate_expr_with_tables(expr, rng, &TableRegistry::new())
+}
+
+// 对表达式求值，table("...") 从给定的注册表中查询
+pub fn evaluate_expr_with_tables<R: DiceRng>(
+    expr: &Expr,
+    rng: &mut R,
+    tables: &TableRegistry,
+) -> Result<RollOutput, String> {
+    evaluate(expr, rng, tables, None)
+}
+
+// 对表达式求值，并记录每个子表达式的结果、掷出的骰子与在输入中的位置 (位置从 spans 中查找)
+pub fn evaluate_expr_with_trace<R: DiceRng>(
+    expr: &Expr,
+    rng: &mut R,
+    tables: &TableRegistry,
+    spans: &SourceSpans,
+) -> Result<RollOutput, String> {
+    evaluate(expr, rng, tables, Some(spans))
+}
+
+fn evaluate<R: DiceRng>(
+    expr: &Expr,
+    rng: &mut R,
+    tables: &TableRegistry,
+    spans: Option<&SourceSpans>,
+) -> Result<RollOutput, String> {
+    if let Type::Invalid(s) = typecheck_expr(expr) {
+        return Err(s);
+    }
+    let mut evaluator = Evaluator {
+        rng,
+        groups: Vec::new(),
+        group_rolls: Vec::new(),
+        repeat: 1,
+        tables,
+        table_rolls: Vec::new(),
+        table_depth: 0,
+        trace: spans.map(|_| Vec::new()),
+        spans,
+    };
+    let result = evaluator.eval_value(expr)?;
+    Ok(RollOutput {
+        result,
+        groups: evaluator.groups,
+        group_rolls: evaluator.group_rolls,
+        tables: evaluator.table_rolls,
+        comment: match expr {
+            Expr::Comment { text, .. } => Some(text.clone()),
+            _ => None,
+        },
+        trace: evaluator
+            .trace
+            .and_then(|mut nodes| nodes.pop())
+            .map(Box::new),
+    })
+}
+
+// ==========================================
+// 辅助处理函数
+// ==========================================
+
+// 求值过程中的骰池，尚未求和
+struct Pool {
+    item: DiceItem,
+    dice: Vec<DieRoll>,
+    critical_success: Option<(CompareOp, Num)>, // cs 指定的大成功条件
+    critical_failure: Option<(CompareOp, Num)>, // cf 指定的大失败条件
+}
+
+impl Pool {
+    fn total(&self) -> Result<Num, String> {
+        Num::sum(
+            self.dice
+                .iter()
+                .filter(|d| d.valid)
+                .map(|d| Num::int(d.value)),
+        )
+    }
+
+    // 根据骰面标记大成功与大失败，追加的骰子同样适用
+    fn mark_criticals(&mut self) {
+        let (success_op, success) = self
+            .critical_success
+            .clone()
+            .unwrap_or((CompareOp::Equal, Num::int(self.item.side.max_face())));
+        let (failure_op, failure) = self
+            .critical_failure
+            .clone()
+            .unwrap_or((CompareOp::Equal, Num::int(self.item.side.min_face())));
+        for die in self.dice.iter_mut() {
+            die.critical_success = compare(&success_op, Num::int(die.face), success);
+            die.critical_failure = compare(&failure_op, Num::int(die.face), failure);
+        }
+    }
+}
+
+// 已经通过类型检查的常数表达式
+fn constant_of(expr: &Expr) -> Num {
+    match typecheck_expr(expr) {
+        Type::Number(NumberType::Constant(c)) => c,
+        t => unreachable!("Expected a constant, got {:?}", t),
+    }
+}
+
+pub fn compare(op: &CompareOp, lhs: Num, rhs: Num) -> bool {
+    match op {
+        CompareOp::Greater => lhs > rhs,
+        CompareOp::Less => lhs < rhs,
+        CompareOp::Equal => lhs == rhs,
+        CompareOp::GreaterEqual => lhs >= rhs,
+        CompareOp::LessEqual => lhs <= rhs,
+    }
+}
+
+// 成功检定的计分规则
+// 满足成功条件计 1，同时满足双倍成功条件再计 1；不成功但满足失败条件计 -1
+pub struct SuccessRule {
+    success: (CompareOp, Num),
+    failure: Option<(CompareOp, Num)>,
+    double_success: Option<(CompareOp, Num)>,
+}
+
+impl SuccessRule {
+    pub fn new(
+        success: &CompareExpr,
+        failure: &Option<CompareExpr>,
+        double_success: &Option<CompareExpr>,
+    ) -> Self {
+        let resolve = |ce: &CompareExpr| (ce.op.clone(), constant_of(&ce.val));
+        SuccessRule {
+            success: resolve(success),
+            failure: failure.as_ref().map(resolve),
+            double_success: double_success.as_ref().map(resolve),
+        }
+    }
+
+    pub fn score(&self, value: Num) -> Num {
+        let matches = |rule: &Option<(CompareOp, Num)>| {
+            rule.as_ref()
+                .is_some_and(|(op, target)| compare(op, value, *target))
+        };
+        if compare(&self.success.0, value, self.success.1) {
+            if matches(&self.double_success) {
+                Num::int(2)
+            } else {
+                Num::ONE
+            }
+        } else if matches(&self.failure) {
+            Num::int(-1)
+        } else {
+            Num::ZERO
+        }
+    }
+}
+
+// 修饰符中的比较参数，求出比较符与目标值
+fn compare_param(param: &Option<ModifierParam>) -> Option<(CompareOp, Num)> {
+    match param {
+        Some(ModifierParam::Compare(CompareExpr { op, val })) => {
+            Some((op.clone(), constant_of(val)))
+        }
+        _ => None,
+    }
+}
+
+fn value_param(param: &Option<ModifierParam>) -> i64 {
+    match param {
+        Some(ModifierParam::Value(v)) => constant_of(v).to_i64(),
+        _ => unreachable!("Modifier requires a value parameter"),
+    }
+}
+
+// 把参数整理为数值列表，与类型检查中的 preprocess_call_args 规则一致
+enum Args {
+    OneNumber(Num),
+    OneList(Vec<Num>),
+    ListAndNumber(Vec<Num>, Num),
+}
+
+fn preprocess_args(values: Vec<RollValue>) -> Args {
+    match values.as_slice() {
+        [RollValue::Number(n)] => Args::OneNumber(*n),
+        [RollValue::List(l)] => Args::OneList(l.clone()),
+        [RollValue::List(l), RollValue::Number(n)] => Args::ListAndNumber(l.clone(), *n),
+        _ => Args::OneList(
+            values
+                .into_iter()
+                .map(|v| match v {
+                    RollValue::Number(n) => n,
+                    _ => unreachable!("List arguments cannot be mixed with other arguments."),
+                })
+                .collect(),
+        ),
+    }
+}
+
+struct Evaluator<'a, R: DiceRng> {
+    rng: &'a mut R,
+    groups: Vec<RollGroup>,
+    group_rolls: Vec<GroupRoll>,
+    repeat: i64, // rpdice 的重复次数，骰池会被重复掷出并求和
+    tables: &'a TableRegistry,
+    table_rolls: Vec<TableRoll>,
+    table_depth: usize,                 // 当前嵌套查询的表数量
+    trace: Option<Vec<TraceNode>>,      // 当前正在求值的节点已经完成的子节点，不需要记录时为 None
+    spans: Option<&'a SourceSpans<'a>>, // 记录中各节点在输入中的位置
+}
+
+impl<R: DiceRng> Evaluator<'_, R> {
+    fn roll_face(&mut self, item: &DiceItem) -> i64 {
+        match &item.side {
+            // 普通骰子直接取随机数，不展开骰面
+            DiceSide::Number(n) => 1 + self.rng.next_index(*n as usize) as i64,
+            side => {
+                let faces = side.faces();
+                faces[self.rng.next_index(faces.len())]
+            }
+        }
+    }
+
+    fn roll_die(&mut self, item: &DiceItem) -> DieRoll {
+        let face = self.roll_face(item);
+        DieRoll {
+            face,
+            value: face,
+            compound: Vec::new(),
+            valid: true,
+            dropped: false,
+            rerolled: false,
+            exploded: false,
+            critical_success: false,
+            critical_failure: false,
+        }
+    }
+
+    fn eval_value(&mut self, expr: &Expr) -> Result<RollValue, String> {
+        let Some(siblings) = self.trace.take() else {
+            return self.eval_node(expr);
+        };
+        // 子表达式的记录收集到新的一层，求值后作为当前节点的子节点
+        self.trace = Some(Vec::new());
+        let first = self.groups.len();
+        let result = self.eval_node(expr);
+        let children = self.trace.replace(siblings).unwrap_or_default();
+        let value = result?;
+        let node = TraceNode {
+            notation: expr.to_string(),
+            span: self.spans.and_then(|spans| spans.get(expr)),
+            value: value.clone(),
+            dice: self.groups[first..]
+                .iter()
+                .flat_map(|g| g.dice.iter().cloned())
+                .collect(),
+            children,
+        };
+        if let Some(nodes) = self.trace.as_mut() {
+            nodes.push(node);
+        }
+        Ok(value)
+    }
+
+    fn eval_node(&mut self, expr: &Expr) -> Result<RollValue, String> {
+        match expr {
+            Expr::Number(n) => Ok(RollValue::Number(*n)),
+            Expr::SuccessCheck {
+                lhs,
+                compare_expr,
+                failure,
+                double_success,
+            } => {
+                let rule = SuccessRule::new(compare_expr, failure, double_success);
+                if is_group(lhs) {
+                    self.eval_group_value(expr, lhs, Some(&rule))
+                } else {
+                    self.eval_successes(expr, lhs, &rule)
+                }
+            }
+            Expr::Modifier {
+                lhs,
+                op: ModifierOp::Count,
+                param: Some(ModifierParam::Compare(compare_expr)),
+            } => {
+                let rule = SuccessRule::new(compare_expr, &None, &None);
+                if is_group(lhs) {
+                    self.eval_group_value(expr, lhs, Some(&rule))
+                } else {
+                    self.eval_successes(expr, lhs, &rule)
+                }
+            }
+            Expr::Group(_) => self.eval_group_value(expr, expr, None),
+            Expr::Modifier { .. } if is_group(expr) => self.eval_group_value(expr, expr, None),
+            Expr::Dice { .. }
+            | Expr::FateDice { .. }
+            | Expr::PercentileDice { .. }
+            | Expr::Modifier { .. } => {
+                let mut total = Num::ZERO;
+                for _ in 0..self.repeat {
+                    let pool = self.eval_pool(expr)?;
+                    let value = pool.total()?;
+                    self.push_group(expr, pool, value);
+                    total = total.checked_add(value)?;
+                }
+                Ok(RollValue::Number(total))
+            }
+
+            Expr::Binary { lhs, op, rhs } => self.eval_binary(lhs, op, rhs),
+            Expr::Call { func_name, args } => self.eval_call(func_name, args),
+            Expr::List(items) => {
+                let mut values = Vec::new();
+                for item in items {
+                    values.push(self.eval_value(item)?);
+                }
+                // 类型检查保证元素要么全是数值，要么全是列表
+                if values.iter().all(|v| matches!(v, RollValue::Number(_))) {
+                    Ok(RollValue::List(
+                        values
+                            .iter()
+                            .map(RollValue::total)
+                            .collect::<Result<_, _>>()?,
+                    ))
+                } else {
+                    Ok(RollValue::Nested(values))
+                }
+            }
+            Expr::Var(_) => unreachable!("Variables are rejected by typecheck"),
+            Expr::Table(name) => self.eval_table(name).map(RollValue::Number),
+            Expr::Label { expr, label } => {
+                let first = self.groups.len();
+                let value = self.eval_value(expr)?;
+                self.label_groups(first, label);
+                Ok(value)
+            }
+            Expr::Comment { expr, .. } => self.eval_value(expr),
+            Expr::Index { list, index } => {
+                let index = constant_of(index).to_i64();
+                let items = self.eval_items(list)?;
+                match resolve_index(index, items.len() as i64) {
+                    Some(i) => Ok(items[i].clone()),
+                    None => Err(format!(
+                        "List index {} is out of range for a list of length {}.",
+                        index,
+                        items.len()
+                    )),
+                }
+            }
+            Expr::Slice { list, start, end } => {
+                let bound = |b: &Option<Box<Expr>>| b.as_ref().map(|e| constant_of(e).to_i64());
+                let (start, end) = (bound(start), bound(end));
+                let is_nested = matches!(typecheck_expr(list), Type::List(l) if l.is_nested());
+                let items = self.eval_items(list)?;
+                let (from, to) = resolve_slice(start, end, items.len() as i64);
+                collect_items(items[from..to].to_vec(), is_nested)
+            }
+            Expr::Filter { list, compare_expr } => {
+                let target = constant_of(&compare_expr.val);
+                match self.eval_value(list)? {
+                    RollValue::List(l) => Ok(RollValue::List(
+                        l.into_iter()
+                            .filter(|v| compare(&compare_expr.op, *v, target))
+                            .collect(),
+                    )),
+                    _ => unreachable!("filter requires a flat list"),
+                }
+            }
+        }
+    }
+
+    // 统计骰池中的成功数，每颗骰子的得分由 rule 决定
+    fn eval_successes(
+        &mut self,
+        expr: &Expr,
+        lhs: &Expr,
+        rule: &SuccessRule,
+    ) -> Result<RollValue, String> {
+        let mut total = Num::ZERO;
+        for _ in 0..self.repeat {
+            let pool = self.eval_pool(lhs)?;
+            let successes = Num::sum(
+                pool.dice
+                    .iter()
+                    .filter(|d| d.valid)
+                    .map(|d| rule.score(Num::int(d.value))),
+            )?;
+            self.push_group(expr, pool, successes);
+            total = total.checked_add(successes)?;
+        }
+        Ok(RollValue::Number(total))
+    }
+
+    // 标签标记从 first 开始新增的骰池，内层的标签优先
+    fn label_groups(&mut self, first: usize, label: &str) {
+        for group in &mut self.groups[first..] {
+            if group.label.is_none() {
+                group.label = Some(label.to_string());
+            }
+        }
+    }
+
+    // 对骰组求值并记录结果，rule 存在时统计保留的子掷骰中的成功数，否则为总值之和
+    fn eval_group_value(
+        &mut self,
+        expr: &Expr,
+        group: &Expr,
+        rule: Option<&SuccessRule>,
+    ) -> Result<RollValue, String> {
+        let items = self.eval_group(group)?;
+        let value = Num::sum(
+            items
+                .iter()
+                .filter(|item| !item.dropped)
+                .map(|item| rule.map_or(item.total, |r| r.score(item.total))),
+        )?;
+        self.group_rolls.push(GroupRoll {
+            notation: expr.to_string(),
+            items,
+            value,
+        });
+        Ok(RollValue::Number(value))
+    }
+
+    // 对骰组 (或修饰后的骰组) 求值，不求和
+    fn eval_group(&mut self, expr: &Expr) -> Result<Vec<GroupItem>, String> {
+        match expr {
+            Expr::Group(items) => {
+                let mut results = Vec::new();
+                for item in items {
+                    results.push(GroupItem {
+                        notation: item.to_string(),
+                        total: self.eval_value(item)?.total()?,
+                        dropped: false,
+                    });
+                }
+                Ok(results)
+            }
+            Expr::Modifier { lhs, op, param } => {
+                let mut items = self.eval_group(lhs)?;
+                keep_or_drop_items(&mut items, op, value_param(param));
+                Ok(items)
+            }
+            Expr::Label { expr, label } => {
+                let first = self.groups.len();
+                let items = self.eval_group(expr)?;
+                self.label_groups(first, label);
+                Ok(items)
+            }
+            _ => unreachable!("Expected a group expression, got {:?}", expr),
+        }
+    }
+
+    // 查询随机表：掷出驱动骰，选中表项后对表项的表达式求值
+    fn eval_table(&mut self, name: &str) -> Result<Num, String> {
+        let tables = self.tables;
+        let table = match tables.get(name) {
+            Some(table) => table,
+            None => return Err(format!("Unknown table: {}", name)),
+        };
+        if self.table_depth >= MAX_TABLE_DEPTH {
+            return Err(format!(
+                "Tables are nested too deeply (more than {} levels).",
+                MAX_TABLE_DEPTH
+            ));
+        }
+        // 表内的骰子不受 rpdice 影响
+        let saved = self.repeat;
+        self.repeat = 1;
+        self.table_depth += 1;
+        let result = self.eval_table_entry(name, table);
+        self.table_depth -= 1;
+        self.repeat = saved;
+        result
+    }
+
+    fn eval_table_entry(&mut self, name: &str, table: &Table) -> Result<Num, String> {
+        let roll = self.eval_value(&table.roll)?.total()?;
+        let entry = match table.lookup(roll) {
+            Some(entry) => entry,
+            None => {
+                return Err(format!(
+                    "Roll result {} of table {} has no entry.",
+                    roll, name
+                ));
+            }
+        };
+        // 先记录外层表，嵌套表的结果排在后面
+        let index = self.table_rolls.len();
+        self.table_rolls.push(TableRoll {
+            table: name.to_string(),
+            roll,
+            entry,
+            text: table.def.entries[entry].text.clone(),
+            value: roll,
+        });
+        if let Some(expr) = table.entry_expr(entry) {
+            let value = self.eval_value(expr)?.total()?;
+            self.table_rolls[index].value = value;
+        }
+        Ok(self.table_rolls[index].value)
+    }
+
+    // 求出列表的所有元素，平铺列表的元素为数值
+    fn eval_items(&mut self, list: &Expr) -> Result<Vec<RollValue>, String> {
+        match self.eval_value(list)? {
+            RollValue::List(l) => Ok(l.into_iter().map(RollValue::Number).collect()),
+            RollValue::Nested(items) => Ok(items),
+            RollValue::Number(_) => unreachable!("Expected a list"),
+        }
+    }
+
+    fn push_group(&mut self, expr: &Expr, mut pool: Pool, value: Num) {
+        pool.mark_criticals();
+        self.groups.push(RollGroup {
+            notation: expr.to_string(),
+            dice: pool.dice,
+            value,
+            label: None,
+        });
+    }
+
+    fn eval_binary(&mut self, lhs: &Expr, op: &BinOp, rhs: &Expr) -> Result<RollValue, String> {
+        use RollValue::*;
+        // 列表与常数相乘时，每一份列表都单独求值 (重新掷骰)
+        match (typecheck_expr(lhs), typecheck_expr(rhs)) {
+            (Type::List(_), Type::Number(_)) => return self.eval_repeat_list(lhs, rhs),
+            (Type::Number(_), Type::List(_)) => return self.eval_repeat_list(rhs, lhs),
+            _ => {}
+        }
+        let l = self.eval_value(lhs)?;
+        let r = self.eval_value(rhs)?;
+        match (l, r) {
+            (Number(l), Number(r)) => apply_bin_op(op, l, r).map(Number),
+            (List(mut l), List(r)) => {
+                l.extend(r);
+                Ok(List(l))
+            }
+            (Nested(mut l), Nested(r)) => {
+                l.extend(r);
+                Ok(Nested(l))
+            }
+            _ => unreachable!("Invalid binary operation between list and number"),
+        }
+    }
+
+    fn eval_repeat_list(&mut self, list: &Expr, times: &Expr) -> Result<RollValue, String> {
+        let times = constant_of(times).to_i64();
+        let mut values = Vec::new();
+        for _ in 0..times {
+            values.extend(self.eval_items(list)?);
+        }
+        let is_nested = matches!(typecheck_expr(list), Type::List(l) if l.is_nested());
+        collect_items(values, is_nested)
+    }
+
+    fn eval_call(&mut self, func_name: &str, args: &[Expr]) -> Result<RollValue, String> {
+        use RollValue::*;
+        if func_name == "rpdice" {
+            let times = match args {
+                [_, n] => constant_of(n).to_i64(),
+                _ => DEFAULT_REPEAT,
+            };
+            // 嵌套的 rpdice 重复次数相乘，总次数同样受限
+            if self.repeat * times > MAX_REPEAT {
+                return Err(format!(
+                    "In rpdice, the total repeat count must not exceed {}.",
+                    MAX_REPEAT
+                ));
+            }
+            let saved = self.repeat;
+            self.repeat *= times;
+            let result = self.eval_value(&args[0]);
+            self.repeat = saved;
+            return result;
+        }
+
+        let mut values = Vec::new();
+        for arg in args {
+            values.push(self.eval_value(arg)?);
+        }
+        // 固定参数个数的函数与嵌套列表不做列表化处理
+        match (func_name, values.as_slice()) {
+            ("max" | "min", [Nested(items)]) => {
+                return match top_n_lists(items, 1, func_name == "max")?.pop() {
+                    Some(v) => Ok(v),
+                    None => Err("max/min function requires at least one element.".to_string()),
+                };
+            }
+            ("max" | "min", [Nested(items), Number(n)]) => {
+                return Ok(Nested(top_n_lists(
+                    items,
+                    n.to_i64() as usize,
+                    func_name == "max",
+                )?));
+            }
+            ("sort", [Nested(items)]) => {
+                let totals = items
+                    .iter()
+                    .map(RollValue::total)
+                    .collect::<Result<Vec<_>, _>>()?;
+                let mut order: Vec<usize> = (0..items.len()).collect();
+                order.sort_by(|a, b| totals[*a].total_cmp(&totals[*b]));
+                return Ok(Nested(
+                    order.into_iter().map(|i| items[i].clone()).collect(),
+                ));
+            }
+            ("len", [Nested(items)]) => return Ok(Number(Num::int(items.len() as i64))),
+            ("pow", [Number(base), Number(exponent)]) => {
+                return apply_bin_op(&BinOp::Pow, *base, *exponent).map(Number);
+            }
+            ("clamp", [Number(x), Number(lo), Number(hi)]) => {
+                if lo > hi {
+                    return Err(
+                        "In clamp, the lower bound must not exceed the upper bound.".to_string()
+                    );
+                }
+                return Ok(Number(x.max(*lo).min(*hi)));
+            }
+            _ => {}
+        }
+        let args = preprocess_args(values);
+        match (func_name, args) {
+            ("max" | "min" | "sum", Args::OneNumber(n)) => Ok(Number(n)),
+            // filter 的结果可能为空，只能在求值时检查
+            ("max" | "min" | "avg" | "median", Args::OneList(l)) if l.is_empty() => Err(format!(
+                "{} function requires at least one element.",
+                func_name
+            )),
+            ("max" | "min", Args::ListAndNumber(l, n)) if Num::int(l.len() as i64) < n => {
+                Err(format!(
+                    "In min/max, the list length {} is less than the count parameter {}.",
+                    l.len(),
+                    n
+                ))
+            }
+            ("sort", Args::OneList(mut l)) => {
+                l.sort_by(Num::total_cmp);
+                Ok(List(l))
+            }
+            ("max", Args::OneList(l)) => Ok(Number(l.into_iter().reduce(Num::max).unwrap())),
+            ("min", Args::OneList(l)) => Ok(Number(l.into_iter().reduce(Num::min).unwrap())),
+            ("sum", Args::OneList(l)) => Num::sum(l).map(Number),
+            ("max" | "min", Args::ListAndNumber(l, n)) => Ok(List(top_n_preserve_order(
+                &l,
+                n.to_i64() as usize,
+                func_name == "max",
+            ))),
+            ("floor", Args::OneNumber(n)) => Ok(Number(n.floor())),
+            ("ceil", Args::OneNumber(n)) => Ok(Number(n.ceil())),
+            ("round", Args::OneNumber(n)) => Ok(Number(n.round())),
+            ("abs", Args::OneNumber(n)) => Ok(Number(n.abs())),
+            ("sqrt", Args::OneNumber(n)) if n < Num::ZERO => {
+                Err("sqrt function requires a non-negative argument.".to_string())
+            }
+            ("sqrt", Args::OneNumber(n)) => Ok(Number(n.sqrt())),
+            ("avg" | "median", Args::OneNumber(n)) => Ok(Number(n)),
+            ("avg", Args::OneList(l)) => {
+                let len = Num::int(l.len() as i64);
+                Num::sum(l)?.checked_div(len).map(Number)
+            }
+            ("median", Args::OneList(l)) => median(&l).map(Number),
+            ("len", Args::OneList(l)) => Ok(Number(Num::int(l.len() as i64))),
+            ("count", Args::ListAndNumber(l, n)) => Ok(Number(Num::int(
+                l.iter().filter(|v| **v == n).count() as i64,
+            ))),
+            _ => unreachable!("Function {} should be rejected by typecheck", func_name),
+        }
+    }
+
+    // 对骰池求值 (骰子或者修饰符节点)，不求和
+    fn eval_pool(&mut self, expr: &Expr) -> Result<Pool, String> {
+        match expr {
+            Expr::Dice { .. } | Expr::FateDice { .. } | Expr::PercentileDice { .. } => {
+                let item = match typecheck_expr(expr) {
+                    Type::Number(NumberType::Variable(VariableNumber::DicePool(
+                        DicePoolType::RawDicePool(item),
+                    ))) => item,
+                    Type::Invalid(s) => return Err(s),
+                    t => return Err(format!("Expected a dice pool, got {:?}", t)),
+                };
+                let dice = (0..item.min_count).map(|_| self.roll_die(&item)).collect();
+                Ok(Pool {
+                    item,
+                    dice,
+                    critical_success: None,
+                    critical_failure: None,
+                })
+            }
+            Expr::Modifier {
+                lhs,
+                op: ModifierOp::Limit,
+                param,
+            } => match lhs.without_labels() {
+                // 类型检查保证 l 只能跟在爆骰 (!、!!、!p) 之后，中间可以有标签
+                Expr::Modifier {
+                    lhs: inner,
+                    op: explode_op,
+                    param: explode_param,
+                } => {
+                    let mut pool = self.eval_pool(inner)?;
+                    let limit = value_param(param) as usize;
+                    self.explode(&mut pool, explode_op, explode_param, limit);
+                    Ok(pool)
+                }
+                _ => unreachable!("Limit modifier must follow an explosion"),
+            },
+            Expr::Modifier { lhs, op, param } => {
+                let mut pool = self.eval_pool(lhs)?;
+                self.apply_modifier(&mut pool, op, param);
+                Ok(pool)
+            }
+            Expr::Label { expr, .. } => self.eval_pool(expr),
+            _ => unreachable!("Expected a dice pool expression, got {:?}", expr),
+        }
+    }
+
+    fn apply_modifier(&mut self, pool: &mut Pool, op: &ModifierOp, param: &Option<ModifierParam>) {
+        use ModifierOp::*;
+        match op {
+            KeepHigh | KeepLow | DropHigh | DropLow => {
+                keep_or_drop(pool, op, value_param(param));
+            }
+            Reroll | RerollOnce => {
+                let (cmp, target) = compare_param(param).unwrap();
+                let max_rerolls = if *op == Reroll { MAX_REROLLS } else { 1 };
+                let mut dice = Vec::new();
+                for die in std::mem::take(&mut pool.dice) {
+                    if !die.valid || !compare(&cmp, Num::int(die.face), target) {
+                        dice.push(die);
+                        continue;
+                    }
+                    let mut current = die;
+                    for _ in 0..max_rerolls {
+                        if !compare(&cmp, Num::int(current.face), target) {
+                            break;
+                        }
+                        current.valid = false;
+                        current.rerolled = true;
+                        dice.push(current);
+                        current = self.roll_die(&pool.item);
+                    }
+                    dice.push(current);
+                }
+                pool.dice = dice;
+            }
+            Explode | ExplodePenetrate | ExplodeCompound => {
+                self.explode(pool, op, param, MAX_EXPLOSIONS)
+            }
+            ClampMin | ClampMax => {
+                let bound = value_param(param);
+                for die in pool.dice.iter_mut().filter(|d| d.valid) {
+                    die.value = if *op == ClampMin {
+                        die.value.max(bound)
+                    } else {
+                        die.value.min(bound)
+                    };
+                }
+            }
+            // 省略参数时使用默认的最大面 / 最小面
+            CritSuccess => pool.critical_success = compare_param(param),
+            CritFailure => pool.critical_failure = compare_param(param),
+            SortAsc => pool.dice.sort_by_key(|d| d.value),
+            SortDesc => pool.dice.sort_by_key(|d| std::cmp::Reverse(d.value)),
+            Unique => {
+                // 与之前保留的骰子点数相同时重骰，最多重骰 MAX_REROLLS 次
+                let mut seen = Vec::new();
+                let mut dice = Vec::new();
+                for die in std::mem::take(&mut pool.dice) {
+                    if !die.valid {
+                        dice.push(die);
+                        continue;
+                    }
+                    let mut current = die;
+                    for _ in 0..MAX_REROLLS {
+                        if !seen.contains(&current.face) {
+                            break;
+                        }
+                        current.valid = false;
+                        current.rerolled = true;
+                        dice.push(current);
+                        current = self.roll_die(&pool.item);
+                    }
+                    seen.push(current.face);
+                    dice.push(current);
+                }
+                pool.dice = dice;
+            }
+            Count => unreachable!("Count is evaluated as a number"),
+            Limit => unreachable!("Limit is handled together with explosion"),
+        }
+    }
+
+    // 爆骰，limit 为每颗骰子的最大爆骰次数
+    // ! 追加新的骰子；!p 追加的骰子结果 -1；!! 追加的骰面累加到原骰子上
+    fn explode(
+        &mut self,
+        pool: &mut Pool,
+        op: &ModifierOp,
+        param: &Option<ModifierParam>,
+        limit: usize,
+    ) {
+        if *op == ModifierOp::ExplodeCompound {
+            return self.explode_compound(pool, param, limit);
+        }
+        let penalty = if *op == ModifierOp::ExplodePenetrate {
+            1
+        } else {
+            0
+        };
+        let (cmp, target) = explode_condition(&pool.item, param);
+        let mut dice = Vec::new();
+        for die in std::mem::take(&mut pool.dice) {
+            if !die.valid {
+                dice.push(die);
+                continue;
+            }
+            let mut current = die;
+            for _ in 0..limit.min(MAX_EXPLOSIONS) {
+                // 是否继续爆骰由掷出的骰面决定，而不是 -1 之后的结果
+                if !compare(&cmp, Num::int(current.face), target) {
+                    break;
+                }
+                current.exploded = true;
+                dice.push(current);
+                current = self.roll_die(&pool.item);
+                current.value -= penalty;
+            }
+            dice.push(current);
+        }
+        pool.dice = dice;
+    }
+
+    fn explode_compound(&mut self, pool: &mut Pool, param: &Option<ModifierParam>, limit: usize) {
+        let (cmp, target) = explode_condition(&pool.item, param);
+        for i in 0..pool.dice.len() {
+            if !pool.dice[i].valid {
+                continue;
+            }
+            let mut last = pool.dice[i].face;
+            let mut count = 0;
+            while count < limit.min(MAX_EXPLOSIONS) && compare(&cmp, Num::int(last), target) {
+                last = self.roll_face(&pool.item);
+                let die = &mut pool.dice[i];
+                die.exploded = true;
+                die.compound.push(last);
+                die.value += last;
+                count += 1;
+            }
+        }
+    }
+}
+
+// 把列表元素重新组装为列表，嵌套列表即使为空也保持嵌套
+fn collect_items(items: Vec<RollValue>, is_nested: bool) -> Result<RollValue, String> {
+    if is_nested {
+        Ok(RollValue::Nested(items))
+    } else {
+        let values = items
+            .iter()
+            .map(RollValue::total)
+            .collect::<Result<_, _>>()?;
+        Ok(RollValue::List(values))
+    }
+}
+
+// 按总和选出最大 / 最小的 n 个子列表，保持原来的顺序
+fn top_n_lists(items: &[RollValue], n: usize, largest: bool) -> Result<Vec<RollValue>, String> {
+    let totals = items
+        .iter()
+        .map(RollValue::total)
+        .collect::<Result<Vec<_>, _>>()?;
+    let mut order: Vec<usize> = (0..items.len()).collect();
+    // 稳定排序，总和相同时先出现的子列表优先
+    order.sort_by(|a, b| {
+        let ord = totals[*a].total_cmp(&totals[*b]);
+        if largest { ord.reverse() } else { ord }
+    });
+    order.truncate(n);
+    order.sort();
+    Ok(order.into_iter().map(|i| items[i].clone()).collect())
+}
+
+// 爆骰条件，省略时为骰子的最大面
+fn explode_condition(item: &DiceItem, param: &Option<ModifierParam>) -> (CompareOp, Num) {
+    compare_param(param).unwrap_or((CompareOp::Equal, Num::int(item.side.max_face())))
+}
+
+fn keep_or_drop(pool: &mut Pool, op: &ModifierOp, n: i64) {
+    use ModifierOp::*;
+    let mut valid: Vec<usize> = (0..pool.dice.len())
+        .filter(|i| pool.dice[*i].valid)
+        .collect();
+    // 稳定排序，相同点数时先掷出的骰子排在前面
+    match op {
+        KeepHigh | DropLow => valid.sort_by_key(|i| std::cmp::Reverse(pool.dice[*i].value)),
+        KeepLow | DropHigh => valid.sort_by_key(|i| pool.dice[*i].value),
+        _ => unreachable!(),
+    }
+    let keep = match op {
+        KeepHigh | KeepLow => n as usize,
+        DropHigh | DropLow => valid.len().saturating_sub(n as usize),
+        _ => unreachable!(),
+    };
+    for i in valid.into_iter().skip(keep) {
+        pool.dice[i].valid = false;
+        pool.dice[i].dropped = true;
+    }
+}
+
+// 骰组的取高/取低，按子掷骰的总值比较，只考虑尚未丢弃的子掷骰
+fn keep_or_drop_items(items: &mut [GroupItem], op: &ModifierOp, n: i64) {
+    use ModifierOp::*;
+    let mut valid: Vec<usize> = (0..items.len()).filter(|i| !items[*i].dropped).collect();
+    // 稳定排序，相同总值时靠前的子掷骰排在前面
+    match op {
+        KeepHigh | DropLow => valid.sort_by(|a, b| items[*b].total.total_cmp(&items[*a].total)),
+        KeepLow | DropHigh => valid.sort_by(|a, b| items[*a].total.total_cmp(&items[*b].total)),
+        _ => unreachable!("Only keep / drop modifiers apply to groups"),
+    }
+    let keep = match op {
+        KeepHigh | KeepLow => n as usize,
+        _ => valid.len().saturating_sub(n as usize),
+    };
+    for i in valid.into_iter().skip(keep) {
+        items[i].dropped = true;
+    }
+}
+
+// 二元运算，结果溢出或没有实数结果时报错
+pub fn apply_bin_op(op: &BinOp, l: Num, r: Num) -> Result<Num, String> {
+    match op {
+        BinOp::Add => l.checked_add(r),
+        BinOp::Sub => l.checked_sub(r),
+        BinOp::Mul => l.checked_mul(r),
+        BinOp::Div => l.checked_div(r),
+        BinOp::Mod => l.checked_rem(r),
+        BinOp::Idiv => l.idiv(r),
+        BinOp::Pow => l.pow(r),
+    }
+}
@@ -0,0 +1,719 @@
+use serde::{Deserialize, Serialize};
+use tsify::Tsify;
+
+use crate::grammar::{BinOp, CompareExpr, CompareOp, Expr, ModifierOp, ModifierParam};
+use crate::number::Num;
+use crate::typecheck::{Type, is_group, typecheck_expr};
+
+// ==========================================
+// 表达式的自然语言说明
+// ==========================================
+
+// 说明文字的语言
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, Tsify, PartialEq)]
+#[tsify(into_wasm_abi, from_wasm_abi)]
+pub enum Language {
+    #[serde(rename = "en")]
+    En,
+    #[serde(rename = "zh-CN")]
+    ZhCn,
+}
+
+// 用自然语言说明表达式，如 4d6dl1 -> "roll four six-sided dice, drop the lowest one"
+// 表达式需要先通过类型检查，自定义函数调用需要先展开
+pub fn explain_expr(expr: &Expr, lang: Language) -> String {
+    Explainer { lang }.explain(expr)
+}
+
+// ==========================================
+// 辅助处理函数
+// ==========================================
+
+struct Explainer {
+    lang: Language,
+}
+
+impl Explainer {
+    // 按语言选择文本，两种语言的文本都会被构造出来
+    fn pick(&self, en: String, zh: String) -> String {
+        match self.lang {
+            Language::En => en,
+            Language::ZhCn => zh,
+        }
+    }
+
+    fn explain(&self, expr: &Expr) -> String {
+        match expr {
+            Expr::Number(n) => format!("{}", n),
+            Expr::Dice { count, side } => {
+                let count = self.count(count);
+                let sides = match side.as_ref() {
+                    Expr::List(faces) => {
+                        let faces = self.join(faces, ", ");
+                        self.pick(
+                            format!("{}with faces {}", plural(&count, "die ", "dice "), faces),
+                            format!("骰面为 {} 的骰子", faces),
+                        )
+                    }
+                    Expr::Number(n) => self.pick(
+                        format!(
+                            "{}-sided {}",
+                            number_word(*n),
+                            plural(&count, "die", "dice")
+                        ),
+                        format!(" {} 面骰", n),
+                    ),
+                    side => {
+                        let side = self.enclose(self.explain(side));
+                        self.pick(
+                            format!("{} with {} sides", plural(&count, "die", "dice"), side),
+                            format!(" {} 面骰", side),
+                        )
+                    }
+                };
+                self.roll(&count, &sides)
+            }
+            Expr::FateDice { count } => {
+                let count = self.count(count);
+                let dice = self.pick(
+                    format!("Fate {}", plural(&count, "die", "dice")),
+                    " Fate 骰".to_string(),
+                );
+                self.roll(&count, &dice)
+            }
+            Expr::PercentileDice { count } => {
+                let count = self.count(count);
+                let dice = self.pick(
+                    format!("percentile {}", plural(&count, "die", "dice")),
+                    "百分骰".to_string(),
+                );
+                self.roll(&count, &dice)
+            }
+            Expr::Binary { lhs, op, rhs } => self.binary(lhs, op, rhs),
+            Expr::Call { func_name, args } => self.call(&func_name.to_lowercase(), args),
+            Expr::List(items) => {
+                let items = self.join(items, ", ");
+                self.pick(format!("the list [{}]", items), format!("列表 [{}]", items))
+            }
+            Expr::Group(items) => {
+                let en = self.join(items, "; ");
+                let zh = self.join(items, "；");
+                self.pick(
+                    format!("roll each of [{}]", en),
+                    format!("分别进行 [{}]", zh),
+                )
+            }
+            Expr::Modifier { lhs, op, param } => {
+                let unit = if is_group(lhs) {
+                    Unit::Total
+                } else {
+                    Unit::Die
+                };
+                format!(
+                    "{}{}{}",
+                    self.explain(lhs),
+                    self.pick(", ".to_string(), "，".to_string()),
+                    self.modifier(op, param, unit)
+                )
+            }
+            Expr::SuccessCheck {
+                lhs,
+                compare_expr,
+                failure,
+                double_success,
+            } => {
+                let unit = if is_group(lhs) {
+                    Unit::Total
+                } else {
+                    Unit::Die
+                };
+                let mut text = format!(
+                    "{}{}",
+                    self.explain(lhs),
+                    self.pick(
+                        format!(
+                            ", count successes ({} that {} {})",
+                            unit.plural(),
+                            unit.verb(),
+                            self.condition(compare_expr)
+                        ),
+                        format!(
+                            "，统计成功数（{}{}）",
+                            unit.zh(),
+                            self.condition(compare_expr)
+                        ),
+                    )
+                );
+                if let Some(failure) = failure {
+                    text += &self.pick(
+                        format!(
+                            ", each {} that {} {} removes a success",
+                            unit.singular(),
+                            unit.verb_singular(),
+                            self.condition(failure)
+                        ),
+                        format!("，{}{} 时扣除一个成功", unit.zh(), self.condition(failure)),
+                    );
+                }
+                if let Some(double) = double_success {
+                    text += &self.pick(
+                        format!(
+                            ", each success that {} {} counts twice",
+                            unit.verb_singular(),
+                            self.condition(double)
+                        ),
+                        format!("，{}{} 时计两个成功", unit.zh(), self.condition(double)),
+                    );
+                }
+                text
+            }
+            Expr::Index { list, index } => {
+                let (list, index) = (self.enclose(self.explain(list)), self.explain(index));
+                self.pick(
+                    format!("the element at index {} of {}", index, list),
+                    format!("{}中下标为 {} 的元素", list, index),
+                )
+            }
+            Expr::Slice { list, start, end } => {
+                let list = self.enclose(self.explain(list));
+                let start = match start {
+                    Some(s) => {
+                        let s = self.explain(s);
+                        self.pick(format!("from index {}", s), format!("从下标 {}", s))
+                    }
+                    None => self.pick("from the start".to_string(), "从开头".to_string()),
+                };
+                let end = match end {
+                    Some(e) => {
+                        let e = self.explain(e);
+                        self.pick(
+                            format!("up to (not including) index {}", e),
+                            format!("到下标 {} 之前", e),
+                        )
+                    }
+                    None => self.pick("to the end".to_string(), "到末尾".to_string()),
+                };
+                self.pick(
+                    format!("the elements of {} {} {}", list, start, end),
+                    format!("{}中{}{}的元素", list, start, end),
+                )
+            }
+            Expr::Filter { list, compare_expr } => {
+                let list = self.enclose(self.explain(list));
+                let condition = self.condition(compare_expr);
+                self.pick(
+                    format!("the elements of {} that are {}", list, condition),
+                    format!("{}中{} 的元素", list, condition),
+                )
+            }
+            Expr::Var(name) => name.clone(),
+            Expr::Table(name) => self.pick(
+                format!("a roll on the table \"{}\"", name),
+                format!("查询随机表“{}”", name),
+            ),
+            Expr::Label { expr, label } => {
+                let expr = self.explain(expr);
+                self.pick(
+                    format!("{} ({})", expr, label),
+                    format!("{}（{}）", expr, label),
+                )
+            }
+            Expr::Comment { expr, text } => {
+                let expr = self.explain(expr);
+                self.pick(
+                    format!("{} (note: {})", expr, text),
+                    format!("{}（备注：{}）", expr, text),
+                )
+            }
+        }
+    }
+
+    // 骰子数量：常数按数值，其他表达式加括号
+    fn count(&self, count: &Expr) -> Count {
+        match count {
+            Expr::Number(n) => Count::Number(*n),
+            _ => Count::Expr(self.enclose(self.explain(count))),
+        }
+    }
+
+    fn roll(&self, count: &Count, dice: &str) -> String {
+        match count {
+            Count::Number(n) => self.pick(
+                format!("roll {} {}", number_word(*n), dice),
+                format!("掷 {} 颗{}", n, dice),
+            ),
+            Count::Expr(text) => self.pick(
+                format!("roll a number of {} equal to {}", dice, text),
+                format!("掷{}颗{}", text, dice),
+            ),
+        }
+    }
+
+    fn binary(&self, lhs: &Expr, op: &BinOp, rhs: &Expr) -> String {
+        // 负数在语法中写作 0 - x
+        if *op == BinOp::Sub && *lhs == Expr::Number(Num::ZERO) {
+            let rhs = self.enclose(self.explain(rhs));
+            return self.pick(format!("negative {}", rhs), format!("负的{}", rhs));
+        }
+        // 优先级更低的运算加括号，右侧的同级运算也要加括号 (包括右结合的乘方，避免歧义)
+        let prec = precedence(op);
+        let (lhs_prec, rhs_prec) = if *op == BinOp::Pow {
+            (prec + 1, prec + 1)
+        } else {
+            (prec, prec + 1)
+        };
+        let l = self.operand(lhs, lhs_prec);
+        let r = self.operand(rhs, rhs_prec);
+        let lhs_list = matches!(typecheck_expr(lhs), Type::List(_));
+        let rhs_list = matches!(typecheck_expr(rhs), Type::List(_));
+        match op {
+            // 列表乘以常数时重复 (重新掷骰)，列表相加时拼接
+            BinOp::Mul if lhs_list != rhs_list => {
+                let (list, times) = if lhs_list { (l, r) } else { (r, l) };
+                self.pick(
+                    format!("{} repeated {} times", list, times),
+                    format!("{} 重复 {} 次", list, times),
+                )
+            }
+            BinOp::Add if lhs_list && rhs_list => self.pick(
+                format!("{} followed by {}", l, r),
+                format!("{} 与 {} 拼接", l, r),
+            ),
+            BinOp::Add => self.pick(format!("{} plus {}", l, r), format!("{} 加 {}", l, r)),
+            BinOp::Sub => self.pick(format!("{} minus {}", l, r), format!("{} 减 {}", l, r)),
+            BinOp::Mul => self.pick(format!("{} times {}", l, r), format!("{} 乘以 {}", l, r)),
+            BinOp::Div => self.pick(
+                format!("{} divided by {}", l, r),
+                format!("{} 除以 {}", l, r),
+            ),
+            BinOp::Idiv => self.pick(
+                format!("{} divided by {}, rounded toward zero", l, r),
+                format!("{} 整除 {}", l, r),
+            ),
+            BinOp::Mod => self.pick(
+                format!("the remainder of {} divided by {}", l, r),
+                format!("{} 除以 {} 的余数", l, r),
+            ),
+            BinOp::Pow => self.pick(
+                format!("{} to the power of {}", l, r),
+                format!("{} 的 {} 次方", l, r),
+            ),
+        }
+    }
+
+    fn operand(&self, expr: &Expr, min_prec: u8) -> String {
+        let text = self.explain(expr);
+        match expr {
+            Expr::Binary { lhs, op, .. }
+                if precedence(op) < min_prec
+                    && !(*op == BinOp::Sub && **lhs == Expr::Number(Num::ZERO)) =>
+            {
+                self.parenthesize(text)
+            }
+            _ => self.wrap(text),
+        }
+    }
+
+    fn call(&self, func_name: &str, args: &[Expr]) -> String {
+        let arg = |i: usize| self.enclose(self.explain(&args[i]));
+        let all = match args {
+            [only] => self.enclose(self.explain(only)),
+            _ => args
+                .iter()
+                .map(|a| self.enclose(self.explain(a)))
+                .collect::<Vec<_>>()
+                .join(&self.pick(", ".to_string(), "、".to_string())),
+        };
+        match (func_name, args.len()) {
+            ("max" | "min", 2) if matches!(typecheck_expr(&args[0]), Type::List(_)) => {
+                let (en, zh) = if func_name == "max" {
+                    ("highest", "最大")
+                } else {
+                    ("lowest", "最小")
+                };
+                self.pick(
+                    format!("the {} {} of {}", en, arg(1), arg(0)),
+                    format!("{}中{}的 {} 个", arg(0), zh, arg(1)),
+                )
+            }
+            ("max", _) => self.pick(
+                format!("the highest of {}", all),
+                format!("{}中的最大值", all),
+            ),
+            ("min", _) => self.pick(
+                format!("the lowest of {}", all),
+                format!("{}中的最小值", all),
+            ),
+            ("sum", _) => self.pick(format!("the sum of {}", all), format!("{}之和", all)),
+            ("avg", _) => self.pick(
+                format!("the average of {}", all),
+                format!("{}的平均值", all),
+            ),
+            ("median", _) => {
+                self.pick(format!("the median of {}", all), format!("{}的中位数", all))
+            }
+            ("len", _) => self.pick(format!("the length of {}", all), format!("{}的长度", all)),
+            ("sort", _) => self.pick(
+                format!("{} sorted from lowest to highest", all),
+                format!("{}按从小到大排序", all),
+            ),
+            ("count", 2) => self.pick(
+                format!("the number of times {} appears in {}", arg(1), arg(0)),
+                format!("{}中 {} 出现的次数", arg(0), arg(1)),
+            ),
+            ("floor", 1) => self.pick(
+                format!("{} rounded down", arg(0)),
+                format!("{}向下取整", arg(0)),
+            ),
+            ("ceil", 1) => self.pick(
+                format!("{} rounded up", arg(0)),
+                format!("{}向上取整", arg(0)),
+            ),
+            ("round", 1) => self.pick(
+                format!("{} rounded to the nearest integer", arg(0)),
+                format!("{}四舍五入", arg(0)),
+            ),
+            ("abs", 1) => self.pick(
+                format!("the absolute value of {}", arg(0)),
+                format!("{}的绝对值", arg(0)),
+            ),
+            ("sqrt", 1) => self.pick(
+                format!("the square root of {}", arg(0)),
+                format!("{}的平方根", arg(0)),
+            ),
+            ("pow", 2) => self.pick(
+                format!("{} to the power of {}", arg(0), arg(1)),
+                format!("{} 的 {} 次方", arg(0), arg(1)),
+            ),
+            ("clamp", 3) => self.pick(
+                format!("{} kept between {} and {}", arg(0), arg(1), arg(2)),
+                format!("{}限制在 {} 到 {} 之间", arg(0), arg(1), arg(2)),
+            ),
+            ("rpdice", 1 | 2) => {
+                let times = match args.get(1) {
+                    Some(n) => self.explain(n),
+                    None => format!("{}", crate::eval::DEFAULT_REPEAT),
+                };
+                self.pick(
+                    format!("{} with every dice pool rolled {} times", arg(0), times),
+                    format!("{}，其中的骰池掷 {} 次", arg(0), times),
+                )
+            }
+            // 未展开的自定义函数按原样输出
+            _ => format!("{}({})", func_name, all),
+        }
+    }
+
+    fn modifier(&self, op: &ModifierOp, param: &Option<ModifierParam>, unit: Unit) -> String {
+        use ModifierOp::*;
+        match op {
+            KeepHigh | KeepLow | DropHigh | DropLow => {
+                let n = match param {
+                    Some(ModifierParam::Value(v)) => self.amount(v, unit),
+                    _ => unreachable!("Keep / drop requires a value parameter"),
+                };
+                let (verb_en, verb_zh) = match op {
+                    KeepHigh | KeepLow => ("keep", "保留"),
+                    _ => ("drop", "去掉"),
+                };
+                let (end_en, end_zh) = match op {
+                    KeepHigh | DropHigh => ("highest", "最高"),
+                    _ => ("lowest", "最低"),
+                };
+                self.pick(
+                    format!("{} the {} {}", verb_en, end_en, n),
+                    format!("{}{}{}的 {}", verb_zh, unit.zh_prefix(), end_zh, n),
+                )
+            }
+            Reroll | RerollOnce => {
+                let condition = self.param_condition(param);
+                let text = self.pick(
+                    format!("reroll dice that roll {}", condition),
+                    format!("重骰结果{} 的骰子", condition),
+                );
+                if *op == RerollOnce {
+                    text + &self.pick(" once".to_string(), "（只重骰一次）".to_string())
+                } else {
+                    text
+                }
+            }
+            Explode | ExplodeCompound | ExplodePenetrate => {
+                let text = match op {
+                    Explode => self.pick("exploding dice".to_string(), "爆骰".to_string()),
+                    ExplodeCompound => {
+                        self.pick("compounding explosions".to_string(), "复合爆骰".to_string())
+                    }
+                    _ => self.pick(
+                        "penetrating explosions (each extra die -1)".to_string(),
+                        "穿透爆骰（追加的骰子 -1）".to_string(),
+                    ),
+                };
+                match param {
+                    Some(_) => {
+                        let condition = self.param_condition(param);
+                        text + &self.pick(
+                            format!(" on {}", condition),
+                            format!("，结果{} 时追加", condition),
+                        )
+                    }
+                    None => text,
+                }
+            }
+            Limit => {
+                let n = self.value(param);
+                self.pick(format!("at most {}", n), format!("最多 {} 次", n))
+            }
+            ClampMin | ClampMax => {
+                let v = self.value(param);
+                if *op == ClampMin {
+                    self.pick(
+                        format!("count dice below {} as {}", v, v),
+                        format!("小于 {} 的骰子按 {} 计", v, v),
+                    )
+                } else {
+                    self.pick(
+                        format!("count dice above {} as {}", v, v),
+                        format!("大于 {} 的骰子按 {} 计", v, v),
+                    )
+                }
+            }
+            SortAsc => self.pick(
+                "sorted from lowest to highest".to_string(),
+                "按从小到大排序".to_string(),
+            ),
+            SortDesc => self.pick(
+                "sorted from highest to lowest".to_string(),
+                "按从大到小排序".to_string(),
+            ),
+            Unique => self.pick(
+                "rerolling duplicates so that every die is different".to_string(),
+                "重骰点数重复的骰子，使每颗骰子的点数各不相同".to_string(),
+            ),
+            Count => {
+                let condition = self.param_condition(param);
+                self.pick(
+                    format!(
+                        "count the {} that {} {}",
+                        unit.plural(),
+                        unit.verb(),
+                        condition
+                    ),
+                    format!("统计{}{} 的数量", unit.zh(), condition),
+                )
+            }
+            CritSuccess | CritFailure => {
+                let (en, zh) = if *op == CritSuccess {
+                    ("critical success", "大成功")
+                } else {
+                    ("critical failure", "大失败")
+                };
+                match param {
+                    Some(_) => {
+                        let condition = self.param_condition(param);
+                        self.pick(
+                            format!("{} on {}", en, condition),
+                            format!("结果{} 时为{}", condition, zh),
+                        )
+                    }
+                    None => self.pick(format!("mark {}es", en), format!("标记{}", zh)),
+                }
+            }
+        }
+    }
+
+    // 取高/取低的数量，如 "one"、"two totals"
+    fn amount(&self, n: &Expr, unit: Unit) -> String {
+        match n {
+            Expr::Number(v) => self.pick(
+                match unit {
+                    Unit::Die => number_word(*v),
+                    Unit::Total if *v == Num::ONE => "total".to_string(),
+                    Unit::Total => format!("{} totals", number_word(*v)),
+                },
+                format!("{} {}", v, unit.zh_measure()),
+            ),
+            _ => self.explain(n),
+        }
+    }
+
+    fn value(&self, param: &Option<ModifierParam>) -> String {
+        match param {
+            Some(ModifierParam::Value(v)) => self.explain(v),
+            _ => unreachable!("Modifier requires a value parameter"),
+        }
+    }
+
+    fn param_condition(&self, param: &Option<ModifierParam>) -> String {
+        match param {
+            Some(ModifierParam::Compare(ce)) => self.condition(ce),
+            Some(ModifierParam::Value(v)) => self.explain(v),
+            None => unreachable!("Modifier requires a comparison parameter"),
+        }
+    }
+
+    // 比较条件，如 ">=5" -> "5 or higher" / "不小于 5"
+    fn condition(&self, ce: &CompareExpr) -> String {
+        let v = self.wrap(self.explain(&ce.val));
+        match ce.op {
+            CompareOp::Equal => self.pick(v.clone(), format!("等于 {}", v)),
+            CompareOp::Greater => self.pick(format!("higher than {}", v), format!("大于 {}", v)),
+            CompareOp::Less => self.pick(format!("lower than {}", v), format!("小于 {}", v)),
+            CompareOp::GreaterEqual => {
+                self.pick(format!("{} or higher", v), format!("不小于 {}", v))
+            }
+            CompareOp::LessEqual => self.pick(format!("{} or lower", v), format!("不大于 {}", v)),
+        }
+    }
+
+    // 括号中的元素 (列表与骰组)，元素中含有分隔符时加括号
+    fn join(&self, items: &[Expr], separator: &str) -> String {
+        items
+            .iter()
+            .map(|item| {
+                let text = self.explain(item);
+                if text.contains(separator.trim()) {
+                    self.parenthesize(text)
+                } else {
+                    text
+                }
+            })
+            .collect::<Vec<_>>()
+            .join(separator)
+    }
+
+    // 含有逗号的说明作为运算数出现时加括号，避免与外层的修饰符说明混淆
+    fn wrap(&self, text: String) -> String {
+        if text.contains(',') || text.contains('，') {
+            self.parenthesize(text)
+        } else {
+            text
+        }
+    }
+
+    // 不是单个数值或名称的说明作为参数出现时加括号，如 "(roll one six-sided die) rounded down"
+    fn enclose(&self, text: String) -> String {
+        let is_bracketed = (text.starts_with('[') || text.starts_with("列表 ["))
+            && text.ends_with(']')
+            && !text.contains("] ");
+        if text.contains(' ') && !is_bracketed {
+            self.parenthesize(text)
+        } else {
+            text
+        }
+    }
+
+    fn parenthesize(&self, text: String) -> String {
+        self.pick(format!("({})", text), format!("（{}）", text))
+    }
+}
+
+// 骰子数量
+enum Count {
+    Number(Num),
+    Expr(String),
+}
+
+// 修饰符作用的单位：骰池中的骰子，或骰组中的子掷骰总值
+#[derive(Clone, Copy)]
+enum Unit {
+    Die,
+    Total,
+}
+
+impl Unit {
+    fn singular(self) -> &'static str {
+        match self {
+            Unit::Die => "die",
+            Unit::Total => "total",
+        }
+    }
+
+    fn plural(self) -> &'static str {
+        match self {
+            Unit::Die => "dice",
+            Unit::Total => "totals",
+        }
+    }
+
+    // 复数与单数主语对应的动词，如 "dice that roll 6"、"each total that is 10"
+    fn verb(self) -> &'static str {
+        match self {
+            Unit::Die => "roll",
+            Unit::Total => "are",
+        }
+    }
+
+    fn verb_singular(self) -> &'static str {
+        match self {
+            Unit::Die => "rolls",
+            Unit::Total => "is",
+        }
+    }
+
+    fn zh(self) -> &'static str {
+        match self {
+            Unit::Die => "结果",
+            Unit::Total => "总值",
+        }
+    }
+
+    fn zh_prefix(self) -> &'static str {
+        match self {
+            Unit::Die => "",
+            Unit::Total => "总值",
+        }
+    }
+
+    fn zh_measure(self) -> &'static str {
+        match self {
+            Unit::Die => "颗",
+            Unit::Total => "个",
+        }
+    }
+}
+
+fn precedence(op: &BinOp) -> u8 {
+    match op {
+        BinOp::Add | BinOp::Sub => 1,
+        BinOp::Mul | BinOp::Div | BinOp::Mod | BinOp::Idiv => 2,
+        BinOp::Pow => 3,
+    }
+}
+
+fn plural(count: &Count, singular: &str, plural: &str) -> String {
+    match count {
+        Count::Number(n) if *n == Num::ONE => singular.to_string(),
+        _ => plural.to_string(),
+    }
+}
+
+// 20 以内的整数用英文单词表示
+fn number_word(n: Num) -> String {
+    const WORDS: [&str; 21] = [
+        "zero",
+        "one",
+        "two",
+        "three",
+        "four",
+        "five",
+        "six",
+        "seven",
+        "eight",
+        "nine",
+        "ten",
+        "eleven",
+        "twelve",
+        "thirteen",
+        "fourteen",
+        "fifteen",
+        "sixteen",
+        "seventeen",
+        "eighteen",
+        "nineteen",
+        "twenty",
+    ];
+    match n.as_i64() {
+        Some(i @ 0..=20) => WORDS[i as usize].to_string(),
+        _ => format!("{}", n),
+    }
+}
@@ -0,0 +1,298 @@
+use std::collections::HashMap;
+
+use crate::grammar::{
+    Expr, FunctionDef, Param, ParamType, is_builtin_function, parse_definition, parse_dice,
+};
+use crate::number::Num;
+use crate::typecheck::{NumberType, Type, is_integer, typecheck_expr};
+
+// ==========================================
+// 自定义函数注册表
+// ==========================================
+
+// 展开的最大嵌套深度，防止定义链过长
+const MAX_EXPANSION_DEPTH: usize = 32;
+
+// 语法中有特殊写法的函数名，不能被自定义函数占用
+const RESERVED_NAMES: [&str; 2] = ["filter", "table"];
+
+// 自定义函数注册表，如 def smite(lv) = (lv + 1)d8
+// 调用在类型检查前展开：实参替换函数体中的参数 (类似宏展开，骰子实参每次使用都会重新掷骰)，
+// 函数体在定义时按参数类型的代表值检查一次，在每个调用处再按实参的类型检查
+#[derive(Debug, Clone, Default)]
+pub struct FunctionRegistry {
+    defs: HashMap<String, FunctionDef>,
+}
+
+impl FunctionRegistry {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn get(&self, name: &str) -> Option<&FunctionDef> {
+        self.defs.get(name)
+    }
+
+    // 所有定义，按函数名排序
+    pub fn definitions(&self) -> Vec<&FunctionDef> {
+        let mut defs: Vec<&FunctionDef> = self.defs.values().collect();
+        defs.sort_by(|a, b| a.name.cmp(&b.name));
+        defs
+    }
+
+    // 载入以换行或分号分隔的多个定义，全部合法才生效，返回载入的定义数量
+    // 同名函数会被覆盖，定义之间可以互相调用，与顺序无关
+    pub fn load(&mut self, source: &str) -> Result<usize, String> {
+        let mut defs = Vec::new();
+        for line in source.split(['\n', ';']) {
+            if line.trim().is_empty() {
+                continue;
+            }
+            let def = parse_definition(line.trim()).map_err(|e| format!("Parse error: {}", e))?;
+            defs.push(def);
+        }
+        let mut registry = self.clone();
+        for def in &defs {
+            registry.defs.insert(def.name.clone(), def.clone());
+        }
+        for def in &defs {
+            registry.validate(def)?;
+        }
+        *self = registry;
+        Ok(defs.len())
+    }
+
+    pub fn remove(&mut self, name: &str) -> bool {
+        self.defs.remove(name).is_some()
+    }
+
+    pub fn clear(&mut self) {
+        self.defs.clear();
+    }
+
+    // 展开表达式中的所有自定义函数调用
+    pub fn expand(&self, expr: &Expr) -> Result<Expr, String> {
+        self.expand_inner(expr, &mut Vec::new())
+    }
+
+    fn expand_inner(&self, expr: &Expr, stack: &mut Vec<String>) -> Result<Expr, String> {
+        // 先展开子表达式 (包括实参)
+        let expr = expr.try_map_children(|child| self.expand_inner(child, stack))?;
+        let (def, args) = match &expr {
+            Expr::Call { func_name, args } => match self.defs.get(func_name) {
+                Some(def) => (def, args),
+                None => return Ok(expr),
+            },
+            _ => return Ok(expr),
+        };
+
+        // 递归保护：定义时已经拒绝了环，这里兜底
+        if stack.contains(&def.name) {
+            return Err(format!("Recursive call to function: {}", def.name));
+        }
+        if stack.len() >= MAX_EXPANSION_DEPTH {
+            return Err(format!(
+                "Function calls are nested too deeply (more than {} levels).",
+                MAX_EXPANSION_DEPTH
+            ));
+        }
+        if args.len() != def.params.len() {
+            return Err(format!(
+                "Function {} expects {} arguments, got {}.",
+                def.name,
+                def.params.len(),
+                args.len()
+            ));
+        }
+        for (param, arg) in def.params.iter().zip(args) {
+            check_argument(def, param, arg)?;
+        }
+
+        let bindings: HashMap<&str, &Expr> = def
+            .params
+            .iter()
+            .map(|p| p.name.as_str())
+            .zip(args.iter())
+            .collect();
+        stack.push(def.name.clone());
+        let body = self.expand_inner(&substitute(&def.body, &bindings), stack);
+        stack.pop();
+        let body = body?;
+
+        // 函数体按实参的类型检查
+        if let Type::Invalid(s) = typecheck_expr(&body) {
+            return Err(format!("In {}: {}", def.name, s));
+        }
+        Ok(body)
+    }
+
+    // 检查定义是否合法，此时注册表中已经包含了该定义
+    fn validate(&self, def: &FunctionDef) -> Result<(), String> {
+        if is_builtin_function(&def.name)
+            || RESERVED_NAMES.contains(&def.name.to_lowercase().as_str())
+        {
+            return Err(format!("Cannot redefine built-in function: {}", def.name));
+        }
+        // 如 dfoo(1) 会被解析为 dF 骰子，这样的函数无法调用
+        let call = Expr::Call {
+            func_name: def.name.clone(),
+            args: vec![Expr::Number(Num::ONE)],
+        };
+        if parse_dice(&format!("{}(1)", def.name)).ok() != Some(call) {
+            return Err(format!(
+                "Function name '{}' conflicts with dice notation.",
+                def.name
+            ));
+        }
+        for (i, param) in def.params.iter().enumerate() {
+            // 以 df 开头等与骰子写法冲突的参数名在函数体中无法作为变量解析
+            if parse_dice(&param.name).ok() != Some(Expr::Var(param.name.clone())) {
+                return Err(format!(
+                    "Parameter name '{}' conflicts with dice notation.",
+                    param.name
+                ));
+            }
+            if def.params[..i].iter().any(|p| p.name == param.name) {
+                return Err(format!(
+                    "Duplicate parameter '{}' in function {}.",
+                    param.name, def.name
+                ));
+            }
+        }
+
+        let mut vars = Vec::new();
+        let mut calls = Vec::new();
+        collect_names(&def.body, &mut vars, &mut calls);
+        if let Some(var) = vars
+            .iter()
+            .find(|v| !def.params.iter().any(|p| &p.name == *v))
+        {
+            return Err(format!(
+                "Unknown variable '{}' in function {}.",
+                var, def.name
+            ));
+        }
+        if let Some(call) = calls
+            .iter()
+            .find(|c| !is_builtin_function(c) && !self.defs.contains_key(**c))
+        {
+            return Err(format!(
+                "Unknown function '{}' in function {}.",
+                call, def.name
+            ));
+        }
+
+        if let Some(cycle) = self.find_cycle(&def.name, &mut vec![def.name.clone()]) {
+            return Err(format!("Recursive definition: {}", cycle.join(" -> ")));
+        }
+
+        // 用代表值代入参数，在定义时完成函数体的类型检查
+        // 数值参数可能是常数 (如 (lv + 1)d8) 也可能是骰子 (如 pool kh1)，任一种能通过即可
+        let call = |dice: bool| Expr::Call {
+            func_name: def.name.clone(),
+            args: def
+                .params
+                .iter()
+                .map(|p| representative(&p.param_type, dice))
+                .collect(),
+        };
+        if let Err(e) = self.expand(&call(false)) {
+            self.expand(&call(true)).map_err(|_| e)?;
+        }
+        Ok(())
+    }
+
+    // 沿调用关系查找回到 target 的路径
+    fn find_cycle(&self, target: &str, path: &mut Vec<String>) -> Option<Vec<String>> {
+        let def = self.defs.get(path.last()?)?;
+        let mut calls = Vec::new();
+        collect_names(&def.body, &mut Vec::new(), &mut calls);
+        for call in calls {
+            if call == target {
+                let mut cycle = path.clone();
+                cycle.push(call.to_string());
+                return Some(cycle);
+            }
+            if self.defs.contains_key(call) && !path.iter().any(|p| p == call) {
+                path.push(call.to_string());
+                if let Some(cycle) = self.find_cycle(target, path) {
+                    return Some(cycle);
+                }
+                path.pop();
+            }
+        }
+        None
+    }
+}
+
+// ==========================================
+// 辅助处理函数
+// ==========================================
+
+fn check_argument(def: &FunctionDef, param: &Param, arg: &Expr) -> Result<(), String> {
+    let (matched, expected) = match (&param.param_type, typecheck_expr(arg)) {
+        (_, Type::Invalid(s)) => return Err(s),
+        (ParamType::Int, Type::Number(NumberType::Constant(c))) => {
+            (is_integer(c), "a constant integer")
+        }
+        (ParamType::Int, _) => (false, "a constant integer"),
+        (ParamType::Num, t) => (matches!(t, Type::Number(_)), "a number"),
+        (ParamType::List, t) => (matches!(t, Type::List(_)), "a list"),
+    };
+    if matched {
+        Ok(())
+    } else {
+        Err(format!(
+            "Argument '{}' of function {} must be {}, got {}.",
+            param.name, def.name, expected, arg
+        ))
+    }
+}
+
+// 参数类型的代表值，dice 为 true 时数值用骰子代表
+// 常整数取 2 而不是 1，因为 1 不能作为骰子的面数
+fn representative(param_type: &ParamType, dice: bool) -> Expr {
+    let number = || {
+        if dice {
+            Expr::Dice {
+                count: Box::new(Expr::Number(Num::int(2))),
+                side: Box::new(Expr::Number(Num::int(20))),
+            }
+        } else {
+            Expr::Number(Num::int(2))
+        }
+    };
+    match param_type {
+        ParamType::Int => Expr::Number(Num::int(2)),
+        ParamType::Num => number(),
+        ParamType::List => Expr::List(vec![number(), number()]),
+    }
+}
+
+// 用实参替换函数体中的参数
+fn substitute(expr: &Expr, bindings: &HashMap<&str, &Expr>) -> Expr {
+    match expr {
+        Expr::Var(name) => match bindings.get(name.as_str()) {
+            Some(arg) => (*arg).clone(),
+            None => expr.clone(),
+        },
+        _ => {
+            let Ok(expr) = expr.try_map_children(|child| {
+                Ok::<_, std::convert::Infallible>(substitute(child, bindings))
+            });
+            expr
+        }
+    }
+}
+
+// 收集表达式中出现的变量名与函数名
+fn collect_names<'a>(expr: &'a Expr, vars: &mut Vec<&'a str>, calls: &mut Vec<&'a str>) {
+    match expr {
+        Expr::Var(name) => vars.push(name),
+        Expr::Call { func_name, .. } => calls.push(func_name),
+        _ => {}
+    }
+    for child in expr.children() {
+        collect_names(child, vars, calls);
+    }
+}
@@ -1,10 +1,15 @@
 use lazy_static::lazy_static;
 use pest::Parser;
+use pest::error::ErrorVariant;
+use pest::iterators::{Pair, Pairs};
 use pest::pratt_parser::{Assoc, Op, PrattParser};
 use pest_derive::Parser;
 use serde::{Deserialize, Serialize};
 use tsify::Tsify;
 
+use crate::advantage::{RollMode, with_roll_mode};
+use crate::number::Num;
+
 // 加载语法文件
 #[derive(Parser)]
 #[grammar = "grammar.pest"]
@@ -24,6 +29,7 @@ pub enum BinOp {
     Div,
     Mod,
     Idiv,
+    Pow,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize, Tsify, PartialEq)]
@@ -47,6 +53,15 @@ pub enum ModifierOp {
     RerollOnce,
     Explode,
     ExplodeCompound,
+    ExplodePenetrate,
+    ClampMin,
+    ClampMax,
+    SortAsc,
+    SortDesc,
+    Unique,
+    Count,
+    CritSuccess,
+    CritFailure,
     Limit,
 }
 
@@ -69,14 +84,25 @@ pub enum ModifierParam {
 #[tsify(into_wasm_abi)]
 pub enum Expr {
     // 基础数值: 1, 1.5
-    Number(f64),
+    Number(Num),
 
     // 骰子: 数量, 面数 (例如 1d20 -> Dice(Number(1), Number(20)))
+    // 面数为列表时表示自定义骰面 (例如 1d{1,1,2} -> Dice(Number(1), List([1, 1, 2])))
     Dice {
         count: Box<Expr>,
         side: Box<Expr>,
     },
 
+    // Fate/Fudge 骰: 数量 (例如 4dF)，每颗骰子的结果为 -1、0、+1
+    FateDice {
+        count: Box<Expr>,
+    },
+
+    // 百分骰: 数量 (例如 1d%)，等价于 d100
+    PercentileDice {
+        count: Box<Expr>,
+    },
+
     // 二元运算: 1 + 2
     Binary {
         lhs: Box<Expr>,
@@ -93,20 +119,246 @@ pub enum Expr {
     // 列表: [1, 2]
     List(Vec<Expr>),
 
+    // 骰组: {1d20 + 5, 1d20 + 3}，结果为各子掷骰总值之和，可以整体取高/取低与成功检定
+    Group(Vec<Expr>),
+
     // 通用修饰符节点 (kh1, r>5, !!)
     Modifier {
         lhs: Box<Expr>,               // lhs: 被修饰的对象 (如 1d20)，不支持标量表达式
-        op: ModifierOp,               // op:修饰符名称 (kh, kl, r, ro, !, !!, l)
+        op: ModifierOp, // op:修饰符名称 (kh, kl, r, ro, !, !!, !p, mi, ma, sa, sd, cnt, cs, cf, l)
         param: Option<ModifierParam>, // param参数，可能是数值或者比较表达式
     },
 
-    // 成功/失败判定 (>10, =5)
+    // 列表下标: xs[0]，负数表示从末尾开始计数
+    Index {
+        list: Box<Expr>,
+        index: Box<Expr>,
+    },
+
+    // 列表切片: xs[1:3]，省略的边界分别为开头与末尾
+    Slice {
+        list: Box<Expr>,
+        start: Option<Box<Expr>>,
+        end: Option<Box<Expr>>,
+    },
+
+    // 列表过滤: filter(xs, >=10)，保留满足条件的元素
+    Filter {
+        list: Box<Expr>,
+        compare_expr: CompareExpr,
+    },
+
+    // 成功/失败判定 (>10, =5, >=8f1ds10)
     SuccessCheck {
-        lhs: Box<Expr>,            // lhs: 被判定的对象，可以是标量也可以是列表
-        compare_expr: CompareExpr, // 比较表达式
+        lhs: Box<Expr>,                      // lhs: 被判定的对象，可以是标量也可以是列表
+        compare_expr: CompareExpr,           // 比较表达式
+        failure: Option<CompareExpr>,        // f: 失败条件，每个失败扣除一个成功
+        double_success: Option<CompareExpr>, // ds: 双倍成功条件，额外计一个成功
+    },
+
+    // 变量: 自定义函数体中的参数，展开函数调用时被替换为实参
+    Var(String),
+
+    // 随机表: table("wild_magic")，求值时按表的驱动掷骰选出一项
+    Table(String),
+
+    // 标签: 2d6 [Sneak Attack]，标记其中的骰池，不影响类型与结果
+    Label {
+        expr: Box<Expr>,
+        label: String,
+    },
+
+    // 注释: 1d20 + 5 # stealth，只出现在整个表达式的最外层
+    Comment {
+        expr: Box<Expr>,
+        text: String,
     },
 }
 
+// 自定义函数的参数类型
+#[derive(Debug, Clone, Serialize, Deserialize, Tsify, PartialEq)]
+#[tsify(into_wasm_abi)]
+pub enum ParamType {
+    Int,  // 常数整数，如骰子数量
+    Num,  // 任意数值 (默认)
+    List, // 列表
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, Tsify, PartialEq)]
+#[tsify(into_wasm_abi)]
+pub struct Param {
+    pub name: String,
+    pub param_type: ParamType,
+}
+
+// 自定义函数定义: def smite(lv) = (lv + 1)d8
+#[derive(Debug, Clone, Serialize, Deserialize, Tsify, PartialEq)]
+#[tsify(into_wasm_abi)]
+pub struct FunctionDef {
+    pub name: String,
+    pub params: Vec<Param>,
+    pub body: Expr,
+}
+
+// 解析时记录的各节点在输入中的位置 (字节范围)，结构与表达式树一致，子节点的顺序与 Expr::children 相同
+// 解析时补上的节点 (如负号展开的 0、省略的骰子数量) 没有位置，展开的优势/劣势没有子节点的位置
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct SpanTree {
+    pub span: Option<(usize, usize)>,
+    pub children: Vec<SpanTree>,
+}
+
+impl SpanTree {
+    fn new(span: Option<(usize, usize)>, children: Vec<SpanTree>) -> Self {
+        SpanTree { span, children }
+    }
+}
+
+impl Expr {
+    // 去掉外层的标签，如 ((1d6!) [x])l3 中 l 修饰的爆骰
+    pub fn without_labels(&self) -> &Expr {
+        match self {
+            Expr::Label { expr, .. } => expr.without_labels(),
+            expr => expr,
+        }
+    }
+
+    // 直接子表达式 (包括修饰符参数与比较表达式中的值)
+    pub fn children(&self) -> Vec<&Expr> {
+        match self {
+            Expr::Number(_) | Expr::Var(_) | Expr::Table(_) => vec![],
+            Expr::Dice { count, side } => vec![count, side],
+            Expr::FateDice { count } | Expr::PercentileDice { count } => vec![count],
+            Expr::Label { expr, .. } | Expr::Comment { expr, .. } => vec![expr],
+            Expr::Binary { lhs, rhs, .. } => vec![lhs, rhs],
+            Expr::Call { args, .. } | Expr::List(args) | Expr::Group(args) => args.iter().collect(),
+            Expr::Modifier { lhs, param, .. } => match param {
+                Some(ModifierParam::Compare(ce)) => vec![lhs, &ce.val],
+                Some(ModifierParam::Value(v)) => vec![lhs, v],
+                None => vec![lhs],
+            },
+            Expr::Index { list, index } => vec![list, index],
+            Expr::Slice { list, start, end } => std::iter::once(list)
+                .chain(start.iter())
+                .chain(end.iter())
+                .map(|e| e.as_ref())
+                .collect(),
+            Expr::Filter { list, compare_expr } => vec![list, &compare_expr.val],
+            Expr::SuccessCheck {
+                lhs,
+                compare_expr,
+                failure,
+                double_success,
+            } => std::iter::once(lhs.as_ref())
+                .chain(std::iter::once(compare_expr.val.as_ref()))
+                .chain(failure.iter().map(|ce| ce.val.as_ref()))
+                .chain(double_success.iter().map(|ce| ce.val.as_ref()))
+                .collect(),
+        }
+    }
+
+    // 对每个直接子表达式应用 f，保持节点本身的结构不变
+    pub fn try_map_children<E>(
+        &self,
+        mut f: impl FnMut(&Expr) -> Result<Expr, E>,
+    ) -> Result<Expr, E> {
+        let mut boxed = |e: &Expr| f(e).map(Box::new);
+        Ok(match self {
+            Expr::Number(_) | Expr::Var(_) | Expr::Table(_) => self.clone(),
+            Expr::Dice { count, side } => Expr::Dice {
+                count: boxed(count)?,
+                side: boxed(side)?,
+            },
+            Expr::FateDice { count } => Expr::FateDice {
+                count: boxed(count)?,
+            },
+            Expr::PercentileDice { count } => Expr::PercentileDice {
+                count: boxed(count)?,
+            },
+            Expr::Label { expr, label } => Expr::Label {
+                expr: boxed(expr)?,
+                label: label.clone(),
+            },
+            Expr::Comment { expr, text } => Expr::Comment {
+                expr: boxed(expr)?,
+                text: text.clone(),
+            },
+            Expr::Binary { lhs, op, rhs } => Expr::Binary {
+                lhs: boxed(lhs)?,
+                op: op.clone(),
+                rhs: boxed(rhs)?,
+            },
+            Expr::Call { func_name, args } => Expr::Call {
+                func_name: func_name.clone(),
+                args: args
+                    .iter()
+                    .map(|a| boxed(a).map(|b| *b))
+                    .collect::<Result<_, _>>()?,
+            },
+            Expr::List(items) => Expr::List(
+                items
+                    .iter()
+                    .map(|a| boxed(a).map(|b| *b))
+                    .collect::<Result<_, _>>()?,
+            ),
+            Expr::Group(items) => Expr::Group(
+                items
+                    .iter()
+                    .map(|a| boxed(a).map(|b| *b))
+                    .collect::<Result<_, _>>()?,
+            ),
+            Expr::Modifier { lhs, op, param } => Expr::Modifier {
+                lhs: boxed(lhs)?,
+                op: op.clone(),
+                param: match param {
+                    Some(ModifierParam::Compare(ce)) => Some(ModifierParam::Compare(CompareExpr {
+                        op: ce.op.clone(),
+                        val: boxed(&ce.val)?,
+                    })),
+                    Some(ModifierParam::Value(v)) => Some(ModifierParam::Value(boxed(v)?)),
+                    None => None,
+                },
+            },
+            Expr::Index { list, index } => Expr::Index {
+                list: boxed(list)?,
+                index: boxed(index)?,
+            },
+            Expr::Slice { list, start, end } => Expr::Slice {
+                list: boxed(list)?,
+                start: start.as_ref().map(|e| boxed(e)).transpose()?,
+                end: end.as_ref().map(|e| boxed(e)).transpose()?,
+            },
+            Expr::Filter { list, compare_expr } => Expr::Filter {
+                list: boxed(list)?,
+                compare_expr: CompareExpr {
+                    op: compare_expr.op.clone(),
+                    val: boxed(&compare_expr.val)?,
+                },
+            },
+            Expr::SuccessCheck {
+                lhs,
+                compare_expr,
+                failure,
+                double_success,
+            } => {
+                let lhs = boxed(lhs)?;
+                let mut compare = |ce: &CompareExpr| -> Result<CompareExpr, E> {
+                    Ok(CompareExpr {
+                        op: ce.op.clone(),
+                        val: boxed(&ce.val)?,
+                    })
+                };
+                Expr::SuccessCheck {
+                    lhs,
+                    compare_expr: compare(compare_expr)?,
+                    failure: failure.as_ref().map(&mut compare).transpose()?,
+                    double_success: double_success.as_ref().map(&mut compare).transpose()?,
+                }
+            }
+        })
+    }
+}
+
 fn string_to_compare_op(s: &str) -> CompareOp {
     match s {
         ">" => CompareOp::Greater,
@@ -134,8 +386,14 @@ lazy_static! {
                 Op::infix(Rule::idiv, Assoc::Left))
             // 优先级 3: 前缀 (负号)
             .op(Op::prefix(Rule::neg) | Op::prefix(Rule::pos))
-            // 优先级 4: 后缀 (修饰符) - 优先级最高，紧贴左侧
-            .op(Op::postfix(Rule::modifier))
+            // 优先级 4: 乘方，右结合，高于负号 (-2^2 = -4)
+            .op(Op::infix(Rule::pow, Assoc::Right))
+            // 优先级 5: 后缀 (修饰符、优势/劣势、下标、切片、标签) - 优先级最高，紧贴左侧
+            .op(Op::postfix(Rule::modifier) |
+                Op::postfix(Rule::roll_mode) |
+                Op::postfix(Rule::slice) |
+                Op::postfix(Rule::index) |
+                Op::postfix(Rule::label))
     };
 }
 
@@ -143,18 +401,101 @@ lazy_static! {
 // 4. 解析函数声明
 // ==========================================
 
+#[allow(clippy::result_large_err)]
 pub fn parse_dice(input: &str) -> Result<Expr, pest::error::Error<Rule>> {
+    parse_dice_with_spans(input).map(|(expr, _)| expr)
+}
+
+// 解析表达式，同时返回每个节点在输入中的位置 (用于标注求值记录)
+#[allow(clippy::result_large_err)]
+pub fn parse_dice_with_spans(input: &str) -> Result<(Expr, SpanTree), pest::error::Error<Rule>> {
     // A. 调用 Pest 解析
     let mut pairs = DiceGrammar::parse(Rule::main, input)?;
+    check_numbers(&pairs)?;
 
     // B. 获取 expr
     let expr_pair = pairs.next().unwrap(); // expr
+    let (expr, tree) = parse_expr_pratt(expr_pair);
 
-    // C. 转换为 AST
-    Ok(parse_expr_pratt(expr_pair))
+    // C. 末尾的注释包裹整个表达式
+    match pairs.next() {
+        Some(pair) if pair.as_rule() == Rule::comment => {
+            let span = join(&tree, source_span(&pair));
+            let text = pair.into_inner().next().unwrap().as_str(); // comment_text
+            let expr = Expr::Comment {
+                expr: Box::new(expr),
+                text: text.trim_end().to_string(),
+            };
+            Ok((expr, SpanTree::new(span, vec![tree])))
+        }
+        _ => Ok((expr, tree)),
+    }
 }
 
-fn parse_expr_pratt(pair: pest::iterators::Pair<Rule>) -> Expr {
+// 数字字面量必须能精确表示，超出 i64 范围的整数等作为解析错误
+#[allow(clippy::result_large_err)]
+fn check_numbers(pairs: &Pairs<Rule>) -> Result<(), pest::error::Error<Rule>> {
+    for pair in pairs.clone().flatten() {
+        if pair.as_rule() == Rule::number
+            && let Err(message) = pair.as_str().parse::<Num>()
+        {
+            return Err(pest::error::Error::new_from_span(
+                ErrorVariant::CustomError { message },
+                pair.as_span(),
+            ));
+        }
+    }
+    Ok(())
+}
+
+// 解析自定义函数定义: def smite(lv) = (lv + 1)d8
+#[allow(clippy::result_large_err)]
+pub fn parse_definition(input: &str) -> Result<FunctionDef, pest::error::Error<Rule>> {
+    let mut pairs = DiceGrammar::parse(Rule::definition_main, input)?;
+    check_numbers(&pairs)?;
+    let mut inner = pairs.next().unwrap().into_inner(); // definition
+    inner.next(); // def_keyword
+    let name = inner.next().unwrap().as_str().to_string(); // ident
+    let mut params = vec![];
+    let mut body = None;
+    for pair in inner {
+        match pair.as_rule() {
+            Rule::params => {
+                for param in pair.into_inner() {
+                    let mut param_inner = param.into_inner();
+                    let name = param_inner.next().unwrap().as_str().to_string();
+                    // 省略类型标注时默认为数值
+                    let param_type = match param_inner.next() {
+                        Some(t) => match t.as_str().to_lowercase().as_str() {
+                            "int" => ParamType::Int,
+                            "list" => ParamType::List,
+                            _ => ParamType::Num,
+                        },
+                        None => ParamType::Num,
+                    };
+                    params.push(Param { name, param_type });
+                }
+            }
+            Rule::expr => body = Some(parse_expr_pratt(pair).0),
+            _ => unreachable!("Unknown definition part: {:?}", pair.as_rule()),
+        }
+    }
+    Ok(FunctionDef {
+        name,
+        params,
+        body: body.unwrap(),
+    })
+}
+
+// 是否为内置函数名 (不区分大小写)
+pub fn is_builtin_function(name: &str) -> bool {
+    match DiceGrammar::parse(Rule::func_name, name) {
+        Ok(mut pairs) => pairs.next().unwrap().as_str().len() == name.len(),
+        Err(_) => false,
+    }
+}
+
+fn parse_expr_pratt(pair: Pair<Rule>) -> Spanned {
     PRATT_PARSER
         .map_primary(process_primary)
         .map_infix(process_infix)
@@ -167,21 +508,35 @@ fn parse_expr_pratt(pair: pest::iterators::Pair<Rule>) -> Expr {
 // 5. 辅助处理函数
 // ==========================================
 
-fn process_primary(pair: pest::iterators::Pair<Rule>) -> Expr {
+// 解析得到的节点与其位置树
+type Spanned = (Expr, SpanTree);
+
+// 语法节点的范围可能包含末尾的空白
+fn source_span(pair: &Pair<Rule>) -> Option<(usize, usize)> {
+    let start = pair.as_span().start();
+    Some((start, start + pair.as_str().trim_end().len()))
+}
+
+// 从 tree 的起点到 end 的终点
+fn join(tree: &SpanTree, end: Option<(usize, usize)>) -> Option<(usize, usize)> {
+    tree.span
+        .zip(end)
+        .map(|((start, _), (_, end))| (start, end))
+}
+
+fn process_primary(pair: Pair<Rule>) -> Spanned {
     match pair.as_rule() {
         Rule::dice_expr => {
+            let span = source_span(&pair);
             // 进入里面一层
             let mut inner_pairs = pair.into_inner();
             let first = inner_pairs.next().unwrap();
             match first.as_rule() {
                 Rule::dice_op => {
                     // 以dice_op开头，省略了数量，则默认为1
-                    let atom_pair = inner_pairs.next().unwrap();
-                    let sides = parse_atom(atom_pair);
-                    Expr::Dice {
-                        count: Box::new(Expr::Number(1.0)),
-                        side: Box::new(sides),
-                    }
+                    let side_pair = inner_pairs.next().unwrap();
+                    let count = (Expr::Number(Num::ONE), SpanTree::default());
+                    build_dice(count, side_pair, span)
                 }
                 Rule::atom => {
                     // 以atom开头，说明有数量，可能是单纯的数值或者ndn的表达式
@@ -189,12 +544,8 @@ fn process_primary(pair: pest::iterators::Pair<Rule>) -> Expr {
                     match inner_pairs.next() {
                         Some(_) => {
                             // 后面跟着dice_op，说明是ndn表达式
-                            let sides_pair = inner_pairs.next().unwrap();
-                            let sides = parse_atom(sides_pair);
-                            Expr::Dice {
-                                count: Box::new(count_or_number),
-                                side: Box::new(sides),
-                            }
+                            let side_pair = inner_pairs.next().unwrap();
+                            build_dice(count_or_number, side_pair, span)
                         }
                         None => {
                             // 只有一个atom，直接返回
@@ -202,6 +553,24 @@ fn process_primary(pair: pest::iterators::Pair<Rule>) -> Expr {
                         }
                     }
                 }
+                Rule::call => {
+                    // 自定义函数调用，可以作为数量，如 smite(3)d8
+                    let call = parse_call(first);
+                    match inner_pairs.next() {
+                        Some(_) => build_dice(call, inner_pairs.next().unwrap(), span),
+                        None => call,
+                    }
+                }
+                Rule::var => {
+                    // 变量作为数量，如 lv d8
+                    let var_span = source_span(&first);
+                    let var = Expr::Var(first.into_inner().next().unwrap().as_str().to_string());
+                    let var = (var, SpanTree::new(var_span, vec![]));
+                    match inner_pairs.next() {
+                        Some(_) => build_dice(var, inner_pairs.next().unwrap(), span),
+                        None => var,
+                    }
+                }
                 _ => unreachable!("Unknown dice expression: {:?}", first.as_rule()),
             }
         }
@@ -211,7 +580,49 @@ fn process_primary(pair: pest::iterators::Pair<Rule>) -> Expr {
     }
 }
 
-fn process_infix(lhs: Expr, op: pest::iterators::Pair<Rule>, rhs: Expr) -> Expr {
+// 根据骰面 (dice_side) 的种类构造骰子节点
+fn build_dice(
+    (count, count_tree): Spanned,
+    side_pair: Pair<Rule>,
+    span: Option<(usize, usize)>,
+) -> Spanned {
+    let side = side_pair.into_inner().next().unwrap();
+    match side.as_rule() {
+        Rule::atom => {
+            let (side, side_tree) = parse_atom(side);
+            let expr = Expr::Dice {
+                count: Box::new(count),
+                side: Box::new(side),
+            };
+            (expr, SpanTree::new(span, vec![count_tree, side_tree]))
+        }
+        Rule::custom_side => {
+            let side_span = source_span(&side);
+            let (items, item_trees) = side.into_inner().map(parse_expr_pratt).unzip();
+            let expr = Expr::Dice {
+                count: Box::new(count),
+                side: Box::new(Expr::List(items)),
+            };
+            let side_tree = SpanTree::new(side_span, item_trees);
+            (expr, SpanTree::new(span, vec![count_tree, side_tree]))
+        }
+        Rule::fate_side => {
+            let expr = Expr::FateDice {
+                count: Box::new(count),
+            };
+            (expr, SpanTree::new(span, vec![count_tree]))
+        }
+        Rule::percent_side => {
+            let expr = Expr::PercentileDice {
+                count: Box::new(count),
+            };
+            (expr, SpanTree::new(span, vec![count_tree]))
+        }
+        _ => unreachable!("Unknown dice side: {:?}", side.as_rule()),
+    }
+}
+
+fn process_infix((lhs, lhs_tree): Spanned, op: Pair<Rule>, (rhs, rhs_tree): Spanned) -> Spanned {
     let bin_op = match op.as_rule() {
         Rule::add => BinOp::Add,
         Rule::sub => BinOp::Sub,
@@ -219,31 +630,108 @@ fn process_infix(lhs: Expr, op: pest::iterators::Pair<Rule>, rhs: Expr) -> Expr
         Rule::div => BinOp::Div,
         Rule::rem => BinOp::Mod,
         Rule::idiv => BinOp::Idiv,
+        Rule::pow => BinOp::Pow,
         _ => unreachable!("Unknown infix operator: {:?}", op.as_rule()),
     };
-    Expr::Binary {
+    let expr = Expr::Binary {
         lhs: Box::new(lhs),
         op: bin_op,
         rhs: Box::new(rhs),
-    }
+    };
+    let span = join(&lhs_tree, rhs_tree.span);
+    (expr, SpanTree::new(span, vec![lhs_tree, rhs_tree]))
 }
 
-fn process_prefix(op: pest::iterators::Pair<Rule>, rhs: Expr) -> Expr {
+fn process_prefix(op: Pair<Rule>, (rhs, rhs_tree): Spanned) -> Spanned {
     match op.as_rule() {
-        Rule::neg => Expr::Binary {
-            lhs: Box::new(Expr::Number(0.0)),
-            op: BinOp::Sub,
-            rhs: Box::new(rhs),
-        },
-        Rule::pos => rhs, // 正号不做处理
+        Rule::neg => {
+            let span = source_span(&op)
+                .zip(rhs_tree.span)
+                .map(|(op, rhs)| (op.0, rhs.1));
+            let expr = Expr::Binary {
+                lhs: Box::new(Expr::Number(Num::ZERO)),
+                op: BinOp::Sub,
+                rhs: Box::new(rhs),
+            };
+            (
+                expr,
+                SpanTree::new(span, vec![SpanTree::default(), rhs_tree]),
+            )
+        }
+        Rule::pos => (rhs, rhs_tree), // 正号不做处理
         _ => unreachable!("Unknown prefix operator: {:?}", op.as_rule()),
     }
 }
 
-fn process_postfix(lhs: Expr, op: pest::iterators::Pair<Rule>) -> Expr {
-    let op = op.into_inner().next().unwrap(); // 取得第一个操作符
-    println!("Processing postfix operator: {:?}", op.as_rule());
+fn process_postfix((lhs, lhs_tree): Spanned, op: Pair<Rule>) -> Spanned {
+    let span = join(&lhs_tree, source_span(&op));
     match op.as_rule() {
+        Rule::index => {
+            let (index, index_tree) = parse_expr_pratt(op.into_inner().next().unwrap()); // expr
+            let expr = Expr::Index {
+                list: Box::new(lhs),
+                index: Box::new(index),
+            };
+            return (expr, SpanTree::new(span, vec![lhs_tree, index_tree]));
+        }
+        Rule::roll_mode => {
+            // 解析时直接展开为 kh1 / kl1
+            let (mut advantage, mut disadvantage) = (false, false);
+            for mode in op.into_inner() {
+                match mode.as_rule() {
+                    Rule::adv => advantage = true,
+                    _ => disadvantage = true,
+                }
+            }
+            let mode = RollMode::from_flags(advantage, disadvantage);
+            let expr = with_roll_mode(lhs.clone(), mode);
+            // 展开后的子节点在输入中没有对应的写法，没有改写时保留原来的位置
+            let children = if expr == lhs {
+                lhs_tree.children
+            } else {
+                vec![]
+            };
+            return (expr, SpanTree::new(span, children));
+        }
+        Rule::label => {
+            let label = op.into_inner().next().unwrap(); // label_text
+            let expr = Expr::Label {
+                expr: Box::new(lhs),
+                label: label.as_str().trim_end().to_string(),
+            };
+            return (expr, SpanTree::new(span, vec![lhs_tree]));
+        }
+        Rule::slice => {
+            // 根据冒号的位置区分起点与终点
+            let colon = op.as_str().find(':').unwrap() + op.as_span().start();
+            let mut start = None;
+            let mut end = None;
+            for bound in op.into_inner() {
+                let is_start = bound.as_span().start() < colon;
+                let (expr, tree) = parse_expr_pratt(bound);
+                let bound = Some((Box::new(expr), tree));
+                if is_start {
+                    start = bound;
+                } else {
+                    end = bound;
+                }
+            }
+            let mut children = vec![lhs_tree];
+            let (start, start_tree) = start.unzip();
+            let (end, end_tree) = end.unzip();
+            children.extend(start_tree);
+            children.extend(end_tree);
+            let expr = Expr::Slice {
+                list: Box::new(lhs),
+                start,
+                end,
+            };
+            return (expr, SpanTree::new(span, children));
+        }
+        _ => {}
+    }
+    let op = op.into_inner().next().unwrap(); // 取得第一个操作符
+    let (expr, param_tree) = match op.as_rule() {
         Rule::keep_high | Rule::keep_low | Rule::drop_high | Rule::drop_low => {
             let op_enum = match op.as_rule() {
                 Rule::keep_high => ModifierOp::KeepHigh,
@@ -253,119 +741,238 @@ fn process_postfix(lhs: Expr, op: pest::iterators::Pair<Rule>) -> Expr {
                 _ => unreachable!(), // should not reach here
             };
             let mut inner_pairs = op.into_inner(); // 进入内部
-            let param = if let Some(mod_param) = inner_pairs.next() {
-                Some(ModifierParam::Value(Box::new(parse_atom(mod_param))))
+            let (param, param_tree) = if let Some(mod_param) = inner_pairs.next() {
+                parse_atom(mod_param)
             } else {
                 // 默认值为1
-                Some(ModifierParam::Value(Box::new(Expr::Number(1.0))))
+                (Expr::Number(Num::ONE), SpanTree::default())
             };
-            Expr::Modifier {
+            let expr = Expr::Modifier {
                 lhs: Box::new(lhs),
                 op: op_enum,
-                param: param,
-            }
+                param: Some(ModifierParam::Value(Box::new(param))),
+            };
+            (expr, Some(param_tree))
         }
-        Rule::reroll_once | Rule::reroll | Rule::explode_compound | Rule::explode => {
+        Rule::reroll_once
+        | Rule::reroll
+        | Rule::explode_compound
+        | Rule::explode_penetrate
+        | Rule::explode
+        | Rule::crit_success
+        | Rule::crit_failure => {
             let op_enum = match op.as_rule() {
                 Rule::reroll_once => ModifierOp::RerollOnce,
                 Rule::reroll => ModifierOp::Reroll,
                 Rule::explode_compound => ModifierOp::ExplodeCompound,
+                Rule::explode_penetrate => ModifierOp::ExplodePenetrate,
                 Rule::explode => ModifierOp::Explode,
+                Rule::crit_success => ModifierOp::CritSuccess,
+                Rule::crit_failure => ModifierOp::CritFailure,
                 _ => unreachable!(), // should not reach here
             };
             let mut inner_pairs = op.into_inner(); // 进入内部
-            let param = if let Some(mod_param) = inner_pairs.next() {
-                let mut mod_param_inner = mod_param.into_inner(); // mod_param内部
-                let first = mod_param_inner.next().unwrap();
-                match first.as_rule() {
-                    Rule::atom => {
-                        // 是值，默认op为等于
-                        let value = parse_atom(first);
-                        Some(ModifierParam::Compare(CompareExpr {
-                            op: CompareOp::Equal,
-                            val: Box::new(value),
-                        }))
-                    }
-                    Rule::compare_op => {
-                        // 是比较表达式
-                        let op_symbol = first; // >, <, =
-                        let val_pair = mod_param_inner.next().unwrap(); // atom
-                        let compare_expr = CompareExpr {
-                            op: string_to_compare_op(op_symbol.as_str()),
-                            val: Box::new(parse_atom(val_pair)),
-                        };
-                        Some(ModifierParam::Compare(compare_expr))
-                    }
-                    _ => unreachable!("Unknown modifier parameter: {:?}", first.as_rule()),
-                }
-            } else {
-                None
+            let (param, param_tree) = inner_pairs.next().map(parse_mod_param).unzip();
+            let expr = Expr::Modifier {
+                lhs: Box::new(lhs),
+                op: op_enum,
+                param: param.map(ModifierParam::Compare),
             };
-            Expr::Modifier {
+            (expr, param_tree)
+        }
+        Rule::clamp_min | Rule::clamp_max => {
+            let op_enum = match op.as_rule() {
+                Rule::clamp_min => ModifierOp::ClampMin,
+                Rule::clamp_max => ModifierOp::ClampMax,
+                _ => unreachable!(), // should not reach here
+            };
+            let inner_pairs = op.into_inner().next().unwrap(); // atom
+            let (param, param_tree) = parse_atom(inner_pairs);
+            let expr = Expr::Modifier {
                 lhs: Box::new(lhs),
                 op: op_enum,
-                param: param,
-            }
+                param: Some(ModifierParam::Value(Box::new(param))),
+            };
+            (expr, Some(param_tree))
+        }
+        Rule::sort_asc | Rule::sort_desc => {
+            let expr = Expr::Modifier {
+                lhs: Box::new(lhs),
+                op: if op.as_rule() == Rule::sort_asc {
+                    ModifierOp::SortAsc
+                } else {
+                    ModifierOp::SortDesc
+                },
+                param: None,
+            };
+            (expr, None)
+        }
+        Rule::unique => {
+            let expr = Expr::Modifier {
+                lhs: Box::new(lhs),
+                op: ModifierOp::Unique,
+                param: None,
+            };
+            (expr, None)
+        }
+        Rule::count => {
+            let mut inner_pairs = op.into_inner(); // 进入count内部
+            let op_symbol = inner_pairs.next().unwrap(); // >, <, =
+            let val_pair = inner_pairs.next().unwrap(); // atom
+            let (val, val_tree) = parse_atom(val_pair);
+            let expr = Expr::Modifier {
+                lhs: Box::new(lhs),
+                op: ModifierOp::Count,
+                param: Some(ModifierParam::Compare(CompareExpr {
+                    op: string_to_compare_op(op_symbol.as_str()),
+                    val: Box::new(val),
+                })),
+            };
+            (expr, Some(val_tree))
         }
         Rule::limit => {
             let inner_pairs = op.into_inner().next().unwrap(); // atom, limit_param是隐式的
-            Expr::Modifier {
+            let (param, param_tree) = parse_atom(inner_pairs);
+            let expr = Expr::Modifier {
                 lhs: Box::new(lhs),
                 op: ModifierOp::Limit,
-                param: Some(ModifierParam::Value(Box::new(parse_atom(inner_pairs)))),
-            }
+                param: Some(ModifierParam::Value(Box::new(param))),
+            };
+            (expr, Some(param_tree))
         }
         Rule::compare_param => {
             let mut inner_pairs = op.into_inner(); // 进入compare_param内部
             let op_symbol = inner_pairs.next().unwrap(); // >, <, =
             let val_pair = inner_pairs.next().unwrap(); // atom
-            Expr::SuccessCheck {
+            let (val, val_tree) = parse_atom(val_pair);
+            let mut failure = None;
+            let mut double_success = None;
+            for option in inner_pairs {
+                // success_fail / success_double 内部都是 mod_param
+                let rule = option.as_rule();
+                let param = parse_mod_param(option.into_inner().next().unwrap());
+                match rule {
+                    Rule::success_fail => failure = Some(param),
+                    Rule::success_double => double_success = Some(param),
+                    _ => unreachable!("Unknown success check option: {:?}", rule),
+                }
+            }
+            // 子节点的顺序与 Expr::children 一致：目标值、失败条件、双倍成功条件
+            let (failure, failure_tree) = failure.unzip();
+            let (double_success, double_tree) = double_success.unzip();
+            let mut children = vec![lhs_tree, val_tree];
+            children.extend(failure_tree);
+            children.extend(double_tree);
+            let expr = Expr::SuccessCheck {
                 lhs: Box::new(lhs), // 被判定的对象
                 compare_expr: CompareExpr {
                     op: string_to_compare_op(op_symbol.as_str()), // 比较符
-                    val: Box::new(parse_atom(val_pair)),          // 目标值
+                    val: Box::new(val),                           // 目标值
                 },
-            }
+                failure,
+                double_success,
+            };
+            return (expr, SpanTree::new(span, children));
         }
         _ => unreachable!("Unknown postfix operator: {:?}", op.as_rule()),
+    };
+    let children = std::iter::once(lhs_tree).chain(param_tree).collect();
+    (expr, SpanTree::new(span, children))
+}
+
+// 修饰符参数：省略比较符时默认为等于，同时返回比较值的位置
+fn parse_mod_param(pair: Pair<Rule>) -> (CompareExpr, SpanTree) {
+    let mut mod_param_inner = pair.into_inner(); // mod_param内部
+    let first = mod_param_inner.next().unwrap();
+    match first.as_rule() {
+        Rule::atom => {
+            // 是值，默认op为等于
+            let (val, tree) = parse_atom(first);
+            let ce = CompareExpr {
+                op: CompareOp::Equal,
+                val: Box::new(val),
+            };
+            (ce, tree)
+        }
+        Rule::compare_op => {
+            // 是比较表达式
+            let val_pair = mod_param_inner.next().unwrap(); // atom
+            let (val, tree) = parse_atom(val_pair);
+            let ce = CompareExpr {
+                op: string_to_compare_op(first.as_str()),
+                val: Box::new(val),
+            };
+            (ce, tree)
+        }
+        _ => unreachable!("Unknown modifier parameter: {:?}", first.as_rule()),
     }
 }
 
-fn parse_atom(pair: pest::iterators::Pair<Rule>) -> Expr {
+// 内置函数 (function) 与自定义函数 (call) 的调用
+fn parse_call(pair: Pair<Rule>) -> Spanned {
+    let span = source_span(&pair);
+    let mut inner = pair.into_inner();
+    let name = inner.next().unwrap().as_str().to_string(); // func_name / ident
+    let (args, arg_trees) = match inner.next() {
+        Some(args_pair) => args_pair.into_inner().map(parse_expr_pratt).unzip(),
+        None => (vec![], vec![]),
+    };
+    let expr = Expr::Call {
+        func_name: name,
+        args,
+    };
+    (expr, SpanTree::new(span, arg_trees))
+}
+
+fn parse_atom(pair: Pair<Rule>) -> Spanned {
+    let span = source_span(&pair);
     let inner_pairs = pair.into_inner().next().unwrap();
-    match inner_pairs.as_rule() {
+    let (expr, children) = match inner_pairs.as_rule() {
         Rule::number => {
             let s = inner_pairs.as_str();
-            Expr::Number(s.parse::<f64>().unwrap_or(0.0))
+            let n = s.parse::<Num>().expect("Checked in check_numbers");
+            (Expr::Number(n), vec![])
         }
         Rule::function => {
+            let (expr, tree) = parse_call(inner_pairs);
+            (expr, tree.children)
+        }
+        Rule::filter => {
             let mut inner = inner_pairs.into_inner();
-            let name = inner.next().unwrap().as_str().to_string(); // func_name
-            let args = match inner.next() {
-                Some(args_pair) => args_pair
-                    .into_inner()
-                    .map(|p| parse_expr_pratt(p))
-                    .collect(),
-                None => vec![],
+            let (list, list_tree) = parse_expr_pratt(inner.next().unwrap()); // expr
+            let op_symbol = inner.next().unwrap(); // >, <, =
+            let val_pair = inner.next().unwrap(); // atom
+            let (val, val_tree) = parse_atom(val_pair);
+            let expr = Expr::Filter {
+                list: Box::new(list),
+                compare_expr: CompareExpr {
+                    op: string_to_compare_op(op_symbol.as_str()),
+                    val: Box::new(val),
+                },
             };
-            Expr::Call {
-                func_name: name,
-                args: args,
-            }
+            (expr, vec![list_tree, val_tree])
+        }
+        Rule::table => {
+            let name = inner_pairs.into_inner().next().unwrap().as_str(); // string
+            (Expr::Table(name.trim_matches('"').to_string()), vec![])
         }
         Rule::list => {
             let mut inner = inner_pairs.into_inner();
-            let items = match inner.next() {
-                Some(args_pair) => args_pair
-                    .into_inner()
-                    .map(|p| parse_expr_pratt(p))
-                    .collect(),
-                None => vec![],
+            let (items, item_trees) = match inner.next() {
+                Some(args_pair) => args_pair.into_inner().map(parse_expr_pratt).unzip(),
+                None => (vec![], vec![]),
             };
-            Expr::List(items)
+            (Expr::List(items), item_trees)
+        }
+        Rule::group => {
+            let (items, item_trees) = inner_pairs.into_inner().map(parse_expr_pratt).unzip();
+            (Expr::Group(items), item_trees)
+        }
+        // 处理括号 (expr)，位置包括括号
+        Rule::expr => {
+            let (expr, tree) = parse_expr_pratt(inner_pairs);
+            (expr, tree.children)
         }
-        // 处理括号 (expr)
-        Rule::expr => parse_expr_pratt(inner_pairs),
 
         // 容错处理
         _ => unreachable!(
@@ -373,5 +980,258 @@ fn parse_atom(pair: pest::iterators::Pair<Rule>) -> Expr {
             inner_pairs.as_rule(),
             inner_pairs.as_str()
         ),
+    };
+    (expr, SpanTree::new(span, children))
+}
+
+// ==========================================
+// 6. AST 转回表达式字符串 (用于展示与存储)
+// ==========================================
+
+// 运算优先级，与 Pratt Parser 的配置保持一致
+const PREC_ADD: u8 = 1;
+const PREC_MUL: u8 = 2;
+const PREC_POW: u8 = 3;
+const PREC_ATOM: u8 = 4;
+
+fn bin_op_symbol(op: &BinOp) -> &'static str {
+    match op {
+        BinOp::Add => "+",
+        BinOp::Sub => "-",
+        BinOp::Mul => "*",
+        BinOp::Div => "/",
+        BinOp::Mod => "%",
+        BinOp::Idiv => "//",
+        BinOp::Pow => "^",
+    }
+}
+
+fn bin_op_precedence(op: &BinOp) -> u8 {
+    match op {
+        BinOp::Add | BinOp::Sub => PREC_ADD,
+        BinOp::Mul | BinOp::Div | BinOp::Mod | BinOp::Idiv => PREC_MUL,
+        BinOp::Pow => PREC_POW,
+    }
+}
+
+fn compare_op_symbol(op: &CompareOp) -> &'static str {
+    match op {
+        CompareOp::Greater => ">",
+        CompareOp::Less => "<",
+        CompareOp::Equal => "=",
+        CompareOp::GreaterEqual => ">=",
+        CompareOp::LessEqual => "<=",
+    }
+}
+
+fn modifier_op_symbol(op: &ModifierOp) -> &'static str {
+    match op {
+        ModifierOp::KeepHigh => "kh",
+        ModifierOp::KeepLow => "kl",
+        ModifierOp::DropHigh => "dh",
+        ModifierOp::DropLow => "dl",
+        ModifierOp::Reroll => "r",
+        ModifierOp::RerollOnce => "ro",
+        ModifierOp::Explode => "!",
+        ModifierOp::ExplodeCompound => "!!",
+        ModifierOp::ExplodePenetrate => "!p",
+        ModifierOp::ClampMin => "mi",
+        ModifierOp::ClampMax => "ma",
+        ModifierOp::SortAsc => "sa",
+        ModifierOp::SortDesc => "sd",
+        ModifierOp::Unique => "u",
+        ModifierOp::Count => "cnt",
+        ModifierOp::CritSuccess => "cs",
+        ModifierOp::CritFailure => "cf",
+        ModifierOp::Limit => "l",
+    }
+}
+
+// 表达式自身的优先级，用于判断是否需要加括号
+fn expr_precedence(expr: &Expr) -> u8 {
+    match expr {
+        Expr::Number(n) if *n < Num::ZERO => 0, // 负数字面量在语法中需要括号
+        Expr::Binary { op, .. } => bin_op_precedence(op),
+        // 标签之后不能再跟修饰符，作为骰子数量或修饰对象时需要括号
+        Expr::Label { .. } => PREC_POW,
+        Expr::Comment { .. } => 0,
+        _ => PREC_ATOM,
+    }
+}
+
+// 按给定的最低优先级输出表达式，不足时加括号
+fn fmt_with_prec(expr: &Expr, min_prec: u8, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+    if expr_precedence(expr) < min_prec {
+        write!(f, "(")?;
+        fmt_expr(expr, f)?;
+        write!(f, ")")
+    } else {
+        fmt_expr(expr, f)
+    }
+}
+
+// 输出语法中的 atom 位置 (骰子的数量与面数、修饰符参数)，非原子表达式需要括号
+fn fmt_atom(expr: &Expr, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+    match expr {
+        Expr::Number(n) if *n >= Num::ZERO => fmt_expr(expr, f),
+        Expr::Call { func_name, .. } if is_builtin_function(func_name) => fmt_expr(expr, f),
+        Expr::List(_) | Expr::Group(_) | Expr::Filter { .. } | Expr::Table(_) => fmt_expr(expr, f),
+        _ => {
+            write!(f, "(")?;
+            fmt_expr(expr, f)?;
+            write!(f, ")")
+        }
+    }
+}
+
+fn fmt_compare(ce: &CompareExpr, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+    write!(f, "{}", compare_op_symbol(&ce.op))?;
+    fmt_atom(&ce.val, f)
+}
+
+fn fmt_args(args: &[Expr], f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+    for (i, arg) in args.iter().enumerate() {
+        if i > 0 {
+            write!(f, ", ")?;
+        }
+        fmt_expr(arg, f)?;
+    }
+    Ok(())
+}
+
+fn fmt_expr(expr: &Expr, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+    match expr {
+        Expr::Number(n) => write!(f, "{}", n),
+        Expr::Dice { count, side } => {
+            fmt_atom(count, f)?;
+            write!(f, "d")?;
+            match side.as_ref() {
+                // 自定义骰面优先使用花括号的写法，单个元素的 {6} 会被解析为 d6，只能用方括号
+                Expr::List(faces) if faces.len() >= 2 => {
+                    write!(f, "{{")?;
+                    fmt_args(faces, f)?;
+                    write!(f, "}}")
+                }
+                _ => fmt_atom(side, f),
+            }
+        }
+        Expr::FateDice { count } => {
+            fmt_atom(count, f)?;
+            write!(f, "dF")
+        }
+        Expr::PercentileDice { count } => {
+            fmt_atom(count, f)?;
+            write!(f, "d%")
+        }
+        Expr::Binary { lhs, op, rhs } => {
+            let prec = bin_op_precedence(op);
+            // 左结合时右侧同级运算需要括号，乘方为右结合，左侧需要括号
+            let (lhs_prec, rhs_prec) = if *op == BinOp::Pow {
+                (prec + 1, prec)
+            } else {
+                (prec, prec + 1)
+            };
+            fmt_with_prec(lhs, lhs_prec, f)?;
+            write!(f, " {} ", bin_op_symbol(op))?;
+            fmt_with_prec(rhs, rhs_prec, f)
+        }
+        Expr::Call { func_name, args } => {
+            write!(f, "{}(", func_name)?;
+            fmt_args(args, f)?;
+            write!(f, ")")
+        }
+        Expr::List(items) => {
+            write!(f, "[")?;
+            fmt_args(items, f)?;
+            write!(f, "]")
+        }
+        Expr::Group(items) => {
+            write!(f, "{{")?;
+            fmt_args(items, f)?;
+            write!(f, "}}")
+        }
+        Expr::Modifier { lhs, op, param } => {
+            // 变量后紧跟修饰符会被当成同一个标识符，如 pkh1
+            if let Expr::Var(_) = lhs.as_ref() {
+                fmt_atom(lhs, f)?;
+            } else {
+                fmt_with_prec(lhs, PREC_ATOM, f)?;
+            }
+            write!(f, "{}", modifier_op_symbol(op))?;
+            match param {
+                Some(ModifierParam::Compare(ce)) => fmt_compare(ce, f),
+                Some(ModifierParam::Value(v)) => fmt_atom(v, f),
+                None => Ok(()),
+            }
+        }
+        Expr::Index { list, index } => {
+            fmt_with_prec(list, PREC_ATOM, f)?;
+            write!(f, "[{}]", index)
+        }
+        Expr::Slice { list, start, end } => {
+            fmt_with_prec(list, PREC_ATOM, f)?;
+            write!(f, "[")?;
+            if let Some(start) = start {
+                fmt_expr(start, f)?;
+            }
+            write!(f, ":")?;
+            if let Some(end) = end {
+                fmt_expr(end, f)?;
+            }
+            write!(f, "]")
+        }
+        Expr::Filter { list, compare_expr } => {
+            write!(f, "filter({}, ", list)?;
+            fmt_compare(compare_expr, f)?;
+            write!(f, ")")
+        }
+        Expr::Label { expr, label } => {
+            fmt_with_prec(expr, PREC_ATOM, f)?;
+            write!(f, " [{}]", label)
+        }
+        Expr::Comment { expr, text } => write!(f, "{} # {}", expr, text),
+        Expr::Var(name) => write!(f, "{}", name),
+        Expr::Table(name) => write!(f, "table(\"{}\")", name),
+        Expr::SuccessCheck {
+            lhs,
+            compare_expr,
+            failure,
+            double_success,
+        } => {
+            fmt_with_prec(lhs, PREC_ATOM, f)?;
+            fmt_compare(compare_expr, f)?;
+            if let Some(ce) = failure {
+                write!(f, "f")?;
+                fmt_compare(ce, f)?;
+            }
+            if let Some(ce) = double_success {
+                write!(f, "ds")?;
+                fmt_compare(ce, f)?;
+            }
+            Ok(())
+        }
+    }
+}
+
+impl std::fmt::Display for Expr {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        fmt_expr(self, f)
+    }
+}
+
+impl std::fmt::Display for FunctionDef {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "def {}(", self.name)?;
+        for (i, param) in self.params.iter().enumerate() {
+            if i > 0 {
+                write!(f, ", ")?;
+            }
+            match param.param_type {
+                ParamType::Int => write!(f, "{}: int", param.name)?,
+                ParamType::Num => write!(f, "{}", param.name)?,
+                ParamType::List => write!(f, "{}: list", param.name)?,
+            }
+        }
+        write!(f, ") = {}", self.body)
     }
 }
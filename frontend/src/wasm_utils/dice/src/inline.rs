@@ -0,0 +1,76 @@
+// ==========================================
+// 文本中的行内掷骰与多条表达式
+// ==========================================
+
+// 文本片段: 普通文本，或 [[...]] 中的表达式 (不含括号)
+#[derive(Debug, Clone, PartialEq)]
+pub enum Segment<'a> {
+    Text(&'a str),
+    Roll(&'a str),
+}
+
+// 把文本拆分为普通文本与行内掷骰，如 "deals [[2d6+3]] damage"
+// 表达式中的列表与字符串可以包含方括号，如 [[max([1d6, 2])]]，只有括号平衡时的 ]] 才结束掷骰
+// 没有闭合的 [[ 按普通文本处理
+pub fn split_inline_rolls(text: &str) -> Vec<Segment<'_>> {
+    let mut segments = Vec::new();
+    let mut rest = text;
+    while let Some(open) = rest.find("[[") {
+        let body = &rest[open + 2..];
+        let Some(close) = find_close(body) else {
+            break;
+        };
+        if open > 0 {
+            segments.push(Segment::Text(&rest[..open]));
+        }
+        segments.push(Segment::Roll(&body[..close]));
+        rest = &body[close + 2..];
+    }
+    if !rest.is_empty() {
+        segments.push(Segment::Text(rest));
+    }
+    segments
+}
+
+// 按顶层的分号拆分多条表达式，如 "1d20+5; 2d6+3"，忽略空白的表达式
+pub fn split_expressions(input: &str) -> Vec<&str> {
+    let mut exprs = Vec::new();
+    let mut start = 0;
+    let mut in_string = false;
+    for (i, c) in input.char_indices() {
+        match c {
+            '"' => in_string = !in_string,
+            ';' if !in_string => {
+                exprs.push(&input[start..i]);
+                start = i + 1;
+            }
+            _ => {}
+        }
+    }
+    exprs.push(&input[start..]);
+    exprs
+        .into_iter()
+        .map(str::trim)
+        .filter(|e| !e.is_empty())
+        .collect()
+}
+
+// 找到与开头的 [[ 配对的 ]]，返回其在 body 中的位置
+fn find_close(body: &str) -> Option<usize> {
+    let bytes = body.as_bytes();
+    let mut depth = 0usize;
+    let mut in_string = false;
+    let mut i = 0;
+    while i < bytes.len() {
+        match bytes[i] {
+            b'"' => in_string = !in_string,
+            _ if in_string => {}
+            b']' if depth == 0 && bytes.get(i + 1) == Some(&b']') => return Some(i),
+            b'[' => depth += 1,
+            b']' => depth = depth.saturating_sub(1),
+            _ => {}
+        }
+        i += 1;
+    }
+    None
+}
@@ -2,16 +2,106 @@
 //!
 //! This crate provides functionality for dice rolling and related utilities.
 
+pub mod advantage;
+pub mod complete;
+pub mod eval;
+pub mod explain;
+pub mod functions;
 pub mod grammar;
+pub mod inline;
+pub mod macros;
+pub mod number;
+pub mod rounding;
+pub mod simplify;
+pub mod stats;
+pub mod tables;
+pub mod tokens;
+pub mod trace;
+pub mod transcript;
 pub mod typecheck;
 
-use crate::grammar::parse_dice;
-use crate::typecheck::typecheck_expr;
+use crate::advantage::{RollMode, apply_roll_mode};
+use crate::complete::{Completions, complete};
+use crate::eval::{RollOutput, SplitMix64, evaluate_expr_with_tables, evaluate_expr_with_trace};
+use crate::explain::{Language, explain_expr};
+use crate::functions::FunctionRegistry;
+use crate::grammar::{Expr, parse_dice, parse_dice_with_spans};
+use crate::inline::{Segment, split_expressions, split_inline_rolls};
+use crate::macros::{MacroValue, replace_macros};
+use crate::number::Num;
+use crate::rounding::{Rounding, apply_rounding, typecheck_integer};
+use crate::simplify::simplify_expr;
+use crate::stats::{DiceStatistics, distribution_of};
+use crate::tables::TableRegistry;
+use crate::tokens::{Token, tokenize};
+use crate::trace::SourceSpans;
+use crate::transcript::{TranscriptFormat, render_transcript};
+use crate::typecheck::{Type, typecheck_expr};
 
 use serde::{Deserialize, Serialize};
+use std::cell::RefCell;
+use std::collections::HashMap;
 use tsify::Tsify;
 use wasm_bindgen::prelude::*;
 
+thread_local! {
+    // 角色卡中的自定义函数，所有表达式在解析后都会展开其中的调用
+    static FUNCTIONS: RefCell<FunctionRegistry> = RefCell::new(FunctionRegistry::new());
+    // 随机表，表达式中通过 table("...") 查询
+    static TABLES: RefCell<TableRegistry> = RefCell::new(TableRegistry::new());
+    // 按输入缓存解析与类型检查的结果，自定义函数或随机表变化时清空
+    static CHECK_CACHE: RefCell<HashMap<String, CheckResult>> = RefCell::new(HashMap::new());
+}
+
+// 解析与类型检查的结果：展开后的表达式与其类型，或解析、展开时的错误
+type CheckResult = Result<(Expr, Type), String>;
+
+// 缓存的最大条目数，超出时整体清空
+const MAX_CACHE_ENTRIES: usize = 1024;
+
+// 解析表达式、展开自定义函数调用，并检查引用的随机表是否存在
+fn parse_input(input: &str) -> Result<Expr, String> {
+    check_input(input).map(|(ast, _)| ast)
+}
+
+// 解析并做类型检查，结果按输入缓存
+fn check_input(input: &str) -> CheckResult {
+    if let Some(cached) = CHECK_CACHE.with(|cache| cache.borrow().get(input).cloned()) {
+        return cached;
+    }
+    let result = parse_dice(input)
+        .map_err(|e| format!("Parse error: {}", e))
+        .and_then(|ast| FUNCTIONS.with(|functions| functions.borrow().expand(&ast)))
+        .and_then(|ast| {
+            TABLES.with(|tables| tables.borrow().check(&ast))?;
+            let ty = typecheck_expr(&ast);
+            Ok((ast, ty))
+        });
+    CHECK_CACHE.with(|cache| {
+        let mut cache = cache.borrow_mut();
+        if cache.len() >= MAX_CACHE_ENTRIES {
+            cache.clear();
+        }
+        cache.insert(input.to_string(), result.clone());
+    });
+    result
+}
+
+fn clear_check_cache() {
+    CHECK_CACHE.with(|cache| cache.borrow_mut().clear());
+}
+
+// 解析并按取整规则改写后做类型检查，不取整时直接使用缓存的结果
+fn check_rounded(input: &str, rounding: Rounding) -> CheckResult {
+    let (ast, ty) = check_input(input)?;
+    if rounding == Rounding::Exact {
+        return Ok((ast, ty));
+    }
+    let ast = apply_rounding(&ast, rounding);
+    let ty = typecheck_expr(&ast);
+    Ok((ast, ty))
+}
+
 // ==========================================
 // 辅助类型定义
 // ==========================================
@@ -21,10 +111,29 @@ use wasm_bindgen::prelude::*;
 #[tsify(into_wasm_abi)]
 #[serde(tag = "result", content = "value")]
 pub enum ConstantIntegerCheckResult {
-    Constant(f64),
+    Constant(Num),
     NotConstant(String),
 }
 
+// 批量检查常量整数的请求，formulas 为 名称 → 公式，公式中的 @name 先按 macros 替换
+// rounding 为除法与结果的取整规则，省略时保留分数
+#[derive(Tsify, Serialize, Deserialize)]
+#[tsify(from_wasm_abi, hashmap_as_object)]
+pub struct BatchCheckRequest {
+    pub formulas: HashMap<String, String>,
+    #[serde(default)]
+    pub macros: HashMap<String, MacroValue>,
+    #[serde(default)]
+    pub rounding: Rounding,
+}
+
+// 批量检查的结果，与请求中的名称一一对应
+#[derive(Tsify, Serialize, Deserialize)]
+#[tsify(into_wasm_abi, hashmap_as_object)]
+pub struct BatchCheckResult {
+    pub results: HashMap<String, ConstantIntegerCheckResult>,
+}
+
 // 用于表示带有原因的布尔结果，如果为False，则包含原因字符串
 #[derive(Tsify, Serialize, Deserialize)]
 #[tsify(into_wasm_abi)]
@@ -34,6 +143,111 @@ pub enum ResultWithReason {
     False(String),
 }
 
+// 化简表达式的结果，成功时包含化简后的表达式字符串
+#[derive(Tsify, Serialize, Deserialize)]
+#[tsify(into_wasm_abi)]
+#[serde(tag = "result", content = "value")]
+pub enum SimplifyResult {
+    Simplified(String),
+    Error(String),
+}
+
+// 解析的结果，成功时包含展开自定义函数之后的表达式树
+#[derive(Tsify, Serialize, Deserialize)]
+#[tsify(into_wasm_abi)]
+#[serde(tag = "result", content = "value")]
+pub enum ParseResult {
+    Parsed(Expr),
+    Error(String),
+}
+
+// 类型检查的结果，成功时包含表达式的类型 (常数、骰池、列表长度等)
+#[derive(Tsify, Serialize, Deserialize)]
+#[tsify(into_wasm_abi)]
+#[serde(tag = "result", content = "value")]
+pub enum TypeResult {
+    Typed(Type),
+    Error(String),
+}
+
+// 表达式说明的结果，成功时包含自然语言的说明文字
+#[derive(Tsify, Serialize, Deserialize)]
+#[tsify(into_wasm_abi)]
+#[serde(tag = "result", content = "value")]
+pub enum ExplainResult {
+    Explained(String),
+    Error(String),
+}
+
+// 掷骰结果，成功时包含每个骰池的详细结果
+#[derive(Tsify, Serialize, Deserialize)]
+#[tsify(into_wasm_abi)]
+#[serde(tag = "result", content = "value")]
+pub enum RollResult {
+    Rolled(RollOutput),
+    Error(String),
+}
+
+// 带有掷骰记录的掷骰结果，transcript 为渲染后的文本，如 "4d6dl1 → [6, 5, ~~2~~, 3] = 14"
+#[derive(Tsify, Serialize, Deserialize)]
+#[tsify(into_wasm_abi)]
+pub struct RollTranscript {
+    pub transcript: String,
+    pub output: RollOutput,
+}
+
+#[derive(Tsify, Serialize, Deserialize)]
+#[tsify(into_wasm_abi)]
+#[serde(tag = "result", content = "value")]
+pub enum TranscriptResult {
+    Rendered(RollTranscript),
+    Error(String),
+}
+
+// 多条表达式 (以分号分隔) 的掷骰结果，每条表达式单独报错
+#[derive(Tsify, Serialize, Deserialize)]
+#[tsify(into_wasm_abi)]
+pub struct MultiRollResult {
+    pub rolls: Vec<RollResult>,
+}
+
+// 行内掷骰文本的片段，notation 为 [[...]] 中的表达式
+#[derive(Tsify, Serialize, Deserialize)]
+#[tsify(into_wasm_abi)]
+#[serde(tag = "type")]
+pub enum InlineSegment {
+    Text {
+        text: String,
+    },
+    Roll {
+        notation: String,
+        result: RollResult,
+    },
+}
+
+// 行内掷骰的结果，按原文顺序排列的片段
+#[derive(Tsify, Serialize, Deserialize)]
+#[tsify(into_wasm_abi)]
+pub struct InlineRollResult {
+    pub segments: Vec<InlineSegment>,
+}
+
+// 公式的记号列表，用于语法高亮与括号配对
+#[derive(Tsify, Serialize, Deserialize)]
+#[tsify(into_wasm_abi)]
+pub struct TokenizeResult {
+    pub tokens: Vec<Token>,
+}
+
+// 统计结果，成功时包含最小值、最大值、期望与概率分布
+#[derive(Tsify, Serialize, Deserialize)]
+#[tsify(into_wasm_abi)]
+#[serde(tag = "result", content = "value")]
+pub enum StatisticsResult {
+    Statistics(DiceStatistics),
+    Error(String),
+}
+
 // ==========================================
 // 相关函数定义
 // ==========================================
@@ -41,18 +255,50 @@ pub enum ResultWithReason {
 // 检查输入的表达式是否为常量整数
 #[wasm_bindgen]
 pub fn check_constant_integer(input: String) -> ConstantIntegerCheckResult {
+    constant_integer(&input, Rounding::Exact)
+}
+
+// 按取整规则检查常量整数，如向下取整时 "@lv1 / 2" 在等级为奇数时也是常量整数
+#[wasm_bindgen]
+pub fn check_constant_integer_with_rounding(
+    input: String,
+    rounding: Rounding,
+) -> ConstantIntegerCheckResult {
+    constant_integer(&input, rounding)
+}
+
+// 批量检查多个公式是否为常量整数 (如角色卡中的豁免、技能、先攻与 AC 加值)，只跨越一次 wasm 边界
+// 例如: { formulas: { initiative: "@dex + @pb" }, macros: { dex: 3, pb: 2 } }
+#[wasm_bindgen]
+pub fn check_constant_integers(request: BatchCheckRequest) -> BatchCheckResult {
+    use ConstantIntegerCheckResult::*;
+    let results = request
+        .formulas
+        .into_iter()
+        .map(|(name, formula)| {
+            let result = match replace_macros(&formula, &request.macros) {
+                Ok(replaced) => constant_integer(&replaced, request.rounding),
+                Err(e) => NotConstant(e),
+            };
+            (name, result)
+        })
+        .collect();
+    BatchCheckResult { results }
+}
+
+fn constant_integer(input: &str, rounding: Rounding) -> ConstantIntegerCheckResult {
     use crate::typecheck::NumberType; // 有Constant命名冲突，所以单独引入
     use crate::typecheck::Type::*;
     use ConstantIntegerCheckResult::*;
-    match parse_dice(&input) {
-        Ok(ast) => match typecheck_expr(&ast) {
+    match check_rounded(input, rounding) {
+        Ok((_, ty)) => match ty {
             Invalid(s) => NotConstant(s),
-            Number(NumberType::Constant(c)) if c.fract() == 0.0 => Constant(c),
+            Number(NumberType::Constant(c)) if c.is_integer() => Constant(c),
             Number(NumberType::Constant(_)) => NotConstant("Not an integer".to_string()),
             Number(NumberType::Variable(_)) => NotConstant("Not a constant number".to_string()),
             List(_) => NotConstant("It's a list, not a number".to_string()),
         },
-        Err(e) => NotConstant(format!("Parse error: {}", e.to_string())),
+        Err(e) => NotConstant(e),
     }
 }
 
@@ -60,11 +306,289 @@ pub fn check_constant_integer(input: String) -> ConstantIntegerCheckResult {
 #[wasm_bindgen]
 pub fn check_valid_dice_expression(input: String) -> ResultWithReason {
     use ResultWithReason::*;
-    match parse_dice(&input) {
-        Ok(ast) => match typecheck_expr(&ast) {
-            crate::typecheck::Type::Invalid(s) => False(s),
+    match check_input(&input) {
+        Ok((_, ty)) => match ty {
+            Type::Invalid(s) => False(s),
             _ => Ture,
         },
-        Err(e) => False(format!("Parse error: {}", e.to_string())),
+        Err(e) => False(e),
     }
 }
+
+// 解析表达式并返回表达式树，用于前端按公式中的骰子显示图标等
+#[wasm_bindgen]
+pub fn parse_dice_expression(input: String) -> ParseResult {
+    use ParseResult::*;
+    match parse_input(&input) {
+        Ok(ast) => Parsed(ast),
+        Err(e) => Error(e),
+    }
+}
+
+// 类型检查表达式并返回推导出的类型，类型无效时返回其中的错误信息
+#[wasm_bindgen]
+pub fn typecheck_dice_expression(input: String) -> TypeResult {
+    use TypeResult::*;
+    match check_input(&input) {
+        Ok((_, ty)) => match ty {
+            Type::Invalid(s) => Error(s),
+            ty => Typed(ty),
+        },
+        Err(e) => Error(e),
+    }
+}
+
+// 整数模式的类型检查，用于要求整数的场合 (如角色卡的额外加值)
+// 按取整规则改写后结果仍可能不是整数时返回错误，并指出产生分数的子表达式
+#[wasm_bindgen]
+pub fn typecheck_integer_dice_expression(input: String, rounding: Rounding) -> TypeResult {
+    use TypeResult::*;
+    match check_rounded(&input, rounding) {
+        Ok((ast, _)) => match typecheck_integer(&ast) {
+            Type::Invalid(s) => Error(s),
+            ty => Typed(ty),
+        },
+        Err(e) => Error(e),
+    }
+}
+
+// 化简表达式 (常量折叠、合并同类骰子、移除 +0 与 *1)，用于宏展开后的展示与存储
+#[wasm_bindgen]
+pub fn simplify_dice_expression(input: String) -> SimplifyResult {
+    use SimplifyResult::*;
+    match check_input(&input) {
+        Ok((ast, ty)) => match ty {
+            Type::Invalid(s) => Error(s),
+            _ => Simplified(simplify_expr(&ast).to_string()),
+        },
+        Err(e) => Error(e),
+    }
+}
+
+// 用自然语言说明表达式，用于编辑时的提示，如 4d6dl1 -> "roll four six-sided dice, drop the lowest one"
+#[wasm_bindgen]
+pub fn explain_dice_expression(input: String, lang: Language) -> ExplainResult {
+    use ExplainResult::*;
+    match check_input(&input) {
+        Ok((ast, ty)) => match ty {
+            Type::Invalid(s) => Error(s),
+            _ => Explained(explain_expr(&ast, lang)),
+        },
+        Err(e) => Error(e),
+    }
+}
+
+// 把公式切分为带分类的记号 (数字、骰子、修饰符、函数、自定义函数、运算符、括号、错误等)
+// 不完整或有错误的输入也会返回记号，用于编辑器的语法高亮与括号配对
+#[wasm_bindgen]
+pub fn tokenize_dice_expression(input: String) -> TokenizeResult {
+    TokenizeResult {
+        tokens: tokenize(&input),
+    }
+}
+
+// 光标处的补全候选与所在函数调用的签名，cursor 为按 UTF-16 计算的光标位置
+#[wasm_bindgen]
+pub fn complete_dice_expression(input: String, cursor: usize, lang: Language) -> Completions {
+    FUNCTIONS.with(|functions| complete(&input, cursor, &functions.borrow(), lang))
+}
+
+// 掷骰并求值，随机数种子由调用方提供 (如 Math.random() * 2 ** 32)
+#[wasm_bindgen]
+pub fn roll_dice_expression(input: String, seed: u32) -> RollResult {
+    roll_input(
+        &input,
+        RollMode::Normal,
+        Rounding::Exact,
+        &mut SplitMix64::new(seed as u64),
+    )
+}
+
+// 按掷骰设置的优势/劣势掷骰，作用于表达式中的每次 d20 掷骰，与表达式中的 adv / dis 按 5e 规则合并
+#[wasm_bindgen]
+pub fn roll_dice_expression_with_mode(input: String, mode: RollMode, seed: u32) -> RollResult {
+    roll_input(
+        &input,
+        mode,
+        Rounding::Exact,
+        &mut SplitMix64::new(seed as u64),
+    )
+}
+
+// 按取整规则掷骰，可能产生分数的除法与最终结果按规则取整，如向下取整时 1d6 / 2 -> floor(1d6 / 2)
+#[wasm_bindgen]
+pub fn roll_dice_expression_with_rounding(
+    input: String,
+    mode: RollMode,
+    rounding: Rounding,
+    seed: u32,
+) -> RollResult {
+    roll_input(&input, mode, rounding, &mut SplitMix64::new(seed as u64))
+}
+
+// 掷骰并记录每个子表达式的结果与在输入中的位置，用于悬停在子表达式上时显示中间结果
+#[wasm_bindgen]
+pub fn roll_dice_expression_with_trace(input: String, mode: RollMode, seed: u32) -> RollResult {
+    use RollResult::*;
+    let ast = match parse_input(&input) {
+        Ok(ast) => apply_roll_mode(&ast, mode),
+        Err(e) => return Error(e),
+    };
+    // 展开与改写前的表达式记录了各节点的位置，与实际求值的表达式对应起来
+    let spans = match parse_dice_with_spans(&input) {
+        Ok((parsed, tree)) => SourceSpans::new(&input, &parsed, &tree, &ast),
+        Err(_) => SourceSpans::empty(),
+    };
+    let mut rng = SplitMix64::new(seed as u64);
+    match TABLES.with(|tables| evaluate_expr_with_trace(&ast, &mut rng, &tables.borrow(), &spans)) {
+        Ok(output) => Rolled(output),
+        Err(s) => Error(s),
+    }
+}
+
+// 掷骰并渲染掷骰记录 (纯文本、Markdown 或 HTML)，用于提示框与聊天记录
+#[wasm_bindgen]
+pub fn roll_dice_transcript(
+    input: String,
+    mode: RollMode,
+    format: TranscriptFormat,
+    seed: u32,
+) -> TranscriptResult {
+    use TranscriptResult::*;
+    match roll_ast(
+        &input,
+        mode,
+        Rounding::Exact,
+        &mut SplitMix64::new(seed as u64),
+    ) {
+        Ok((ast, output)) => Rendered(RollTranscript {
+            transcript: render_transcript(&ast, &output, format),
+            output,
+        }),
+        Err(e) => Error(e),
+    }
+}
+
+// 掷出以分号分隔的多条表达式，如 "1d20+5; 2d6+3"，共用同一个随机数序列
+#[wasm_bindgen]
+pub fn roll_dice_expressions(input: String, seed: u32) -> MultiRollResult {
+    let mut rng = SplitMix64::new(seed as u64);
+    MultiRollResult {
+        rolls: split_expressions(&input)
+            .into_iter()
+            .map(|expr| roll_input(expr, RollMode::Normal, Rounding::Exact, &mut rng))
+            .collect(),
+    }
+}
+
+// 掷出文本中所有的行内掷骰，如 "deals [[2d6+3]] slashing damage"
+#[wasm_bindgen]
+pub fn roll_inline_text(text: String, seed: u32) -> InlineRollResult {
+    let mut rng = SplitMix64::new(seed as u64);
+    InlineRollResult {
+        segments: split_inline_rolls(&text)
+            .into_iter()
+            .map(|segment| match segment {
+                Segment::Text(text) => InlineSegment::Text {
+                    text: text.to_string(),
+                },
+                Segment::Roll(notation) => InlineSegment::Roll {
+                    notation: notation.trim().to_string(),
+                    result: roll_input(notation, RollMode::Normal, Rounding::Exact, &mut rng),
+                },
+            })
+            .collect(),
+    }
+}
+
+// 解析并掷骰，解析、类型检查与求值的错误都作为结果返回
+fn roll_input(input: &str, mode: RollMode, rounding: Rounding, rng: &mut SplitMix64) -> RollResult {
+    use RollResult::*;
+    match roll_ast(input, mode, rounding, rng) {
+        Ok((_, output)) => Rolled(output),
+        Err(e) => Error(e),
+    }
+}
+
+// 解析并掷骰，同时返回加上优势/劣势并按规则取整之后的表达式
+fn roll_ast(
+    input: &str,
+    mode: RollMode,
+    rounding: Rounding,
+    rng: &mut SplitMix64,
+) -> Result<(Expr, RollOutput), String> {
+    let ast = apply_rounding(&apply_roll_mode(&parse_input(input)?, mode), rounding);
+    let output = TABLES.with(|tables| evaluate_expr_with_tables(&ast, rng, &tables.borrow()))?;
+    Ok((ast, output))
+}
+
+// 计算表达式结果的统计信息 (最小值、最大值、期望与概率分布)
+#[wasm_bindgen]
+pub fn dice_expression_statistics(input: String) -> StatisticsResult {
+    use StatisticsResult::*;
+    match parse_input(&input) {
+        Ok(ast) => match distribution_of(&ast) {
+            Ok(distribution) => Statistics(distribution.to_statistics()),
+            Err(s) => Error(s),
+        },
+        Err(e) => Error(e),
+    }
+}
+
+// 载入自定义函数定义 (以换行或分号分隔)，替换之前载入的所有定义
+// 例如: def smite(lv: int) = (lv + 1)d8; def attack(bonus) = 1d20 + bonus
+#[wasm_bindgen]
+pub fn load_dice_functions(source: String) -> ResultWithReason {
+    use ResultWithReason::*;
+    let mut registry = FunctionRegistry::new();
+    match registry.load(&source) {
+        Ok(_) => {
+            FUNCTIONS.with(|functions| *functions.borrow_mut() = registry);
+            clear_check_cache();
+            Ture
+        }
+        Err(s) => False(s),
+    }
+}
+
+// 清除所有自定义函数
+#[wasm_bindgen]
+pub fn clear_dice_functions() {
+    FUNCTIONS.with(|functions| functions.borrow_mut().clear());
+    clear_check_cache();
+}
+
+// 载入 JSON 格式的随机表 ({ "tables": [...] })，替换之前载入的所有表
+#[wasm_bindgen]
+pub fn load_dice_tables_json(source: String) -> ResultWithReason {
+    load_dice_tables(|registry| registry.load_json(&source))
+}
+
+// 载入 TOML 格式的随机表 ([[tables]])，替换之前载入的所有表
+#[wasm_bindgen]
+pub fn load_dice_tables_toml(source: String) -> ResultWithReason {
+    load_dice_tables(|registry| registry.load_toml(&source))
+}
+
+fn load_dice_tables<F>(load: F) -> ResultWithReason
+where
+    F: FnOnce(&mut TableRegistry) -> Result<usize, String>,
+{
+    use ResultWithReason::*;
+    let mut registry = TableRegistry::new();
+    match load(&mut registry) {
+        Ok(_) => {
+            TABLES.with(|tables| *tables.borrow_mut() = registry);
+            clear_check_cache();
+            Ture
+        }
+        Err(s) => False(s),
+    }
+}
+
+// 清除所有随机表
+#[wasm_bindgen]
+pub fn clear_dice_tables() {
+    TABLES.with(|tables| tables.borrow_mut().clear());
+    clear_check_cache();
+}
@@ -0,0 +1,92 @@
+use std::collections::HashMap;
+
+use serde::{Deserialize, Serialize};
+use tsify::Tsify;
+
+// ==========================================
+// 角色卡宏 (@str、@pb、@lv1 等) 的替换
+// ==========================================
+
+// 替换的最大轮数，与前端 recusiveMacroReplace 一致
+pub const MAX_MACRO_DEPTH: usize = 5;
+
+// 宏的值：数值 (如属性调整值) 或另一个公式 (其中可以继续引用宏)
+#[derive(Debug, Clone, Serialize, Deserialize, Tsify, PartialEq)]
+#[tsify(into_wasm_abi, from_wasm_abi)]
+#[serde(untagged)]
+pub enum MacroValue {
+    Number(f64),
+    Formula(String),
+}
+
+// 逐轮替换输入中的 @name (名称不区分大小写)，直到不再变化
+// 负数与公式的值加上括号，使替换后的运算顺序不变，如 @str * 2 → (-1) * 2
+pub fn replace_macros(input: &str, macros: &HashMap<String, MacroValue>) -> Result<String, String> {
+    let macros: HashMap<String, &MacroValue> = macros
+        .iter()
+        .map(|(name, value)| (name.to_lowercase(), value))
+        .collect();
+    let mut result = input.to_string();
+    for _ in 0..MAX_MACRO_DEPTH {
+        let replaced = replace_once(&result, &macros);
+        if replaced == result {
+            break;
+        }
+        result = replaced;
+    }
+    match find_macro(&result) {
+        Some(name) if macros.contains_key(&name.to_lowercase()) => Err(format!(
+            "Macro replacement did not converge (more than {} levels): @{}",
+            MAX_MACRO_DEPTH, name
+        )),
+        Some(name) => Err(format!("Unknown macro: @{}", name)),
+        None => Ok(result),
+    }
+}
+
+// ==========================================
+// 辅助处理函数
+// ==========================================
+
+fn replace_once(input: &str, macros: &HashMap<String, &MacroValue>) -> String {
+    let mut output = String::with_capacity(input.len());
+    let mut rest = input;
+    while let Some(at) = rest.find('@') {
+        output.push_str(&rest[..at]);
+        let name = macro_name(&rest[at + 1..]);
+        match macros.get(&name.to_lowercase()) {
+            Some(value) if !name.is_empty() => output.push_str(&macro_text(value)),
+            _ => {
+                output.push('@');
+                output.push_str(name);
+            }
+        }
+        rest = &rest[at + 1 + name.len()..];
+    }
+    output.push_str(rest);
+    output
+}
+
+fn macro_text(value: &MacroValue) -> String {
+    match value {
+        MacroValue::Number(n) if *n < 0.0 => format!("({})", n),
+        MacroValue::Number(n) => n.to_string(),
+        MacroValue::Formula(formula) => format!("({})", formula.trim()),
+    }
+}
+
+// @ 之后的宏名称：连续的字母、数字与下划线
+fn macro_name(text: &str) -> &str {
+    let len = text
+        .find(|c: char| !c.is_ascii_alphanumeric() && c != '_')
+        .unwrap_or(text.len());
+    &text[..len]
+}
+
+// 输入中第一个未替换的宏名称
+fn find_macro(input: &str) -> Option<&str> {
+    input
+        .match_indices('@')
+        .map(|(at, _)| macro_name(&input[at + 1..]))
+        .find(|name| !name.is_empty())
+}
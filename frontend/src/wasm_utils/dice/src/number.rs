@@ -0,0 +1,388 @@
+use std::cmp::Ordering;
+use std::fmt;
+use std::ops::Neg;
+use std::str::FromStr;
+
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+use wasm_bindgen::prelude::*;
+
+// ==========================================
+// 精确的数值类型
+// ==========================================
+
+// 导出给前端时数值统一为 JS 的 number
+#[wasm_bindgen(typescript_custom_section)]
+const NUM_TS: &str = "export type Num = number;";
+
+pub const OVERFLOW: &str = "Arithmetic overflow: the result is out of range.";
+
+// 表达式中的数值：整数与分数按有理数精确计算，超出 i64 范围时报错
+// 只有无法精确表示的结果 (如 sqrt(2)、2 ^ 0.5) 才使用浮点数
+#[derive(Clone, Copy, Debug)]
+pub struct Num(Repr);
+
+#[derive(Clone, Copy, Debug)]
+enum Repr {
+    Ratio(i64, i64), // 分子与分母，已约分，分母为正，分子不为 i64::MIN
+    Float(f64),
+}
+
+use Repr::*;
+
+impl Num {
+    pub const ZERO: Num = Num(Ratio(0, 1));
+    pub const ONE: Num = Num(Ratio(1, 1));
+
+    pub const fn int(n: i64) -> Self {
+        Num(Ratio(n, 1))
+    }
+
+    // 分数 n / d，约分后超出 i64 范围时报错
+    pub fn ratio(n: i128, d: i128) -> Result<Self, String> {
+        if d == 0 {
+            return Err("Division by zero.".to_string());
+        }
+        let g = gcd(n.unsigned_abs(), d.unsigned_abs()) as i128;
+        let (mut n, mut d) = (n / g, d / g);
+        if d < 0 {
+            (n, d) = (-n, -d);
+        }
+        match (i64::try_from(n), i64::try_from(d)) {
+            (Ok(n), Ok(d)) if n != i64::MIN => Ok(Num(Ratio(n, d))),
+            _ => Err(OVERFLOW.to_string()),
+        }
+    }
+
+    // 浮点数结果，整数值 (在精确范围内) 仍按整数保存
+    pub fn float(f: f64) -> Self {
+        if f.fract() == 0.0 && f.abs() < 9_007_199_254_740_992.0 {
+            Num::int(f as i64)
+        } else {
+            Num(Float(f))
+        }
+    }
+
+    pub fn to_f64(self) -> f64 {
+        match self.0 {
+            Ratio(n, d) => n as f64 / d as f64,
+            Float(f) => f,
+        }
+    }
+
+    // 是否为精确值 (整数或分数)
+    pub fn is_exact(self) -> bool {
+        matches!(self.0, Ratio(..))
+    }
+
+    // 能否按十进制写出而不损失精度 (整数、有限小数与浮点数)，用于把结果写回表达式
+    pub fn is_decimal(self) -> bool {
+        match self.0 {
+            Ratio(_, d) => decimal_digits(d).is_some(),
+            Float(_) => true,
+        }
+    }
+
+    pub fn is_integer(self) -> bool {
+        self.as_i64().is_some()
+    }
+
+    // 整数值，不是整数或超出 i64 范围时为 None
+    pub fn as_i64(self) -> Option<i64> {
+        match self.0 {
+            Ratio(n, 1) => Some(n),
+            Ratio(..) => None,
+            Float(f) if f.fract() == 0.0 && f.abs() < 9.2e18 => Some(f as i64),
+            Float(_) => None,
+        }
+    }
+
+    // 向 0 取整后的整数，超出 i64 范围时取边界值，与 f64 as i64 一致
+    pub fn to_i64(self) -> i64 {
+        match self.0 {
+            Ratio(n, d) => n / d,
+            Float(f) => f as i64,
+        }
+    }
+
+    pub fn is_zero(self) -> bool {
+        self.to_f64() == 0.0
+    }
+
+    pub fn abs(self) -> Self {
+        if self < Num::ZERO { -self } else { self }
+    }
+
+    pub fn checked_add(self, other: Num) -> Result<Self, String> {
+        match (self.0, other.0) {
+            (Ratio(a, b), Ratio(c, d)) => {
+                let (a, b, c, d) = (a as i128, b as i128, c as i128, d as i128);
+                Num::ratio(a * d + c * b, b * d)
+            }
+            _ => finite(self.to_f64() + other.to_f64()),
+        }
+    }
+
+    pub fn checked_sub(self, other: Num) -> Result<Self, String> {
+        self.checked_add(-other)
+    }
+
+    pub fn checked_mul(self, other: Num) -> Result<Self, String> {
+        match (self.0, other.0) {
+            (Ratio(a, b), Ratio(c, d)) => Num::ratio(a as i128 * c as i128, b as i128 * d as i128),
+            _ => finite(self.to_f64() * other.to_f64()),
+        }
+    }
+
+    // 除数为 0 时报错
+    pub fn checked_div(self, other: Num) -> Result<Self, String> {
+        if other.is_zero() {
+            return Err("Division by zero.".to_string());
+        }
+        match (self.0, other.0) {
+            (Ratio(a, b), Ratio(c, d)) => Num::ratio(a as i128 * d as i128, b as i128 * c as i128),
+            _ => finite(self.to_f64() / other.to_f64()),
+        }
+    }
+
+    // 整数取模与整除 (向 0 取整)，操作数必须为整数且除数不为 0
+    pub fn checked_rem(self, other: Num) -> Result<Self, String> {
+        let (a, b) = integer_operands(self, other)?;
+        a.checked_rem(b).map(Num::int).ok_or(OVERFLOW.to_string())
+    }
+
+    pub fn idiv(self, other: Num) -> Result<Self, String> {
+        let (a, b) = integer_operands(self, other)?;
+        a.checked_div(b).map(Num::int).ok_or(OVERFLOW.to_string())
+    }
+
+    // 整数次幂精确计算，其余按浮点数计算
+    pub fn pow(self, exponent: Num) -> Result<Self, String> {
+        match (self.0, exponent.as_i64()) {
+            (Ratio(n, d), Some(e)) => {
+                let (n, d) = if e < 0 {
+                    if n == 0 {
+                        return Err("Exponentiation result is not a finite number.".to_string());
+                    }
+                    (d * n.signum(), n.abs())
+                } else {
+                    (n, d)
+                };
+                let e = e.unsigned_abs();
+                Num::ratio(checked_pow(n, e)? as i128, checked_pow(d, e)? as i128)
+            }
+            _ => {
+                let result = self.to_f64().powf(exponent.to_f64());
+                if result.is_finite() {
+                    Ok(Num::float(result))
+                } else {
+                    Err("Exponentiation result is not a finite number.".to_string())
+                }
+            }
+        }
+    }
+
+    // 平方根，完全平方数 (及其比值) 的结果是精确的，参数不能为负数
+    pub fn sqrt(self) -> Self {
+        match self.0 {
+            Ratio(n, d) if n >= 0 => match (exact_sqrt(n), exact_sqrt(d)) {
+                (Some(n), Some(d)) => Num(Ratio(n, d)),
+                _ => Num::float(self.to_f64().sqrt()),
+            },
+            _ => Num::float(self.to_f64().sqrt()),
+        }
+    }
+
+    pub fn floor(self) -> Self {
+        match self.0 {
+            Ratio(n, d) => Num::int(n.div_euclid(d)),
+            Float(f) => Num::float(f.floor()),
+        }
+    }
+
+    pub fn ceil(self) -> Self {
+        -(-self).floor()
+    }
+
+    // 四舍五入，.5 时远离 0，与 f64::round 一致
+    pub fn round(self) -> Self {
+        match self.0 {
+            Ratio(n, d) => {
+                let half_up = (2 * n.unsigned_abs() as u128 + d as u128) / (2 * d as u128);
+                Num::int(half_up as i64 * n.signum())
+            }
+            Float(f) => Num::float(f.round()),
+        }
+    }
+
+    // 所有数值的和，溢出时报错
+    pub fn sum<I: IntoIterator<Item = Num>>(values: I) -> Result<Self, String> {
+        values.into_iter().try_fold(Num::ZERO, Num::checked_add)
+    }
+
+    pub fn max(self, other: Num) -> Self {
+        if other > self { other } else { self }
+    }
+
+    pub fn min(self, other: Num) -> Self {
+        if other < self { other } else { self }
+    }
+
+    // 全序比较，用于排序
+    pub fn total_cmp(&self, other: &Num) -> Ordering {
+        self.partial_cmp(other)
+            .unwrap_or_else(|| self.to_f64().total_cmp(&other.to_f64()))
+    }
+}
+
+// ==========================================
+// 辅助处理函数
+// ==========================================
+
+fn gcd(mut a: u128, mut b: u128) -> u128 {
+    while b != 0 {
+        (a, b) = (b, a % b);
+    }
+    a.max(1)
+}
+
+fn finite(f: f64) -> Result<Num, String> {
+    if f.is_finite() {
+        Ok(Num::float(f))
+    } else {
+        Err(OVERFLOW.to_string())
+    }
+}
+
+fn integer_operands(a: Num, b: Num) -> Result<(i64, i64), String> {
+    match (a.as_i64(), b.as_i64()) {
+        (_, Some(0)) => Err("Division or modulo by zero.".to_string()),
+        (Some(a), Some(b)) => Ok((a, b)),
+        _ => Err("Modulo or integer division operator requires integer operands.".to_string()),
+    }
+}
+
+fn checked_pow(base: i64, exponent: u64) -> Result<i64, String> {
+    match base {
+        0 | 1 => Ok(if exponent == 0 { 1 } else { base }),
+        -1 => Ok(if exponent.is_multiple_of(2) { 1 } else { -1 }),
+        _ => u32::try_from(exponent)
+            .ok()
+            .and_then(|e| base.checked_pow(e))
+            .ok_or(OVERFLOW.to_string()),
+    }
+}
+
+fn exact_sqrt(n: i64) -> Option<i64> {
+    let root = n.isqrt();
+    (root * root == n).then_some(root)
+}
+
+impl From<i64> for Num {
+    fn from(n: i64) -> Self {
+        Num::int(n)
+    }
+}
+
+impl From<f64> for Num {
+    fn from(f: f64) -> Self {
+        Num::float(f)
+    }
+}
+
+// 分子不会是 i64::MIN，取负不会溢出
+impl Neg for Num {
+    type Output = Num;
+
+    fn neg(self) -> Num {
+        match self.0 {
+            Ratio(n, d) => Num(Ratio(-n, d)),
+            Float(f) => Num(Float(-f)),
+        }
+    }
+}
+
+impl PartialEq for Num {
+    fn eq(&self, other: &Self) -> bool {
+        self.partial_cmp(other) == Some(Ordering::Equal)
+    }
+}
+
+impl PartialOrd for Num {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        match (self.0, other.0) {
+            (Ratio(a, b), Ratio(c, d)) => {
+                Some((a as i128 * d as i128).cmp(&(c as i128 * b as i128)))
+            }
+            _ => self.to_f64().partial_cmp(&other.to_f64()),
+        }
+    }
+}
+
+// 小数字面量按十进制精确解析，如 0.1 为 1/10，超出范围时退回浮点数
+impl FromStr for Num {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (int_part, frac_part) = s.split_once('.').unwrap_or((s, ""));
+        if int_part.is_empty()
+            || !(int_part.chars().chain(frac_part.chars())).all(|c| c.is_ascii_digit())
+        {
+            return Err(format!("Invalid number: {}", s));
+        }
+        // 字面量总是精确的有理数，无法用 i64 表示时报错，而不是退回浮点数
+        let frac_part = frac_part.trim_end_matches('0');
+        let exact = format!("{}{}", int_part, frac_part)
+            .parse::<i64>()
+            .ok()
+            .zip(10i64.checked_pow(frac_part.len() as u32))
+            .and_then(|(n, d)| Num::ratio(n as i128, d as i128).ok());
+        match exact {
+            Some(n) => Ok(n),
+            None if int_part.parse::<i64>().is_err() => Err(format!("Number out of range: {}", s)),
+            None => Err(format!("Too many decimal places: {}", s)),
+        }
+    }
+}
+
+// 整数与有限小数按十进制精确显示，其余分数与浮点数按 f64 显示
+impl fmt::Display for Num {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.0 {
+            Ratio(n, 1) => write!(f, "{}", n),
+            Ratio(n, d) => match decimal_digits(d) {
+                Some(digits) => {
+                    let scaled = n.unsigned_abs() as u128 * (10u128.pow(digits) / d as u128);
+                    let unit = 10u128.pow(digits);
+                    let frac = format!("{:0width$}", scaled % unit, width = digits as usize);
+                    let sign = if n < 0 { "-" } else { "" };
+                    write!(
+                        f,
+                        "{}{}.{}",
+                        sign,
+                        scaled / unit,
+                        frac.trim_end_matches('0')
+                    )
+                }
+                None => write!(f, "{}", self.to_f64()),
+            },
+            Float(x) => write!(f, "{}", x),
+        }
+    }
+}
+
+// 分母只含因子 2 与 5 时，有限小数的位数
+fn decimal_digits(d: i64) -> Option<u32> {
+    (0..=18).find(|k| 10u128.pow(*k) % d as u128 == 0)
+}
+
+impl Serialize for Num {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_f64(self.to_f64())
+    }
+}
+
+impl<'de> Deserialize<'de> for Num {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        f64::deserialize(deserializer).map(Num::float)
+    }
+}
@@ -0,0 +1,171 @@
+use std::convert::Infallible;
+
+use serde::{Deserialize, Serialize};
+use tsify::Tsify;
+
+use crate::grammar::{BinOp, Expr};
+use crate::number::Num;
+use crate::typecheck::{ListType, NumberType, Type, typecheck_expr};
+
+// ==========================================
+// 除法与最终结果的取整规则
+// ==========================================
+
+// 取整规则，与掷骰设置中的 'exact' | 'down' | 'nearest' | 'up' 对应
+// 5e 中几乎所有除法都向下取整，exact 保留分数 (只有 // 做整数除法)
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, Tsify, PartialEq, Default)]
+#[tsify(into_wasm_abi, from_wasm_abi)]
+pub enum Rounding {
+    #[default]
+    #[serde(rename = "exact")]
+    Exact,
+    #[serde(rename = "down")]
+    Down,
+    #[serde(rename = "nearest")]
+    Nearest,
+    #[serde(rename = "up")]
+    Up,
+}
+
+impl Rounding {
+    // 对应的内置函数，round 为四舍五入 (远离零)
+    pub fn function(self) -> Option<&'static str> {
+        match self {
+            Rounding::Exact => None,
+            Rounding::Down => Some("floor"),
+            Rounding::Nearest => Some("round"),
+            Rounding::Up => Some("ceil"),
+        }
+    }
+}
+
+// 按取整规则改写表达式，结果仍是普通的 floor / round / ceil 调用：
+// 每个可能产生分数的除法单独取整，如 1d6 / 2 -> floor(1d6 / 2)
+// 最终结果仍可能不是整数时 (如 sqrt、avg) 整体再取整一次
+// 标签与注释保留在最外层，列表结果不取整
+pub fn apply_rounding(expr: &Expr, rounding: Rounding) -> Expr {
+    let Some(func_name) = rounding.function() else {
+        return expr.clone();
+    };
+    round_total(round_divisions(expr, func_name), func_name)
+}
+
+// 整数模式的类型检查：在 typecheck_expr 的基础上，
+// 结果可能不是整数时 (如角色卡的额外加值) 返回无效类型，并指出产生分数的子表达式
+pub fn typecheck_integer(expr: &Expr) -> Type {
+    match typecheck_expr(expr) {
+        Type::Invalid(s) => Type::Invalid(s),
+        ty => match fractional_source(expr) {
+            Some(s) => Type::Invalid(s),
+            None => ty,
+        },
+    }
+}
+
+// ==========================================
+// 辅助处理函数
+// ==========================================
+
+fn call(func_name: &str, expr: Expr) -> Expr {
+    Expr::Call {
+        func_name: func_name.to_string(),
+        args: vec![expr],
+    }
+}
+
+fn round_divisions(expr: &Expr, func_name: &str) -> Expr {
+    let Ok(expr) =
+        expr.try_map_children(|child| Ok::<_, Infallible>(round_divisions(child, func_name)));
+    match expr {
+        Expr::Binary { op: BinOp::Div, .. } if fractional_source(&expr).is_some() => {
+            call(func_name, expr)
+        }
+        expr => expr,
+    }
+}
+
+fn round_total(expr: Expr, func_name: &str) -> Expr {
+    match expr {
+        Expr::Label { expr, label } => Expr::Label {
+            expr: Box::new(round_total(*expr, func_name)),
+            label,
+        },
+        Expr::Comment { expr, text } => Expr::Comment {
+            expr: Box::new(round_total(*expr, func_name)),
+            text,
+        },
+        expr => match typecheck_expr(&expr) {
+            Type::Number(_) if fractional_source(&expr).is_some() => call(func_name, expr),
+            _ => expr,
+        },
+    }
+}
+
+// 表达式的结果可能不是整数时，返回说明原因的错误信息
+// 常数按实际的值判断，变量按产生分数的运算判断 (除法、开方、平均值、负数次方等)
+fn fractional_source(expr: &Expr) -> Option<String> {
+    match typecheck_expr(expr) {
+        Type::Number(NumberType::Constant(c)) => {
+            return (!c.is_integer()).then(|| format!("{} is {}, not an integer.", expr, c));
+        }
+        Type::List(ListType::ConstantList(lst)) => {
+            return lst
+                .iter()
+                .find(|c| !c.is_integer())
+                .map(|c| format!("{} contains {}, not an integer.", expr, c));
+        }
+        _ => {}
+    }
+    let children = || expr.children().into_iter().find_map(fractional_source);
+    match expr {
+        // 骰子的点数都是整数，成功检定的结果为成功数
+        Expr::Dice { .. }
+        | Expr::FateDice { .. }
+        | Expr::PercentileDice { .. }
+        | Expr::SuccessCheck { .. } => None,
+        // 表项的表达式单独求值，结果按表项自身计算
+        Expr::Number(_) | Expr::Var(_) | Expr::Table(_) => None,
+        // % 与 // 要求整数操作数，结果也是整数
+        Expr::Binary {
+            op: BinOp::Mod | BinOp::Idiv,
+            ..
+        } => None,
+        Expr::Binary {
+            op: BinOp::Div, ..
+        } => children().or_else(|| {
+            Some(format!(
+                "Division may produce a non-integer result: {}. Use // for integer division, or a rounding option.",
+                expr
+            ))
+        }),
+        Expr::Binary {
+            lhs,
+            op: BinOp::Pow,
+            rhs,
+        } => power_source(expr, lhs, rhs),
+        Expr::Call { func_name, args } => match (func_name.as_str(), args.as_slice()) {
+            ("floor" | "ceil" | "round" | "len" | "count", _) => None,
+            ("pow", [base, exponent]) => power_source(expr, base, exponent),
+            ("sqrt" | "avg" | "median", _) => children().or_else(|| {
+                Some(format!("{} may produce a non-integer result.", expr))
+            }),
+            _ => children(),
+        },
+        // 修饰符与比较中的参数不影响结果的值
+        Expr::Modifier { lhs: list, .. }
+        | Expr::Index { list, .. }
+        | Expr::Slice { list, .. }
+        | Expr::Filter { list, .. } => fractional_source(list),
+        _ => children(),
+    }
+}
+
+// 乘方只有在指数为非负整数常数时保持整数
+fn power_source(expr: &Expr, base: &Expr, exponent: &Expr) -> Option<String> {
+    match typecheck_expr(exponent) {
+        Type::Number(NumberType::Constant(c)) if c.is_integer() && c >= Num::ZERO => {
+            fractional_source(base)
+        }
+        _ => Some(format!("{} may produce a non-integer result.", expr)),
+    }
+}
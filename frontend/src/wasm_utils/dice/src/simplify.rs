@@ -0,0 +1,308 @@
+use crate::grammar::{BinOp, CompareExpr, Expr, ModifierParam};
+use crate::number::Num;
+use crate::typecheck::{ListType, MAX_DICE, NumberType, Type, binary_op_type, typecheck_expr};
+
+// ==========================================
+// 主化简函数
+// ==========================================
+
+// 化简表达式，返回新的表达式：
+// 1. 折叠常量子树 (1 + 2 -> 3, max(1, 2) -> 2)
+// 2. 合并同类骰子 (1d6 + 2d6 -> 3d6)，仅合并没有修饰符、符号相同的骰子
+// 3. 移除 +0、-0、*1、/1
+// 无效的表达式 (类型检查失败) 原样返回
+pub fn simplify_expr(expr: &Expr) -> Expr {
+    if let Type::Invalid(_) = typecheck_expr(expr) {
+        return expr.clone();
+    }
+    simplify_valid(expr).0
+}
+
+// ==========================================
+// 辅助处理函数
+// ==========================================
+
+// 已知 expr 类型合法时的化简，同时返回化简后表达式的类型
+// 先化简子节点，再由子节点的类型推出当前节点的类型，整棵树只做一次类型检查：
+// 加减乘除直接由两侧的类型推出，其余节点的子节点多已折叠为常数，检查代价很小
+fn simplify_valid(expr: &Expr) -> (Expr, Type) {
+    match expr {
+        // 0. 保留标签与注释，只化简其中的表达式
+        Expr::Label { expr, label } => {
+            let (expr, ty) = simplify_valid(expr);
+            let expr = Expr::Label {
+                expr: Box::new(expr),
+                label: label.clone(),
+            };
+            (expr, ty)
+        }
+        Expr::Comment { expr, text } => {
+            let (expr, ty) = simplify_valid(expr);
+            let expr = Expr::Comment {
+                expr: Box::new(expr),
+                text: text.clone(),
+            };
+            (expr, ty)
+        }
+        Expr::Number(c) => (expr.clone(), Type::constant(*c)),
+        // 1. 二元运算：常量直接折叠，否则合并同类项、移除单位元
+        Expr::Binary { lhs, op, rhs } => {
+            let (lhs, lhs_type) = simplify_valid(lhs);
+            let (rhs, rhs_type) = simplify_valid(rhs);
+            let ty = binary_op_type(lhs_type, op, rhs_type);
+            match fold_constant(&ty) {
+                Some(folded) => (folded, ty),
+                None => (simplify_binary(lhs, op, rhs, &ty), ty),
+            }
+        }
+        // 2. 其余节点递归化简子节点，常量子树直接折叠
+        _ => {
+            let expr = simplify_children(expr);
+            let ty = typecheck_expr(&expr);
+            match fold_constant(&ty) {
+                Some(folded) => (folded, ty),
+                None => (expr, ty),
+            }
+        }
+    }
+}
+
+// 常量折叠后的表达式，无限循环小数 (如 1 / 3) 保留原来的写法
+fn fold_constant(ty: &Type) -> Option<Expr> {
+    match ty {
+        Type::Number(NumberType::Constant(c)) if c.is_decimal() => Some(Expr::Number(*c)),
+        Type::List(ListType::ConstantList(lst)) if lst.iter().all(|c| c.is_decimal()) => {
+            Some(Expr::List(lst.iter().copied().map(Expr::Number).collect()))
+        }
+        _ => None,
+    }
+}
+
+fn simplify_child(expr: &Expr) -> Expr {
+    simplify_valid(expr).0
+}
+
+fn simplify_children(expr: &Expr) -> Expr {
+    match expr {
+        Expr::Number(_) | Expr::Var(_) | Expr::Table(_) => expr.clone(),
+        Expr::Label { .. } | Expr::Comment { .. } | Expr::Binary { .. } => {
+            unreachable!("Handled in simplify_valid")
+        }
+        Expr::Dice { count, side } => Expr::Dice {
+            count: Box::new(simplify_child(count)),
+            side: Box::new(simplify_child(side)),
+        },
+        Expr::FateDice { count } => Expr::FateDice {
+            count: Box::new(simplify_child(count)),
+        },
+        Expr::PercentileDice { count } => Expr::PercentileDice {
+            count: Box::new(simplify_child(count)),
+        },
+        Expr::Call { func_name, args } => Expr::Call {
+            func_name: func_name.clone(),
+            args: args.iter().map(simplify_child).collect(),
+        },
+        Expr::List(items) => Expr::List(items.iter().map(simplify_child).collect()),
+        Expr::Group(items) => Expr::Group(items.iter().map(simplify_child).collect()),
+        Expr::Modifier { lhs, op, param } => Expr::Modifier {
+            lhs: Box::new(simplify_child(lhs)),
+            op: op.clone(),
+            param: param.as_ref().map(simplify_param),
+        },
+        Expr::SuccessCheck {
+            lhs,
+            compare_expr,
+            failure,
+            double_success,
+        } => Expr::SuccessCheck {
+            lhs: Box::new(simplify_child(lhs)),
+            compare_expr: simplify_compare(compare_expr),
+            failure: failure.as_ref().map(simplify_compare),
+            double_success: double_success.as_ref().map(simplify_compare),
+        },
+        Expr::Index { list, index } => Expr::Index {
+            list: Box::new(simplify_child(list)),
+            index: Box::new(simplify_child(index)),
+        },
+        Expr::Slice { list, start, end } => Expr::Slice {
+            list: Box::new(simplify_child(list)),
+            start: start.as_ref().map(|e| Box::new(simplify_child(e))),
+            end: end.as_ref().map(|e| Box::new(simplify_child(e))),
+        },
+        Expr::Filter { list, compare_expr } => Expr::Filter {
+            list: Box::new(simplify_child(list)),
+            compare_expr: simplify_compare(compare_expr),
+        },
+    }
+}
+
+fn simplify_compare(ce: &CompareExpr) -> CompareExpr {
+    CompareExpr {
+        op: ce.op.clone(),
+        val: Box::new(simplify_child(&ce.val)),
+    }
+}
+
+fn simplify_param(param: &ModifierParam) -> ModifierParam {
+    match param {
+        ModifierParam::Compare(ce) => ModifierParam::Compare(simplify_compare(ce)),
+        ModifierParam::Value(v) => ModifierParam::Value(Box::new(simplify_child(v))),
+    }
+}
+
+fn binary(lhs: Expr, op: BinOp, rhs: Expr) -> Expr {
+    Expr::Binary {
+        lhs: Box::new(lhs),
+        op,
+        rhs: Box::new(rhs),
+    }
+}
+
+// 子节点已经化简完毕，处理当前的二元运算，ty 为运算结果的类型
+fn simplify_binary(lhs: Expr, op: &BinOp, rhs: Expr, ty: &Type) -> Expr {
+    match op {
+        BinOp::Add | BinOp::Sub => {
+            let node = binary(lhs, op.clone(), rhs);
+            // 列表的加法是拼接，不参与加减链的重排
+            match ty {
+                Type::Number(_) => simplify_additive_chain(node),
+                _ => node,
+            }
+        }
+        BinOp::Mul => match (&lhs, &rhs) {
+            (_, Expr::Number(n)) if *n == Num::ONE => lhs,
+            (Expr::Number(n), _) if *n == Num::ONE => rhs,
+            _ => binary(lhs, BinOp::Mul, rhs),
+        },
+        BinOp::Div => match &rhs {
+            Expr::Number(n) if *n == Num::ONE => lhs,
+            _ => binary(lhs, BinOp::Div, rhs),
+        },
+        BinOp::Pow => match &rhs {
+            Expr::Number(n) if *n == Num::ONE => lhs,
+            _ => binary(lhs, BinOp::Pow, rhs),
+        },
+        _ => binary(lhs, op.clone(), rhs),
+    }
+}
+
+// 加减链中的一项，negative 表示该项前面是减号
+struct Term {
+    negative: bool,
+    expr: Expr,
+}
+
+// 把加减链展开为若干项，常数项累加到 constant 中，累加溢出时报错
+fn collect_terms(
+    expr: Expr,
+    negative: bool,
+    terms: &mut Vec<Term>,
+    constant: &mut Num,
+) -> Result<(), String> {
+    match expr {
+        Expr::Binary {
+            lhs,
+            op: BinOp::Add,
+            rhs,
+        } => {
+            collect_terms(*lhs, negative, terms, constant)?;
+            collect_terms(*rhs, negative, terms, constant)?;
+        }
+        Expr::Binary {
+            lhs,
+            op: BinOp::Sub,
+            rhs,
+        } => {
+            collect_terms(*lhs, negative, terms, constant)?;
+            collect_terms(*rhs, !negative, terms, constant)?;
+        }
+        Expr::Number(c) => *constant = constant.checked_add(if negative { -c } else { c })?,
+        expr => terms.push(Term { negative, expr }),
+    }
+    Ok(())
+}
+
+// 没有修饰符、数量为常数的骰子，返回其数量
+fn plain_dice_count(expr: &Expr) -> Option<Num> {
+    match expr {
+        Expr::Dice { count, side } => match (count.as_ref(), side.as_ref()) {
+            (Expr::Number(c), Expr::Number(_) | Expr::List(_)) => Some(*c),
+            _ => None,
+        },
+        Expr::FateDice { count } | Expr::PercentileDice { count } => match count.as_ref() {
+            Expr::Number(c) => Some(*c),
+            _ => None,
+        },
+        _ => None,
+    }
+}
+
+// 替换骰子的数量，骰面保持不变
+fn with_dice_count(expr: &Expr, count: Num) -> Expr {
+    let count = Box::new(Expr::Number(count));
+    match expr {
+        Expr::Dice { side, .. } => Expr::Dice {
+            count,
+            side: side.clone(),
+        },
+        Expr::FateDice { .. } => Expr::FateDice { count },
+        Expr::PercentileDice { .. } => Expr::PercentileDice { count },
+        _ => unreachable!("Not a dice expression: {:?}", expr),
+    }
+}
+
+// 两个骰子的骰面是否相同 (忽略数量)
+fn same_dice_kind(a: &Expr, b: &Expr) -> bool {
+    with_dice_count(a, Num::ONE) == with_dice_count(b, Num::ONE)
+}
+
+fn simplify_additive_chain(expr: Expr) -> Expr {
+    let mut raw_terms = Vec::new();
+    let mut constant = Num::ZERO;
+    if collect_terms(expr.clone(), false, &mut raw_terms, &mut constant).is_err() {
+        return expr;
+    }
+
+    // 合并同类骰子：符号相同、面数相同的无修饰骰子 (1d6 + 2d6 -> 3d6)
+    let mut terms: Vec<Term> = Vec::new();
+    for term in raw_terms {
+        if let Some(count) = plain_dice_count(&term.expr) {
+            let same = terms.iter_mut().find(|t| {
+                t.negative == term.negative
+                    && plain_dice_count(&t.expr).is_some()
+                    && same_dice_kind(&t.expr, &term.expr)
+            });
+            // 合并后超过骰子数量上限时保持分开，避免化简出无效的表达式
+            if let Some(existing) = same
+                && let Ok(total) = plain_dice_count(&existing.expr).unwrap().checked_add(count)
+                && total <= Num::int(MAX_DICE)
+            {
+                existing.expr = with_dice_count(&existing.expr, total);
+                continue;
+            }
+        }
+        terms.push(term);
+    }
+
+    // 重新组装，常数项放在最后，值为 0 时省略
+    // 第一项为负时，正的常数项移到最前面 (0 - 1d6 + 2 -> 2 - 1d6)
+    let mut result: Option<Expr> = None;
+    if terms.first().is_some_and(|t| t.negative) && constant > Num::ZERO {
+        result = Some(Expr::Number(constant));
+        constant = Num::ZERO;
+    }
+    for term in terms {
+        result = Some(match result {
+            None if term.negative => binary(Expr::Number(Num::ZERO), BinOp::Sub, term.expr),
+            None => term.expr,
+            Some(acc) if term.negative => binary(acc, BinOp::Sub, term.expr),
+            Some(acc) => binary(acc, BinOp::Add, term.expr),
+        });
+    }
+    match result {
+        None => Expr::Number(constant),
+        Some(acc) if constant > Num::ZERO => binary(acc, BinOp::Add, Expr::Number(constant)),
+        Some(acc) if constant < Num::ZERO => binary(acc, BinOp::Sub, Expr::Number(-constant)),
+        Some(acc) => acc,
+    }
+}
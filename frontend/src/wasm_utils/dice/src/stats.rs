@@ -0,0 +1,746 @@
+use serde::{Deserialize, Serialize};
+use tsify::Tsify;
+
+use crate::eval::{DEFAULT_REPEAT, MAX_EXPLOSIONS, SuccessRule, apply_bin_op, compare};
+use crate::grammar::{BinOp, CompareExpr, CompareOp, Expr, ModifierOp, ModifierParam};
+use crate::number::Num;
+use crate::typecheck::{
+    DiceItem, DicePoolType, DiceSide, NumberType, Type, VariableNumber, is_group,
+    top_n_preserve_order, typecheck_expr,
+};
+
+// 概率低于该值的爆骰分支直接舍弃
+const MIN_PROBABILITY: f64 = 1e-12;
+
+// 取高/取低时枚举骰池组合 (多重集) 的上限，超过时不计算
+const MAX_POOL_COMBINATIONS: f64 = 200_000.0;
+
+// 分布中不同结果数量的上限，以及组合两个分布时枚举的结果对数量的上限，超过时不计算
+const MAX_OUTCOMES: usize = 10_000;
+const MAX_PAIRS: usize = 1_000_000;
+
+// ==========================================
+// 类型定义
+// ==========================================
+
+// 离散概率分布，按数值从小到大排列
+#[derive(Clone, PartialEq, Debug)]
+pub struct Distribution {
+    outcomes: Vec<(f64, f64)>, // (数值, 概率)
+}
+
+// 单个结果的概率 (导出给前端)
+#[derive(Debug, Clone, Serialize, Deserialize, Tsify, PartialEq)]
+#[tsify(into_wasm_abi)]
+pub struct Outcome {
+    pub value: f64,
+    pub probability: f64,
+}
+
+// 表达式的统计信息 (导出给前端)
+#[derive(Debug, Clone, Serialize, Deserialize, Tsify, PartialEq)]
+#[tsify(into_wasm_abi)]
+pub struct DiceStatistics {
+    pub min: f64,
+    pub max: f64,
+    pub mean: f64,
+    pub distribution: Vec<Outcome>,
+}
+
+impl Distribution {
+    pub fn constant(value: f64) -> Self {
+        Distribution {
+            outcomes: vec![(value, 1.0)],
+        }
+    }
+
+    // 单颗骰子的分布，每个骰面等概率出现，骰面过多时不计算
+    pub fn die(side: &DiceSide) -> Result<Self, String> {
+        if let DiceSide::Number(n) = side
+            && *n > MAX_OUTCOMES as i64
+        {
+            return Err(too_many_outcomes());
+        }
+        Ok(Distribution::uniform(&side.faces()))
+    }
+
+    // 每个骰面等概率出现
+    pub fn uniform(faces: &[i64]) -> Self {
+        let p = 1.0 / faces.len() as f64;
+        Distribution::from_outcomes(faces.iter().map(|f| (*f as f64, p)).collect())
+    }
+
+    // 整理结果：排序、合并相同数值、去掉零概率
+    fn from_outcomes(mut outcomes: Vec<(f64, f64)>) -> Self {
+        outcomes.sort_by(|a, b| a.0.total_cmp(&b.0));
+        let mut merged: Vec<(f64, f64)> = Vec::new();
+        for (value, p) in outcomes {
+            match merged.last_mut() {
+                Some(last) if last.0 == value => last.1 += p,
+                _ if p > 0.0 => merged.push((value, p)),
+                _ => {}
+            }
+        }
+        Distribution { outcomes: merged }
+    }
+
+    pub fn outcomes(&self) -> &[(f64, f64)] {
+        &self.outcomes
+    }
+
+    pub fn min(&self) -> f64 {
+        self.outcomes.first().map(|o| o.0).unwrap_or(0.0)
+    }
+
+    pub fn max(&self) -> f64 {
+        self.outcomes.last().map(|o| o.0).unwrap_or(0.0)
+    }
+
+    pub fn mean(&self) -> f64 {
+        self.outcomes.iter().map(|(v, p)| v * p).sum()
+    }
+
+    // 满足条件的概率
+    pub fn probability<F: Fn(f64) -> bool>(&self, pred: F) -> f64 {
+        self.outcomes
+            .iter()
+            .filter(|(v, _)| pred(*v))
+            .map(|(_, p)| p)
+            .sum()
+    }
+
+    pub fn map<F: Fn(f64) -> f64>(&self, f: F) -> Self {
+        Distribution::from_outcomes(self.outcomes.iter().map(|(v, p)| (f(*v), *p)).collect())
+    }
+
+    // 两个独立随机变量的组合
+    pub fn combine<F: Fn(f64, f64) -> Result<f64, String>>(
+        &self,
+        other: &Distribution,
+        f: F,
+    ) -> Result<Self, String> {
+        if self.outcomes.len() * other.outcomes.len() > MAX_PAIRS {
+            return Err(too_many_outcomes());
+        }
+        let mut outcomes = Vec::new();
+        for (a, pa) in &self.outcomes {
+            for (b, pb) in &other.outcomes {
+                outcomes.push((f(*a, *b)?, pa * pb));
+            }
+        }
+        let result = Distribution::from_outcomes(outcomes);
+        if result.outcomes.len() > MAX_OUTCOMES {
+            return Err(too_many_outcomes());
+        }
+        Ok(result)
+    }
+
+    // 两个独立变量之和，数值都是范围不大的整数时按下标累加到连续的数组中，不需要排序
+    pub fn add(&self, other: &Distribution) -> Result<Self, String> {
+        let is_integer = |d: &Distribution| d.outcomes.iter().all(|(v, _)| v.fract() == 0.0);
+        let lo = self.min() + other.min();
+        let len = (self.max() - self.min() + other.max() - other.min()) as usize + 1;
+        if !is_integer(self) || !is_integer(other) || len > MAX_OUTCOMES {
+            return self.combine(other, |a, b| Ok(a + b));
+        }
+        let mut dense = vec![0.0; len];
+        for (a, pa) in &self.outcomes {
+            for (b, pb) in &other.outcomes {
+                dense[(a + b - lo) as usize] += pa * pb;
+            }
+        }
+        let outcomes = dense
+            .into_iter()
+            .enumerate()
+            .filter(|(_, p)| *p > 0.0)
+            .map(|(i, p)| (lo + i as f64, p))
+            .collect();
+        Ok(Distribution { outcomes })
+    }
+
+    // n 个独立同分布变量之和，按二进制拆分 n，只需 O(log n) 次加法
+    pub fn repeat_sum(&self, n: i64) -> Result<Self, String> {
+        let mut result = Distribution::constant(0.0);
+        let mut power = self.clone();
+        let mut n = n;
+        while n > 0 {
+            if n & 1 == 1 {
+                result = result.add(&power)?;
+            }
+            n >>= 1;
+            if n > 0 {
+                power = power.add(&power)?;
+            }
+        }
+        Ok(result)
+    }
+
+    pub fn to_statistics(&self) -> DiceStatistics {
+        DiceStatistics {
+            min: self.min(),
+            max: self.max(),
+            mean: self.mean(),
+            distribution: self
+                .outcomes
+                .iter()
+                .map(|(value, probability)| Outcome {
+                    value: *value,
+                    probability: *probability,
+                })
+                .collect(),
+        }
+    }
+}
+
+// ==========================================
+// 主统计函数
+// ==========================================
+
+// 计算表达式结果的概率分布，列表和部分组合暂不支持
+pub fn distribution_of(expr: &Expr) -> Result<Distribution, String> {
+    if let Type::Invalid(s) = typecheck_expr(expr) {
+        return Err(s);
+    }
+    number_distribution(expr, 1)
+}
+
+// ==========================================
+// 辅助处理函数
+// ==========================================
+
+// 统计过程中的骰池
+enum PoolDistribution {
+    // 尚未求和的骰池：单颗骰子的分布与骰子数量
+    // extra_dice 表示爆骰 (!) 可能追加骰子，此时骰子数量不固定
+    Dice {
+        die: Distribution,
+        count: i64,
+        extra_dice: bool,
+    },
+    // 已经按取高/取低求和后的分布
+    Summed(Distribution),
+}
+
+impl PoolDistribution {
+    fn into_sum(self) -> Result<Distribution, String> {
+        match self {
+            PoolDistribution::Dice { die, count, .. } => die.repeat_sum(count),
+            PoolDistribution::Summed(d) => Ok(d),
+        }
+    }
+}
+
+fn unsupported() -> String {
+    "Statistics are not available for this expression.".to_string()
+}
+
+fn too_many_outcomes() -> String {
+    "Too many possible results to compute statistics.".to_string()
+}
+
+// 分布中的数值按浮点数计算，运算规则与求值时相同
+fn float_bin_op(op: &BinOp, a: f64, b: f64) -> Result<f64, String> {
+    apply_bin_op(op, Num::float(a), Num::float(b)).map(Num::to_f64)
+}
+
+fn float_compare(op: &CompareOp, a: f64, b: f64) -> bool {
+    compare(op, Num::float(a), Num::float(b))
+}
+
+fn float_score(rule: &SuccessRule, value: f64) -> f64 {
+    rule.score(Num::float(value)).to_f64()
+}
+
+fn constant_of(expr: &Expr) -> f64 {
+    match typecheck_expr(expr) {
+        Type::Number(NumberType::Constant(c)) => c.to_f64(),
+        t => unreachable!("Expected a constant, got {:?}", t),
+    }
+}
+
+fn compare_param(param: &Option<ModifierParam>) -> Option<(CompareOp, f64)> {
+    match param {
+        Some(ModifierParam::Compare(CompareExpr { op, val })) => {
+            Some((op.clone(), constant_of(val)))
+        }
+        _ => None,
+    }
+}
+
+fn number_distribution(expr: &Expr, repeat: i64) -> Result<Distribution, String> {
+    match typecheck_expr(expr) {
+        Type::Number(NumberType::Constant(c)) => return Ok(Distribution::constant(c.to_f64())),
+        Type::List(_) => return Err("Statistics are not available for lists.".to_string()),
+        _ => {}
+    }
+    match expr {
+        Expr::SuccessCheck {
+            lhs,
+            compare_expr,
+            failure,
+            double_success,
+        } => {
+            let rule = SuccessRule::new(compare_expr, failure, double_success);
+            if is_group(lhs) {
+                group_distribution(lhs, repeat, Some(&rule))
+            } else {
+                success_distribution(lhs, &rule, repeat)
+            }
+        }
+        Expr::Modifier {
+            lhs,
+            op: ModifierOp::Count,
+            param: Some(ModifierParam::Compare(compare_expr)),
+        } => {
+            let rule = SuccessRule::new(compare_expr, &None, &None);
+            if is_group(lhs) {
+                group_distribution(lhs, repeat, Some(&rule))
+            } else {
+                success_distribution(lhs, &rule, repeat)
+            }
+        }
+        Expr::Group(_) => group_distribution(expr, repeat, None),
+        Expr::Modifier { .. } if is_group(expr) => group_distribution(expr, repeat, None),
+        Expr::Dice { .. }
+        | Expr::FateDice { .. }
+        | Expr::PercentileDice { .. }
+        | Expr::Modifier { .. } => pool_distribution(expr)?.into_sum()?.repeat_sum(repeat),
+        Expr::Binary { lhs, op, rhs } => {
+            let l = number_distribution(lhs, repeat)?;
+            let r = number_distribution(rhs, repeat)?;
+            l.combine(&r, |a, b| float_bin_op(op, a, b))
+        }
+        Expr::Call { func_name, args } => match (func_name.as_str(), args.as_slice()) {
+            ("rpdice", [x]) => number_distribution(x, repeat * DEFAULT_REPEAT),
+            ("rpdice", [x, n]) => number_distribution(x, repeat * constant_of(n) as i64),
+            ("pow", [base, exponent]) => {
+                let b = number_distribution(base, repeat)?;
+                let e = number_distribution(exponent, repeat)?;
+                b.combine(&e, |a, b| float_bin_op(&BinOp::Pow, a, b))
+            }
+            ("sqrt", [x]) => {
+                let d = number_distribution(x, repeat)?;
+                if d.min() < 0.0 {
+                    return Err("sqrt function requires a non-negative argument.".to_string());
+                }
+                Ok(d.map(f64::sqrt))
+            }
+            ("clamp", [x, lo, hi]) => {
+                let d = number_distribution(x, repeat)?;
+                match (typecheck_expr(lo), typecheck_expr(hi)) {
+                    (
+                        Type::Number(NumberType::Constant(lo)),
+                        Type::Number(NumberType::Constant(hi)),
+                    ) => Ok(d.map(|v| v.clamp(lo.to_f64(), hi.to_f64()))),
+                    _ => Err(unsupported()),
+                }
+            }
+            ("floor" | "ceil" | "round" | "abs", [x]) => {
+                let d = number_distribution(x, repeat)?;
+                Ok(match func_name.as_str() {
+                    "floor" => d.map(f64::floor),
+                    "ceil" => d.map(f64::ceil),
+                    "round" => d.map(f64::round),
+                    _ => d.map(f64::abs),
+                })
+            }
+            ("max" | "min" | "sum", _) => {
+                // 只支持全部是数值的参数
+                let mut result: Option<Distribution> = None;
+                for arg in args {
+                    let d = number_distribution(arg, repeat)?;
+                    result = Some(match result {
+                        None => d,
+                        Some(acc) => acc.combine(&d, |a, b| {
+                            Ok(match func_name.as_str() {
+                                "max" => a.max(b),
+                                "min" => a.min(b),
+                                _ => a + b,
+                            })
+                        })?,
+                    });
+                }
+                result.ok_or_else(unsupported)
+            }
+            _ => Err(unsupported()),
+        },
+        // 取下标的结果依赖整个列表的联合分布，暂不支持
+        Expr::Index { .. } | Expr::Slice { .. } | Expr::Filter { .. } => Err(unsupported()),
+        Expr::Table(_) => Err("Statistics are not available for random tables.".to_string()),
+        Expr::Label { expr, .. } | Expr::Comment { expr, .. } => number_distribution(expr, repeat),
+        Expr::Number(_) | Expr::List(_) => unreachable!("Handled by constant folding"),
+        Expr::Var(_) => unreachable!("Variables are rejected by typecheck"),
+    }
+}
+
+// 每颗骰子的得分独立同分布，总成功数为得分之和
+fn success_distribution(
+    lhs: &Expr,
+    rule: &SuccessRule,
+    repeat: i64,
+) -> Result<Distribution, String> {
+    match pool_distribution(lhs)? {
+        PoolDistribution::Dice {
+            die,
+            count,
+            extra_dice: false,
+        } => die.map(|v| float_score(rule, v)).repeat_sum(count * repeat),
+        _ => Err(unsupported()),
+    }
+}
+
+// 骰组的结果：保留的子掷骰总值之和，rule 存在时为其中的成功数
+fn group_distribution(
+    expr: &Expr,
+    repeat: i64,
+    rule: Option<&SuccessRule>,
+) -> Result<Distribution, String> {
+    let outcomes = group_states(expr, repeat)?
+        .into_iter()
+        .map(|(totals, p)| {
+            let value = totals
+                .iter()
+                .map(|t| rule.map_or(*t, |r| float_score(r, *t)))
+                .sum();
+            (value, p)
+        })
+        .collect();
+    Ok(Distribution::from_outcomes(outcomes))
+}
+
+// 骰组中保留的子掷骰总值的联合分布，子掷骰之间相互独立，按笛卡尔积枚举
+fn group_states(expr: &Expr, repeat: i64) -> Result<Vec<(Vec<f64>, f64)>, String> {
+    use ModifierOp::*;
+    match expr {
+        Expr::Group(items) => {
+            let mut dists = Vec::new();
+            for item in items {
+                dists.push(number_distribution(item, repeat)?);
+            }
+            let combinations: f64 = dists.iter().map(|d| d.outcomes().len() as f64).product();
+            if combinations > MAX_POOL_COMBINATIONS {
+                return Err("Too many dice to compute statistics.".to_string());
+            }
+            let mut states = vec![(Vec::new(), 1.0)];
+            for d in &dists {
+                states = states
+                    .into_iter()
+                    .flat_map(|(totals, p): (Vec<f64>, f64)| {
+                        d.outcomes().iter().map(move |(v, pv)| {
+                            let mut totals = totals.clone();
+                            totals.push(*v);
+                            (totals, p * pv)
+                        })
+                    })
+                    .collect();
+            }
+            Ok(states)
+        }
+        Expr::Modifier { lhs, op, param } => {
+            let n = match param {
+                Some(ModifierParam::Value(v)) => constant_of(v) as usize,
+                _ => unreachable!("Keep / drop requires a value parameter"),
+            };
+            let states = group_states(lhs, repeat)?;
+            Ok(states
+                .into_iter()
+                .map(|(totals, p)| {
+                    let (keep, highest) = match op {
+                        KeepHigh => (n, true),
+                        KeepLow => (n, false),
+                        DropHigh => (totals.len() - n, false),
+                        DropLow => (totals.len() - n, true),
+                        _ => unreachable!("Only keep / drop modifiers apply to groups"),
+                    };
+                    (top_n_preserve_order(&totals, keep, highest), p)
+                })
+                .collect())
+        }
+        Expr::Label { expr, .. } => group_states(expr, repeat),
+        _ => unreachable!("Expected a group expression, got {:?}", expr),
+    }
+}
+
+fn dice_item_of(expr: &Expr) -> DiceItem {
+    match typecheck_expr(expr) {
+        Type::Number(NumberType::Variable(VariableNumber::DicePool(
+            DicePoolType::RawDicePool(item),
+        ))) => item,
+        t => unreachable!("Expected a dice pool, got {:?}", t),
+    }
+}
+
+fn pool_distribution(expr: &Expr) -> Result<PoolDistribution, String> {
+    use ModifierOp::*;
+    match expr {
+        Expr::Dice { .. } | Expr::FateDice { .. } | Expr::PercentileDice { .. } => {
+            let item = dice_item_of(expr);
+            Ok(PoolDistribution::Dice {
+                die: Distribution::die(&item.side)?,
+                count: item.min_count,
+                extra_dice: false,
+            })
+        }
+        Expr::Modifier {
+            lhs,
+            op: Limit,
+            param,
+        } => match lhs.without_labels() {
+            // 类型检查保证 l 只能跟在爆骰 (!、!!、!p) 之后，中间可以有标签
+            Expr::Modifier {
+                lhs: inner,
+                op: explode_op,
+                param: explode_param,
+            } => {
+                let limit = match param {
+                    Some(ModifierParam::Value(v)) => constant_of(v) as usize,
+                    _ => unreachable!("Limit modifier requires a value parameter"),
+                };
+                explode_pool(
+                    pool_distribution(inner)?,
+                    inner,
+                    explode_op,
+                    explode_param,
+                    limit,
+                )
+            }
+            _ => unreachable!("Limit modifier must follow an explosion"),
+        },
+        Expr::Modifier { lhs, op, param } => {
+            let pool = pool_distribution(lhs)?;
+            match op {
+                Explode | ExplodePenetrate | ExplodeCompound => {
+                    explode_pool(pool, lhs, op, param, MAX_EXPLOSIONS)
+                }
+                Reroll | RerollOnce => {
+                    let (cmp, target) = compare_param(param).unwrap();
+                    let matches = |v: f64| float_compare(&cmp, v, target);
+                    match pool {
+                        PoolDistribution::Dice {
+                            die,
+                            count,
+                            extra_dice: false,
+                        } => {
+                            let die = if *op == Reroll {
+                                reroll_distribution(&die, matches)?
+                            } else {
+                                reroll_once_distribution(&die, matches)
+                            };
+                            Ok(PoolDistribution::Dice {
+                                die,
+                                count,
+                                extra_dice: false,
+                            })
+                        }
+                        _ => Err(unsupported()),
+                    }
+                }
+                KeepHigh | KeepLow | DropHigh | DropLow => match pool {
+                    PoolDistribution::Dice {
+                        die,
+                        count,
+                        extra_dice: false,
+                    } => {
+                        let n = match param {
+                            Some(ModifierParam::Value(v)) => constant_of(v) as i64,
+                            _ => unreachable!("Keep / drop requires a value parameter"),
+                        };
+                        let (keep, highest) = match op {
+                            KeepHigh => (n, true),
+                            KeepLow => (n, false),
+                            DropHigh => (count - n, false),
+                            _ => (count - n, true),
+                        };
+                        keep_distribution(&die, count, keep, highest).map(PoolDistribution::Summed)
+                    }
+                    _ => Err(unsupported()),
+                },
+                SortAsc | SortDesc | CritSuccess | CritFailure => Ok(pool),
+                ClampMin | ClampMax => match pool {
+                    // ! 与 !p 追加的骰子各自受到限制，无法从单颗骰子的总值分布推出
+                    PoolDistribution::Dice {
+                        die,
+                        count,
+                        extra_dice: false,
+                    } => {
+                        let bound = match param {
+                            Some(ModifierParam::Value(v)) => constant_of(v),
+                            _ => unreachable!("Clamp requires a value parameter"),
+                        };
+                        let die = if *op == ClampMin {
+                            die.map(|v| v.max(bound))
+                        } else {
+                            die.map(|v| v.min(bound))
+                        };
+                        Ok(PoolDistribution::Dice {
+                            die,
+                            count,
+                            extra_dice: false,
+                        })
+                    }
+                    _ => Err(unsupported()),
+                },
+                // 重骰后的骰子互不独立
+                Unique => Err(unsupported()),
+                Count => unreachable!("Count is handled as a number"),
+                Limit => unreachable!("Limit is handled together with explosion"),
+            }
+        }
+        Expr::Label { expr, .. } => pool_distribution(expr),
+        _ => unreachable!("Expected a dice pool expression, got {:?}", expr),
+    }
+}
+
+// 爆骰后单颗骰子 (包含追加骰子) 的总值分布
+// ! 与 !p 会追加骰子，之后骰子数量不固定；!p 追加的骰子结果 -1
+fn explode_pool(
+    pool: PoolDistribution,
+    lhs: &Expr,
+    op: &ModifierOp,
+    param: &Option<ModifierParam>,
+    limit: usize,
+) -> Result<PoolDistribution, String> {
+    let extra_dice = *op != ModifierOp::ExplodeCompound;
+    let penalty = if *op == ModifierOp::ExplodePenetrate {
+        1.0
+    } else {
+        0.0
+    };
+    let (die, count) = match pool {
+        PoolDistribution::Dice {
+            die,
+            count,
+            extra_dice: false,
+        } => (die, count),
+        _ => return Err(unsupported()),
+    };
+    let item = base_dice_item(lhs);
+    let (cmp, target) =
+        compare_param(param).unwrap_or((CompareOp::Equal, item.side.max_face() as f64));
+    // 追加的骰子按原始骰面掷出
+    let fresh = Distribution::die(&item.side)?;
+    let mut outcomes = Vec::new();
+    // (已累计的值, 当前骰面, 概率, 已爆骰次数)
+    let mut stack: Vec<(f64, f64, f64, usize)> = die
+        .outcomes()
+        .iter()
+        .map(|(v, p)| (0.0, *v, *p, 0))
+        .collect();
+    while let Some((acc, face, p, depth)) = stack.pop() {
+        // 是否继续爆骰由掷出的骰面决定，穿透只影响计入的值
+        let total = acc + face - if depth > 0 { penalty } else { 0.0 };
+        if depth < limit && float_compare(&cmp, face, target) && p > MIN_PROBABILITY {
+            for (next, pn) in fresh.outcomes() {
+                stack.push((total, *next, p * pn, depth + 1));
+            }
+        } else {
+            outcomes.push((total, p));
+        }
+    }
+    Ok(PoolDistribution::Dice {
+        die: Distribution::from_outcomes(outcomes),
+        count,
+        extra_dice,
+    })
+}
+
+// 找到修饰符链最内层的骰子
+fn base_dice_item(expr: &Expr) -> DiceItem {
+    match expr {
+        Expr::Modifier { lhs, .. } => base_dice_item(lhs),
+        _ => dice_item_of(expr),
+    }
+}
+
+// 无限重骰：相当于排除满足条件的骰面后重新归一化
+fn reroll_distribution<F: Fn(f64) -> bool>(
+    die: &Distribution,
+    matches: F,
+) -> Result<Distribution, String> {
+    let remain = 1.0 - die.probability(&matches);
+    if remain <= 0.0 {
+        return Err("Every face would be rerolled.".to_string());
+    }
+    Ok(Distribution::from_outcomes(
+        die.outcomes()
+            .iter()
+            .filter(|(v, _)| !matches(*v))
+            .map(|(v, p)| (*v, p / remain))
+            .collect(),
+    ))
+}
+
+// 只重骰一次：满足条件时按原分布重新掷一次
+fn reroll_once_distribution<F: Fn(f64) -> bool>(die: &Distribution, matches: F) -> Distribution {
+    let p_match = die.probability(&matches);
+    let mut outcomes: Vec<(f64, f64)> = die
+        .outcomes()
+        .iter()
+        .filter(|(v, _)| !matches(*v))
+        .cloned()
+        .collect();
+    outcomes.extend(die.outcomes().iter().map(|(v, p)| (*v, p * p_match)));
+    Distribution::from_outcomes(outcomes)
+}
+
+fn binomial(n: i64, k: i64) -> f64 {
+    (0..k).fold(1.0, |acc, i| acc * (n - i) as f64 / (i + 1) as f64)
+}
+
+// 取最高/最低的 keep 颗骰子之和：枚举所有骰面多重集
+fn keep_distribution(
+    die: &Distribution,
+    count: i64,
+    keep: i64,
+    highest: bool,
+) -> Result<Distribution, String> {
+    let faces = die.outcomes();
+    if binomial(count + faces.len() as i64 - 1, count) > MAX_POOL_COMBINATIONS {
+        return Err("Too many dice to compute statistics.".to_string());
+    }
+    let mut outcomes = Vec::new();
+    let mut chosen = Vec::new();
+    enumerate_multisets(faces, 0, count, 1.0, &mut chosen, &mut |values, p| {
+        // values 按骰面从小到大排列
+        let kept: f64 = if highest {
+            values.iter().rev().take(keep as usize).sum()
+        } else {
+            values.iter().take(keep as usize).sum()
+        };
+        outcomes.push((kept, p));
+    });
+    Ok(Distribution::from_outcomes(outcomes))
+}
+
+// 枚举从 faces[start..] 中选出 remain 个 (可重复) 的所有组合，回调传入概率
+fn enumerate_multisets<F: FnMut(&[f64], f64)>(
+    faces: &[(f64, f64)],
+    start: usize,
+    remain: i64,
+    p: f64,
+    chosen: &mut Vec<f64>,
+    callback: &mut F,
+) {
+    if remain == 0 {
+        callback(chosen, p);
+        return;
+    }
+    if start == faces.len() {
+        return;
+    }
+    let (value, pv) = faces[start];
+    // 该骰面出现 k 次，剩余的骰子从更大的骰面中选出
+    // 逐个骰面乘上组合数，累计起来就是多重集的排列数
+    for k in (0..=remain).rev() {
+        let weight = binomial(remain, k) * pv.powi(k as i32);
+        for _ in 0..k {
+            chosen.push(value);
+        }
+        if weight > 0.0 {
+            enumerate_multisets(faces, start + 1, remain - k, p * weight, chosen, callback);
+        }
+        for _ in 0..k {
+            chosen.pop();
+        }
+    }
+}
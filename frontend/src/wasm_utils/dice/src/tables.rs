@@ -0,0 +1,329 @@
+use std::collections::HashMap;
+
+use serde::{Deserialize, Serialize};
+
+use crate::grammar::{Expr, parse_dice};
+use crate::number::Num;
+use crate::stats::distribution_of;
+use crate::typecheck::{Type, typecheck_expr};
+
+// 嵌套表的最大深度，定义时已经拒绝了环，这里兜底
+pub const MAX_TABLE_DEPTH: usize = 16;
+
+// ==========================================
+// 表定义 (从 JSON / TOML 载入)
+// ==========================================
+
+// 表项对应的掷骰范围: 单个数值 3，或闭区间 [1, 5]
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+#[serde(untagged)]
+pub enum EntryRange {
+    Single(i64),
+    Span(i64, i64),
+}
+
+// 表项: 范围与权重二选一 (同一张表中不能混用)，文本与表达式至少有一个
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Default)]
+pub struct TableEntry {
+    #[serde(default)]
+    pub range: Option<EntryRange>, // 驱动掷骰落在该范围时选中
+    #[serde(default)]
+    pub weight: Option<u32>, // 权重，省略时为 1
+    #[serde(default)]
+    pub text: Option<String>, // 展示的文本
+    #[serde(default)]
+    pub expr: Option<String>, // 选中后求值的表达式，可以嵌套 table("...")
+}
+
+// 随机表，如野魔法浪涌表
+// 使用范围时由 roll 驱动 (省略时为 1d最大值)，使用权重时固定为 1d权重总和
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct TableDef {
+    pub name: String,
+    #[serde(default)]
+    pub roll: Option<String>,
+    pub entries: Vec<TableEntry>,
+}
+
+// 表文件: { "tables": [...] } 或 TOML 的 [[tables]]
+#[derive(Deserialize)]
+struct TableFile {
+    tables: Vec<TableDef>,
+}
+
+// 解析后的表
+#[derive(Debug, Clone)]
+pub struct Table {
+    pub def: TableDef,
+    pub roll: Expr,           // 驱动掷骰
+    bounds: Vec<(i64, i64)>,  // 每一项对应的掷骰范围，权重按累计换算为范围
+    exprs: Vec<Option<Expr>>, // 每一项的表达式
+}
+
+impl Table {
+    // 驱动掷骰的结果对应的表项
+    pub fn lookup(&self, roll: Num) -> Option<usize> {
+        self.bounds
+            .iter()
+            .position(|(lo, hi)| Num::int(*lo) <= roll && roll <= Num::int(*hi))
+    }
+
+    pub fn entry_expr(&self, index: usize) -> Option<&Expr> {
+        self.exprs[index].as_ref()
+    }
+
+    fn compile(def: TableDef) -> Result<Self, String> {
+        let name = &def.name;
+        if name.is_empty() || name.contains('"') {
+            return Err(format!("Invalid table name: {:?}", name));
+        }
+        if def.entries.is_empty() {
+            return Err(format!("Table {} has no entries.", name));
+        }
+
+        let mut exprs = Vec::new();
+        for (i, entry) in def.entries.iter().enumerate() {
+            if entry.text.is_none() && entry.expr.is_none() {
+                return Err(format!(
+                    "Entry {} of table {} needs text or expr.",
+                    i + 1,
+                    name
+                ));
+            }
+            exprs.push(match &entry.expr {
+                Some(source) => Some(parse_number_expr(source, name)?),
+                None => None,
+            });
+        }
+
+        let ranged = def.entries.iter().filter(|e| e.range.is_some()).count();
+        let (roll, bounds) = if ranged == def.entries.len() {
+            if def.entries.iter().any(|e| e.weight.is_some()) {
+                return Err(format!("Table {} mixes ranges and weights.", name));
+            }
+            ranged_bounds(&def)?
+        } else if ranged == 0 {
+            weighted_bounds(&def)?
+        } else {
+            return Err(format!("Table {} mixes ranges and weights.", name));
+        };
+
+        Ok(Table {
+            def,
+            roll,
+            bounds,
+            exprs,
+        })
+    }
+
+    // 驱动掷骰与表项表达式中引用的其他表
+    fn references(&self) -> Vec<&str> {
+        let mut names = Vec::new();
+        collect_tables(&self.roll, &mut names);
+        for expr in self.exprs.iter().flatten() {
+            collect_tables(expr, &mut names);
+        }
+        names
+    }
+}
+
+// ==========================================
+// 表注册表
+// ==========================================
+
+#[derive(Debug, Clone, Default)]
+pub struct TableRegistry {
+    tables: HashMap<String, Table>,
+}
+
+impl TableRegistry {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn get(&self, name: &str) -> Option<&Table> {
+        self.tables.get(name)
+    }
+
+    // 所有表名，按名称排序
+    pub fn names(&self) -> Vec<&str> {
+        let mut names: Vec<&str> = self.tables.keys().map(|k| k.as_str()).collect();
+        names.sort();
+        names
+    }
+
+    pub fn load_json(&mut self, source: &str) -> Result<usize, String> {
+        let file: TableFile =
+            serde_json::from_str(source).map_err(|e| format!("JSON error: {}", e))?;
+        self.load_defs(file.tables)
+    }
+
+    pub fn load_toml(&mut self, source: &str) -> Result<usize, String> {
+        let file: TableFile = toml::from_str(source).map_err(|e| format!("TOML error: {}", e))?;
+        self.load_defs(file.tables)
+    }
+
+    // 载入多张表，全部合法才生效，同名的表会被覆盖，表之间可以互相引用
+    pub fn load_defs(&mut self, defs: Vec<TableDef>) -> Result<usize, String> {
+        let count = defs.len();
+        let mut registry = self.clone();
+        let mut names = Vec::new();
+        for def in defs {
+            let table = Table::compile(def)?;
+            names.push(table.def.name.clone());
+            registry.tables.insert(table.def.name.clone(), table);
+        }
+        for name in &names {
+            for reference in registry.tables[name].references() {
+                if !registry.tables.contains_key(reference) {
+                    return Err(format!("Unknown table '{}' in table {}.", reference, name));
+                }
+            }
+            if let Some(cycle) = registry.find_cycle(name, &mut vec![name.clone()]) {
+                return Err(format!("Recursive table: {}", cycle.join(" -> ")));
+            }
+        }
+        *self = registry;
+        Ok(count)
+    }
+
+    pub fn remove(&mut self, name: &str) -> bool {
+        self.tables.remove(name).is_some()
+    }
+
+    pub fn clear(&mut self) {
+        self.tables.clear();
+    }
+
+    // 检查表达式中引用的表都存在
+    pub fn check(&self, expr: &Expr) -> Result<(), String> {
+        let mut names = Vec::new();
+        collect_tables(expr, &mut names);
+        match names.iter().find(|n| !self.tables.contains_key(**n)) {
+            Some(name) => Err(format!("Unknown table: {}", name)),
+            None => Ok(()),
+        }
+    }
+
+    // 沿引用关系查找回到 target 的路径
+    fn find_cycle(&self, target: &str, path: &mut Vec<String>) -> Option<Vec<String>> {
+        let table = self.tables.get(path.last()?)?;
+        for reference in table.references() {
+            if reference == target {
+                let mut cycle = path.clone();
+                cycle.push(reference.to_string());
+                return Some(cycle);
+            }
+            if !path.iter().any(|p| p == reference) {
+                path.push(reference.to_string());
+                if let Some(cycle) = self.find_cycle(target, path) {
+                    return Some(cycle);
+                }
+                path.pop();
+            }
+        }
+        None
+    }
+}
+
+// ==========================================
+// 辅助处理函数
+// ==========================================
+
+// 表中的表达式 (驱动掷骰与表项) 必须是数值
+fn parse_number_expr(source: &str, table: &str) -> Result<Expr, String> {
+    let expr = parse_dice(source).map_err(|e| format!("Parse error in table {}: {}", table, e))?;
+    match typecheck_expr(&expr) {
+        Type::Number(_) => Ok(expr),
+        Type::Invalid(s) => Err(format!("In table {}: {}", table, s)),
+        Type::List(_) => Err(format!(
+            "In table {}: '{}' must be a number, not a list.",
+            table, source
+        )),
+    }
+}
+
+// 默认的驱动掷骰 1d面数，只有一个可能结果时为常数 1 (1d1 不是合法的骰子)
+fn die_roll(sides: i64, table: &str) -> Result<Expr, String> {
+    if sides == 1 {
+        return Ok(Expr::Number(Num::ONE));
+    }
+    parse_number_expr(&format!("1d{}", sides), table)
+}
+
+// 范围表: 范围不能重叠，驱动掷骰的每个可能结果都要有对应的表项
+fn ranged_bounds(def: &TableDef) -> Result<(Expr, Vec<(i64, i64)>), String> {
+    let name = &def.name;
+    let bounds: Vec<(i64, i64)> = def
+        .entries
+        .iter()
+        .map(|e| match e.range {
+            Some(EntryRange::Single(v)) => (v, v),
+            Some(EntryRange::Span(lo, hi)) => (lo, hi),
+            None => unreachable!("Every entry of a ranged table has a range"),
+        })
+        .collect();
+    if let Some((lo, hi)) = bounds.iter().find(|(lo, hi)| lo > hi) {
+        return Err(format!("Invalid range [{}, {}] in table {}.", lo, hi, name));
+    }
+    let mut sorted = bounds.clone();
+    sorted.sort();
+    if let Some(w) = sorted.windows(2).find(|w| w[0].1 >= w[1].0) {
+        return Err(format!(
+            "Ranges [{}, {}] and [{}, {}] overlap in table {}.",
+            w[0].0, w[0].1, w[1].0, w[1].1, name
+        ));
+    }
+
+    let roll = match &def.roll {
+        Some(source) => parse_number_expr(source, name)?,
+        None => die_roll(sorted.last().unwrap().1, name)?,
+    };
+    // 无法计算分布时 (如嵌套表) 只能在求值时报错
+    if let Ok(distribution) = distribution_of(&roll) {
+        for (value, _) in distribution.outcomes() {
+            if !bounds
+                .iter()
+                .any(|(lo, hi)| *lo as f64 <= *value && *value <= *hi as f64)
+            {
+                return Err(format!(
+                    "Roll result {} of table {} has no entry.",
+                    value, name
+                ));
+            }
+        }
+    }
+    Ok((roll, bounds))
+}
+
+// 权重表: 依次把权重换算为 1d总和 上的连续范围
+fn weighted_bounds(def: &TableDef) -> Result<(Expr, Vec<(i64, i64)>), String> {
+    let name = &def.name;
+    if def.roll.is_some() {
+        return Err(format!(
+            "Table {} uses weights, its roll is always 1d(total weight).",
+            name
+        ));
+    }
+    let mut bounds = Vec::new();
+    let mut total: i64 = 0;
+    for entry in &def.entries {
+        let weight = entry.weight.unwrap_or(1) as i64;
+        if weight == 0 {
+            return Err(format!("Weights in table {} must be positive.", name));
+        }
+        bounds.push((total + 1, total + weight));
+        total += weight;
+    }
+    Ok((die_roll(total, name)?, bounds))
+}
+
+// 收集表达式中引用的表名
+fn collect_tables<'a>(expr: &'a Expr, names: &mut Vec<&'a str>) {
+    if let Expr::Table(name) = expr {
+        names.push(name);
+    }
+    for child in expr.children() {
+        collect_tables(child, names);
+    }
+}
@@ -0,0 +1,309 @@
+use serde::{Deserialize, Serialize};
+use tsify::Tsify;
+
+use crate::grammar::{is_builtin_function, parse_dice};
+use crate::trace::Span;
+
+// ==========================================
+// 公式编辑器的词法高亮 (导出给前端)
+// ==========================================
+
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, Tsify, PartialEq)]
+#[tsify(into_wasm_abi)]
+pub enum TokenKind {
+    Number,    // 1, 1.5
+    Dice,      // 骰子操作符 d 与特殊骰面 F、%
+    Modifier,  // kh、r、!!、cnt、adv 等后缀修饰符
+    Function,  // 内置函数名与 filter、table
+    Macro,     // 自定义函数调用
+    Variable,  // 自定义函数体中的参数
+    Operator,  // 算术运算符与比较符
+    Bracket,   // 括号
+    Separator, // 逗号与切片中的冒号
+    String,    // 随机表名 "..."
+    Label,     // 标签 [fire]
+    Comment,   // 末尾的注释 # stealth
+    Error,     // 无法识别的字符、不配对的括号与解析出错的位置
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, Tsify, PartialEq)]
+#[tsify(into_wasm_abi)]
+pub struct Token {
+    pub kind: TokenKind,
+    pub text: String,
+    pub span: Span,          // 按 UTF-16 计算的位置
+    pub pair: Option<usize>, // 括号配对的另一个括号的下标，没有闭合时为 None
+}
+
+// 把输入切分为带分类的记号，输入不完整或有错误时也会尽量分类，用于语法高亮与括号配对
+// 空白不产生记号；解析失败时出错位置所在的记号标记为 Error
+pub fn tokenize(input: &str) -> Vec<Token> {
+    let mut lexer = Lexer {
+        input,
+        pos: 0,
+        state: State::Operand,
+        brackets: Vec::new(),
+        tokens: Vec::new(),
+    };
+    lexer.run();
+    let mut tokens = lexer.tokens;
+    for (open, _) in lexer.brackets {
+        tokens[open].kind = TokenKind::Error;
+    }
+    if let Err(e) = parse_dice(input) {
+        let pos = match e.location {
+            pest::error::InputLocation::Pos(p) => p,
+            pest::error::InputLocation::Span((p, _)) => p,
+        };
+        if let Some(token) = tokens.iter_mut().find(|t| t.start <= pos && pos < t.end) {
+            token.kind = TokenKind::Error;
+        }
+    }
+    tokens
+        .into_iter()
+        .map(|t| Token {
+            kind: t.kind,
+            text: input[t.start..t.end].to_string(),
+            span: Span {
+                start: input[..t.start].encode_utf16().count(),
+                end: input[..t.end].encode_utf16().count(),
+            },
+            pair: t.pair,
+        })
+        .collect()
+}
+
+// ==========================================
+// 辅助处理函数
+// ==========================================
+
+// 后缀修饰符的关键字，长的在前
+const MODIFIERS: [&str; 20] = [
+    "cnt", "adv", "dis", "kh", "kl", "dh", "dl", "ro", "mi", "ma", "sd", "sa", "cs", "cf", "ds",
+    "r", "s", "u", "l", "f",
+];
+
+// 当前位置期待的内容
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum State {
+    Operand, // 运算数的开头 (表达式开头、运算符与逗号之后)
+    Count,   // 运算数之后，可以跟 d 组成骰子，如 2d6 中的 2、(1d4)d6 中的 (1d4)
+    Side,    // d 之后的骰面
+    Postfix, // 骰面或修饰符之后，字母都是修饰符
+}
+
+struct RawToken {
+    kind: TokenKind,
+    start: usize,
+    end: usize,
+    pair: Option<usize>,
+}
+
+struct Lexer<'a> {
+    input: &'a str,
+    pos: usize,
+    state: State,
+    brackets: Vec<(usize, State)>, // 未闭合的括号的下标，以及闭合后恢复的状态
+    tokens: Vec<RawToken>,
+}
+
+impl Lexer<'_> {
+    fn run(&mut self) {
+        while let Some(c) = self.peek(0) {
+            if c.is_whitespace() {
+                self.pos += c.len_utf8();
+                continue;
+            }
+            match c {
+                '#' => self.push(TokenKind::Comment, self.input.len() - self.pos),
+                '0'..='9' => self.number(),
+                '"' => {
+                    let len = match self.input[self.pos + 1..].find('"') {
+                        Some(i) => i + 2,
+                        None => self.input.len() - self.pos,
+                    };
+                    self.push(TokenKind::String, len);
+                }
+                '(' | '[' | '{' => self.open(c),
+                ')' | ']' | '}' => self.close(c),
+                ',' | ':' => {
+                    self.push(TokenKind::Separator, 1);
+                    self.state = State::Operand;
+                }
+                '%' if self.state == State::Side => {
+                    self.push(TokenKind::Dice, 1);
+                    self.state = State::Postfix;
+                }
+                '+' | '-' | '*' | '/' | '%' | '^' => {
+                    let len = if self.input[self.pos..].starts_with("//") {
+                        2
+                    } else {
+                        1
+                    };
+                    self.push(TokenKind::Operator, len);
+                    self.state = State::Operand;
+                }
+                // 比较符是修饰符或 filter 的参数
+                '>' | '<' | '=' => {
+                    let len = if self.peek(1) == Some('=') && c != '=' {
+                        2
+                    } else {
+                        1
+                    };
+                    self.push(TokenKind::Operator, len);
+                    if self.state != State::Operand {
+                        self.state = State::Postfix;
+                    }
+                }
+                '!' => {
+                    let len = match self.peek(1) {
+                        Some('!' | 'p' | 'P') => 2,
+                        _ => 1,
+                    };
+                    self.push(TokenKind::Modifier, len);
+                    self.state = State::Postfix;
+                }
+                c if c.is_ascii_alphabetic() || c == '_' => self.word(),
+                c => self.push(TokenKind::Error, c.len_utf8()),
+            }
+        }
+    }
+
+    fn peek(&self, n: usize) -> Option<char> {
+        self.input[self.pos..].chars().nth(n)
+    }
+
+    fn push(&mut self, kind: TokenKind, len: usize) {
+        self.tokens.push(RawToken {
+            kind,
+            start: self.pos,
+            end: self.pos + len,
+            pair: None,
+        });
+        self.pos += len;
+    }
+
+    fn number(&mut self) {
+        let rest = &self.input[self.pos..];
+        let mut len = rest
+            .find(|c: char| !c.is_ascii_digit())
+            .unwrap_or(rest.len());
+        if rest[len..].starts_with('.') && rest[len + 1..].starts_with(|c: char| c.is_ascii_digit())
+        {
+            let fraction = &rest[len + 1..];
+            len += 1 + fraction
+                .find(|c: char| !c.is_ascii_digit())
+                .unwrap_or(fraction.len());
+        }
+        self.push(TokenKind::Number, len);
+        self.state = match self.state {
+            State::Side | State::Postfix => State::Postfix,
+            State::Operand | State::Count => State::Count,
+        };
+    }
+
+    // d 之后是骰面的开头 (或输入末尾，正在输入骰面) 时作为骰子操作符
+    // 以 df 开头的标识符同样会被解析为 Fate 骰
+    fn is_dice_op(&self) -> bool {
+        matches!(self.peek(0), Some('d' | 'D'))
+            && matches!(
+                self.peek(1),
+                Some('0'..='9' | '(' | '[' | '{' | '%' | 'f' | 'F') | None
+            )
+            && matches!(self.state, State::Operand | State::Count)
+    }
+
+    fn word(&mut self) {
+        if self.is_dice_op() {
+            self.push(TokenKind::Dice, 1);
+            self.state = State::Side;
+            return;
+        }
+        let rest = &self.input[self.pos..];
+        let len = rest
+            .find(|c: char| !c.is_ascii_alphanumeric() && c != '_')
+            .unwrap_or(rest.len());
+        match self.state {
+            State::Operand => {
+                let name = rest[..len].to_lowercase();
+                let is_call = rest[len..].trim_start().starts_with('(');
+                let kind = if !is_call {
+                    TokenKind::Variable
+                } else if is_builtin_function(&name) || name == "filter" || name == "table" {
+                    TokenKind::Function
+                } else {
+                    TokenKind::Macro
+                };
+                self.push(kind, len);
+                self.state = State::Count;
+            }
+            State::Side if matches!(self.peek(0), Some('f' | 'F')) => {
+                self.push(TokenKind::Dice, 1);
+                self.state = State::Postfix;
+            }
+            _ => {
+                let lower = rest.to_lowercase();
+                match MODIFIERS.iter().find(|m| lower.starts_with(*m)) {
+                    Some(m) => self.push(TokenKind::Modifier, m.len()),
+                    None => self.push(TokenKind::Error, len),
+                }
+                self.state = State::Postfix;
+            }
+        }
+    }
+
+    fn open(&mut self, c: char) {
+        // 运算数之后的 [...] 可能是标签，内容不是合法的下标或切片时按标签处理
+        if c == '['
+            && matches!(self.state, State::Count | State::Postfix)
+            && let Some(close) = self.input[self.pos..].find(']')
+        {
+            let content = self.input[self.pos + 1..self.pos + close].trim();
+            let is_ident = !content.is_empty()
+                && !content.starts_with(|c: char| c.is_ascii_digit())
+                && content
+                    .chars()
+                    .all(|c| c.is_ascii_alphanumeric() || c == '_');
+            if !content.contains(':') && (is_ident || parse_dice(content).is_err()) {
+                self.push(TokenKind::Label, close + 1);
+                self.state = State::Postfix;
+                return;
+            }
+        }
+        let restore = match self.state {
+            State::Operand => State::Count,
+            State::Count if c == '(' => State::Count,
+            _ => State::Postfix,
+        };
+        self.brackets.push((self.tokens.len(), restore));
+        self.push(TokenKind::Bracket, 1);
+        self.state = State::Operand;
+    }
+
+    fn close(&mut self, c: char) {
+        let expected = match c {
+            ')' => '(',
+            ']' => '[',
+            _ => '{',
+        };
+        let matched = self
+            .brackets
+            .last()
+            .copied()
+            .filter(|(open, _)| self.input[self.tokens[*open].start..].starts_with(expected));
+        let Some((open, restore)) = matched else {
+            self.push(TokenKind::Error, 1);
+            return;
+        };
+        self.brackets.pop();
+        self.tokens[open].pair = Some(self.tokens.len());
+        self.tokens.push(RawToken {
+            kind: TokenKind::Bracket,
+            start: self.pos,
+            end: self.pos + 1,
+            pair: Some(open),
+        });
+        self.pos += 1;
+        self.state = restore;
+    }
+}
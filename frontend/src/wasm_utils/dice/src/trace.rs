@@ -0,0 +1,108 @@
+use std::collections::HashMap;
+use std::convert::Infallible;
+use std::marker::PhantomData;
+
+use serde::{Deserialize, Serialize};
+use tsify::Tsify;
+
+use crate::eval::{DieRoll, RollValue};
+use crate::grammar::{Expr, SpanTree};
+use crate::number::Num;
+
+// ==========================================
+// 求值过程的记录 (导出给前端)
+// ==========================================
+
+// 子表达式在输入中的位置，按 UTF-16 编码计算，与 JS 字符串的下标一致
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, Tsify, PartialEq)]
+#[tsify(into_wasm_abi)]
+pub struct Span {
+    pub start: usize,
+    pub end: usize,
+}
+
+// 一个子表达式的求值记录，子节点与表达式树中单独求值的子表达式对应
+// 只作为父节点一部分求值的子表达式 (如 4d6dl1 中的 4d6、修饰符的参数) 没有单独的节点
+// 多次求值的子表达式 (如 [1d6] * 4 中的列表) 每次求值都有一个节点
+#[derive(Debug, Clone, Serialize, Deserialize, Tsify, PartialEq)]
+#[tsify(into_wasm_abi)]
+pub struct TraceNode {
+    pub notation: String,   // 子表达式，如 "4d6dl1"
+    pub span: Option<Span>, // 在输入中的位置，展开的函数体、随机表中的表达式等没有位置
+    pub value: RollValue,   // 子表达式的结果
+    pub dice: Vec<DieRoll>, // 子表达式中掷出的所有骰子
+    pub children: Vec<TraceNode>,
+}
+
+// 求值的表达式树中各节点在输入中的位置，求值时按节点查找
+// 按节点的地址记录，借用表达式树保证求值期间地址不变
+pub struct SourceSpans<'a> {
+    spans: HashMap<*const Expr, Span>,
+    expr: PhantomData<&'a Expr>,
+}
+
+impl<'a> SourceSpans<'a> {
+    // parsed 与 tree 为解析得到的表达式与位置 (见 parse_dice_with_spans)，expr 为实际求值的表达式
+    // expr 可能经过改写 (展开函数调用、加上优势/劣势)，改写过的节点取原来写法的位置，其中的子节点没有位置
+    pub fn new(input: &str, parsed: &Expr, tree: &SpanTree, expr: &'a Expr) -> Self {
+        let mut spans = SourceSpans::empty();
+        let offsets = utf16_offsets(input);
+        spans.collect(parsed, tree, expr, &offsets);
+        spans
+    }
+
+    // 没有任何位置，用于不需要标注位置的求值
+    pub fn empty() -> Self {
+        SourceSpans {
+            spans: HashMap::new(),
+            expr: PhantomData,
+        }
+    }
+
+    pub fn get(&self, expr: &Expr) -> Option<Span> {
+        self.spans.get(&(expr as *const Expr)).copied()
+    }
+
+    fn collect(&mut self, parsed: &Expr, tree: &SpanTree, expr: &'a Expr, offsets: &[usize]) {
+        if let Some((start, end)) = tree.span {
+            let span = Span {
+                start: offsets[start],
+                end: offsets[end],
+            };
+            self.spans.insert(expr, span);
+        }
+        if shallow(parsed) != shallow(expr) {
+            return;
+        }
+        for ((parsed, tree), expr) in parsed
+            .children()
+            .into_iter()
+            .zip(&tree.children)
+            .zip(expr.children())
+        {
+            self.collect(parsed, tree, expr, offsets);
+        }
+    }
+}
+
+// ==========================================
+// 辅助处理函数
+// ==========================================
+
+// 去掉子表达式后的节点本身，用于判断节点是否被改写
+fn shallow(expr: &Expr) -> Expr {
+    let Ok(expr) = expr.try_map_children(|_| Ok::<_, Infallible>(Expr::Number(Num::ZERO)));
+    expr
+}
+
+// 每个字节位置对应的 UTF-16 位置
+fn utf16_offsets(input: &str) -> Vec<usize> {
+    let mut offsets = vec![0; input.len() + 1];
+    let mut offset = 0;
+    for (i, c) in input.char_indices() {
+        offsets[i] = offset;
+        offset += c.len_utf16();
+    }
+    offsets[input.len()] = offset;
+    offsets
+}
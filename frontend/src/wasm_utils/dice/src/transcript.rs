@@ -0,0 +1,277 @@
+use serde::{Deserialize, Serialize};
+use tsify::Tsify;
+
+use crate::eval::{DieRoll, GroupRoll, RollGroup, RollOutput, RollValue, TableRoll};
+use crate::grammar::Expr;
+use crate::number::Num;
+
+// ==========================================
+// 掷骰记录的文本渲染
+// ==========================================
+
+// 掷骰记录的格式，用于提示框 (HTML) 与聊天记录 (Markdown / 纯文本)
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, Tsify, PartialEq)]
+#[tsify(into_wasm_abi, from_wasm_abi)]
+pub enum TranscriptFormat {
+    #[serde(rename = "plain")]
+    Plain,
+    #[serde(rename = "markdown")]
+    Markdown,
+    #[serde(rename = "html")]
+    Html,
+}
+
+// 把求值结果渲染为掷骰记录，每个骰池、骰组与随机表查询一行，最后一行为表达式的结果
+// 表达式只有一个骰池时合并为一行，如 4d6dl1 → [6, 5, ~~2~~, 3] = 14
+// 丢弃与被重骰替换的骰子加删除线，爆骰的骰子后加 !，大成功加粗、大失败用斜体 (纯文本不标记)
+pub fn render_transcript(expr: &Expr, output: &RollOutput, format: TranscriptFormat) -> String {
+    let renderer = Renderer { format };
+    // 注释放在最后一行的末尾，不作为表达式的一部分
+    let notation = match expr {
+        Expr::Comment { expr: inner, .. } => inner.to_string(),
+        _ => expr.to_string(),
+    };
+    let mut lines: Vec<String> = match output.groups.as_slice() {
+        [only]
+            if pool_notation(only) == notation
+                && output.group_rolls.is_empty()
+                && output.tables.is_empty() =>
+        {
+            vec![renderer.pool(only)]
+        }
+        _ => {
+            let mut lines: Vec<String> = output.groups.iter().map(|g| renderer.pool(g)).collect();
+            lines.extend(output.group_rolls.iter().map(|g| renderer.group(g)));
+            lines.extend(output.tables.iter().map(|t| renderer.table(t)));
+            lines.push(format!(
+                "{} = {}",
+                renderer.notation(&notation),
+                renderer.total(&output.result)
+            ));
+            lines
+        }
+    };
+    if let (Some(comment), Some(last)) = (&output.comment, lines.last_mut()) {
+        last.push_str(&renderer.comment(comment));
+    }
+    renderer.join_lines(lines)
+}
+
+// ==========================================
+// 辅助处理函数
+// ==========================================
+
+struct Renderer {
+    format: TranscriptFormat,
+}
+
+impl Renderer {
+    // 骰池，如 4d6dl1 → [6, 5, ~~2~~, 3] = 14
+    fn pool(&self, group: &RollGroup) -> String {
+        let dice = group
+            .dice
+            .iter()
+            .map(|die| self.die(die))
+            .collect::<Vec<_>>()
+            .join(", ");
+        format!(
+            "{} → [{}] = {}",
+            self.notation(&pool_notation(group)),
+            dice,
+            self.total(&RollValue::Number(group.value))
+        )
+    }
+
+    // 骰组，子掷骰只显示总值，如 {1d20 + 5, 1d20 + 3}kh1 → [18, ~~12~~] = 18
+    fn group(&self, group: &GroupRoll) -> String {
+        let items = group
+            .items
+            .iter()
+            .map(|item| {
+                let text = self.escape(&item.total.to_string());
+                if item.dropped {
+                    self.strike(text)
+                } else {
+                    text
+                }
+            })
+            .collect::<Vec<_>>()
+            .join(", ");
+        format!(
+            "{} → [{}] = {}",
+            self.notation(&group.notation),
+            items,
+            self.total(&RollValue::Number(group.value))
+        )
+    }
+
+    // 随机表查询，如 table("surge") → 37: Fireball，表项有表达式时附上其结果
+    fn table(&self, table: &TableRoll) -> String {
+        let mut line = format!(
+            "{} → {}",
+            self.notation(&format!("table(\"{}\")", table.table)),
+            self.escape(&table.roll.to_string())
+        );
+        if let Some(text) = &table.text {
+            line.push_str(&format!(": {}", self.escape(text)));
+        }
+        if table.value != table.roll {
+            line.push_str(&format!(
+                " = {}",
+                self.total(&RollValue::Number(table.value))
+            ));
+        }
+        line
+    }
+
+    fn die(&self, die: &DieRoll) -> String {
+        // 复合爆骰显示每次追加的骰面，如 6!+6!+2
+        let mut text = if die.compound.is_empty() {
+            let mut text = die.value.to_string();
+            if die.exploded {
+                text.push('!');
+            }
+            text
+        } else {
+            let faces: Vec<i64> = std::iter::once(die.face)
+                .chain(die.compound.iter().copied())
+                .collect();
+            faces
+                .iter()
+                .enumerate()
+                .map(|(i, face)| {
+                    if i + 1 < faces.len() {
+                        format!("{}!", face)
+                    } else {
+                        face.to_string()
+                    }
+                })
+                .collect::<Vec<_>>()
+                .join("+")
+        };
+        text = self.escape(&text);
+        if die.critical_success {
+            text = self.emphasize(text, "critical-success", "**", "strong");
+        } else if die.critical_failure {
+            text = self.emphasize(text, "critical-failure", "*", "em");
+        }
+        if die.dropped || die.rerolled {
+            text = self.strike(text);
+        }
+        match self.format {
+            TranscriptFormat::Html => {
+                let mut class = String::from("die");
+                for (flag, name) in [
+                    (die.dropped, " dropped"),
+                    (die.rerolled, " rerolled"),
+                    (die.exploded, " exploded"),
+                ] {
+                    if flag {
+                        class.push_str(name);
+                    }
+                }
+                format!("<span class=\"{}\">{}</span>", class, text)
+            }
+            _ => text,
+        }
+    }
+
+    fn emphasize(&self, text: String, class: &str, markdown: &str, tag: &str) -> String {
+        match self.format {
+            TranscriptFormat::Plain => text,
+            TranscriptFormat::Markdown => format!("{}{}{}", markdown, text, markdown),
+            TranscriptFormat::Html => format!("<{} class=\"{}\">{}</{}>", tag, class, text, tag),
+        }
+    }
+
+    // 纯文本没有删除线，用括号标记不计入结果的值
+    fn strike(&self, text: String) -> String {
+        match self.format {
+            TranscriptFormat::Plain => format!("({})", text),
+            TranscriptFormat::Markdown => format!("~~{}~~", text),
+            TranscriptFormat::Html => format!("<s>{}</s>", text),
+        }
+    }
+
+    fn notation(&self, notation: &str) -> String {
+        match self.format {
+            TranscriptFormat::Html => format!(
+                "<span class=\"dice-notation\">{}</span>",
+                self.escape(notation)
+            ),
+            _ => self.escape(notation),
+        }
+    }
+
+    fn total(&self, value: &RollValue) -> String {
+        let text = self.escape(&value_text(value));
+        match self.format {
+            TranscriptFormat::Html => format!("<span class=\"dice-total\">{}</span>", text),
+            _ => text,
+        }
+    }
+
+    fn comment(&self, comment: &str) -> String {
+        let text = self.escape(comment);
+        match self.format {
+            TranscriptFormat::Plain => format!(" # {}", text),
+            TranscriptFormat::Markdown => format!(" *{}*", text),
+            TranscriptFormat::Html => format!(" <span class=\"dice-comment\">{}</span>", text),
+        }
+    }
+
+    // Markdown 行末加两个空格强制换行
+    fn join_lines(&self, lines: Vec<String>) -> String {
+        match self.format {
+            TranscriptFormat::Plain => lines.join("\n"),
+            TranscriptFormat::Markdown => lines.join("  \n"),
+            TranscriptFormat::Html => format!(
+                "<div class=\"dice-transcript\">{}</div>",
+                lines.join("<br>")
+            ),
+        }
+    }
+
+    fn escape(&self, text: &str) -> String {
+        match self.format {
+            TranscriptFormat::Plain => text.to_string(),
+            TranscriptFormat::Markdown => text
+                .chars()
+                .flat_map(|c| match c {
+                    '\\' | '`' | '*' | '_' | '~' | '[' | ']' | '<' | '>' | '#' | '|' => {
+                        vec!['\\', c]
+                    }
+                    _ => vec![c],
+                })
+                .collect(),
+            TranscriptFormat::Html => text
+                .replace('&', "&amp;")
+                .replace('<', "&lt;")
+                .replace('>', "&gt;")
+                .replace('"', "&quot;")
+                .replace('\'', "&#39;"),
+        }
+    }
+}
+
+// 骰池的表达式，带标签时附上标签，如 2d6 [fire]
+fn pool_notation(group: &RollGroup) -> String {
+    match &group.label {
+        Some(label) => format!("{} [{}]", group.notation, label),
+        None => group.notation.clone(),
+    }
+}
+
+fn value_text(value: &RollValue) -> String {
+    match value {
+        RollValue::Number(n) => n.to_string(),
+        RollValue::List(l) => format!(
+            "[{}]",
+            l.iter().map(Num::to_string).collect::<Vec<_>>().join(", ")
+        ),
+        RollValue::Nested(items) => format!(
+            "[{}]",
+            items.iter().map(value_text).collect::<Vec<_>>().join(", ")
+        ),
+    }
+}
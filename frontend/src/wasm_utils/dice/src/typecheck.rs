@@ -1,42 +1,71 @@
+use serde::{Deserialize, Serialize};
+use tsify::Tsify;
+
+use crate::eval::compare;
 use crate::grammar::CompareExpr;
+use crate::number::Num;
+
+use super::grammar::{BinOp, Expr, ModifierOp, ModifierParam, is_builtin_function};
 
-use super::grammar::{BinOp, Expr, ModifierOp, ModifierParam};
+// 单个骰池最多的骰子数量，列表重复与 rpdice 最多的重复次数，以及重复后列表的最大长度
+// 防止 1000000000d6、[1d6] * 100000000 这类表达式在求值时耗尽内存
+pub const MAX_DICE: i64 = 1000;
+pub const MAX_REPEAT: i64 = 100;
+pub const MAX_LIST_LEN: i64 = 1000;
 
 // ==========================================
 // 类型定义
 // ==========================================
 
-#[derive(Clone, PartialEq, Debug)]
+#[derive(Clone, PartialEq, Debug, Serialize, Deserialize, Tsify)]
+#[tsify(into_wasm_abi)]
+pub enum DiceSide {
+    Number(i64),      // 普通骰子 dN，骰面为 1..=N
+    Fate,             // Fate/Fudge 骰 dF，骰面为 -1、0、+1
+    Percentile,       // 百分骰 d%，骰面为 1..=100
+    Custom(Vec<i64>), // 自定义骰面 d{1,1,2}，按书写顺序保存，允许重复
+}
+
+#[derive(Clone, PartialEq, Debug, Serialize, Deserialize, Tsify)]
+#[tsify(into_wasm_abi)]
 pub struct DiceItem {
     pub min_count: i64, // 最小值，因为explode可能会导致这个值的增长
-    pub side: i64,      // 骰子面数，一般是不会改变的
+    pub side: DiceSide, // 骰子面，一般是不会改变的
 }
 
-#[derive(Clone, PartialEq, Debug)]
+#[derive(Clone, PartialEq, Debug, Serialize, Deserialize, Tsify)]
+#[tsify(into_wasm_abi)]
 pub enum DicePoolType {
-    RawDicePool(DiceItem), // 原始的骰池，除了ExplodeCompound外，生成的骰池子均为此类型
-    LimitableDicePool(DiceItem), // 可限制爆骰的骰池，只有通过ExplodeCompound生成
+    RawDicePool(DiceItem),       // 原始的骰池，除了爆骰外，生成的骰池子均为此类型
+    LimitableDicePool(DiceItem), // 可限制爆骰次数的骰池，只有通过 !、!!、!p 生成
 }
 
-#[derive(Clone, PartialEq, Debug)]
+#[derive(Clone, PartialEq, Debug, Serialize, Deserialize, Tsify)]
+#[tsify(into_wasm_abi)]
 pub enum VariableNumber {
     Unknown,                // 未知的变量数值
     DicePool(DicePoolType), // 来自骰池的变量数值
+    Group(i64),             // 骰组 {a, b}，记录参与计算的子掷骰数量
 }
 
-#[derive(Clone, PartialEq, Debug)]
+#[derive(Clone, PartialEq, Debug, Serialize, Deserialize, Tsify)]
+#[tsify(into_wasm_abi)]
 pub enum NumberType {
-    Constant(f64),            // 常数数值
+    Constant(Num),            // 常数数值
     Variable(VariableNumber), // 变量数值
 }
 
-#[derive(Clone, PartialEq, Debug)]
+#[derive(Clone, PartialEq, Debug, Serialize, Deserialize, Tsify)]
+#[tsify(into_wasm_abi)]
 pub enum ListType {
-    ConstantList(Vec<f64>), // 常数列表
-    VariableList(i64),      // 变量列表，记录长度
+    ConstantList(Vec<Num>),    // 常数列表
+    VariableList(i64),         // 变量列表，记录长度
+    BoundedList(i64),          // 长度不确定的变量列表 (如 filter 的结果)，记录最大长度
+    NestedList(Vec<ListType>), // 嵌套列表，记录每个元素的类型
 }
 
-#[derive(Clone, PartialEq, Debug)]
+#[derive(Clone, PartialEq, Debug, Serialize, Deserialize, Tsify)]
+#[tsify(into_wasm_abi)]
 pub enum Type {
     Invalid(String),    // 无效类型，携带错误信息
     Number(NumberType), // 数值类型
@@ -48,8 +77,8 @@ pub enum Type {
 // ==========================================
 
 impl Type {
-    pub fn constant(val: f64) -> Self {
-        Type::Number(NumberType::Constant(val))
+    pub fn constant(val: impl Into<Num>) -> Self {
+        Type::Number(NumberType::Constant(val.into()))
     }
 
     pub fn unknown_var() -> Self {
@@ -68,13 +97,111 @@ impl Type {
         )))
     }
 
-    pub fn const_list(list: Vec<f64>) -> Self {
-        Type::List(ListType::ConstantList(list))
+    pub fn group(len: i64) -> Self {
+        Type::Number(NumberType::Variable(VariableNumber::Group(len)))
+    }
+
+    pub fn const_list<T: Into<Num>>(list: Vec<T>) -> Self {
+        Type::List(ListType::ConstantList(
+            list.into_iter().map(Into::into).collect(),
+        ))
     }
 
     pub fn var_list(len: i64) -> Self {
         Type::List(ListType::VariableList(len))
     }
+
+    pub fn bounded_list(max_len: i64) -> Self {
+        Type::List(ListType::BoundedList(max_len))
+    }
+
+    pub fn nested_list(items: Vec<ListType>) -> Self {
+        Type::List(ListType::NestedList(items))
+    }
+}
+
+impl ListType {
+    // 列表的长度，BoundedList 只能确定最大长度
+    pub fn max_len(&self) -> i64 {
+        match self {
+            ListType::ConstantList(lst) => lst.len() as i64,
+            ListType::VariableList(len) | ListType::BoundedList(len) => *len,
+            ListType::NestedList(items) => items.len() as i64,
+        }
+    }
+
+    pub fn is_nested(&self) -> bool {
+        matches!(self, ListType::NestedList(_))
+    }
+
+    // 多个列表类型的公共类型，用于排序、取最大值等会打乱元素位置的操作
+    // 形状不兼容 (平铺列表与嵌套列表混合) 时返回 None
+    pub fn common(types: &[ListType]) -> Option<ListType> {
+        use ListType::*;
+        let first = types.first()?;
+        if types.iter().all(|t| t == first) {
+            return Some(first.clone());
+        }
+        if types.iter().any(|t| t.is_nested()) {
+            return None;
+        }
+        let max_len = types.iter().map(|t| t.max_len()).max().unwrap_or(0);
+        let same_len = types
+            .iter()
+            .all(|t| !matches!(t, BoundedList(_)) && t.max_len() == max_len);
+        if same_len {
+            Some(VariableList(max_len))
+        } else {
+            Some(BoundedList(max_len))
+        }
+    }
+}
+
+impl DiceSide {
+    // 骰子的所有骰面，按从小到大排列
+    pub fn faces(&self) -> Vec<i64> {
+        match self {
+            DiceSide::Number(n) => (1..=*n).collect(),
+            DiceSide::Fate => vec![-1, 0, 1],
+            DiceSide::Percentile => (1..=100).collect(),
+            DiceSide::Custom(faces) => {
+                let mut sorted = faces.clone();
+                sorted.sort();
+                sorted
+            }
+        }
+    }
+
+    // 不同骰面的数量
+    pub fn distinct_faces(&self) -> i64 {
+        match self {
+            DiceSide::Number(n) => *n,
+            DiceSide::Fate => 3,
+            DiceSide::Percentile => 100,
+            DiceSide::Custom(_) => {
+                let mut faces = self.faces();
+                faces.dedup();
+                faces.len() as i64
+            }
+        }
+    }
+
+    pub fn min_face(&self) -> i64 {
+        match self {
+            DiceSide::Number(_) | DiceSide::Percentile => 1,
+            DiceSide::Fate => -1,
+            DiceSide::Custom(faces) => faces.iter().copied().min().unwrap_or(0),
+        }
+    }
+
+    pub fn max_face(&self) -> i64 {
+        match self {
+            DiceSide::Number(n) => *n,
+            DiceSide::Fate => 1,
+            DiceSide::Percentile => 100,
+            DiceSide::Custom(faces) => faces.iter().copied().max().unwrap_or(0),
+        }
+    }
 }
 
 // ==========================================
@@ -85,11 +212,28 @@ pub fn typecheck_expr(expr: &Expr) -> Type {
     match expr {
         Expr::Number(x) => Type::constant(*x),
         Expr::Dice { count, side } => type_of_dice(count, side),
+        Expr::FateDice { count } => type_of_special_dice(count, DiceSide::Fate),
+        Expr::PercentileDice { count } => type_of_special_dice(count, DiceSide::Percentile),
         Expr::Binary { lhs, op, rhs } => type_of_binary_op(lhs, op, rhs),
         Expr::Call { func_name, args } => type_of_call(func_name, args),
+        // 变量只能出现在自定义函数体中，调用前会被展开
+        Expr::Var(name) => Type::Invalid(format!("Unknown variable: {}", name)),
+        // 表项可能是文本或表达式，结果只知道是数值，表是否存在由注册表检查
+        Expr::Table(_) => Type::unknown_var(),
+        // 标签与注释只用于展示
+        Expr::Label { expr, .. } | Expr::Comment { expr, .. } => typecheck_expr(expr),
         Expr::List(args) => type_of_list(args),
+        Expr::Group(items) => type_of_group(items),
         Expr::Modifier { lhs, op, param } => type_of_modifier(lhs, op, param),
-        Expr::SuccessCheck { lhs, compare_expr } => type_of_success_check(lhs, compare_expr),
+        Expr::Index { list, index } => type_of_index(list, index),
+        Expr::Slice { list, start, end } => type_of_slice(list, start, end),
+        Expr::Filter { list, compare_expr } => type_of_filter(list, compare_expr),
+        Expr::SuccessCheck {
+            lhs,
+            compare_expr,
+            failure,
+            double_success,
+        } => type_of_success_check(lhs, compare_expr, failure, double_success),
     }
 }
 
@@ -97,9 +241,43 @@ pub fn typecheck_expr(expr: &Expr) -> Type {
 // 辅助处理函数
 // ==========================================
 
-// 判断一个浮点数是否为整数
-pub fn is_integer(num: f64) -> bool {
-    num.fract() == 0.0
+// 判断一个数值是否为整数
+pub fn is_integer(num: Num) -> bool {
+    num.is_integer()
+}
+
+// 常数运算的结果，溢出等错误时为无效类型
+fn constant_or_invalid(result: Result<Num, String>) -> Type {
+    match result {
+        Ok(c) => Type::constant(c),
+        Err(s) => Type::Invalid(s),
+    }
+}
+
+// 骰组 (或取高/取低后的骰组) 类型的表达式
+pub fn is_group(expr: &Expr) -> bool {
+    matches!(
+        typecheck_expr(expr),
+        Type::Number(NumberType::Variable(VariableNumber::Group(_)))
+    )
+}
+
+// 列表下标，负数表示从末尾开始计数，越界时返回 None
+pub fn resolve_index(index: i64, len: i64) -> Option<usize> {
+    let index = if index < 0 { index + len } else { index };
+    if (0..len).contains(&index) {
+        Some(index as usize)
+    } else {
+        None
+    }
+}
+
+// 列表切片的范围 [start, end)，规则与 Python 相同：负数从末尾计数，越界时截断
+pub fn resolve_slice(start: Option<i64>, end: Option<i64>, len: i64) -> (usize, usize) {
+    let clip = |i: i64| (if i < 0 { i + len } else { i }).clamp(0, len) as usize;
+    let start = start.map_or(0, clip);
+    let end = end.map_or(len as usize, clip);
+    (start, end.max(start))
 }
 
 // 从切片中选出前 n 个最大值或最小值，并按原顺序返回
@@ -142,7 +320,22 @@ pub fn top_n_preserve_order<T: Clone + PartialOrd>(
     top_n.into_iter().map(|(_, v)| v.clone()).collect()
 }
 
+// 中位数，偶数个元素时取中间两个的平均值
+pub fn median(values: &[Num]) -> Result<Num, String> {
+    let mut sorted = values.to_vec();
+    sorted.sort_by(Num::total_cmp);
+    let mid = sorted.len() / 2;
+    if sorted.len().is_multiple_of(2) {
+        sorted[mid - 1]
+            .checked_add(sorted[mid])?
+            .checked_div(Num::int(2))
+    } else {
+        Ok(sorted[mid])
+    }
+}
+
 fn type_of_dice(count: &Expr, side: &Expr) -> Type {
+    use ListType::*;
     use NumberType::*;
     use Type::*;
 
@@ -153,10 +346,12 @@ fn type_of_dice(count: &Expr, side: &Expr) -> Type {
         (_, Invalid(s)) => Invalid(s),
         // 两边必须都是常数
         (Number(Constant(c)), Number(Constant(s))) => {
-            if is_integer(c) && is_integer(s) && c > 0.0 && s >= 2.0 {
+            if c > Num::int(MAX_DICE) {
+                too_many_dice(c)
+            } else if is_integer(c) && is_integer(s) && c > Num::ZERO && s >= Num::int(2) {
                 let dice_item = DiceItem {
-                    min_count: c as i64,
-                    side: s as i64,
+                    min_count: c.to_i64(),
+                    side: DiceSide::Number(s.to_i64()),
                 };
                 Type::raw_dice_pool(dice_item)
             } else {
@@ -166,6 +361,24 @@ fn type_of_dice(count: &Expr, side: &Expr) -> Type {
                 ))
             }
         }
+        // 自定义骰面：常整数列表，至少两个骰面
+        (Number(Constant(c)), List(ConstantList(faces))) => {
+            if !is_integer(c) || c <= Num::ZERO {
+                Invalid(format!("Invalid dice parameters: count = {}", c))
+            } else if c > Num::int(MAX_DICE) {
+                too_many_dice(c)
+            } else if faces.len() < 2 {
+                Invalid("Custom dice require at least two faces.".to_string())
+            } else if !faces.iter().all(|f| is_integer(*f)) {
+                Invalid("Custom dice faces must be integers.".to_string())
+            } else {
+                Type::raw_dice_pool(DiceItem {
+                    min_count: c.to_i64(),
+                    side: DiceSide::Custom(faces.iter().map(|f| f.to_i64()).collect()),
+                })
+            }
+        }
+        (_, List(_)) => Invalid("Custom dice faces must be constant numbers.".to_string()),
         // 针对变量的特殊警告
         (Number(Variable(_)), _) | (_, Number(Variable(_))) => {
             Invalid("Dice count and side must be constant numbers.".to_string())
@@ -174,13 +387,47 @@ fn type_of_dice(count: &Expr, side: &Expr) -> Type {
     }
 }
 
+fn too_many_dice(count: Num) -> Type {
+    Type::Invalid(format!(
+        "Too many dice: count = {}, the limit is {}.",
+        count, MAX_DICE
+    ))
+}
+
+// dF、d% 等骰面固定的骰子，只需要检查数量
+fn type_of_special_dice(count: &Expr, side: DiceSide) -> Type {
+    use NumberType::*;
+    use Type::*;
+
+    match typecheck_expr(count) {
+        Invalid(s) => Invalid(s),
+        Number(Constant(c)) => {
+            if c > Num::int(MAX_DICE) {
+                too_many_dice(c)
+            } else if is_integer(c) && c > Num::ZERO {
+                Type::raw_dice_pool(DiceItem {
+                    min_count: c.to_i64(),
+                    side,
+                })
+            } else {
+                Invalid(format!("Invalid dice parameters: count = {}", c))
+            }
+        }
+        Number(Variable(_)) => Invalid("Dice count must be a constant number.".to_string()),
+        List(_) => Invalid("Dice count must be a number.".to_string()),
+    }
+}
+
 fn type_of_binary_op(lhs: &Expr, op: &BinOp, rhs: &Expr) -> Type {
+    binary_op_type(typecheck_expr(lhs), op, typecheck_expr(rhs))
+}
+
+// 由两侧子表达式的类型推出二元运算的类型，供已知子节点类型的调用方 (如化简) 使用
+pub fn binary_op_type(lhs_type: Type, op: &BinOp, rhs_type: Type) -> Type {
     use ListType::*;
     use NumberType::*;
     use Type::*;
 
-    let lhs_type = typecheck_expr(lhs);
-    let rhs_type = typecheck_expr(rhs);
     match (lhs_type, rhs_type) {
         (Invalid(s), _) => Invalid(s),
         (_, Invalid(s)) => Invalid(s),
@@ -190,30 +437,30 @@ fn type_of_binary_op(lhs: &Expr, op: &BinOp, rhs: &Expr) -> Type {
                 (Constant(lc), Constant(rc)) => {
                     // 常数与常数之间的操作，结果仍为常数
                     match op {
-                        BinOp::Add => Type::constant(lc + rc),
-                        BinOp::Sub => Type::constant(lc - rc),
-                        BinOp::Mul => Type::constant(lc * rc),
+                        BinOp::Add => constant_or_invalid(lc.checked_add(rc)),
+                        BinOp::Sub => constant_or_invalid(lc.checked_sub(rc)),
+                        BinOp::Mul => constant_or_invalid(lc.checked_mul(rc)),
                         BinOp::Div => {
-                            if rc != 0.0 {
-                                Type::constant(lc / rc)
+                            if !rc.is_zero() {
+                                constant_or_invalid(lc.checked_div(rc))
                             } else {
                                 Invalid("Division by zero.".to_string())
                             }
                         }
                         BinOp::Mod => {
-                            if rc == 0.0 {
+                            if rc.is_zero() {
                                 Invalid("Modulo by zero.".to_string())
                             } else if is_integer(lc) && is_integer(rc) {
-                                Type::constant((lc as i64 % rc as i64) as f64)
+                                constant_or_invalid(lc.checked_rem(rc))
                             } else {
                                 Invalid("Modulo operator requires integer operands.".to_string())
                             }
                         }
                         BinOp::Idiv => {
-                            if rc == 0.0 {
+                            if rc.is_zero() {
                                 Invalid("Integer division by zero.".to_string())
                             } else if is_integer(lc) && is_integer(rc) {
-                                Type::constant((lc as i64 / rc as i64) as f64)
+                                constant_or_invalid(lc.idiv(rc))
                             } else {
                                 Invalid(
                                     "Integer division operator requires integer operands."
@@ -221,11 +468,15 @@ fn type_of_binary_op(lhs: &Expr, op: &BinOp, rhs: &Expr) -> Type {
                                 )
                             }
                         }
+                        // 0 的负数次方、负数的小数次方等没有实数结果
+                        BinOp::Pow => constant_or_invalid(lc.pow(rc)),
                     }
                 }
                 (_, Constant(rc)) => {
                     // 检查除零和整数要求
-                    if (op == &BinOp::Div || op == &BinOp::Mod || op == &BinOp::Idiv) && rc == 0.0 {
+                    if (op == &BinOp::Div || op == &BinOp::Mod || op == &BinOp::Idiv)
+                        && rc.is_zero()
+                    {
                         Invalid("Division or modulo by zero.".to_string())
                     } else if (op == &BinOp::Mod || op == &BinOp::Idiv) && !is_integer(rc) {
                         Invalid(
@@ -242,23 +493,42 @@ fn type_of_binary_op(lhs: &Expr, op: &BinOp, rhs: &Expr) -> Type {
         }
         // 列表与常数标量之间的操作
         (List(l), Number(Constant(c))) | (Number(Constant(c)), List(l)) => {
-            if !is_integer(c) || c < 0.0 {
+            if !is_integer(c) || c < Num::ZERO {
                 Invalid("List operations require non-negative integer constants.".to_string())
             } else if *op != BinOp::Mul {
                 Invalid("Only multiplication is allowed between list and constant.".to_string())
+            } else if c > Num::int(MAX_REPEAT) {
+                Invalid(format!(
+                    "List repeat count {} exceeds the limit of {}.",
+                    c, MAX_REPEAT
+                ))
+            } else if l.max_len() * c.to_i64() > MAX_LIST_LEN {
+                Invalid(format!(
+                    "List is too long: the limit is {} items.",
+                    MAX_LIST_LEN
+                ))
             } else {
                 match l {
                     ConstantList(lst) => {
                         // 列表与常数相乘，结果为常数列表
-                        let mut new_list = Vec::new();
-                        for _ in 0..(c as i64) {
+                        let mut new_list: Vec<Num> = Vec::new();
+                        for _ in 0..(c.to_i64()) {
                             new_list.extend(lst.iter());
                         }
                         Type::const_list(new_list)
                     }
                     VariableList(len) => {
                         // 列表与常数相乘，结果为变量列表，长度为原长度乘以常数
-                        Type::var_list(len * (c as i64))
+                        Type::var_list(len * (c.to_i64()))
+                    }
+                    BoundedList(len) => Type::bounded_list(len * (c.to_i64())),
+                    NestedList(items) => {
+                        // 嵌套列表与常数相乘，每一份都重新求值
+                        let mut new_items = Vec::new();
+                        for _ in 0..(c.to_i64()) {
+                            new_items.extend(items.iter().cloned());
+                        }
+                        Type::nested_list(new_items)
                     }
                 }
             }
@@ -284,6 +554,19 @@ fn type_of_binary_op(lhs: &Expr, op: &BinOp, rhs: &Expr) -> Type {
                             //常数列表与变量列表相加，结果为变量列表，长度为常数列表长度加变量列表长度
                             Type::var_list(len + c.len() as i64)
                         }
+                        (NestedList(li), NestedList(ri)) => {
+                            // 两个嵌套列表相加，结果为嵌套列表，元素依次拼接
+                            let mut new_items = li.clone();
+                            new_items.extend(ri.iter().cloned());
+                            Type::nested_list(new_items)
+                        }
+                        (l, r) if l.is_nested() || r.is_nested() => Invalid(
+                            "Cannot concatenate a nested list with a flat list.".to_string(),
+                        ),
+                        (l, r) => {
+                            // 其中一个列表长度不确定，结果长度也不确定
+                            Type::bounded_list(l.max_len() + r.max_len())
+                        }
                     }
                 }
                 _ => Invalid("Only addition is allowed between lists.".to_string()),
@@ -297,6 +580,7 @@ fn type_of_binary_op(lhs: &Expr, op: &BinOp, rhs: &Expr) -> Type {
 }
 
 #[derive(Clone)]
+#[allow(clippy::enum_variant_names)]
 enum ArgsType {
     OneNumber(NumberType),
     OneList(ListType),
@@ -336,12 +620,139 @@ fn preprocess_call_args(args: &Vec<Type>) -> Result<ArgsType, String> {
         }
     }
 }
-fn type_of_call(func_name: &str, args: &Vec<Expr>) -> Type {
+
+// 内置函数的签名与说明 (英文, 中文)，与 type_of_call 中的规则对应，用于编辑器的补全与签名提示
+// filter 与 table 在语法中有单独的写法，同样列在这里
+pub struct FunctionSignature {
+    pub name: &'static str,
+    pub signatures: &'static [&'static str], // 可以接受的参数形式
+    pub description: (&'static str, &'static str),
+}
+
+pub const BUILTIN_FUNCTIONS: [FunctionSignature; 18] = [
+    FunctionSignature {
+        name: "max",
+        signatures: &["max(a, b, ...)", "max(list)", "max(list, n)"],
+        description: (
+            "The highest value, or the highest n elements of a list in their original order",
+            "最大值，或列表中最大的 n 个元素 (保持原来的顺序)",
+        ),
+    },
+    FunctionSignature {
+        name: "min",
+        signatures: &["min(a, b, ...)", "min(list)", "min(list, n)"],
+        description: (
+            "The lowest value, or the lowest n elements of a list in their original order",
+            "最小值，或列表中最小的 n 个元素 (保持原来的顺序)",
+        ),
+    },
+    FunctionSignature {
+        name: "sum",
+        signatures: &["sum(a, b, ...)", "sum(list)"],
+        description: ("The sum of the values", "求和"),
+    },
+    FunctionSignature {
+        name: "avg",
+        signatures: &["avg(a, b, ...)", "avg(list)"],
+        description: ("The average of the values", "平均值"),
+    },
+    FunctionSignature {
+        name: "median",
+        signatures: &["median(a, b, ...)", "median(list)"],
+        description: ("The median of the values", "中位数"),
+    },
+    FunctionSignature {
+        name: "floor",
+        signatures: &["floor(x)"],
+        description: ("Round down", "向下取整"),
+    },
+    FunctionSignature {
+        name: "ceil",
+        signatures: &["ceil(x)"],
+        description: ("Round up", "向上取整"),
+    },
+    FunctionSignature {
+        name: "round",
+        signatures: &["round(x)"],
+        description: ("Round to the nearest integer", "四舍五入"),
+    },
+    FunctionSignature {
+        name: "abs",
+        signatures: &["abs(x)"],
+        description: ("Absolute value", "绝对值"),
+    },
+    FunctionSignature {
+        name: "sqrt",
+        signatures: &["sqrt(x)"],
+        description: ("Square root", "平方根"),
+    },
+    FunctionSignature {
+        name: "pow",
+        signatures: &["pow(base, exponent)"],
+        description: (
+            "Power, same as base ^ exponent",
+            "乘方，与 base ^ exponent 相同",
+        ),
+    },
+    FunctionSignature {
+        name: "clamp",
+        signatures: &["clamp(x, lo, hi)"],
+        description: (
+            "Limit x to the range from lo to hi",
+            "把 x 限制在 lo 到 hi 之间",
+        ),
+    },
+    FunctionSignature {
+        name: "rpdice",
+        signatures: &["rpdice(expr)", "rpdice(expr, n)"],
+        description: (
+            "Roll every dice pool n times (2 by default), e.g. for critical hits",
+            "其中的每个骰池掷 n 次 (默认为 2)，如暴击伤害",
+        ),
+    },
+    FunctionSignature {
+        name: "len",
+        signatures: &["len(list)"],
+        description: ("The number of elements in a list", "列表的长度"),
+    },
+    FunctionSignature {
+        name: "count",
+        signatures: &["count(list, value)"],
+        description: (
+            "The number of elements equal to value",
+            "列表中等于 value 的元素个数",
+        ),
+    },
+    FunctionSignature {
+        name: "sort",
+        signatures: &["sort(list)"],
+        description: ("Sort a list from lowest to highest", "按从小到大排序"),
+    },
+    FunctionSignature {
+        name: "filter",
+        signatures: &["filter(list, >=value)"],
+        description: (
+            "The elements of a list that meet the condition",
+            "列表中满足条件的元素",
+        ),
+    },
+    FunctionSignature {
+        name: "table",
+        signatures: &["table(\"name\")"],
+        description: ("A roll on a random table", "查询随机表"),
+    },
+];
+
+fn type_of_call(func_name: &str, args: &[Expr]) -> Type {
     use ArgsType::*;
     use ListType::*;
     use NumberType::*;
     use VariableNumber::*;
-    let raw_args_type: Vec<Type> = args.iter().map(|arg| typecheck_expr(arg)).collect();
+    // 自定义函数在类型检查前展开，剩下的调用都应是内置函数
+    if !is_builtin_function(func_name) {
+        return Type::Invalid(format!("Unknown function: {}", func_name));
+    }
+    let raw_args_type: Vec<Type> = args.iter().map(typecheck_expr).collect();
     let args_type = match preprocess_call_args(&raw_args_type) {
         Err(s) => return Type::Invalid(s),
         Ok(at) => at,
@@ -356,14 +767,24 @@ fn type_of_call(func_name: &str, args: &Vec<Expr>) -> Type {
                         Type::Invalid("max/min function requires at least one element.".to_string())
                     } else {
                         let extreme = if func_name == "max" {
-                            lst.iter().cloned().fold(f64::MIN, f64::max)
+                            lst.iter().copied().reduce(Num::max)
                         } else {
-                            lst.iter().cloned().fold(f64::MAX, f64::min)
+                            lst.iter().copied().reduce(Num::min)
                         };
-                        Type::constant(extreme)
+                        Type::constant(extreme.unwrap())
                     }
                 }
-                OneList(VariableList(_)) => Type::unknown_var(), // 列表参数结果为未知变量数值
+                OneList(VariableList(_) | BoundedList(_)) => Type::unknown_var(), // 列表参数结果为未知变量数值
+                // 嵌套列表按子列表的总和比较，结果为其中一个子列表
+                OneList(NestedList(items)) => match ListType::common(&items) {
+                    Some(common) => Type::List(common),
+                    None if items.is_empty() => {
+                        Type::Invalid("max/min function requires at least one element.".to_string())
+                    }
+                    None => Type::Invalid(
+                        "Elements of the nested list have incompatible shapes.".to_string(),
+                    ),
+                },
                 // 从列表中取最大/小的 n 个元素
                 OneListAndOneNumber(lst, nt) => {
                     let nt = if let Constant(c) = nt {
@@ -371,21 +792,42 @@ fn type_of_call(func_name: &str, args: &Vec<Expr>) -> Type {
                     } else {
                         return Type::Invalid("If the first argument is a list, the second argument must be a constant number.".to_string());
                     };
-                    if !is_integer(nt) || nt <= 0.0 {
+                    if !is_integer(nt) || nt <= Num::ZERO {
                         return Type::Invalid(
                             "In min/max, the count parameter must be a positive integer."
                                 .to_string(),
                         );
                     }
                     match lst {
+                        // 长度不确定时，元素不足只能在求值时发现
+                        BoundedList(_) => Type::var_list(nt.to_i64()),
+                        NestedList(items) => {
+                            if (items.len() as i64) < nt.to_i64() {
+                                Type::Invalid(format!(
+                                    "In min/max, the list length {} is less than the count parameter {}.",
+                                    items.len(),
+                                    nt
+                                ))
+                            } else {
+                                match ListType::common(&items) {
+                                    Some(common) => {
+                                        Type::nested_list(vec![common; nt.to_i64() as usize])
+                                    }
+                                    None => Type::Invalid(
+                                        "Elements of the nested list have incompatible shapes."
+                                            .to_string(),
+                                    ),
+                                }
+                            }
+                        }
                         VariableList(len) => {
-                            if len < nt as i64 {
+                            if len < nt.to_i64() {
                                 Type::Invalid(format!(
                                     "In min/max, the list length {} is less than the count parameter {}.",
                                     len, nt
                                 ))
                             } else {
-                                Type::var_list(nt as i64)
+                                Type::var_list(nt.to_i64())
                             }
                         }
                         ConstantList(ls) => {
@@ -394,15 +836,18 @@ fn type_of_call(func_name: &str, args: &Vec<Expr>) -> Type {
                                     "In min/max, the list argument must have at least one element."
                                         .to_string(),
                                 )
-                            } else if (nt as i64) > ls.len() as i64 {
+                            } else if (nt.to_i64()) > ls.len() as i64 {
                                 Type::Invalid(format!(
                                     "In min/max, the list length {} is less than the count parameter {}.",
                                     ls.len(),
                                     nt
                                 ))
                             } else {
-                                let selected =
-                                    top_n_preserve_order(&ls, nt as usize, func_name == "max");
+                                let selected = top_n_preserve_order(
+                                    &ls,
+                                    nt.to_i64() as usize,
+                                    func_name == "max",
+                                );
                                 Type::const_list(selected)
                             }
                         }
@@ -414,11 +859,11 @@ fn type_of_call(func_name: &str, args: &Vec<Expr>) -> Type {
             match args_type {
                 OneNumber(Constant(c)) => Type::constant(c), // 单常数参数，结果为该常数
                 OneNumber(Variable(_)) => Type::unknown_var(), // 单变量参数，结果为未知变量数值
-                OneList(ConstantList(lst)) => {
-                    let total: f64 = lst.iter().sum();
-                    Type::constant(total)
+                OneList(ConstantList(lst)) => constant_or_invalid(Num::sum(lst)),
+                OneList(VariableList(_) | BoundedList(_)) => Type::unknown_var(), // 列表参数结果为未知变量数值
+                OneList(NestedList(_)) => {
+                    Type::Invalid("sum function does not accept nested lists.".to_string())
                 }
-                OneList(VariableList(_)) => Type::unknown_var(), // 列表参数结果为未知变量数值
                 OneListAndOneNumber(_, _) => Type::Invalid(
                     "sum function does not accept one list and one number as arguments."
                         .to_string(),
@@ -452,11 +897,16 @@ fn type_of_call(func_name: &str, args: &Vec<Expr>) -> Type {
                 [t] => t.clone(), // 其他情况的单参数调用，直接返回该参数的类型
                 // 也可以接受第二个参数为常数数值，表示重复次数
                 [t, Type::Number(Constant(c))] => {
-                    if !is_integer(*c) || *c <= 1.0 {
+                    if !is_integer(*c) || *c <= Num::ONE {
                         Type::Invalid(
                             "In rpdice, the repeat count parameter must be a integer larger than 1."
                                 .to_string(),
                         )
+                    } else if *c > Num::int(MAX_REPEAT) {
+                        Type::Invalid(format!(
+                            "In rpdice, the repeat count must not exceed {}.",
+                            MAX_REPEAT
+                        ))
                     } else {
                         match t {
                             Type::Number(Variable(DicePool(_))) => Type::unknown_var(),
@@ -473,6 +923,121 @@ fn type_of_call(func_name: &str, args: &Vec<Expr>) -> Type {
                 ),
             }
         }
+        "avg" | "median" => {
+            match args_type {
+                OneNumber(Constant(c)) => Type::constant(c), // 单常数参数，结果为该常数
+                OneNumber(Variable(_)) => Type::unknown_var(), // 单变量参数，结果为未知变量数值
+                OneList(ConstantList(lst)) => {
+                    if lst.is_empty() {
+                        Type::Invalid(format!(
+                            "{} function requires at least one element.",
+                            func_name
+                        ))
+                    } else if func_name == "avg" {
+                        let len = Num::int(lst.len() as i64);
+                        constant_or_invalid(Num::sum(lst).and_then(|total| total.checked_div(len)))
+                    } else {
+                        constant_or_invalid(median(&lst))
+                    }
+                }
+                OneList(VariableList(_) | BoundedList(_)) => Type::unknown_var(), // 列表参数结果为未知变量数值
+                OneList(NestedList(_)) => Type::Invalid(format!(
+                    "{} function does not accept nested lists.",
+                    func_name
+                )),
+                OneListAndOneNumber(_, _) => Type::Invalid(format!(
+                    "{} function does not accept one list and one number as arguments.",
+                    func_name
+                )),
+            }
+        }
+        "len" => {
+            // 除了 filter 的结果，列表的长度在类型检查时就已经确定
+            match args_type {
+                OneList(BoundedList(_)) => Type::unknown_var(),
+                OneList(lst) => Type::constant(lst.max_len()),
+                _ => Type::Invalid("len function requires a list argument.".to_string()),
+            }
+        }
+        "count" => {
+            // 统计列表中等于给定值的元素个数
+            match args_type {
+                OneListAndOneNumber(ConstantList(lst), Constant(c)) => {
+                    Type::constant(lst.iter().filter(|v| **v == c).count() as i64)
+                }
+                OneListAndOneNumber(NestedList(_), _) => {
+                    Type::Invalid("count function does not accept nested lists.".to_string())
+                }
+                OneListAndOneNumber(_, _) => Type::unknown_var(),
+                _ => Type::Invalid(
+                    "count function requires a list and a number as arguments.".to_string(),
+                ),
+            }
+        }
+        "sort" => {
+            // 升序排列，嵌套列表按子列表的总和排序
+            match args_type {
+                OneList(ConstantList(mut lst)) => {
+                    lst.sort_by(Num::total_cmp);
+                    Type::const_list(lst)
+                }
+                OneList(NestedList(items)) => match ListType::common(&items) {
+                    Some(common) => Type::nested_list(vec![common; items.len()]),
+                    None if items.is_empty() => Type::nested_list(items),
+                    None => Type::Invalid(
+                        "Elements of the nested list have incompatible shapes.".to_string(),
+                    ),
+                },
+                OneList(lst) => Type::List(lst),
+                _ => Type::Invalid("sort function requires a single list argument.".to_string()),
+            }
+        }
+        "sqrt" => match args_type {
+            OneNumber(Constant(c)) => {
+                if c < Num::ZERO {
+                    Type::Invalid("sqrt function requires a non-negative argument.".to_string())
+                } else {
+                    Type::constant(c.sqrt())
+                }
+            }
+            OneNumber(Variable(_)) => Type::unknown_var(),
+            _ => Type::Invalid("sqrt function requires a single numeric argument.".to_string()),
+        },
+        "pow" => match args {
+            // 与 ^ 运算符相同
+            [base, exponent] => type_of_binary_op(base, &BinOp::Pow, exponent),
+            _ => Type::Invalid("pow function requires exactly two arguments.".to_string()),
+        },
+        "clamp" => {
+            // clamp(x, lo, hi)，三个参数都必须是数值
+            let numbers: Vec<&NumberType> = raw_args_type
+                .iter()
+                .filter_map(|t| match t {
+                    Type::Number(n) => Some(n),
+                    _ => None,
+                })
+                .collect();
+            match numbers.as_slice() {
+                [x, Constant(lo), Constant(hi)] if numbers.len() == args.len() => {
+                    if lo > hi {
+                        Type::Invalid(
+                            "In clamp, the lower bound must not exceed the upper bound."
+                                .to_string(),
+                        )
+                    } else {
+                        match x {
+                            Constant(c) => Type::constant(c.max(*lo).min(*hi)),
+                            Variable(_) => Type::unknown_var(),
+                        }
+                    }
+                }
+                [_, _, _] if numbers.len() == args.len() => Type::unknown_var(),
+                _ => Type::Invalid(
+                    "clamp function requires three numeric arguments: value, lower and upper bound."
+                        .to_string(),
+                ),
+            }
+        }
         _ => Type::Invalid(format!("Unknown function: {}", func_name)), // 未知函数，should be unreachable
     }
 }
@@ -482,12 +1047,13 @@ fn type_of_list(args: &Vec<Expr>) -> Type {
     use Type::*;
     let mut is_variable = false;
     let mut consts = Vec::new();
+    let mut lists = Vec::new();
     for arg in args {
         let arg_type = typecheck_expr(arg);
         match arg_type {
             Invalid(s) => return Invalid(s), // 遇到无效类型，直接返回错误
-            List(_) => return Invalid("Nested lists are not allowed.".to_string()), // 不允许嵌套列表
-            Number(Variable(_)) => is_variable = true,                              // 统计变量数值
+            List(l) => lists.push(l),        // 收集嵌套的列表
+            Number(Variable(_)) => is_variable = true, // 统计变量数值
             Number(Constant(c)) => {
                 if !is_variable {
                     consts.push(c); // 仅当没有变量数值时，收集常数数值
@@ -495,13 +1061,155 @@ fn type_of_list(args: &Vec<Expr>) -> Type {
             }
         }
     }
-    if is_variable {
+    if !lists.is_empty() {
+        // 元素要么全是数值，要么全是列表
+        if lists.len() != args.len() {
+            Invalid("List elements must be all numbers or all lists.".to_string())
+        } else {
+            Type::nested_list(lists)
+        }
+    } else if is_variable {
         Type::var_list(args.len() as i64)
     } else {
         Type::const_list(consts)
     }
 }
 
+// 骰组的每个子掷骰都必须是数值，即使全是常数也保留骰组类型，以便之后取高/取低
+fn type_of_group(items: &[Expr]) -> Type {
+    for item in items {
+        match typecheck_expr(item) {
+            Type::Invalid(s) => return Type::Invalid(s),
+            Type::List(_) => return Type::Invalid("Group items must be numbers.".to_string()),
+            Type::Number(_) => {}
+        }
+    }
+    Type::group(items.len() as i64)
+}
+
+// 骰组只支持取高/取低与计数，数量不能超过子掷骰的数量
+fn type_of_group_modifier(len: i64, op: &ModifierOp, param: &Option<ModifierParam>) -> Type {
+    use ModifierOp::*;
+    match op {
+        KeepHigh | KeepLow | DropHigh | DropLow => match positive_integer_constant(param) {
+            Err(s) => Type::Invalid(s),
+            Ok(c) => {
+                let remain_count = match op {
+                    KeepHigh | KeepLow => c,
+                    _ => len - c,
+                };
+                if remain_count <= 0 || remain_count > len {
+                    Type::Invalid("Drop / keep count exceeds group size.".to_string())
+                } else {
+                    Type::group(remain_count)
+                }
+            }
+        },
+        Count => match valid_compare_param(param) {
+            Err(s) => Type::Invalid(s),
+            Ok(None) => Type::Invalid("Modifier requires a comparison parameter.".to_string()), // should be unreachable
+            Ok(Some(())) => Type::unknown_var(),
+        },
+        _ => Type::Invalid(
+            "Only keep / drop, count and success checks can be applied to groups.".to_string(),
+        ),
+    }
+}
+
+// 下标与切片的边界必须是常整数
+fn integer_index(expr: &Expr) -> Result<i64, String> {
+    match typecheck_expr(expr) {
+        Type::Invalid(s) => Err(s),
+        Type::Number(NumberType::Constant(c)) if is_integer(c) => Ok(c.to_i64()),
+        _ => Err("List index must be a constant integer.".to_string()),
+    }
+}
+
+fn type_of_index(list: &Expr, index: &Expr) -> Type {
+    use ListType::*;
+    let list_type = match typecheck_expr(list) {
+        Type::Invalid(s) => return Type::Invalid(s),
+        Type::List(l) => l,
+        Type::Number(_) => return Type::Invalid("Only lists can be indexed.".to_string()),
+    };
+    let index = match integer_index(index) {
+        Err(s) => return Type::Invalid(s),
+        Ok(i) => i,
+    };
+    let position = match resolve_index(index, list_type.max_len()) {
+        None => {
+            return Type::Invalid(format!(
+                "List index {} is out of range for a list of length {}.",
+                index,
+                list_type.max_len()
+            ));
+        }
+        Some(p) => p,
+    };
+    match list_type {
+        ConstantList(lst) => Type::constant(lst[position]),
+        VariableList(_) => Type::unknown_var(),
+        // 长度不确定时，越界只能在求值时发现
+        BoundedList(_) => Type::unknown_var(),
+        NestedList(items) => Type::List(items[position].clone()),
+    }
+}
+
+fn type_of_slice(list: &Expr, start: &Option<Box<Expr>>, end: &Option<Box<Expr>>) -> Type {
+    use ListType::*;
+    let list_type = match typecheck_expr(list) {
+        Type::Invalid(s) => return Type::Invalid(s),
+        Type::List(l) => l,
+        Type::Number(_) => return Type::Invalid("Only lists can be sliced.".to_string()),
+    };
+    let mut bounds = [None, None];
+    for (bound, expr) in bounds.iter_mut().zip([start, end]) {
+        if let Some(expr) = expr {
+            match integer_index(expr) {
+                Err(s) => return Type::Invalid(s),
+                Ok(i) => *bound = Some(i),
+            }
+        }
+    }
+    let (from, to) = resolve_slice(bounds[0], bounds[1], list_type.max_len());
+    match list_type {
+        ConstantList(lst) => Type::const_list(lst[from..to].to_vec()),
+        VariableList(_) => Type::var_list((to - from) as i64),
+        // 负数边界依赖实际长度，只能确定最大长度
+        BoundedList(len) => Type::bounded_list(len),
+        NestedList(items) => Type::nested_list(items[from..to].to_vec()),
+    }
+}
+
+fn type_of_filter(list: &Expr, compare_expr: &CompareExpr) -> Type {
+    use ListType::*;
+    let list_type = match typecheck_expr(list) {
+        Type::Invalid(s) => return Type::Invalid(s),
+        Type::List(l) => l,
+        Type::Number(_) => {
+            return Type::Invalid("filter function requires a list argument.".to_string());
+        }
+    };
+    let target = match typecheck_expr(&compare_expr.val) {
+        Type::Invalid(s) => return Type::Invalid(s),
+        Type::Number(NumberType::Constant(c)) => c,
+        _ => {
+            return Type::Invalid(
+                "In filter, the comparison parameter must be a constant number.".to_string(),
+            );
+        }
+    };
+    match list_type {
+        ConstantList(lst) => Type::const_list(
+            lst.into_iter()
+                .filter(|v| compare(&compare_expr.op, *v, target))
+                .collect(),
+        ),
+        VariableList(len) | BoundedList(len) => Type::bounded_list(len),
+        NestedList(_) => Type::Invalid("filter function does not accept nested lists.".to_string()),
+    }
+}
+
 fn positive_integer_constant(param: &Option<ModifierParam>) -> Result<i64, String> {
     use NumberType::*;
     use Type::*;
@@ -509,8 +1217,8 @@ fn positive_integer_constant(param: &Option<ModifierParam>) -> Result<i64, Strin
         match typecheck_expr(n) {
             Invalid(s) => Err(s),
             Number(Constant(c)) => {
-                if is_integer(c) && c >= 0.0 {
-                    Ok(c as i64)
+                if is_integer(c) && c >= Num::ZERO {
+                    Ok(c.to_i64())
                 } else {
                     Err("Modifier parameter must be a non-negative integer.".to_string())
                 }
@@ -521,6 +1229,20 @@ fn positive_integer_constant(param: &Option<ModifierParam>) -> Result<i64, Strin
         Err("Modifier requires a count parameter.".to_string()) // should be unreachable
     }
 }
+fn integer_constant(param: &Option<ModifierParam>) -> Result<i64, String> {
+    use NumberType::*;
+    use Type::*;
+    if let Some(ModifierParam::Value(n)) = param {
+        match typecheck_expr(n) {
+            Invalid(s) => Err(s),
+            Number(Constant(c)) if is_integer(c) => Ok(c.to_i64()),
+            Number(Constant(_)) => Err("Modifier parameter must be an integer.".to_string()),
+            _ => Err("Modifier parameter must be a constant number.".to_string()),
+        }
+    } else {
+        Err("Modifier requires a value parameter.".to_string()) // should be unreachable
+    }
+}
 fn valid_compare_param(param: &Option<ModifierParam>) -> Result<Option<()>, String> {
     match param {
         Some(ModifierParam::Compare(ce)) => {
@@ -554,6 +1276,7 @@ fn type_of_modifier(lhs: &Expr, op: &ModifierOp, param: &Option<ModifierParam>)
     let dice_pool = match lhs_type {
         Invalid(s) => return Invalid(s),
         Number(Variable(DicePool(pool))) => pool, // 正常进行后续计算
+        Number(Variable(Group(len))) => return type_of_group_modifier(len, op, param),
         _ => return Invalid("Modifiers can only be applied to dice expressions.".to_string()),
     };
 
@@ -562,14 +1285,14 @@ fn type_of_modifier(lhs: &Expr, op: &ModifierOp, param: &Option<ModifierParam>)
         KeepHigh | KeepLow | DropHigh | DropLow => {
             // 这些修饰符需要一个常整数参数
             match positive_integer_constant(param) {
-                Err(s) => return Invalid(s),
+                Err(s) => Invalid(s),
                 Ok(c) => {
                     match dice_pool {
                         RawDicePool(item) | LimitableDicePool(item) => {
                             let remain_count = {
                                 match op {
-                                    KeepHigh | KeepLow => c as i64,
-                                    DropHigh | DropLow => item.min_count - (c as i64),
+                                    KeepHigh | KeepLow => c,
+                                    DropHigh | DropLow => item.min_count - c,
                                     _ => unreachable!(),
                                 }
                             };
@@ -594,27 +1317,57 @@ fn type_of_modifier(lhs: &Expr, op: &ModifierOp, param: &Option<ModifierParam>)
                 RawDicePool(item) | LimitableDicePool(item) => Type::raw_dice_pool(item),
             },
         },
-        Explode => match valid_compare_param(param) {
-            Err(s) => Invalid(s),
-            Ok(_) => match dice_pool {
-                // 参数是可选的，不强制要求
-                RawDicePool(item) | LimitableDicePool(item) => Type::raw_dice_pool(item),
-            },
-        },
-        ExplodeCompound => match valid_compare_param(param) {
+        Explode | ExplodeCompound | ExplodePenetrate => match valid_compare_param(param) {
             Err(s) => Invalid(s),
             Ok(_) => {
                 match dice_pool {
                     // 参数是可选的，不需要检查
-                    // 只是唯一会生成 LimitableDicePool 的修饰符
+                    // 爆骰是唯一会生成 LimitableDicePool 的修饰符
                     RawDicePool(item) | LimitableDicePool(item) => Type::limitable_dice_pool(item),
                 }
             }
         },
+        CritSuccess | CritFailure => match valid_compare_param(param) {
+            // 参数可选，只标记骰子，不改变骰池
+            Err(s) => Invalid(s),
+            Ok(_) => match dice_pool {
+                RawDicePool(item) | LimitableDicePool(item) => Type::raw_dice_pool(item),
+            },
+        },
+        SortAsc | SortDesc => match dice_pool {
+            // 排序不改变骰池的内容
+            RawDicePool(item) | LimitableDicePool(item) => Type::raw_dice_pool(item),
+        },
+        Unique => match dice_pool {
+            // 骰面不够时无法让所有骰子的点数各不相同
+            RawDicePool(item) | LimitableDicePool(item) => {
+                if item.min_count > item.side.distinct_faces() {
+                    Invalid(
+                        "Unique modifier requires at least as many distinct faces as dice."
+                            .to_string(),
+                    )
+                } else {
+                    Type::raw_dice_pool(item)
+                }
+            }
+        },
+        Count => match valid_compare_param(param) {
+            // 计数的结果与成功检定一样是未知值，之后不能再使用修饰符
+            Err(s) => Invalid(s),
+            Ok(None) => Invalid("Modifier requires a comparison parameter.".to_string()), // should be unreachable
+            Ok(Some(())) => Type::unknown_var(),
+        },
+        ClampMin | ClampMax => match integer_constant(param) {
+            // 下限与上限可以为负数 (如 Fate 骰)
+            Err(s) => Invalid(s),
+            Ok(_) => match dice_pool {
+                RawDicePool(item) | LimitableDicePool(item) => Type::raw_dice_pool(item),
+            },
+        },
         Limit => {
             // 这些修饰符需要一个常整数参数
             match positive_integer_constant(param) {
-                Err(s) => return Invalid(s),
+                Err(s) => Invalid(s),
                 Ok(_) => match dice_pool {
                     LimitableDicePool(item) => Type::raw_dice_pool(item),
                     RawDicePool(_) => Invalid(
@@ -626,31 +1379,42 @@ fn type_of_modifier(lhs: &Expr, op: &ModifierOp, param: &Option<ModifierParam>)
     }
 }
 
-fn type_of_success_check(lhs: &Expr, param: &CompareExpr) -> Type {
+fn type_of_success_check(
+    lhs: &Expr,
+    param: &CompareExpr,
+    failure: &Option<CompareExpr>,
+    double_success: &Option<CompareExpr>,
+) -> Type {
     use NumberType::*;
     use Type::*;
     use VariableNumber::*;
     let lhs_type = typecheck_expr(lhs);
     match lhs_type {
-        Invalid(s) => Invalid(s),
-        Number(Variable(DicePool(_))) => {
-            match typecheck_expr(&param.val) {
-                Invalid(s) => Invalid(s),
-                Number(n) => {
-                    match n {
-                        Variable(_) => Invalid(
-                            "Comparison parameter for success check cannot be a variable number."
-                                .to_string(),
-                        ),
-                        Constant(_) => Type::unknown_var(), // 成功检定的结果为未知值
-                    }
-                }
-                _ => Invalid(
+        Invalid(s) => return Invalid(s),
+        Number(Variable(DicePool(_) | Group(_))) => {}
+        _ => return Invalid("Success check can only be applied to dice expressions.".to_string()),
+    }
+    // 目标值、失败条件与双倍成功条件都必须是常数
+    for ce in std::iter::once(param)
+        .chain(failure.as_ref())
+        .chain(double_success.as_ref())
+    {
+        match typecheck_expr(&ce.val) {
+            Invalid(s) => return Invalid(s),
+            Number(Constant(_)) => {}
+            Number(Variable(_)) => {
+                return Invalid(
+                    "Comparison parameter for success check cannot be a variable number."
+                        .to_string(),
+                );
+            }
+            _ => {
+                return Invalid(
                     "Comparison parameter for success check must be a numeric expression."
                         .to_string(),
-                ),
+                );
             }
         }
-        _ => Invalid("Success check can only be applied to dice expressions.".to_string()),
     }
+    Type::unknown_var() // 成功检定的结果为未知值
 }
@@ -0,0 +1,92 @@
+use dice_roller::advantage::{RollMode, apply_roll_mode, with_roll_mode};
+use dice_roller::grammar::parse_dice;
+use dice_roller::typecheck::{Type, typecheck_expr};
+
+fn parse(input: &str) -> String {
+    parse_dice(input).expect("Parse error").to_string()
+}
+
+fn with_mode(input: &str, mode: RollMode) -> String {
+    apply_roll_mode(&parse_dice(input).expect("Parse error"), mode).to_string()
+}
+
+#[test]
+fn test_roll_mode_suffix() {
+    assert_eq!(parse("1d20adv + 5"), "2d20kh1 + 5");
+    assert_eq!(parse("d20DIS"), "2d20kl1");
+    assert_eq!(parse("1dF adv"), "2dFkh1");
+    // 优势与劣势互相抵消，多个优势不叠加
+    assert_eq!(parse("1d20adv dis + 5"), "1d20 + 5");
+    assert_eq!(parse("1d20 adv adv dis"), "1d20");
+    assert_eq!(parse("1d20adv adv"), "2d20kh1");
+    assert_eq!(parse("2d20kh1dis"), "1d20");
+    assert_eq!(parse("2d20kl1 adv"), "1d20");
+
+    // 只有 d20 的写法视为已有的优势/劣势，其他 kh1 / kl1 整体掷两次
+    assert_eq!(parse("3d20kh1adv"), "{3d20kh1, 3d20kh1}kh1");
+    assert_eq!(parse("4d6kh1dis"), "{4d6kh1, 4d6kh1}kl1");
+    assert_eq!(parse("4d6kh1adv"), "{4d6kh1, 4d6kh1}kh1");
+    assert_eq!(parse("2dFkh1dis"), "{2dFkh1, 2dFkh1}kl1");
+
+    // 不是单颗骰子时整体掷两次
+    assert_eq!(parse("1d20ro1adv"), "{1d20ro=1, 1d20ro=1}kh1");
+    assert_eq!(parse("2d6dis"), "{2d6, 2d6}kl1");
+    assert_eq!(parse("1d20ro1adv dis"), "1d20ro=1");
+
+    // 展开后与手写的 kh1 / kl1 类型一致
+    assert_eq!(
+        typecheck_expr(&parse_dice("1d20adv").unwrap()),
+        typecheck_expr(&parse_dice("2d20kh1").unwrap())
+    );
+    assert_eq!(
+        typecheck_expr(&parse_dice("1d20ro1dis").unwrap()),
+        Type::group(1)
+    );
+    assert!(parse_dice("1d20advantage").is_err());
+}
+
+#[test]
+fn test_roll_mode_context() {
+    use RollMode::*;
+    assert_eq!(with_mode("1d20 + 5", Advantage), "2d20kh1 + 5");
+    assert_eq!(with_mode("1d20 + 5", Disadvantage), "2d20kl1 + 5");
+    assert_eq!(with_mode("1d20 + 5", Normal), "1d20 + 5");
+
+    // 设置与表达式中的优势/劣势按规则合并
+    assert_eq!(with_mode("1d20dis + 5", Advantage), "1d20 + 5");
+    assert_eq!(with_mode("2d20kh1 + 5", Advantage), "2d20kh1 + 5");
+    assert_eq!(with_mode("2d20kl1 + 5", Disadvantage), "2d20kl1 + 5");
+    assert_eq!(with_mode("3d20kh1 + 5", Disadvantage), "3d20kh1 + 5");
+    assert_eq!(with_mode("{1d20ro1, 1d20ro1}kl1", Advantage), "1d20ro=1");
+    assert_eq!(
+        with_mode("1d20ro1 + 2", Advantage),
+        "{1d20ro=1, 1d20ro=1}kh1 + 2"
+    );
+
+    // 只影响 d20 掷骰
+    assert_eq!(
+        with_mode("2d6 + 1d8 + 1d20", Advantage),
+        "2d6 + 1d8 + 2d20kh1"
+    );
+    assert_eq!(with_mode("2d20 + 1d4", Advantage), "2d20 + 1d4");
+    assert_eq!(
+        with_mode("1d20 [attack] + 5", Disadvantage),
+        "2d20kl1 [attack] + 5"
+    );
+    assert_eq!(
+        with_mode("{1d20 + 5, 1d20 + 3}kh1", Advantage),
+        "{2d20kh1 + 5, 2d20kh1 + 3}kh1"
+    );
+}
+
+#[test]
+fn test_roll_mode_combine() {
+    use RollMode::*;
+    assert_eq!(Advantage.combine(Advantage), Advantage);
+    assert_eq!(Advantage.combine(Disadvantage), Normal);
+    assert_eq!(Normal.combine(Disadvantage), Disadvantage);
+    assert_eq!(
+        with_roll_mode(parse_dice("1d%").unwrap(), Advantage).to_string(),
+        "2d%kh1"
+    );
+}
@@ -0,0 +1,91 @@
+use dice_roller::complete::{CompletionKind, complete};
+use dice_roller::explain::Language;
+use dice_roller::functions::FunctionRegistry;
+
+fn registry() -> FunctionRegistry {
+    let mut functions = FunctionRegistry::new();
+    functions
+        .load("def smite(lv: int) = (lv + 1)d8; def sneak(n) = n d6")
+        .unwrap();
+    functions
+}
+
+// 光标在末尾时的候选名称
+fn labels(input: &str) -> Vec<String> {
+    let cursor = input.encode_utf16().count();
+    complete(input, cursor, &registry(), Language::En)
+        .items
+        .into_iter()
+        .map(|c| c.label)
+        .collect()
+}
+
+#[test]
+fn test_complete_modifiers() {
+    assert_eq!(labels("1d20k"), vec!["kh", "kl"]);
+    assert_eq!(labels("4d6r"), vec!["r", "ro"]);
+    assert_eq!(labels("1d20a"), vec!["adv"]);
+    assert_eq!(
+        labels("1d"),
+        vec!["4", "6", "8", "10", "12", "20", "100", "F", "%"]
+    );
+    // 前面已经出错时没有候选
+    assert_eq!(labels("1d20 + * k"), Vec::<String>::new());
+
+    let result = complete("1d20kh", 6, &registry(), Language::ZhCn);
+    assert_eq!(result.items[0].label, "kh");
+    assert_eq!(result.items[0].kind, CompletionKind::Modifier);
+    assert_eq!(result.items[0].description, "保留最高的 n 颗骰子");
+    assert_eq!((result.replace.start, result.replace.end), (4, 6));
+}
+
+#[test]
+fn test_complete_functions() {
+    assert_eq!(labels("1d20 + ma"), vec!["max"]);
+    assert_eq!(labels("s"), vec!["sum", "sqrt", "sort", "smite", "sneak"]);
+    assert_eq!(labels("floor(1d6 + r"), vec!["round", "rpdice"]);
+
+    let result = complete("2 * sm", 6, &registry(), Language::En);
+    let smite = &result.items[0];
+    assert_eq!(smite.kind, CompletionKind::Macro);
+    assert_eq!(smite.insert, "smite(");
+    assert_eq!(smite.detail, "smite(lv: int)");
+
+    // @ 之后列出自定义函数，替换范围包括 @
+    let result = complete("1d20 + @s", 9, &registry(), Language::En);
+    let names: Vec<_> = result.items.iter().map(|c| c.label.as_str()).collect();
+    assert_eq!(names, vec!["smite", "sneak"]);
+    assert_eq!((result.replace.start, result.replace.end), (7, 9));
+}
+
+#[test]
+fn test_complete_signature() {
+    let result = complete("max(", 4, &registry(), Language::En);
+    let signature = result.signature.unwrap();
+    assert_eq!(signature.name, "max");
+    assert_eq!(
+        signature.signatures,
+        vec!["max(a, b, ...)", "max(list)", "max(list, n)"]
+    );
+    assert_eq!(signature.active, 0);
+
+    // 参数下标只计算顶层的逗号
+    let signature = complete("clamp(max([1, 2], 1), 3, ", 25, &registry(), Language::En)
+        .signature
+        .unwrap();
+    assert_eq!((signature.name.as_str(), signature.active), ("clamp", 2));
+    let signature = complete("clamp(max([1, 2], 1", 19, &registry(), Language::En)
+        .signature
+        .unwrap();
+    assert_eq!((signature.name.as_str(), signature.active), ("max", 1));
+
+    let signature = complete("smite(3) + sneak(", 17, &registry(), Language::En)
+        .signature
+        .unwrap();
+    assert_eq!(signature.signatures, vec!["sneak(n)"]);
+
+    // 光标之后的内容不影响补全
+    let result = complete("1d20k + max(1, 2)", 5, &registry(), Language::En);
+    assert_eq!(result.items.len(), 2);
+    assert!(result.signature.is_none());
+}
@@ -0,0 +1,415 @@
+use dice_roller::eval::{DiceRng, RollOutput, RollValue, SplitMix64, evaluate_expr};
+use dice_roller::grammar::parse_dice;
+use dice_roller::number::Num;
+
+// 按给定序列返回骰面下标的随机数来源
+struct SequenceRng {
+    indices: Vec<usize>,
+    pos: usize,
+}
+
+impl DiceRng for SequenceRng {
+    fn next_index(&mut self, n: usize) -> usize {
+        let index = self.indices[self.pos % self.indices.len()];
+        self.pos += 1;
+        assert!(index < n);
+        index
+    }
+}
+
+// 以骰面下标序列掷骰，普通骰子的下标 i 对应骰面 i + 1
+fn roll(input: &str, indices: &[usize]) -> RollOutput {
+    let expr = parse_dice(input).expect("Parse error");
+    let mut rng = SequenceRng {
+        indices: indices.to_vec(),
+        pos: 0,
+    };
+    evaluate_expr(&expr, &mut rng).expect("Evaluation error")
+}
+
+fn faces(output: &RollOutput, group: usize) -> Vec<i64> {
+    output.groups[group].dice.iter().map(|d| d.face).collect()
+}
+
+#[test]
+fn test_eval_arithmetic() {
+    let output = roll("1d20 + 5", &[11]);
+    assert_eq!(output.result, RollValue::Number(Num::int(17)));
+    assert_eq!(output.groups.len(), 1);
+    assert_eq!(output.groups[0].notation, "1d20");
+    assert_eq!(output.groups[0].value, Num::int(12));
+
+    let output = roll("(2d6 + 1) * 2 // 3", &[2, 4]);
+    assert_eq!(output.result, RollValue::Number(Num::int(6)));
+
+    let output = roll("max(1d4, 3)", &[0]);
+    assert_eq!(output.result, RollValue::Number(Num::int(3)));
+
+    // 戏法伤害随等级成长
+    let output = roll("(1 + floor((11 + 1) / 6))d10", &[0, 1, 2]);
+    assert_eq!(output.result, RollValue::Number(Num::int(6)));
+
+    let output = roll("2 ^ 1d4", &[2]);
+    assert_eq!(output.result, RollValue::Number(Num::int(8)));
+
+    let output = roll("clamp(1d20, 5, 15)", &[1]);
+    assert_eq!(output.result, RollValue::Number(Num::int(5)));
+
+    let output = roll("median([1d6, 1d6, 1d6]) + avg(1d4, 3)", &[5, 0, 2, 0]);
+    assert_eq!(output.result, RollValue::Number(Num::int(5)));
+
+    let output = roll("count([1d6] * 4, 6) + len([1d6, 1d6])", &[5, 5, 0, 2, 0, 0]);
+    assert_eq!(output.result, RollValue::Number(Num::int(4)));
+
+    let output = roll("[1d6, 2] + [3]", &[5]);
+    assert_eq!(
+        output.result,
+        RollValue::List(vec![Num::int(6), Num::int(2), Num::int(3)])
+    );
+
+    // 列表重复时每一份都重新掷骰
+    let output = roll("[1d6] * 3", &[0, 1, 2]);
+    assert_eq!(
+        output.result,
+        RollValue::List(vec![Num::int(1), Num::int(2), Num::int(3)])
+    );
+    assert_eq!(output.groups.len(), 3);
+}
+
+#[test]
+fn test_eval_special_dice() {
+    // Fate 骰面为 -1、0、+1
+    let output = roll("4dF", &[0, 1, 2, 2]);
+    assert_eq!(faces(&output, 0), vec![-1, 0, 1, 1]);
+    assert_eq!(output.result, RollValue::Number(Num::int(1)));
+
+    let output = roll("d%", &[99]);
+    assert_eq!(output.result, RollValue::Number(Num::int(100)));
+    assert_eq!(output.groups[0].notation, "1d%");
+
+    let output = roll("4dFkh2", &[0, 2, 1, 0]);
+    assert_eq!(output.result, RollValue::Number(Num::int(1)));
+}
+
+#[test]
+fn test_eval_custom_dice() {
+    // 骰面按从小到大排列后取下标: [1, 1, 2, 2, 3, 4]
+    let output = roll("3d{1, 1, 2, 2, 3, 4}", &[0, 3, 5]);
+    assert_eq!(faces(&output, 0), vec![1, 2, 4]);
+    assert_eq!(output.result, RollValue::Number(Num::int(7)));
+
+    let output = roll("3d{1, 1, 2, 2, 3, 4}kh1", &[0, 3, 5]);
+    assert_eq!(output.result, RollValue::Number(Num::int(4)));
+
+    // 在最大的骰面 (4) 上爆骰
+    let output = roll("1d{1, 1, 2, 2, 3, 4}!", &[5, 4]);
+    assert_eq!(faces(&output, 0), vec![4, 3]);
+
+    // 重骰比较的是骰面
+    let output = roll("1d{1, 1, 2, 2, 3, 4}r<2", &[1, 0, 2]);
+    assert_eq!(faces(&output, 0), vec![1, 1, 2]);
+    assert_eq!(output.result, RollValue::Number(Num::int(2)));
+}
+
+#[test]
+fn test_eval_modifiers() {
+    // 4d6dl1: [3, 5, 1, 6] 丢弃 1
+    let output = roll("4d6dl1", &[2, 4, 0, 5]);
+    assert_eq!(output.result, RollValue::Number(Num::int(14)));
+    let dropped: Vec<bool> = output.groups[0].dice.iter().map(|d| d.dropped).collect();
+    assert_eq!(dropped, vec![false, false, true, false]);
+
+    let output = roll("2d20kl1", &[14, 3]);
+    assert_eq!(output.result, RollValue::Number(Num::int(4)));
+
+    // 重骰 1，直到不是 1 为止
+    let output = roll("1d6r1", &[0, 0, 3]);
+    assert_eq!(faces(&output, 0), vec![1, 1, 4]);
+    assert_eq!(output.result, RollValue::Number(Num::int(4)));
+
+    // 只重骰一次
+    let output = roll("1d6ro1", &[0, 0]);
+    assert_eq!(faces(&output, 0), vec![1, 1]);
+    assert_eq!(output.result, RollValue::Number(Num::int(1)));
+
+    // 爆骰：6 会追加一颗骰子
+    let output = roll("2d6!", &[5, 2, 5, 0]);
+    assert_eq!(faces(&output, 0), vec![6, 6, 1, 3]);
+    assert_eq!(output.result, RollValue::Number(Num::int(16)));
+
+    // 复合爆骰：追加的骰面累加到同一颗骰子
+    let output = roll("1d6!!", &[5, 5, 1]);
+    assert_eq!(output.groups[0].dice.len(), 1);
+    assert_eq!(output.groups[0].dice[0].compound, vec![6, 2]);
+    assert_eq!(output.result, RollValue::Number(Num::int(14)));
+
+    // 限制复合爆骰次数
+    let output = roll("1d6!!l1", &[5, 5, 5]);
+    assert_eq!(output.result, RollValue::Number(Num::int(12)));
+
+    // 限制普通爆骰次数
+    let output = roll("1d6!l2", &[5, 5, 5, 0]);
+    assert_eq!(faces(&output, 0), vec![6, 6, 6]);
+    assert_eq!(output.result, RollValue::Number(Num::int(18)));
+
+    // 穿透爆骰：追加的骰子 -1，是否继续爆骰看掷出的骰面
+    let output = roll("1d6!p", &[5, 5, 2]);
+    assert_eq!(faces(&output, 0), vec![6, 6, 3]);
+    let values: Vec<i64> = output.groups[0].dice.iter().map(|d| d.value).collect();
+    assert_eq!(values, vec![6, 5, 2]);
+    assert_eq!(output.result, RollValue::Number(Num::int(13)));
+
+    let output = roll("1d6!pl1", &[5, 5, 5]);
+    assert_eq!(output.result, RollValue::Number(Num::int(11)));
+
+    // 单颗骰子的下限与上限，骰面保持不变
+    let output = roll("3d20mi10", &[2, 14, 8]);
+    assert_eq!(faces(&output, 0), vec![3, 15, 9]);
+    let values: Vec<i64> = output.groups[0].dice.iter().map(|d| d.value).collect();
+    assert_eq!(values, vec![10, 15, 10]);
+    assert_eq!(output.result, RollValue::Number(Num::int(35)));
+
+    let output = roll("2d6ma4kh1", &[5, 2]);
+    assert_eq!(output.result, RollValue::Number(Num::int(4)));
+
+    // 排序会体现在骰池的骰子顺序中
+    let output = roll("4d6sa", &[3, 0, 5, 2]);
+    assert_eq!(faces(&output, 0), vec![1, 3, 4, 6]);
+    let output = roll("4d6dl1sd", &[3, 0, 5, 2]);
+    assert_eq!(faces(&output, 0), vec![6, 4, 3, 1]);
+    assert!(output.groups[0].dice[3].dropped);
+    assert_eq!(output.result, RollValue::Number(Num::int(13)));
+
+    // 唯一：与之前的骰子点数相同时重骰，重骰前的骰子保留为无效
+    let output = roll("3d6u", &[3, 3, 0, 3, 0, 5]);
+    assert_eq!(faces(&output, 0), vec![4, 4, 4, 1, 1, 6]);
+    assert_eq!(output.result, RollValue::Number(Num::int(11)));
+    let valid: Vec<bool> = output.groups[0].dice.iter().map(|d| d.valid).collect();
+    assert_eq!(valid, vec![true, false, false, true, false, true]);
+
+    // 计数：统计满足条件的骰子数量，骰池仍然保留
+    let output = roll("6d6cnt>=5", &[4, 5, 0, 3, 1, 4]);
+    assert_eq!(output.result, RollValue::Number(Num::int(3)));
+    assert_eq!(output.groups[0].value, Num::int(3));
+    assert_eq!(output.groups[0].dice.len(), 6);
+
+    // 成功判定：统计满足条件的骰子数量
+    let output = roll("5d10>=8", &[9, 7, 6, 0, 8]);
+    assert_eq!(output.result, RollValue::Number(Num::int(3)));
+
+    // 失败扣除成功，双倍成功额外计一个
+    let output = roll("5d10>=8f1ds10", &[9, 7, 6, 0, 8]);
+    assert_eq!(output.result, RollValue::Number(Num::int(3)));
+    let output = roll("4d10>=8f1", &[0, 0, 3, 8]);
+    assert_eq!(output.result, RollValue::Number(Num::int(-1)));
+}
+
+#[test]
+fn test_eval_criticals() {
+    let flags = |output: &RollOutput| -> Vec<(bool, bool)> {
+        output.groups[0]
+            .dice
+            .iter()
+            .map(|d| (d.critical_success, d.critical_failure))
+            .collect()
+    };
+
+    // 默认：最大面为大成功，最小面为大失败
+    let output = roll("3d20", &[19, 0, 9]);
+    assert_eq!(
+        flags(&output),
+        vec![(true, false), (false, true), (false, false)]
+    );
+
+    // 自定义范围，爆骰追加的骰子同样标记
+    let output = roll("2d20cs>=19cf<=2", &[18, 1]);
+    assert_eq!(flags(&output), vec![(true, false), (false, true)]);
+    let output = roll("1d6!cs>=5", &[5, 4]);
+    assert_eq!(flags(&output), vec![(true, false), (true, false)]);
+    // 标记不影响结果
+    assert_eq!(output.result, RollValue::Number(Num::int(11)));
+}
+
+#[test]
+fn test_eval_list_operations() {
+    let output = roll("[1d6, 2d6][1]", &[0, 2, 3]);
+    assert_eq!(output.result, RollValue::Number(Num::int(7)));
+
+    let output = roll("([1d6] * 4)[1:3]", &[0, 1, 2, 3]);
+    assert_eq!(
+        output.result,
+        RollValue::List(vec![Num::int(2), Num::int(3)])
+    );
+
+    let output = roll("sort([1d6] * 3)", &[4, 0, 2]);
+    assert_eq!(
+        output.result,
+        RollValue::List(vec![Num::int(1), Num::int(3), Num::int(5)])
+    );
+
+    let output = roll("filter([1d20] * 4, >=10)", &[9, 2, 14, 19]);
+    assert_eq!(
+        output.result,
+        RollValue::List(vec![Num::int(10), Num::int(15), Num::int(20)])
+    );
+
+    // 嵌套列表按子列表的总和比较
+    let output = roll("[[1d6, 1d6]] * 3", &[0, 0, 5, 5, 2, 2]);
+    assert_eq!(
+        output.result,
+        RollValue::Nested(vec![
+            RollValue::List(vec![Num::int(1), Num::int(1)]),
+            RollValue::List(vec![Num::int(6), Num::int(6)]),
+            RollValue::List(vec![Num::int(3), Num::int(3)]),
+        ])
+    );
+
+    let output = roll("max([[1d6, 1d6]] * 3)", &[0, 0, 5, 5, 2, 2]);
+    assert_eq!(
+        output.result,
+        RollValue::List(vec![Num::int(6), Num::int(6)])
+    );
+
+    let output = roll("min([[1d6, 1d6]] * 3, 2)", &[0, 0, 5, 5, 2, 2]);
+    assert_eq!(
+        output.result,
+        RollValue::Nested(vec![
+            RollValue::List(vec![Num::int(1), Num::int(1)]),
+            RollValue::List(vec![Num::int(3), Num::int(3)]),
+        ])
+    );
+
+    let output = roll("sort([[1d6, 1d6]] * 3)[0]", &[0, 0, 5, 5, 2, 2]);
+    assert_eq!(
+        output.result,
+        RollValue::List(vec![Num::int(1), Num::int(1)])
+    );
+
+    // 过滤后的列表长度只有在求值时才知道
+    let mut rng = SplitMix64::new(1);
+    assert!(evaluate_expr(&parse_dice("filter([1d6] * 2, >6)[0]").unwrap(), &mut rng).is_err());
+    assert!(evaluate_expr(&parse_dice("max(filter([1d6] * 2, >6))").unwrap(), &mut rng).is_err());
+}
+
+#[test]
+fn test_eval_groups() {
+    // 两次 d20 分别为 8 与 15
+    let output = roll("{1d20 + 5, 1d20 + 3}kh1", &[7, 14]);
+    assert_eq!(output.result, RollValue::Number(Num::int(18)));
+    assert_eq!(output.groups.len(), 2);
+    assert_eq!(output.group_rolls.len(), 1);
+    let group = &output.group_rolls[0];
+    assert_eq!(group.notation, "{1d20 + 5, 1d20 + 3}kh1");
+    assert_eq!(group.value, Num::int(18));
+    let totals: Vec<(f64, bool)> = group
+        .items
+        .iter()
+        .map(|i| (i.total.to_f64(), i.dropped))
+        .collect();
+    assert_eq!(totals, vec![(13.0, true), (18.0, false)]);
+    assert_eq!(group.items[0].notation, "1d20 + 5");
+
+    // 不带修饰符时为所有子掷骰之和
+    let output = roll("{1d6, 2} + 1", &[3]);
+    assert_eq!(output.result, RollValue::Number(Num::int(7)));
+
+    // 成功检定按子掷骰的总值计数，丢弃的子掷骰不参与
+    let output = roll("{3d6, 3d6, 3d6}>=10", &[0, 0, 0, 5, 5, 5, 3, 3, 3]);
+    assert_eq!(output.result, RollValue::Number(Num::int(2)));
+    let output = roll("{3d6, 3d6, 3d6}kl2>=10", &[0, 0, 0, 5, 5, 5, 3, 3, 3]);
+    assert_eq!(output.result, RollValue::Number(Num::int(1)));
+    let output = roll("{1d6, 1d6}cnt>3", &[5, 1]);
+    assert_eq!(output.result, RollValue::Number(Num::int(1)));
+
+    // 相同总值时保留靠前的子掷骰；嵌套的骰组先记录
+    let output = roll("{{1d6, 1d6}kl1, 1d6}kh1", &[2, 4, 2]);
+    assert_eq!(output.result, RollValue::Number(Num::int(3)));
+    assert_eq!(output.group_rolls.len(), 2);
+    assert_eq!(output.group_rolls[0].notation, "{1d6, 1d6}kl1");
+    assert_eq!(
+        output.group_rolls[1]
+            .items
+            .iter()
+            .map(|i| i.dropped)
+            .collect::<Vec<_>>(),
+        vec![false, true]
+    );
+}
+
+#[test]
+fn test_eval_labels() {
+    let output = roll("2d6 [Sneak Attack] + 1d8 + 3 # rogue", &[0, 1, 2]);
+    assert_eq!(output.result, RollValue::Number(Num::int(9)));
+    assert_eq!(output.groups[0].label, Some("Sneak Attack".to_string()));
+    assert_eq!(output.groups[1].label, None);
+    assert_eq!(output.comment, Some("rogue".to_string()));
+
+    // 内层的标签优先
+    let output = roll("(1d4 [inner] + 1d6) [outer]", &[0, 0]);
+    assert_eq!(output.groups[0].label, Some("inner".to_string()));
+    assert_eq!(output.groups[1].label, Some("outer".to_string()));
+    assert_eq!(output.comment, None);
+
+    let output = roll("(4d6 [str])kh3", &[0, 1, 2, 3]);
+    assert_eq!(output.result, RollValue::Number(Num::int(9)));
+
+    // 爆骰与 l 之间可以有标签
+    let output = roll("((1d6!) [x])l3", &[5, 5, 5, 5, 0]);
+    assert_eq!(output.result, RollValue::Number(Num::int(24)));
+    let output = roll("((1d6!!) [x])l3", &[5, 5, 5, 5, 0]);
+    assert_eq!(output.result, RollValue::Number(Num::int(24)));
+}
+
+#[test]
+fn test_eval_rpdice() {
+    // 暴击：骰子翻倍，常数不变
+    let output = roll("rpdice(1d8 + 3)", &[7, 0]);
+    assert_eq!(output.result, RollValue::Number(Num::int(12)));
+    assert_eq!(output.groups.len(), 2);
+
+    let output = roll("rpdice(2d6, 3)", &[0, 1, 2, 3, 4, 5]);
+    assert_eq!(output.result, RollValue::Number(Num::int(21)));
+
+    let output = roll("rpdice(42, 2)", &[0]);
+    assert_eq!(output.result, RollValue::Number(Num::int(42)));
+
+    // 嵌套的 rpdice 重复次数相乘
+    let mut rng = SplitMix64::new(1);
+    assert_eq!(
+        evaluate_expr(
+            &parse_dice("rpdice(rpdice(1d6, 50), 50)").unwrap(),
+            &mut rng
+        )
+        .unwrap_err(),
+        "In rpdice, the total repeat count must not exceed 100."
+    );
+}
+
+#[test]
+fn test_eval_errors() {
+    let mut rng = SplitMix64::new(1);
+    assert!(evaluate_expr(&parse_dice("1d0").unwrap(), &mut rng).is_err());
+    // 运行时的除零
+    assert!(evaluate_expr(&parse_dice("10 / (1dF * 0)").unwrap(), &mut rng).is_err());
+    assert!(evaluate_expr(&parse_dice("sqrt(1dF - 2)").unwrap(), &mut rng).is_err());
+    assert!(evaluate_expr(&parse_dice("clamp(5, 1d4 + 4, 1d4)").unwrap(), &mut rng).is_err());
+}
+
+#[test]
+fn test_eval_large_side() {
+    // 骰面很多的骰子直接取随机数，不展开所有骰面
+    let output = roll("1d1000000000", &[999_999_999]);
+    assert_eq!(output.result, RollValue::Number(Num::int(1_000_000_000)));
+}
+
+#[test]
+fn test_eval_seeded_range() {
+    let expr = parse_dice("3dF + 1d%").unwrap();
+    let mut rng = SplitMix64::new(42);
+    for _ in 0..200 {
+        match evaluate_expr(&expr, &mut rng).unwrap().result {
+            RollValue::Number(n) => assert!((-2.0..=103.0).contains(&n.to_f64())),
+            _ => panic!("Expected a number"),
+        }
+    }
+}
@@ -0,0 +1,100 @@
+use dice_roller::explain::{Language, explain_expr};
+use dice_roller::grammar::parse_dice;
+
+fn en(input: &str) -> String {
+    explain_expr(&parse_dice(input).expect("Parse error"), Language::En)
+}
+
+fn zh(input: &str) -> String {
+    explain_expr(&parse_dice(input).expect("Parse error"), Language::ZhCn)
+}
+
+#[test]
+fn test_explain_dice() {
+    assert_eq!(
+        en("4d6dl1"),
+        "roll four six-sided dice, drop the lowest one"
+    );
+    assert_eq!(zh("4d6dl1"), "掷 4 颗 6 面骰，去掉最低的 1 颗");
+    assert_eq!(
+        en("1d6!!l3"),
+        "roll one six-sided die, compounding explosions, at most 3"
+    );
+    assert_eq!(zh("1d6!!l3"), "掷 1 颗 6 面骰，复合爆骰，最多 3 次");
+    assert_eq!(en("d%"), "roll one percentile die");
+    assert_eq!(zh("4dF"), "掷 4 颗 Fate 骰");
+    assert_eq!(en("1d{1,1,2}"), "roll one die with faces 1, 1, 2");
+    assert_eq!(
+        en("(1d4)d6"),
+        "roll a number of six-sided dice equal to (roll one four-sided die)"
+    );
+    assert_eq!(zh("(1d4)d6"), "掷（掷 1 颗 4 面骰）颗 6 面骰");
+}
+
+#[test]
+fn test_explain_modifiers() {
+    assert_eq!(
+        en("1d20ro1"),
+        "roll one twenty-sided die, reroll dice that roll 1 once"
+    );
+    assert_eq!(zh("1d6!>=5"), "掷 1 颗 6 面骰，爆骰，结果不小于 5 时追加");
+    assert_eq!(
+        en("6d6cnt>=5"),
+        "roll six six-sided dice, count the dice that roll 5 or higher"
+    );
+    assert_eq!(
+        en("10d10>=8f1ds10"),
+        "roll ten ten-sided dice, count successes (dice that roll 8 or higher), \
+         each die that rolls 1 removes a success, each success that rolls 10 counts twice"
+    );
+    assert_eq!(
+        zh("10d10>=8f1"),
+        "掷 10 颗 10 面骰，统计成功数（结果不小于 8），结果等于 1 时扣除一个成功"
+    );
+    assert_eq!(
+        zh("1d20cs>=19"),
+        "掷 1 颗 20 面骰，结果不小于 19 时为大成功"
+    );
+}
+
+#[test]
+fn test_explain_operators() {
+    assert_eq!(en("1d20 + 5"), "roll one twenty-sided die plus 5");
+    assert_eq!(zh("1d20 + 5"), "掷 1 颗 20 面骰 加 5");
+    // 带修饰符的掷骰作为运算数时加括号
+    assert_eq!(
+        en("2d20kh1 + 3"),
+        "(roll two twenty-sided dice, keep the highest one) plus 3"
+    );
+    assert_eq!(en("(1 + 2) * 3"), "(1 plus 2) times 3");
+    assert_eq!(en("1 - (2 - 3)"), "1 minus (2 minus 3)");
+    assert_eq!(en("2 ^ 3 ^ 2"), "2 to the power of (3 to the power of 2)");
+    assert_eq!(en("-1d4"), "negative (roll one four-sided die)");
+    assert_eq!(
+        en("2d6 [fire] + 1 # hi"),
+        "roll two six-sided dice (fire) plus 1 (note: hi)"
+    );
+}
+
+#[test]
+fn test_explain_groups_and_lists() {
+    assert_eq!(
+        en("{1d20+5, 1d20+3}kh1"),
+        "roll each of [roll one twenty-sided die plus 5; roll one twenty-sided die plus 3], \
+         keep the highest total"
+    );
+    assert_eq!(
+        zh("{3d6, 3d6}>=10"),
+        "分别进行 [掷 3 颗 6 面骰；掷 3 颗 6 面骰]，统计成功数（总值不小于 10）"
+    );
+    assert_eq!(
+        en("max(1d6, 1d8)"),
+        "the highest of (roll one six-sided die), (roll one eight-sided die)"
+    );
+    assert_eq!(
+        en("max([1d6]*4, 2)"),
+        "the highest 2 of (the list [roll one six-sided die] repeated 4 times)"
+    );
+    assert_eq!(zh("floor(1d6 / 2)"), "（掷 1 颗 6 面骰 除以 2）向下取整");
+    assert_eq!(en("table(\"surge\")"), "a roll on the table \"surge\"");
+}
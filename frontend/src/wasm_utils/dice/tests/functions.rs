@@ -0,0 +1,188 @@
+use dice_roller::functions::FunctionRegistry;
+use dice_roller::grammar::{Expr, Param, ParamType, parse_definition, parse_dice};
+use dice_roller::number::Num;
+use dice_roller::typecheck::{Type, typecheck_expr};
+
+fn registry(source: &str) -> FunctionRegistry {
+    let mut registry = FunctionRegistry::new();
+    registry.load(source).expect("Load error");
+    registry
+}
+
+fn expand(registry: &FunctionRegistry, input: &str) -> Result<String, String> {
+    let expr = parse_dice(input).map_err(|e| format!("Parse error: {}", e))?;
+    registry.expand(&expr).map(|e| e.to_string())
+}
+
+#[test]
+fn test_parse_definition() {
+    let def = parse_definition("def smite(lv) = (lv + 1)d8").unwrap();
+    assert_eq!(def.name, "smite");
+    assert_eq!(
+        def.params,
+        vec![Param {
+            name: "lv".to_string(),
+            param_type: ParamType::Num,
+        }]
+    );
+    assert_eq!(def.body, parse_dice("(lv + 1)d8").unwrap());
+
+    let def = parse_definition("DEF volley(n: int, xs: list) = n d6 + sum(xs)").unwrap();
+    assert_eq!(def.params[0].param_type, ParamType::Int);
+    assert_eq!(def.params[1].param_type, ParamType::List);
+    assert_eq!(
+        def.to_string(),
+        "def volley(n: int, xs: list) = (n)d6 + sum(xs)"
+    );
+    assert_eq!(parse_definition(&def.to_string()).unwrap(), def);
+
+    let def = parse_definition("def init() = 1d20 + 2").unwrap();
+    assert!(def.params.is_empty());
+
+    assert!(parse_definition("defsmite(lv) = lv").is_err());
+    assert!(parse_definition("def smite(lv) =").is_err());
+    assert!(parse_definition("def smite(lv: str) = lv").is_err());
+}
+
+#[test]
+fn test_parse_var_and_call() {
+    assert_eq!(parse_dice("lv").unwrap(), Expr::Var("lv".to_string()));
+    assert_eq!(
+        parse_dice("lv d8").unwrap(),
+        Expr::Dice {
+            count: Box::new(Expr::Var("lv".to_string())),
+            side: Box::new(Expr::Number(Num::int(8))),
+        }
+    );
+    assert_eq!(
+        parse_dice("smite(3) + 1").unwrap(),
+        Expr::Binary {
+            lhs: Box::new(Expr::Call {
+                func_name: "smite".to_string(),
+                args: vec![Expr::Number(Num::int(3))],
+            }),
+            op: dice_roller::grammar::BinOp::Add,
+            rhs: Box::new(Expr::Number(Num::int(1))),
+        }
+    );
+
+    // 骰子写法优先于标识符
+    assert!(matches!(parse_dice("d20").unwrap(), Expr::Dice { .. }));
+    assert!(matches!(parse_dice("dF").unwrap(), Expr::FateDice { .. }));
+    assert!(matches!(
+        parse_dice("4dFmi(1)").unwrap(),
+        Expr::Modifier { .. }
+    ));
+
+    // 修饰符后紧跟变量需要加括号
+    let expr = parse_dice("(p)kh1").unwrap();
+    assert_eq!(expr.to_string(), "(p)kh1");
+    assert_eq!(parse_dice(&expr.to_string()).unwrap(), expr);
+}
+
+#[test]
+fn test_expand_functions() {
+    let registry = registry(
+        "def smite(lv: int) = (lv + 1)d8
+         def attack(bonus) = 1d20 + bonus; def twice(x) = x * 2
+         def best(pool) = pool kh1",
+    );
+    assert_eq!(expand(&registry, "smite(3)").unwrap(), "(3 + 1)d8");
+    assert_eq!(
+        expand(&registry, "attack(5) + smite(1)").unwrap(),
+        "1d20 + 5 + (1 + 1)d8"
+    );
+    // 实参也会被展开
+    assert_eq!(
+        expand(&registry, "twice(attack(2))").unwrap(),
+        "(1d20 + 2) * 2"
+    );
+    assert_eq!(expand(&registry, "best(2d20)").unwrap(), "2d20kh1");
+
+    // 没有自定义函数调用时表达式不变
+    assert_eq!(expand(&registry, "max(1d6, 3)").unwrap(), "max(1d6, 3)");
+
+    let names: Vec<&str> = registry
+        .definitions()
+        .iter()
+        .map(|d| d.name.as_str())
+        .collect();
+    assert_eq!(names, vec!["attack", "best", "smite", "twice"]);
+}
+
+#[test]
+fn test_expand_errors() {
+    let registry = registry("def smite(lv: int) = (lv + 1)d8; def best(pool) = pool kh1");
+    for input in [
+        "smite()",
+        "smite(1, 2)",
+        "smite(1d4)",
+        "smite(1.5)",
+        "smite([1])",
+        "best(3)",
+    ] {
+        assert!(expand(&registry, input).is_err(), "{}", input);
+    }
+    assert_eq!(
+        expand(&registry, "smite(1d4)").unwrap_err(),
+        "Argument 'lv' of function smite must be a constant integer, got 1d4."
+    );
+    assert!(
+        expand(&registry, "best(3)")
+            .unwrap_err()
+            .starts_with("In best: ")
+    );
+
+    // 未定义的函数保留到类型检查时报错
+    let expr = registry.expand(&parse_dice("unknown(1)").unwrap()).unwrap();
+    assert_eq!(
+        typecheck_expr(&expr),
+        Type::Invalid("Unknown function: unknown".to_string())
+    );
+}
+
+#[test]
+fn test_load_errors() {
+    for source in [
+        "def max(x) = x",
+        "def table(x) = x",
+        "def f(x) = y",
+        "def f(x) = g(x)",
+        "def f(x, x) = x",
+        "def f(df) = df",
+        "def dfoo(x) = x",
+        "def f(x) = f(x)",
+        "def f(x) = g(x); def g(x) = h(x); def h(x) = f(x)",
+        "def bad() = 1d0",
+        // 带参数的函数体同样在定义时检查
+        "def h(n: int) = max()",
+        "def f(x: list) = x + 1d6",
+        "def f(x) = x + [1]",
+        "def f(x) = x +",
+    ] {
+        let mut registry = FunctionRegistry::new();
+        assert!(registry.load(source).is_err(), "{}", source);
+    }
+
+    let mut registry = FunctionRegistry::new();
+    assert_eq!(
+        registry
+            .load("def f(x) = g(x); def g(x) = f(x)")
+            .unwrap_err(),
+        "Recursive definition: f -> g -> f"
+    );
+
+    // 载入失败时保持原有的定义
+    let mut registry = registry_with_attack();
+    assert!(registry.load("def attack(x) = attack(x)").is_err());
+    assert_eq!(expand(&registry, "attack(1)").unwrap(), "1d20 + 1");
+
+    // 重新定义时不能与已有定义形成环
+    let mut registry = registry_with_attack();
+    registry.load("def crit(x) = attack(x) + 1d8").unwrap();
+    assert!(registry.load("def attack(x) = crit(x)").is_err());
+}
+
+fn registry_with_attack() -> FunctionRegistry {
+    registry("def attack(bonus) = 1d20 + bonus")
+}
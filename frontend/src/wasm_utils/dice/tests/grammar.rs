@@ -1,10 +1,11 @@
 use dice_roller::grammar::*;
+use dice_roller::number::Num;
 
 #[test]
 fn test_number_constant() {
     let result = parse_dice("20");
     assert!(result.is_ok());
-    assert_eq!(result.unwrap(), Expr::Number(20.0));
+    assert_eq!(result.unwrap(), Expr::Number(Num::int(20)));
 }
 
 #[test]
@@ -14,8 +15,8 @@ fn test_dice_expr() {
     assert_eq!(
         result.unwrap(),
         Expr::Dice {
-            count: Box::new(Expr::Number(2.0)),
-            side: Box::new(Expr::Number(20.0))
+            count: Box::new(Expr::Number(Num::int(2))),
+            side: Box::new(Expr::Number(Num::int(20)))
         }
     );
 }
@@ -28,11 +29,11 @@ fn test_recursive_dice_expr() {
         result.unwrap(),
         Expr::Dice {
             count: Box::new(Expr::Binary {
-                lhs: Box::new(Expr::Number(1.0)),
+                lhs: Box::new(Expr::Number(Num::int(1))),
                 op: BinOp::Add,
-                rhs: Box::new(Expr::Number(2.0))
+                rhs: Box::new(Expr::Number(Num::int(2)))
             }),
-            side: Box::new(Expr::Number(6.0)),
+            side: Box::new(Expr::Number(Num::int(6))),
         }
     );
 }
@@ -45,18 +46,18 @@ fn test_recursive_normal_expr() {
         result.unwrap(),
         Expr::Binary {
             lhs: Box::new(Expr::Binary {
-                lhs: Box::new(Expr::Number(1.0)),
+                lhs: Box::new(Expr::Number(Num::int(1))),
                 op: BinOp::Add,
-                rhs: Box::new(Expr::Number(2.0))
+                rhs: Box::new(Expr::Number(Num::int(2)))
             }),
             op: BinOp::Sub,
             rhs: Box::new(Expr::Binary {
-                lhs: Box::new(Expr::Number(3.0)),
+                lhs: Box::new(Expr::Number(Num::int(3))),
                 op: BinOp::Sub,
                 rhs: Box::new(Expr::Binary {
-                    lhs: Box::new(Expr::Number(1.0)),
+                    lhs: Box::new(Expr::Number(Num::int(1))),
                     op: BinOp::Add,
-                    rhs: Box::new(Expr::Number(1.0))
+                    rhs: Box::new(Expr::Number(Num::int(1)))
                 })
             })
         }
@@ -70,8 +71,8 @@ fn test_implict_dice_expr() {
     assert_eq!(
         result.unwrap(),
         Expr::Dice {
-            count: Box::new(Expr::Number(1.0)),
-            side: Box::new(Expr::Number(20.0))
+            count: Box::new(Expr::Number(Num::int(1))),
+            side: Box::new(Expr::Number(Num::int(20)))
         }
     );
 }
@@ -83,15 +84,15 @@ fn test_priority_expr() {
     assert_eq!(
         result.unwrap(),
         Expr::Binary {
-            lhs: Box::new(Expr::Number(1.0)),
+            lhs: Box::new(Expr::Number(Num::int(1))),
             op: BinOp::Add,
             rhs: Box::new(Expr::Binary {
                 lhs: Box::new(Expr::Dice {
-                    count: Box::new(Expr::Number(2.0)),
-                    side: Box::new(Expr::Number(20.0)),
+                    count: Box::new(Expr::Number(Num::int(2))),
+                    side: Box::new(Expr::Number(Num::int(20))),
                 }),
                 op: BinOp::Mul,
-                rhs: Box::new(Expr::Number(3.0))
+                rhs: Box::new(Expr::Number(Num::int(3)))
             })
         }
     );
@@ -104,11 +105,11 @@ fn test_div_expr() {
     assert_eq!(
         result.unwrap(),
         Expr::Binary {
-            lhs: Box::new(Expr::Number(10.0)),
+            lhs: Box::new(Expr::Number(Num::int(10))),
             op: BinOp::Div,
             rhs: Box::new(Expr::Dice {
-                count: Box::new(Expr::Number(2.0)),
-                side: Box::new(Expr::Number(5.0)),
+                count: Box::new(Expr::Number(Num::int(2))),
+                side: Box::new(Expr::Number(Num::int(5))),
             })
         }
     );
@@ -121,11 +122,11 @@ fn test_idiv_expr() {
     assert_eq!(
         result.unwrap(),
         Expr::Binary {
-            lhs: Box::new(Expr::Number(10.0)),
+            lhs: Box::new(Expr::Number(Num::int(10))),
             op: BinOp::Idiv,
             rhs: Box::new(Expr::Dice {
-                count: Box::new(Expr::Number(2.0)),
-                side: Box::new(Expr::Number(5.0)),
+                count: Box::new(Expr::Number(Num::int(2))),
+                side: Box::new(Expr::Number(Num::int(5))),
             })
         }
     );
@@ -139,15 +140,79 @@ fn test_mod_expr() {
         result.unwrap(),
         Expr::Binary {
             lhs: Box::new(Expr::Dice {
-                count: Box::new(Expr::Number(3.0)),
-                side: Box::new(Expr::Number(4.0)),
+                count: Box::new(Expr::Number(Num::int(3))),
+                side: Box::new(Expr::Number(Num::int(4))),
             }),
             op: BinOp::Mod,
-            rhs: Box::new(Expr::Number(10.0))
+            rhs: Box::new(Expr::Number(Num::int(10)))
         }
     );
 }
 
+#[test]
+fn test_pow_expr() {
+    // 乘方右结合，优先级高于负号与乘除
+    let num = |n: f64| Box::new(Expr::Number(n.into()));
+    assert_eq!(
+        parse_dice("2 ^ 3 ^ 2").unwrap(),
+        Expr::Binary {
+            lhs: num(2.0),
+            op: BinOp::Pow,
+            rhs: Box::new(Expr::Binary {
+                lhs: num(3.0),
+                op: BinOp::Pow,
+                rhs: num(2.0),
+            }),
+        }
+    );
+    assert_eq!(
+        parse_dice("-2^2").unwrap(),
+        Expr::Binary {
+            lhs: num(0.0),
+            op: BinOp::Sub,
+            rhs: Box::new(Expr::Binary {
+                lhs: num(2.0),
+                op: BinOp::Pow,
+                rhs: num(2.0),
+            }),
+        }
+    );
+    assert_eq!(
+        parse_dice("3 * 1d6^2").unwrap(),
+        Expr::Binary {
+            lhs: num(3.0),
+            op: BinOp::Mul,
+            rhs: Box::new(Expr::Binary {
+                lhs: Box::new(Expr::Dice {
+                    count: num(1.0),
+                    side: num(6.0),
+                }),
+                op: BinOp::Pow,
+                rhs: num(2.0),
+            }),
+        }
+    );
+
+    assert_eq!(parse_dice("(2^3)^2").unwrap().to_string(), "(2 ^ 3) ^ 2");
+    assert_eq!(parse_dice("2^3^2").unwrap().to_string(), "2 ^ 3 ^ 2");
+}
+
+#[test]
+fn test_math_functions_expr() {
+    for input in [
+        "clamp(1d20, 5, 15)",
+        "pow(2, 1d4)",
+        "sqrt(16)",
+        "median([1d6, 2d6, 3d6])",
+        "avg(1, 2, 3)",
+        "count([1, 2, 2], 2)",
+        "len([1d6] * 6)",
+    ] {
+        let expr = parse_dice(input).unwrap();
+        assert_eq!(expr.to_string(), input);
+    }
+}
+
 #[test]
 fn test_list_expr() {
     let result = parse_dice("[2d6, 3d4, 1d20]");
@@ -156,16 +221,16 @@ fn test_list_expr() {
         result.unwrap(),
         Expr::List(vec![
             Expr::Dice {
-                count: Box::new(Expr::Number(2.0)),
-                side: Box::new(Expr::Number(6.0)),
+                count: Box::new(Expr::Number(Num::int(2))),
+                side: Box::new(Expr::Number(Num::int(6))),
             },
             Expr::Dice {
-                count: Box::new(Expr::Number(3.0)),
-                side: Box::new(Expr::Number(4.0)),
+                count: Box::new(Expr::Number(Num::int(3))),
+                side: Box::new(Expr::Number(Num::int(4))),
             },
             Expr::Dice {
-                count: Box::new(Expr::Number(1.0)),
-                side: Box::new(Expr::Number(20.0)),
+                count: Box::new(Expr::Number(Num::int(1))),
+                side: Box::new(Expr::Number(Num::int(20))),
             },
         ])
     );
@@ -180,20 +245,20 @@ fn test_list_multi_expr() {
         Expr::Binary {
             lhs: Box::new(Expr::List(vec![
                 Expr::Dice {
-                    count: Box::new(Expr::Number(1.0)),
-                    side: Box::new(Expr::Number(6.0)),
+                    count: Box::new(Expr::Number(Num::int(1))),
+                    side: Box::new(Expr::Number(Num::int(6))),
                 },
                 Expr::Dice {
-                    count: Box::new(Expr::Number(2.0)),
-                    side: Box::new(Expr::Number(8.0)),
+                    count: Box::new(Expr::Number(Num::int(2))),
+                    side: Box::new(Expr::Number(Num::int(8))),
                 },
                 Expr::Dice {
-                    count: Box::new(Expr::Number(3.0)),
-                    side: Box::new(Expr::Number(10.0)),
+                    count: Box::new(Expr::Number(Num::int(3))),
+                    side: Box::new(Expr::Number(Num::int(10))),
                 },
             ])),
             op: BinOp::Mul,
-            rhs: Box::new(Expr::Number(2.0))
+            rhs: Box::new(Expr::Number(Num::int(2)))
         }
     );
 }
@@ -208,16 +273,16 @@ fn test_max_list() {
             func_name: "max".to_string(),
             args: vec![Expr::List(vec![
                 Expr::Dice {
-                    count: Box::new(Expr::Number(2.0)),
-                    side: Box::new(Expr::Number(6.0)),
+                    count: Box::new(Expr::Number(Num::int(2))),
+                    side: Box::new(Expr::Number(Num::int(6))),
                 },
                 Expr::Dice {
-                    count: Box::new(Expr::Number(3.0)),
-                    side: Box::new(Expr::Number(4.0)),
+                    count: Box::new(Expr::Number(Num::int(3))),
+                    side: Box::new(Expr::Number(Num::int(4))),
                 },
                 Expr::Dice {
-                    count: Box::new(Expr::Number(1.0)),
-                    side: Box::new(Expr::Number(20.0)),
+                    count: Box::new(Expr::Number(Num::int(1))),
+                    side: Box::new(Expr::Number(Num::int(20))),
                 }
             ])],
         }
@@ -234,16 +299,16 @@ fn test_max_args() {
             func_name: "max".to_string(),
             args: vec![
                 Expr::Dice {
-                    count: Box::new(Expr::Number(2.0)),
-                    side: Box::new(Expr::Number(6.0)),
+                    count: Box::new(Expr::Number(Num::int(2))),
+                    side: Box::new(Expr::Number(Num::int(6))),
                 },
                 Expr::Dice {
-                    count: Box::new(Expr::Number(3.0)),
-                    side: Box::new(Expr::Number(4.0)),
+                    count: Box::new(Expr::Number(Num::int(3))),
+                    side: Box::new(Expr::Number(Num::int(4))),
                 },
                 Expr::Dice {
-                    count: Box::new(Expr::Number(1.0)),
-                    side: Box::new(Expr::Number(20.0)),
+                    count: Box::new(Expr::Number(Num::int(1))),
+                    side: Box::new(Expr::Number(Num::int(20))),
                 },
             ],
         }
@@ -297,11 +362,11 @@ fn test_keephigh() {
         result.unwrap(),
         Expr::Modifier {
             lhs: Box::new(Expr::Dice {
-                count: Box::new(Expr::Number(2.0)),
-                side: Box::new(Expr::Number(20.0)),
+                count: Box::new(Expr::Number(Num::int(2))),
+                side: Box::new(Expr::Number(Num::int(20))),
             }),
             op: ModifierOp::KeepHigh,
-            param: Some(ModifierParam::Value(Box::new(Expr::Number(1.0)))),
+            param: Some(ModifierParam::Value(Box::new(Expr::Number(Num::int(1))))),
         }
     );
 }
@@ -314,11 +379,11 @@ fn test_keeplow() {
         result.unwrap(),
         Expr::Modifier {
             lhs: Box::new(Expr::Dice {
-                count: Box::new(Expr::Number(3.0)),
-                side: Box::new(Expr::Number(20.0)),
+                count: Box::new(Expr::Number(Num::int(3))),
+                side: Box::new(Expr::Number(Num::int(20))),
             }),
             op: ModifierOp::KeepLow,
-            param: Some(ModifierParam::Value(Box::new(Expr::Number(1.0)))),
+            param: Some(ModifierParam::Value(Box::new(Expr::Number(Num::int(1))))),
         }
     );
 }
@@ -331,11 +396,11 @@ fn test_drophigh() {
         result.unwrap(),
         Expr::Modifier {
             lhs: Box::new(Expr::Dice {
-                count: Box::new(Expr::Number(4.0)),
-                side: Box::new(Expr::Number(20.0)),
+                count: Box::new(Expr::Number(Num::int(4))),
+                side: Box::new(Expr::Number(Num::int(20))),
             }),
             op: ModifierOp::DropHigh,
-            param: Some(ModifierParam::Value(Box::new(Expr::Number(1.0)))),
+            param: Some(ModifierParam::Value(Box::new(Expr::Number(Num::int(1))))),
         }
     );
 }
@@ -348,11 +413,11 @@ fn test_droplow() {
         result.unwrap(),
         Expr::Modifier {
             lhs: Box::new(Expr::Dice {
-                count: Box::new(Expr::Number(5.0)),
-                side: Box::new(Expr::Number(20.0)),
+                count: Box::new(Expr::Number(Num::int(5))),
+                side: Box::new(Expr::Number(Num::int(20))),
             }),
             op: ModifierOp::DropLow,
-            param: Some(ModifierParam::Value(Box::new(Expr::Number(1.0)))),
+            param: Some(ModifierParam::Value(Box::new(Expr::Number(Num::int(1))))),
         }
     );
 }
@@ -365,11 +430,11 @@ fn test_keephigh_with_param() {
         result.unwrap(),
         Expr::Modifier {
             lhs: Box::new(Expr::Dice {
-                count: Box::new(Expr::Number(2.0)),
-                side: Box::new(Expr::Number(20.0)),
+                count: Box::new(Expr::Number(Num::int(2))),
+                side: Box::new(Expr::Number(Num::int(20))),
             }),
             op: ModifierOp::KeepHigh,
-            param: Some(ModifierParam::Value(Box::new(Expr::Number(1.0)))),
+            param: Some(ModifierParam::Value(Box::new(Expr::Number(Num::int(1))))),
         }
     );
 }
@@ -382,11 +447,11 @@ fn test_keeplow_with_param() {
         result.unwrap(),
         Expr::Modifier {
             lhs: Box::new(Expr::Dice {
-                count: Box::new(Expr::Number(3.0)),
-                side: Box::new(Expr::Number(20.0)),
+                count: Box::new(Expr::Number(Num::int(3))),
+                side: Box::new(Expr::Number(Num::int(20))),
             }),
             op: ModifierOp::KeepLow,
-            param: Some(ModifierParam::Value(Box::new(Expr::Number(2.0)))),
+            param: Some(ModifierParam::Value(Box::new(Expr::Number(Num::int(2))))),
         }
     );
 }
@@ -399,11 +464,11 @@ fn test_drophigh_with_param() {
         result.unwrap(),
         Expr::Modifier {
             lhs: Box::new(Expr::Dice {
-                count: Box::new(Expr::Number(4.0)),
-                side: Box::new(Expr::Number(20.0)),
+                count: Box::new(Expr::Number(Num::int(4))),
+                side: Box::new(Expr::Number(Num::int(20))),
             }),
             op: ModifierOp::DropHigh,
-            param: Some(ModifierParam::Value(Box::new(Expr::Number(3.0)))),
+            param: Some(ModifierParam::Value(Box::new(Expr::Number(Num::int(3))))),
         }
     );
 }
@@ -416,11 +481,11 @@ fn test_droplow_with_param() {
         result.unwrap(),
         Expr::Modifier {
             lhs: Box::new(Expr::Dice {
-                count: Box::new(Expr::Number(5.0)),
-                side: Box::new(Expr::Number(20.0)),
+                count: Box::new(Expr::Number(Num::int(5))),
+                side: Box::new(Expr::Number(Num::int(20))),
             }),
             op: ModifierOp::DropLow,
-            param: Some(ModifierParam::Value(Box::new(Expr::Number(4.0)))),
+            param: Some(ModifierParam::Value(Box::new(Expr::Number(Num::int(4))))),
         }
     );
 }
@@ -433,11 +498,11 @@ fn test_pos() {
         result.unwrap(),
         Expr::Modifier {
             lhs: Box::new(Expr::Dice {
-                count: Box::new(Expr::Number(5.0)),
-                side: Box::new(Expr::Number(20.0)),
+                count: Box::new(Expr::Number(Num::int(5))),
+                side: Box::new(Expr::Number(Num::int(20))),
             }),
             op: ModifierOp::DropLow,
-            param: Some(ModifierParam::Value(Box::new(Expr::Number(4.0)))),
+            param: Some(ModifierParam::Value(Box::new(Expr::Number(Num::int(4))))),
         }
     );
 }
@@ -449,15 +514,15 @@ fn test_neg() {
     assert_eq!(
         result.unwrap(),
         Expr::Binary {
-            lhs: Box::new(Expr::Number(0.0)),
+            lhs: Box::new(Expr::Number(Num::int(0))),
             op: BinOp::Sub,
             rhs: Box::new(Expr::Modifier {
                 lhs: Box::new(Expr::Dice {
-                    count: Box::new(Expr::Number(5.0)),
-                    side: Box::new(Expr::Number(20.0)),
+                    count: Box::new(Expr::Number(Num::int(5))),
+                    side: Box::new(Expr::Number(Num::int(20))),
                 }),
                 op: ModifierOp::DropLow,
-                param: Some(ModifierParam::Value(Box::new(Expr::Number(4.0)))),
+                param: Some(ModifierParam::Value(Box::new(Expr::Number(Num::int(4))))),
             })
         }
     );
@@ -471,13 +536,15 @@ fn test_compare_expr() {
         result.unwrap(),
         Expr::SuccessCheck {
             lhs: Box::new(Expr::Dice {
-                count: Box::new(Expr::Number(2.0)),
-                side: Box::new(Expr::Number(20.0))
+                count: Box::new(Expr::Number(Num::int(2))),
+                side: Box::new(Expr::Number(Num::int(20)))
             },),
             compare_expr: CompareExpr {
                 op: CompareOp::LessEqual,
-                val: Box::new(Expr::Number(15.0)),
+                val: Box::new(Expr::Number(Num::int(15))),
             },
+            failure: None,
+            double_success: None,
         }
     );
 }
@@ -490,13 +557,15 @@ fn test_compare_expr2() {
         result.unwrap(),
         Expr::SuccessCheck {
             lhs: Box::new(Expr::Dice {
-                count: Box::new(Expr::Number(2.0)),
-                side: Box::new(Expr::Number(20.0))
+                count: Box::new(Expr::Number(Num::int(2))),
+                side: Box::new(Expr::Number(Num::int(20)))
             },),
             compare_expr: CompareExpr {
                 op: CompareOp::GreaterEqual,
-                val: Box::new(Expr::Number(15.0)),
+                val: Box::new(Expr::Number(Num::int(15))),
             },
+            failure: None,
+            double_success: None,
         }
     );
 }
@@ -509,13 +578,15 @@ fn test_compare_expr3() {
         result.unwrap(),
         Expr::SuccessCheck {
             lhs: Box::new(Expr::Dice {
-                count: Box::new(Expr::Number(2.0)),
-                side: Box::new(Expr::Number(20.0))
+                count: Box::new(Expr::Number(Num::int(2))),
+                side: Box::new(Expr::Number(Num::int(20)))
             },),
             compare_expr: CompareExpr {
                 op: CompareOp::Equal,
-                val: Box::new(Expr::Number(15.0)),
+                val: Box::new(Expr::Number(Num::int(15))),
             },
+            failure: None,
+            double_success: None,
         }
     );
 }
@@ -528,13 +599,15 @@ fn test_compare_expr4() {
         result.unwrap(),
         Expr::SuccessCheck {
             lhs: Box::new(Expr::Dice {
-                count: Box::new(Expr::Number(2.0)),
-                side: Box::new(Expr::Number(20.0))
+                count: Box::new(Expr::Number(Num::int(2))),
+                side: Box::new(Expr::Number(Num::int(20)))
             },),
             compare_expr: CompareExpr {
                 op: CompareOp::Greater,
-                val: Box::new(Expr::Number(15.0)),
+                val: Box::new(Expr::Number(Num::int(15))),
             },
+            failure: None,
+            double_success: None,
         }
     );
 }
@@ -547,13 +620,15 @@ fn test_compare_expr5() {
         result.unwrap(),
         Expr::SuccessCheck {
             lhs: Box::new(Expr::Dice {
-                count: Box::new(Expr::Number(2.0)),
-                side: Box::new(Expr::Number(20.0))
+                count: Box::new(Expr::Number(Num::int(2))),
+                side: Box::new(Expr::Number(Num::int(20)))
             },),
             compare_expr: CompareExpr {
                 op: CompareOp::Less,
-                val: Box::new(Expr::Number(15.0)),
+                val: Box::new(Expr::Number(Num::int(15))),
             },
+            failure: None,
+            double_success: None,
         }
     );
 }
@@ -566,8 +641,8 @@ fn test_explode_expr() {
         result.unwrap(),
         Expr::Modifier {
             lhs: Box::new(Expr::Dice {
-                count: Box::new(Expr::Number(2.0)),
-                side: Box::new(Expr::Number(6.0)),
+                count: Box::new(Expr::Number(Num::int(2))),
+                side: Box::new(Expr::Number(Num::int(6))),
             }),
             op: ModifierOp::Explode,
             param: None,
@@ -583,13 +658,13 @@ fn test_explode_expr_with_param() {
         result.unwrap(),
         Expr::Modifier {
             lhs: Box::new(Expr::Dice {
-                count: Box::new(Expr::Number(2.0)),
-                side: Box::new(Expr::Number(6.0)),
+                count: Box::new(Expr::Number(Num::int(2))),
+                side: Box::new(Expr::Number(Num::int(6))),
             }),
             op: ModifierOp::Explode,
             param: Some(ModifierParam::Compare(CompareExpr {
                 op: CompareOp::Equal,
-                val: Box::new(Expr::Number(3.0))
+                val: Box::new(Expr::Number(Num::int(3)))
             })),
         }
     );
@@ -603,8 +678,8 @@ fn test_explode_compound_expr() {
         result.unwrap(),
         Expr::Modifier {
             lhs: Box::new(Expr::Dice {
-                count: Box::new(Expr::Number(2.0)),
-                side: Box::new(Expr::Number(6.0)),
+                count: Box::new(Expr::Number(Num::int(2))),
+                side: Box::new(Expr::Number(Num::int(6))),
             }),
             op: ModifierOp::ExplodeCompound,
             param: None,
@@ -620,13 +695,13 @@ fn test_explode_compound_expr_with_param() {
         result.unwrap(),
         Expr::Modifier {
             lhs: Box::new(Expr::Dice {
-                count: Box::new(Expr::Number(2.0)),
-                side: Box::new(Expr::Number(6.0)),
+                count: Box::new(Expr::Number(Num::int(2))),
+                side: Box::new(Expr::Number(Num::int(6))),
             }),
             op: ModifierOp::ExplodeCompound,
             param: Some(ModifierParam::Compare(CompareExpr {
                 op: CompareOp::LessEqual,
-                val: Box::new(Expr::Number(4.0))
+                val: Box::new(Expr::Number(Num::int(4)))
             })),
         }
     );
@@ -641,25 +716,62 @@ fn test_explode_compound_expr_with_param_and_limit() {
         Expr::Modifier {
             lhs: Box::new(Expr::Modifier {
                 lhs: Box::new(Expr::Dice {
-                    count: Box::new(Expr::Number(2.0)),
-                    side: Box::new(Expr::Number(6.0)),
+                    count: Box::new(Expr::Number(Num::int(2))),
+                    side: Box::new(Expr::Number(Num::int(6))),
                 }),
                 op: ModifierOp::ExplodeCompound,
                 param: Some(ModifierParam::Compare(CompareExpr {
                     op: CompareOp::LessEqual,
-                    val: Box::new(Expr::Number(4.0))
+                    val: Box::new(Expr::Number(Num::int(4)))
                 })),
             }),
             op: ModifierOp::Limit,
             param: Some(ModifierParam::Value(Box::new(Expr::Binary {
-                lhs: Box::new(Expr::Number(1.0)),
+                lhs: Box::new(Expr::Number(Num::int(1))),
                 op: BinOp::Add,
-                rhs: Box::new(Expr::Number(1.0))
+                rhs: Box::new(Expr::Number(Num::int(1)))
             })))
         }
     );
 }
 
+#[test]
+fn test_explode_penetrate_expr_with_limit() {
+    let result = parse_dice("2d6!p>5l2");
+    assert_eq!(
+        result.unwrap(),
+        Expr::Modifier {
+            lhs: Box::new(Expr::Modifier {
+                lhs: Box::new(Expr::Dice {
+                    count: Box::new(Expr::Number(Num::int(2))),
+                    side: Box::new(Expr::Number(Num::int(6))),
+                }),
+                op: ModifierOp::ExplodePenetrate,
+                param: Some(ModifierParam::Compare(CompareExpr {
+                    op: CompareOp::Greater,
+                    val: Box::new(Expr::Number(Num::int(5)))
+                })),
+            }),
+            op: ModifierOp::Limit,
+            param: Some(ModifierParam::Value(Box::new(Expr::Number(Num::int(2)))))
+        }
+    );
+
+    let result = parse_dice("1d6!P");
+    assert_eq!(
+        result.unwrap(),
+        Expr::Modifier {
+            lhs: Box::new(Expr::Dice {
+                count: Box::new(Expr::Number(Num::int(1))),
+                side: Box::new(Expr::Number(Num::int(6))),
+            }),
+            op: ModifierOp::ExplodePenetrate,
+            param: None
+        }
+    );
+    assert_eq!(parse_dice("1d6!pl3").unwrap().to_string(), "1d6!pl3");
+}
+
 #[test]
 fn test_reroll_expr() {
     let result = parse_dice("2d20r<5");
@@ -668,13 +780,13 @@ fn test_reroll_expr() {
         result.unwrap(),
         Expr::Modifier {
             lhs: Box::new(Expr::Dice {
-                count: Box::new(Expr::Number(2.0)),
-                side: Box::new(Expr::Number(20.0)),
+                count: Box::new(Expr::Number(Num::int(2))),
+                side: Box::new(Expr::Number(Num::int(20))),
             }),
             op: ModifierOp::Reroll,
             param: Some(ModifierParam::Compare(CompareExpr {
                 op: CompareOp::Less,
-                val: Box::new(Expr::Number(5.0))
+                val: Box::new(Expr::Number(Num::int(5)))
             })),
         }
     );
@@ -688,13 +800,13 @@ fn test_reroll_once_expr() {
         result.unwrap(),
         Expr::Modifier {
             lhs: Box::new(Expr::Dice {
-                count: Box::new(Expr::Number(2.0)),
-                side: Box::new(Expr::Number(20.0)),
+                count: Box::new(Expr::Number(Num::int(2))),
+                side: Box::new(Expr::Number(Num::int(20))),
             }),
             op: ModifierOp::RerollOnce,
             param: Some(ModifierParam::Compare(CompareExpr {
                 op: CompareOp::GreaterEqual,
-                val: Box::new(Expr::Number(5.0))
+                val: Box::new(Expr::Number(Num::int(5)))
             })),
         }
     );
@@ -711,3 +823,447 @@ fn test_reroll_once_expr_without_param() {
     let result = parse_dice("2d20ro");
     assert!(result.is_err());
 }
+
+#[test]
+fn test_clamp_expr() {
+    let result = parse_dice("1d20mi10");
+    assert_eq!(
+        result.unwrap(),
+        Expr::Modifier {
+            lhs: Box::new(Expr::Dice {
+                count: Box::new(Expr::Number(Num::int(1))),
+                side: Box::new(Expr::Number(Num::int(20))),
+            }),
+            op: ModifierOp::ClampMin,
+            param: Some(ModifierParam::Value(Box::new(Expr::Number(Num::int(10))))),
+        }
+    );
+
+    let result = parse_dice("4d6MA5kh3");
+    assert_eq!(
+        result.unwrap(),
+        Expr::Modifier {
+            lhs: Box::new(Expr::Modifier {
+                lhs: Box::new(Expr::Dice {
+                    count: Box::new(Expr::Number(Num::int(4))),
+                    side: Box::new(Expr::Number(Num::int(6))),
+                }),
+                op: ModifierOp::ClampMax,
+                param: Some(ModifierParam::Value(Box::new(Expr::Number(Num::int(5))))),
+            }),
+            op: ModifierOp::KeepHigh,
+            param: Some(ModifierParam::Value(Box::new(Expr::Number(Num::int(3))))),
+        }
+    );
+
+    // 参数是必须的
+    assert!(parse_dice("1d20mi").is_err());
+    assert_eq!(parse_dice("2d6mi2ma5").unwrap().to_string(), "2d6mi2ma5");
+}
+
+#[test]
+fn test_sort_and_count_expr() {
+    let dice = Expr::Dice {
+        count: Box::new(Expr::Number(Num::int(4))),
+        side: Box::new(Expr::Number(Num::int(6))),
+    };
+    let sort_asc = Expr::Modifier {
+        lhs: Box::new(dice.clone()),
+        op: ModifierOp::SortAsc,
+        param: None,
+    };
+    assert_eq!(parse_dice("4d6s").unwrap(), sort_asc);
+    assert_eq!(parse_dice("4d6sa").unwrap(), sort_asc);
+    assert_eq!(
+        parse_dice("4d6SD").unwrap(),
+        Expr::Modifier {
+            lhs: Box::new(dice.clone()),
+            op: ModifierOp::SortDesc,
+            param: None,
+        }
+    );
+
+    assert_eq!(
+        parse_dice("4d6cnt>=5").unwrap(),
+        Expr::Modifier {
+            lhs: Box::new(dice),
+            op: ModifierOp::Count,
+            param: Some(ModifierParam::Compare(CompareExpr {
+                op: CompareOp::GreaterEqual,
+                val: Box::new(Expr::Number(Num::int(5)))
+            })),
+        }
+    );
+
+    // 计数必须带比较符
+    assert!(parse_dice("4d6cnt5").is_err());
+    assert_eq!(parse_dice("4d6s").unwrap().to_string(), "4d6sa");
+    assert_eq!(parse_dice("4d6sdcnt=6").unwrap().to_string(), "4d6sdcnt=6");
+
+    assert_eq!(
+        parse_dice("4d6U").unwrap(),
+        Expr::Modifier {
+            lhs: Box::new(Expr::Dice {
+                count: Box::new(Expr::Number(Num::int(4))),
+                side: Box::new(Expr::Number(Num::int(6))),
+            }),
+            op: ModifierOp::Unique,
+            param: None,
+        }
+    );
+    assert_eq!(parse_dice("4d6ukh3").unwrap().to_string(), "4d6ukh3");
+}
+
+#[test]
+fn test_critical_expr() {
+    let dice = Expr::Dice {
+        count: Box::new(Expr::Number(Num::int(1))),
+        side: Box::new(Expr::Number(Num::int(20))),
+    };
+    assert_eq!(
+        parse_dice("1d20cs>=19").unwrap(),
+        Expr::Modifier {
+            lhs: Box::new(dice.clone()),
+            op: ModifierOp::CritSuccess,
+            param: Some(ModifierParam::Compare(CompareExpr {
+                op: CompareOp::GreaterEqual,
+                val: Box::new(Expr::Number(Num::int(19)))
+            })),
+        }
+    );
+    assert_eq!(
+        parse_dice("1d20cf").unwrap(),
+        Expr::Modifier {
+            lhs: Box::new(dice),
+            op: ModifierOp::CritFailure,
+            param: None,
+        }
+    );
+
+    // cs 之后的比较符属于 cs，成功检定需要写在后面
+    assert_eq!(
+        parse_dice("1d20cs20cf<=2>=10").unwrap().to_string(),
+        "1d20cs=20cf<=2>=10"
+    );
+}
+
+#[test]
+fn test_success_options_expr() {
+    let expected = Expr::SuccessCheck {
+        lhs: Box::new(Expr::Dice {
+            count: Box::new(Expr::Number(Num::int(10))),
+            side: Box::new(Expr::Number(Num::int(10))),
+        }),
+        compare_expr: CompareExpr {
+            op: CompareOp::GreaterEqual,
+            val: Box::new(Expr::Number(Num::int(8))),
+        },
+        failure: Some(CompareExpr {
+            op: CompareOp::Equal,
+            val: Box::new(Expr::Number(Num::int(1))),
+        }),
+        double_success: Some(CompareExpr {
+            op: CompareOp::Equal,
+            val: Box::new(Expr::Number(Num::int(10))),
+        }),
+    };
+    assert_eq!(parse_dice("10d10>=8f1ds10").unwrap(), expected);
+    // 两个选项的顺序任意
+    assert_eq!(parse_dice("10d10>=8ds10f1").unwrap(), expected);
+    assert_eq!(expected.to_string(), "10d10>=8f=1ds=10");
+
+    let result = parse_dice("5d6>4f<=2");
+    assert!(result.is_ok());
+
+    // 每个选项最多出现一次
+    assert!(parse_dice("10d10>=8f1f2").is_err());
+}
+
+#[test]
+fn test_list_access_expr() {
+    let list = Expr::List(vec![
+        Expr::Number(Num::int(1)),
+        Expr::Number(Num::int(2)),
+        Expr::Number(Num::int(3)),
+    ]);
+    assert_eq!(
+        parse_dice("[1, 2, 3][-1]").unwrap(),
+        Expr::Index {
+            list: Box::new(list.clone()),
+            index: Box::new(Expr::Binary {
+                lhs: Box::new(Expr::Number(Num::int(0))),
+                op: BinOp::Sub,
+                rhs: Box::new(Expr::Number(Num::int(1))),
+            }),
+        }
+    );
+    assert_eq!(
+        parse_dice("[1, 2, 3][1:]").unwrap(),
+        Expr::Slice {
+            list: Box::new(list.clone()),
+            start: Some(Box::new(Expr::Number(Num::int(1)))),
+            end: None,
+        }
+    );
+    assert_eq!(
+        parse_dice("[1, 2, 3][:2]").unwrap(),
+        Expr::Slice {
+            list: Box::new(list.clone()),
+            start: None,
+            end: Some(Box::new(Expr::Number(Num::int(2)))),
+        }
+    );
+    assert_eq!(
+        parse_dice("filter([1, 2, 3], >=2)").unwrap(),
+        Expr::Filter {
+            list: Box::new(list),
+            compare_expr: CompareExpr {
+                op: CompareOp::GreaterEqual,
+                val: Box::new(Expr::Number(Num::int(2))),
+            },
+        }
+    );
+
+    // 下标可以连续使用
+    assert!(matches!(
+        parse_dice("[[1, 2]][0][1]").unwrap(),
+        Expr::Index { .. }
+    ));
+    assert!(parse_dice("[1, 2][]").is_err());
+}
+
+#[test]
+fn test_group_expr() {
+    let d20 = Expr::Dice {
+        count: Box::new(Expr::Number(Num::int(1))),
+        side: Box::new(Expr::Number(Num::int(20))),
+    };
+    let plus = |n: f64| Expr::Binary {
+        lhs: Box::new(d20.clone()),
+        op: BinOp::Add,
+        rhs: Box::new(Expr::Number(n.into())),
+    };
+    assert_eq!(
+        parse_dice("{1d20+5, 1d20+3}kh1").unwrap(),
+        Expr::Modifier {
+            lhs: Box::new(Expr::Group(vec![plus(5.0), plus(3.0)])),
+            op: ModifierOp::KeepHigh,
+            param: Some(ModifierParam::Value(Box::new(Expr::Number(Num::int(1))))),
+        }
+    );
+    assert!(matches!(
+        parse_dice("{3d6, 4d6, 5d6}>=15").unwrap(),
+        Expr::SuccessCheck { .. }
+    ));
+
+    // 只有一个元素时与括号相同，d 之后仍然是自定义骰面
+    assert_eq!(
+        parse_dice("{4d6}kh3").unwrap(),
+        parse_dice("4d6kh3").unwrap()
+    );
+    assert!(matches!(
+        parse_dice("2d{1, 1, 2}").unwrap(),
+        Expr::Dice { side, .. } if matches!(*side, Expr::List(_))
+    ));
+    assert!(parse_dice("{1d20, }kh1").is_err());
+}
+
+#[test]
+fn test_label_and_comment_expr() {
+    let dice = |count: f64, side: f64| Expr::Dice {
+        count: Box::new(Expr::Number(count.into())),
+        side: Box::new(Expr::Number(side.into())),
+    };
+    assert_eq!(
+        parse_dice("2d6 [Sneak Attack] + 1d8").unwrap(),
+        Expr::Binary {
+            lhs: Box::new(Expr::Label {
+                expr: Box::new(dice(2.0, 6.0)),
+                label: "Sneak Attack".to_string(),
+            }),
+            op: BinOp::Add,
+            rhs: Box::new(dice(1.0, 8.0)),
+        }
+    );
+    assert_eq!(
+        parse_dice("1d20 + 5 #  stealth check ").unwrap(),
+        Expr::Comment {
+            expr: Box::new(Expr::Binary {
+                lhs: Box::new(dice(1.0, 20.0)),
+                op: BinOp::Add,
+                rhs: Box::new(Expr::Number(Num::int(5))),
+            }),
+            text: "stealth check".to_string(),
+        }
+    );
+
+    // 单独的标识符是标签，其他表达式仍然是下标
+    assert!(matches!(
+        parse_dice("4d6kh3[fire]").unwrap(),
+        Expr::Label { .. }
+    ));
+    assert!(matches!(
+        parse_dice("[1, 2][1]").unwrap(),
+        Expr::Index { .. }
+    ));
+    assert!(matches!(
+        parse_dice("[1, 2][(i)]").unwrap(),
+        Expr::Index { .. }
+    ));
+
+    // 标签必须在修饰符之后，注释必须在末尾
+    assert!(parse_dice("2d6 [fire] kh1").is_err());
+    assert_eq!(
+        parse_dice("1d20 # stealth + 5").unwrap().to_string(),
+        "1d20 # stealth + 5"
+    );
+    assert!(parse_dice("2d6 [] + 1").is_err());
+}
+
+#[test]
+fn test_display_round_trip() {
+    for input in [
+        "1d20 + 5",
+        "(1 + 2) * 3",
+        "1 - (2 - 3)",
+        "(1d8 + 2)d6",
+        "4d6dl1",
+        "2d6!!<=4l(1 + 1)",
+        "2d20ro>=5kh1",
+        "3d6>=4",
+        "max([2d6, 3d4], 1) // 2",
+        "rpdice(4d6kh3, 6)",
+        "-5d20dl4",
+        "([1d6] * 4)[1:3]",
+        "[[1, 2], [3]][-1][0]",
+        "sort(filter([1d20] * 4, >=10))[:2]",
+        "2d6 [Sneak Attack] + 1d8 [Hunter's Mark]",
+        "(2d6 [fire])kh1 + (1d4 [a])d6",
+        "(1d20 + 5) [to hit] # attack",
+        "{1d20 + 5, 1d20 + 3}kh1 + 2",
+        "{{1d20, 1d20}kl1, 1d20}kh1",
+        "{3d6, 4d6 [str]}>=10f3",
+    ] {
+        let expr = parse_dice(input).unwrap();
+        assert_eq!(parse_dice(&expr.to_string()).unwrap(), expr);
+    }
+
+    assert_eq!(parse_dice("1d20+5").unwrap().to_string(), "1d20 + 5");
+    assert_eq!(parse_dice("2d20kh").unwrap().to_string(), "2d20kh1");
+    assert_eq!(Expr::Number(Num::int(-3)).to_string(), "-3");
+}
+
+#[test]
+fn test_fate_dice_expr() {
+    let result = parse_dice("4dF");
+    assert_eq!(
+        result.unwrap(),
+        Expr::FateDice {
+            count: Box::new(Expr::Number(Num::int(4)))
+        }
+    );
+
+    let result = parse_dice("df + 1");
+    assert_eq!(
+        result.unwrap(),
+        Expr::Binary {
+            lhs: Box::new(Expr::FateDice {
+                count: Box::new(Expr::Number(Num::int(1)))
+            }),
+            op: BinOp::Add,
+            rhs: Box::new(Expr::Number(Num::int(1)))
+        }
+    );
+
+    let result = parse_dice("4dFkh2");
+    assert_eq!(
+        result.unwrap(),
+        Expr::Modifier {
+            lhs: Box::new(Expr::FateDice {
+                count: Box::new(Expr::Number(Num::int(4)))
+            }),
+            op: ModifierOp::KeepHigh,
+            param: Some(ModifierParam::Value(Box::new(Expr::Number(Num::int(2)))))
+        }
+    );
+
+    // floor 不能被误认为 Fate 骰
+    let result = parse_dice("1dfloor(6.5)");
+    assert_eq!(
+        result.unwrap(),
+        Expr::Dice {
+            count: Box::new(Expr::Number(Num::int(1))),
+            side: Box::new(Expr::Call {
+                func_name: "floor".to_string(),
+                args: vec![Expr::Number(Num::float(6.5))]
+            })
+        }
+    );
+}
+
+#[test]
+fn test_percentile_dice_expr() {
+    let result = parse_dice("d%");
+    assert_eq!(
+        result.unwrap(),
+        Expr::PercentileDice {
+            count: Box::new(Expr::Number(Num::int(1)))
+        }
+    );
+
+    let result = parse_dice("2d% % 10");
+    assert_eq!(
+        result.unwrap(),
+        Expr::Binary {
+            lhs: Box::new(Expr::PercentileDice {
+                count: Box::new(Expr::Number(Num::int(2)))
+            }),
+            op: BinOp::Mod,
+            rhs: Box::new(Expr::Number(Num::int(10)))
+        }
+    );
+
+    // 原有的取模运算不受影响
+    let result = parse_dice("1d20%3");
+    assert_eq!(
+        result.unwrap(),
+        Expr::Binary {
+            lhs: Box::new(Expr::Dice {
+                count: Box::new(Expr::Number(Num::int(1))),
+                side: Box::new(Expr::Number(Num::int(20)))
+            }),
+            op: BinOp::Mod,
+            rhs: Box::new(Expr::Number(Num::int(3)))
+        }
+    );
+
+    assert_eq!(parse_dice("4dF + d%").unwrap().to_string(), "4dF + 1d%");
+}
+
+#[test]
+fn test_custom_dice_expr() {
+    let expected = Expr::Dice {
+        count: Box::new(Expr::Number(Num::int(2))),
+        side: Box::new(Expr::List(vec![
+            Expr::Number(Num::int(1)),
+            Expr::Number(Num::int(1)),
+            Expr::Number(Num::int(2)),
+        ])),
+    };
+    assert_eq!(parse_dice("2d{1, 1, 2}").unwrap(), expected);
+    assert_eq!(parse_dice("2d[1, 1, 2]").unwrap(), expected);
+    assert_eq!(expected.to_string(), "2d{1, 1, 2}");
+
+    // 单个元素的花括号仍然是普通的括号
+    let result = parse_dice("d{6}");
+    assert_eq!(
+        result.unwrap(),
+        Expr::Dice {
+            count: Box::new(Expr::Number(Num::int(1))),
+            side: Box::new(Expr::Number(Num::int(6)))
+        }
+    );
+
+    let result = parse_dice("4d{0, 0, 1 + 1}kh2");
+    assert!(result.is_ok());
+}
@@ -0,0 +1,81 @@
+use dice_roller::inline::{Segment, split_expressions, split_inline_rolls};
+use dice_roller::{InlineSegment, RollResult, roll_dice_expressions, roll_inline_text};
+
+#[test]
+fn test_split_inline_rolls() {
+    assert_eq!(
+        split_inline_rolls("deals [[2d6+3]] slashing damage, DC [[8+2+3]]"),
+        vec![
+            Segment::Text("deals "),
+            Segment::Roll("2d6+3"),
+            Segment::Text(" slashing damage, DC "),
+            Segment::Roll("8+2+3"),
+        ]
+    );
+
+    // 表达式中的列表与字符串可以包含方括号
+    assert_eq!(
+        split_inline_rolls("[[max([1d6, 2])]] and [[ [[1], [2]][0][0] ]]!"),
+        vec![
+            Segment::Roll("max([1d6, 2])"),
+            Segment::Text(" and "),
+            Segment::Roll(" [[1], [2]][0][0] "),
+            Segment::Text("!"),
+        ]
+    );
+    assert_eq!(
+        split_inline_rolls("[[table(\"a]]b\")]]"),
+        vec![Segment::Roll("table(\"a]]b\")")]
+    );
+
+    // 没有闭合的 [[ 按普通文本处理
+    assert_eq!(
+        split_inline_rolls("no rolls [[1d6"),
+        vec![Segment::Text("no rolls [[1d6")]
+    );
+    assert_eq!(split_inline_rolls(""), vec![]);
+}
+
+#[test]
+fn test_split_expressions() {
+    assert_eq!(
+        split_expressions("1d20+5; 2d6+3;; "),
+        vec!["1d20+5", "2d6+3"]
+    );
+    assert_eq!(
+        split_expressions("table(\"a;b\") ; 1"),
+        vec!["table(\"a;b\")", "1"]
+    );
+    assert!(split_expressions("  ").is_empty());
+}
+
+#[test]
+fn test_roll_text() {
+    let result = roll_dice_expressions("1d20+5; 1d0; 3".to_string(), 1);
+    assert_eq!(result.rolls.len(), 3);
+    assert!(matches!(result.rolls[0], RollResult::Rolled(_)));
+    assert!(matches!(result.rolls[1], RollResult::Error(_)));
+    assert!(matches!(result.rolls[2], RollResult::Rolled(_)));
+
+    // 每个行内掷骰单独报错，不影响其他片段
+    let result = roll_inline_text("hit [[ 1d20 + 5 ]] for [[2d6 +]] damage".to_string(), 1);
+    assert_eq!(result.segments.len(), 5);
+    match &result.segments[1] {
+        InlineSegment::Roll { notation, result } => {
+            assert_eq!(notation, "1d20 + 5");
+            assert!(matches!(result, RollResult::Rolled(_)));
+        }
+        _ => panic!("Expected a roll"),
+    }
+    assert!(matches!(
+        &result.segments[3],
+        InlineSegment::Roll {
+            result: RollResult::Error(_),
+            ..
+        }
+    ));
+    assert!(matches!(
+        &result.segments[4],
+        InlineSegment::Text { text } if text == " damage"
+    ));
+}
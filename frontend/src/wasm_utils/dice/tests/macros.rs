@@ -0,0 +1,90 @@
+use std::collections::HashMap;
+
+use dice_roller::macros::{MacroValue, replace_macros};
+use dice_roller::number::Num;
+use dice_roller::rounding::Rounding;
+use dice_roller::{
+    BatchCheckRequest, ConstantIntegerCheckResult, check_constant_integer, check_constant_integers,
+    clear_dice_functions, load_dice_functions,
+};
+
+fn macros() -> HashMap<String, MacroValue> {
+    HashMap::from([
+        ("str".to_string(), MacroValue::Number(-1.0)),
+        ("dex".to_string(), MacroValue::Number(3.0)),
+        ("pb".to_string(), MacroValue::Number(2.0)),
+        ("lv1".to_string(), MacroValue::Number(5.0)),
+        (
+            "Attack".to_string(),
+            MacroValue::Formula("@dex + @pb".to_string()),
+        ),
+        ("loop".to_string(), MacroValue::Formula("@loop".to_string())),
+    ])
+}
+
+#[test]
+fn test_replace_macros() {
+    assert_eq!(replace_macros("@dex + @PB", &macros()).unwrap(), "3 + 2");
+    assert_eq!(replace_macros("@str * 2", &macros()).unwrap(), "(-1) * 2");
+    assert_eq!(
+        replace_macros("1d20 + @attack", &macros()).unwrap(),
+        "1d20 + (3 + 2)"
+    );
+    assert_eq!(
+        replace_macros("@lv1d6", &macros()).unwrap_err(),
+        "Unknown macro: @lv1d6"
+    );
+    assert_eq!(replace_macros("1d20 @", &macros()).unwrap(), "1d20 @");
+    assert_eq!(
+        replace_macros("@loop", &macros()).unwrap_err(),
+        "Macro replacement did not converge (more than 5 levels): @loop"
+    );
+}
+
+#[test]
+fn test_check_constant_integers() {
+    use ConstantIntegerCheckResult::*;
+    let request = BatchCheckRequest {
+        formulas: HashMap::from([
+            ("initiative".to_string(), "@dex + @pb".to_string()),
+            ("ac".to_string(), "10 + max(@dex, 2)".to_string()),
+            ("attack".to_string(), "1d20 + @attack".to_string()),
+            ("half".to_string(), "@dex / 2".to_string()),
+            ("unknown".to_string(), "@wis".to_string()),
+        ]),
+        macros: macros(),
+        rounding: Rounding::Exact,
+    };
+    let results = check_constant_integers(request).results;
+    assert_eq!(results.len(), 5);
+    assert!(matches!(results["initiative"], Constant(c) if c == Num::int(5)));
+    assert!(matches!(results["ac"], Constant(c) if c == Num::int(13)));
+    assert!(matches!(&results["attack"], NotConstant(s) if s == "Not a constant number"));
+    assert!(matches!(&results["half"], NotConstant(s) if s == "Not an integer"));
+    assert!(matches!(&results["unknown"], NotConstant(s) if s == "Unknown macro: @wis"));
+}
+
+#[test]
+fn test_check_cache_invalidation() {
+    use ConstantIntegerCheckResult::*;
+    // 缓存的结果在自定义函数变化后失效
+    assert!(matches!(
+        check_constant_integer("bonus(2)".to_string()),
+        NotConstant(_)
+    ));
+    load_dice_functions("def bonus(n: int) = n * 2".to_string());
+    assert!(matches!(
+        check_constant_integer("bonus(2)".to_string()),
+        Constant(c) if c == Num::int(4)
+    ));
+    load_dice_functions("def bonus(n: int) = n * 3".to_string());
+    assert!(matches!(
+        check_constant_integer("bonus(2)".to_string()),
+        Constant(c) if c == Num::int(6)
+    ));
+    clear_dice_functions();
+    assert!(matches!(
+        check_constant_integer("bonus(2)".to_string()),
+        NotConstant(_)
+    ));
+}
@@ -0,0 +1,103 @@
+use dice_roller::eval::{RollValue, SplitMix64, evaluate_expr};
+use dice_roller::grammar::parse_dice;
+use dice_roller::number::{Num, OVERFLOW};
+use dice_roller::typecheck::{Type, typecheck_expr};
+use dice_roller::{ConstantIntegerCheckResult, check_constant_integer};
+
+fn typecheck(input: &str) -> Type {
+    typecheck_expr(&parse_dice(input).expect("Parse error"))
+}
+
+fn roll(input: &str) -> Result<RollValue, String> {
+    let mut rng = SplitMix64::new(1);
+    evaluate_expr(&parse_dice(input).expect("Parse error"), &mut rng).map(|output| output.result)
+}
+
+#[test]
+fn test_exact_rational() {
+    // 有理数精确运算，不会出现 0.9999999999999999
+    assert_eq!(typecheck("1 / 49 * 49"), Type::constant(1.0));
+    assert_eq!(typecheck("1 / 3 * 3"), Type::constant(1.0));
+    assert_eq!(
+        typecheck("0.1 + 0.2"),
+        Type::constant("0.3".parse::<Num>().unwrap())
+    );
+    assert_eq!(roll("1 / 3 * 3").unwrap(), RollValue::Number(Num::ONE));
+
+    assert!("0.1".parse::<Num>().unwrap().is_exact());
+    assert_eq!(Num::ratio(2, 4).unwrap(), "0.5".parse::<Num>().unwrap());
+    assert!(Num::ratio(1, 3).unwrap().is_exact());
+    assert!(!Num::ratio(1, 3).unwrap().is_decimal());
+}
+
+#[test]
+fn test_overflow() {
+    // i64 范围之外的整数运算报错，而不是悄悄丢失精度
+    assert_eq!(
+        typecheck("9223372036854775807 + 1"),
+        Type::Invalid(OVERFLOW.to_string())
+    );
+    assert_eq!(roll("9223372036854775807 * 2").unwrap_err(), OVERFLOW);
+    assert_eq!(
+        Num::int(i64::MAX).checked_add(Num::ONE).unwrap_err(),
+        OVERFLOW.to_string()
+    );
+    assert_eq!(
+        Num::int(i64::MAX).checked_add(Num::ZERO).unwrap(),
+        Num::int(i64::MAX)
+    );
+
+    // 无法精确表示的字面量是解析错误，而不是退回浮点数
+    let err = parse_dice("100000000000000000000 + 1").unwrap_err();
+    assert!(
+        err.to_string()
+            .contains("Number out of range: 100000000000000000000")
+    );
+    assert!(parse_dice("0.12345678901234567890").is_err());
+    assert_eq!(
+        "1.50000000000000000000".parse::<Num>().unwrap(),
+        Num::ratio(3, 2).unwrap()
+    );
+    assert!(parse_dice("9223372036854775807").is_ok());
+}
+
+#[test]
+fn test_constant_integer_result() {
+    use ConstantIntegerCheckResult::*;
+    // 常量整数的结果保持精确，超过 2^53 也不会丢失精度
+    assert!(matches!(
+        check_constant_integer("9007199254740993".to_string()),
+        Constant(c) if c.to_i64() == 9_007_199_254_740_993
+    ));
+    assert!(matches!(
+        check_constant_integer("100000000000000000000".to_string()),
+        NotConstant(_)
+    ));
+}
+
+#[test]
+fn test_sqrt_and_rounding() {
+    // 完全平方数开方保持精确，其余退回浮点数
+    assert_eq!(Num::int(16).sqrt(), Num::int(4));
+    assert!(Num::int(16).sqrt().is_exact());
+    assert!(!Num::int(2).sqrt().is_exact());
+    assert_eq!(Num::ratio(9, 4).unwrap().sqrt(), Num::ratio(3, 2).unwrap());
+
+    // 四舍五入远离零
+    assert_eq!(Num::ratio(5, 2).unwrap().round(), Num::int(3));
+    assert_eq!(Num::ratio(-5, 2).unwrap().round(), Num::int(-3));
+    assert_eq!(Num::ratio(-5, 2).unwrap().floor(), Num::int(-3));
+    assert_eq!(Num::ratio(-5, 2).unwrap().ceil(), Num::int(-2));
+}
+
+#[test]
+fn test_display() {
+    assert_eq!(Num::int(42).to_string(), "42");
+    assert_eq!(Num::ratio(1, 2).unwrap().to_string(), "0.5");
+    assert_eq!(Num::ratio(-3, 8).unwrap().to_string(), "-0.375");
+    assert_eq!(
+        Num::ratio(1, 3).unwrap().to_string(),
+        (1.0f64 / 3.0).to_string()
+    );
+    assert_eq!(parse_dice("0.1 + 1").unwrap().to_string(), "0.1 + 1");
+}
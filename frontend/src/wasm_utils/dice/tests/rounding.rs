@@ -0,0 +1,140 @@
+use std::collections::HashMap;
+
+use dice_roller::eval::{RollValue, SplitMix64, evaluate_expr};
+use dice_roller::grammar::parse_dice;
+use dice_roller::macros::MacroValue;
+use dice_roller::number::Num;
+use dice_roller::rounding::{Rounding, apply_rounding, typecheck_integer};
+use dice_roller::typecheck::{Type, typecheck_expr};
+use dice_roller::{
+    BatchCheckRequest, ConstantIntegerCheckResult, check_constant_integer_with_rounding,
+    check_constant_integers,
+};
+
+fn rounded(input: &str, rounding: Rounding) -> String {
+    apply_rounding(&parse_dice(input).expect("Parse error"), rounding).to_string()
+}
+
+fn integer_error(input: &str) -> Option<String> {
+    match typecheck_integer(&parse_dice(input).expect("Parse error")) {
+        Type::Invalid(s) => Some(s),
+        _ => None,
+    }
+}
+
+#[test]
+fn test_apply_rounding() {
+    // 可能产生分数的除法单独取整
+    assert_eq!(rounded("1d6 / 2", Rounding::Down), "floor(1d6 / 2)");
+    assert_eq!(rounded("1d6 / 2", Rounding::Nearest), "round(1d6 / 2)");
+    assert_eq!(rounded("1d6 / 2", Rounding::Up), "ceil(1d6 / 2)");
+    assert_eq!(
+        rounded("1d20 + 7 / 2 # half", Rounding::Down),
+        "1d20 + floor(7 / 2) # half"
+    );
+    assert_eq!(rounded("1d6 / 2", Rounding::Exact), "1d6 / 2");
+
+    // 结果为整数的除法、整数除法与没有除法的表达式保持不变
+    assert_eq!(rounded("8 / 2 + 1d6", Rounding::Down), "8 / 2 + 1d6");
+    assert_eq!(rounded("1d6 // 2", Rounding::Down), "1d6 // 2");
+    assert_eq!(rounded("4d6kh3 + 2", Rounding::Down), "4d6kh3 + 2");
+
+    // 其他产生分数的运算只取整最终结果
+    assert_eq!(
+        rounded("avg([1d6, 1d8])", Rounding::Down),
+        "floor(avg([1d6, 1d8]))"
+    );
+    assert_eq!(rounded("[1d6 / 2, 3]", Rounding::Up), "[ceil(1d6 / 2), 3]");
+
+    // 取整后的除数可以作为骰子数量
+    let expr = parse_dice("(7 / 2)d6").unwrap();
+    assert!(matches!(typecheck_expr(&expr), Type::Invalid(_)));
+    assert!(!matches!(
+        typecheck_expr(&apply_rounding(&expr, Rounding::Down)),
+        Type::Invalid(_)
+    ));
+}
+
+#[test]
+fn test_rounded_evaluation() {
+    let roll = |input: &str, rounding: Rounding| {
+        let expr = apply_rounding(&parse_dice(input).unwrap(), rounding);
+        evaluate_expr(&expr, &mut SplitMix64::new(1))
+            .unwrap()
+            .result
+    };
+    assert_eq!(
+        roll("7 / 2", Rounding::Exact),
+        RollValue::Number(Num::ratio(7, 2).unwrap())
+    );
+    assert_eq!(
+        roll("7 / 2", Rounding::Down),
+        RollValue::Number(Num::int(3))
+    );
+    assert_eq!(
+        roll("7 / 2", Rounding::Nearest),
+        RollValue::Number(Num::int(4))
+    );
+    assert_eq!(roll("7 / 2", Rounding::Up), RollValue::Number(Num::int(4)));
+    assert_eq!(
+        roll("-7 / 2", Rounding::Down),
+        RollValue::Number(Num::int(-4))
+    );
+    // 每个除法单独取整：floor(7 / 2) + floor(7 / 2) = 6
+    assert_eq!(
+        roll("7 / 2 + 7 / 2", Rounding::Down),
+        RollValue::Number(Num::int(6))
+    );
+    assert_eq!(roll("sqrt(2)", Rounding::Down), RollValue::Number(Num::ONE));
+}
+
+#[test]
+fn test_typecheck_integer() {
+    assert_eq!(integer_error("1d20 + 5"), None);
+    assert_eq!(integer_error("6 / 2"), None);
+    assert_eq!(integer_error("1 / 3 * 3"), None);
+    assert_eq!(integer_error("1d6 // 2"), None);
+    assert_eq!(integer_error("floor(1d6 / 2)"), None);
+    assert_eq!(integer_error("filter([1d6, 1d8], >2.5)"), None);
+    assert_eq!(
+        integer_error("5 / 2").unwrap(),
+        "5 / 2 is 2.5, not an integer."
+    );
+    assert_eq!(
+        integer_error("1d20 + 1d6 / 2").unwrap(),
+        "Division may produce a non-integer result: 1d6 / 2. Use // for integer division, or a rounding option."
+    );
+    assert_eq!(
+        integer_error("2 ^ (1d4 - 2)").unwrap(),
+        "2 ^ (1d4 - 2) may produce a non-integer result."
+    );
+    assert!(integer_error("median([1d6, 1d6])").is_some());
+    assert!(integer_error("[1, 1.5]").is_some());
+
+    // 取整之后通过整数检查
+    let expr = apply_rounding(&parse_dice("1d20 + 1d6 / 2").unwrap(), Rounding::Down);
+    assert!(!matches!(typecheck_integer(&expr), Type::Invalid(_)));
+}
+
+#[test]
+fn test_constant_integer_with_rounding() {
+    use ConstantIntegerCheckResult::*;
+    let check = |input: &str, rounding: Rounding| {
+        check_constant_integer_with_rounding(input.to_string(), rounding)
+    };
+    assert!(matches!(check("5 / 2", Rounding::Exact), NotConstant(s) if s == "Not an integer"));
+    assert!(matches!(check("5 / 2", Rounding::Down), Constant(c) if c == Num::int(2)));
+    assert!(matches!(check("5 / 2", Rounding::Nearest), Constant(c) if c == Num::int(3)));
+    assert!(matches!(check("5 / 2", Rounding::Up), Constant(c) if c == Num::int(3)));
+    assert!(
+        matches!(check("1d6 / 2", Rounding::Down), NotConstant(s) if s == "Not a constant number")
+    );
+
+    let request = BatchCheckRequest {
+        formulas: HashMap::from([("half".to_string(), "@lv1 / 2".to_string())]),
+        macros: HashMap::from([("lv1".to_string(), MacroValue::Number(5.0))]),
+        rounding: Rounding::Down,
+    };
+    let results = check_constant_integers(request).results;
+    assert!(matches!(results["half"], Constant(c) if c == Num::int(2)));
+}
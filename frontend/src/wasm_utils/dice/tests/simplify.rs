@@ -0,0 +1,102 @@
+use dice_roller::grammar::{Expr, parse_dice};
+use dice_roller::simplify::simplify_expr;
+
+fn simplify(input: &str) -> String {
+    let parsed_expr = parse_dice(input).expect("Parse error");
+    simplify_expr(&parsed_expr).to_string()
+}
+
+#[test]
+fn test_simplify_constant_fold() {
+    assert_eq!(simplify("1 + 2 * 3"), "7");
+    assert_eq!(simplify("max(1, 10, 5) // 3"), "3");
+    assert_eq!(simplify("[1, 1 + 1] * 2"), "[1, 2, 1, 2]");
+    assert_eq!(simplify("(1 + 2)d(3 + 3)"), "3d6");
+    assert_eq!(simplify("4d6kh(1 + 2)"), "4d6kh3");
+    assert_eq!(simplify("2d20r<(2 - 1)"), "2d20r<1");
+    assert_eq!(simplify("3d6 >= (2 + 2)"), "3d6>=4");
+    assert_eq!(simplify("max(1d6, 2 * 3)"), "max(1d6, 6)");
+    assert_eq!(simplify("1 - 3"), "-2");
+}
+
+#[test]
+fn test_simplify_merge_dice() {
+    assert_eq!(simplify("1d6 + 2d6"), "3d6");
+    assert_eq!(simplify("1d6 + 1d8 + 2d6"), "3d6 + 1d8");
+    assert_eq!(simplify("1d6 + 3 + 2d6 - 1"), "3d6 + 2");
+    assert_eq!(simplify("2 + 1d6 - 5"), "1d6 - 3");
+    assert_eq!(simplify("1d6 - (1d6 - 2d6)"), "3d6 - 1d6");
+    assert_eq!(simplify("-1d6 - 2d6"), "0 - 3d6");
+    assert_eq!(simplify("1dF + 2dF"), "3dF");
+    assert_eq!(simplify("1d{1, 2} + 1d{1, 1 + 1}"), "2d{1, 2}");
+
+    // 正负不同的骰子不能合并
+    assert_eq!(simplify("1d6 - 1d6"), "1d6 - 1d6");
+    // 带修饰符的骰子不能合并
+    assert_eq!(simplify("2d20kh1 + 1d20"), "2d20kh1 + 1d20");
+    // 乘法中的骰子不能合并
+    assert_eq!(simplify("2 * 1d6 + 1d6"), "2 * 1d6 + 1d6");
+    // 合并后超过骰子数量上限时保持分开
+    assert_eq!(simplify("600d6 + 600d6"), "600d6 + 600d6");
+    assert_eq!(simplify("600d6 + 400d6"), "1000d6");
+}
+
+#[test]
+fn test_simplify_identity() {
+    assert_eq!(simplify("1d20 + 0"), "1d20");
+    assert_eq!(simplify("0 + 1d20"), "1d20");
+    assert_eq!(simplify("1d20 - (3 - 3)"), "1d20");
+    assert_eq!(simplify("1d20 * 1"), "1d20");
+    assert_eq!(simplify("1 * 1d20"), "1d20");
+    assert_eq!(simplify("(1d8 + 2) * (2 - 1)"), "1d8 + 2");
+    assert_eq!(simplify("1d20 / 1"), "1d20");
+    assert_eq!(simplify("[1d6, 2] * 1"), "[1d6, 2]");
+    assert_eq!(simplify("[1d6 + 0] + [2]"), "[1d6] + [2]");
+}
+
+#[test]
+fn test_simplify_keep_labels() {
+    assert_eq!(
+        simplify("2d6 [Sneak Attack] + 1 + 2"),
+        "2d6 [Sneak Attack] + 3"
+    );
+    assert_eq!(simplify("(1 + 2) [bonus]"), "3 [bonus]");
+    assert_eq!(simplify("1d20 + 2 + 3 # stealth"), "1d20 + 5 # stealth");
+}
+
+#[test]
+fn test_simplify_keep_invalid() {
+    let parsed_expr = parse_dice("[1, 2] + 0").unwrap();
+    assert_eq!(simplify_expr(&parsed_expr), parsed_expr);
+}
+
+#[test]
+fn test_simplify_round_trip() {
+    for input in [
+        "1d6 + 2d6 + 3",
+        "(1d8 + 2) * 2",
+        "4d6dl1 + 1d6!!<=4l2",
+        "max(rpdice(4d6kh3, 6), 3)",
+        "10 - (1d4 - 1d6)",
+    ] {
+        let simplified = simplify_expr(&parse_dice(input).unwrap());
+        let reparsed: Expr = parse_dice(&simplified.to_string()).unwrap();
+        assert_eq!(simplify_expr(&reparsed), simplified);
+    }
+}
+
+#[test]
+fn test_simplify_leading_negative() {
+    // 第一项为负时，正的常数项写在最前面
+    assert_eq!(simplify("2 - 1d6"), "2 - 1d6");
+    assert_eq!(simplify("-1d6 + 3"), "3 - 1d6");
+    assert_eq!(simplify("10 - (1d4 - 1d6)"), "10 - 1d4 + 1d6");
+    assert_eq!(simplify("-1d6 - 3"), "0 - 1d6 - 3");
+}
+
+#[test]
+fn test_simplify_long_chain() {
+    // 长加减链只做一次类型检查
+    let input = vec!["1d6"; 300].join(" + ");
+    assert_eq!(simplify(&input), "300d6");
+}
@@ -0,0 +1,204 @@
+use dice_roller::grammar::parse_dice;
+use dice_roller::stats::{Distribution, distribution_of};
+
+fn distribution(input: &str) -> Result<Distribution, String> {
+    distribution_of(&parse_dice(input).expect("Parse error"))
+}
+
+fn assert_close(a: f64, b: f64) {
+    assert!((a - b).abs() < 1e-9, "{} != {}", a, b);
+}
+
+#[test]
+fn test_stats_basic() {
+    let d = distribution("1d6").unwrap();
+    assert_eq!(d.min(), 1.0);
+    assert_eq!(d.max(), 6.0);
+    assert_close(d.mean(), 3.5);
+
+    let d = distribution("2d6 + 3").unwrap();
+    assert_eq!(d.min(), 5.0);
+    assert_eq!(d.max(), 15.0);
+    assert_close(d.mean(), 10.0);
+    assert_close(d.probability(|v| v == 10.0), 6.0 / 36.0);
+
+    let d = distribution("5").unwrap();
+    assert_eq!(d.outcomes(), &[(5.0, 1.0)]);
+}
+
+#[test]
+fn test_stats_special_dice() {
+    let d = distribution("4dF").unwrap();
+    assert_eq!(d.min(), -4.0);
+    assert_eq!(d.max(), 4.0);
+    assert_close(d.mean(), 0.0);
+    assert_close(d.probability(|v| v == 4.0), 1.0 / 81.0);
+
+    let d = distribution("d%").unwrap();
+    assert_eq!(d.max(), 100.0);
+    assert_close(d.mean(), 50.5);
+}
+
+#[test]
+fn test_stats_custom_dice() {
+    let d = distribution("1d{1, 1, 2, 2, 3, 4}").unwrap();
+    assert_eq!(d.min(), 1.0);
+    assert_eq!(d.max(), 4.0);
+    assert_close(d.probability(|v| v == 1.0), 2.0 / 6.0);
+    assert_close(d.mean(), 13.0 / 6.0);
+
+    let d = distribution("2d{1, 1, 2, 2, 3, 4}kh1").unwrap();
+    assert_close(d.probability(|v| v == 1.0), 4.0 / 36.0);
+    assert_close(d.probability(|v| v == 4.0), 11.0 / 36.0);
+
+    let d = distribution("1d{1, 1, 2, 2, 3, 4}r1").unwrap();
+    assert_close(d.mean(), 11.0 / 4.0);
+
+    // 在 4 上复合爆骰一次
+    let d = distribution("1d{1, 1, 2, 2, 3, 4}!!l1").unwrap();
+    assert_eq!(d.max(), 8.0);
+    assert_close(d.mean(), 13.0 / 6.0 * 7.0 / 6.0);
+
+    let d = distribution("4d{0, 1}>=1").unwrap();
+    assert_close(d.mean(), 2.0);
+}
+
+#[test]
+fn test_stats_modifiers() {
+    // 优势：期望 13.825
+    let d = distribution("2d20kh1").unwrap();
+    assert_close(d.mean(), 13.825);
+    assert_close(d.probability(|v| v == 20.0), 39.0 / 400.0);
+
+    // 4d6dl1 的期望约为 12.2446
+    let d = distribution("4d6dl1").unwrap();
+    assert_close(d.mean(), 15869.0 / 1296.0);
+
+    // 重骰 1：骰面 2..6 均匀分布
+    let d = distribution("1d6r1").unwrap();
+    assert_close(d.mean(), 4.0);
+
+    // 只重骰一次 1
+    let d = distribution("1d6ro1").unwrap();
+    assert_close(d.mean(), 20.0 / 6.0 + 3.5 / 6.0);
+
+    // 复合爆骰限制 1 次：最大值为 12
+    let d = distribution("1d6!!l1").unwrap();
+    assert_eq!(d.max(), 12.0);
+    assert_close(d.mean(), 3.5 + 3.5 / 6.0);
+
+    // 普通爆骰与穿透爆骰的次数限制
+    let d = distribution("1d6!l2").unwrap();
+    assert_eq!(d.max(), 18.0);
+    assert_close(d.probability(|v| v == 18.0), 1.0 / 216.0);
+
+    let d = distribution("1d6!pl1").unwrap();
+    assert_eq!(d.max(), 11.0);
+    assert_close(d.mean(), 3.5 + 2.5 / 6.0);
+
+    // 可靠才能：小于 10 的 d20 按 10 计
+    let d = distribution("1d20mi10").unwrap();
+    assert_eq!(d.min(), 10.0);
+    assert_close(d.probability(|v| v == 10.0), 0.5);
+    assert_close(d.mean(), 12.75);
+
+    let d = distribution("2d6ma5").unwrap();
+    assert_eq!(d.max(), 10.0);
+
+    // 排序不影响分布，计数与成功判定相同
+    let d = distribution("4d6sdkh3").unwrap();
+    assert_close(d.mean(), 15869.0 / 1296.0);
+    let d = distribution("2d20cs>=19kh1").unwrap();
+    assert_close(d.mean(), 13.825);
+    let d = distribution("6d6cnt>=5").unwrap();
+    assert_close(d.mean(), 2.0);
+
+    // 成功判定是二项分布
+    let d = distribution("3d10>=6").unwrap();
+    assert_close(d.mean(), 1.5);
+    assert_close(d.probability(|v| v == 3.0), 0.125);
+
+    // 失败与双倍成功：单颗骰子期望 (3 + 1 - 1) / 10
+    let d = distribution("10d10>=8f1ds10").unwrap();
+    assert_close(d.mean(), 3.0);
+    assert_eq!(d.min(), -10.0);
+    assert_eq!(d.max(), 20.0);
+
+    let d = distribution("clamp(1d20, 5, 15)").unwrap();
+    assert_eq!(d.min(), 5.0);
+    assert_close(d.probability(|v| v == 15.0), 0.3);
+
+    let d = distribution("1d4 ^ 2").unwrap();
+    assert_close(d.mean(), 7.5);
+
+    // 暴击：骰子翻倍
+    let d = distribution("rpdice(1d6 + 2)").unwrap();
+    assert_eq!(d.min(), 4.0);
+    assert_eq!(d.max(), 14.0);
+
+    // 爆骰与 l 之间可以有标签
+    assert_eq!(
+        distribution("((1d6!) [x])l1").unwrap(),
+        distribution("1d6!l1").unwrap()
+    );
+}
+
+#[test]
+fn test_stats_groups() {
+    // 两个 d20 取高与 2d20kh1 相同
+    let d = distribution("{1d20, 1d20}kh1").unwrap();
+    let expected = distribution("2d20kh1").unwrap();
+    for ((v, p), (ev, ep)) in d.outcomes().iter().zip(expected.outcomes()) {
+        assert_eq!(v, ev);
+        assert_close(*p, *ep);
+    }
+    let d = distribution("{1d20 + 5, 1d20 + 3}kh1").unwrap();
+    assert_eq!(d.min(), 6.0);
+    assert_eq!(d.max(), 25.0);
+    assert_close(d.probability(|v| v == 6.0), 3.0 / 400.0);
+    assert_close(d.probability(|v| v == 25.0), 1.0 / 20.0);
+
+    // 不带修饰符时为各子掷骰之和
+    assert_close(distribution("{1d6, 1d6}").unwrap().mean(), 7.0);
+
+    // 成功检定：每个子掷骰独立判定
+    let d = distribution("{1d6, 1d6, 1d6}>=5").unwrap();
+    assert_close(d.mean(), 1.0);
+    assert_close(d.probability(|v| v == 3.0), 1.0 / 27.0);
+    let d = distribution("{1d6, 1d6}kh1>=5").unwrap();
+    assert_close(d.mean(), 1.0 - 4.0 / 9.0);
+
+    assert!(distribution("{1d100, 1d100, 1d100}kh1").is_err());
+}
+
+#[test]
+fn test_stats_large_pools() {
+    // 大量骰子求和按二进制拆分计算，结果仍是精确分布
+    let d = distribution("100d100").unwrap();
+    assert_eq!(d.min(), 100.0);
+    assert_eq!(d.max(), 10000.0);
+    assert_eq!(d.outcomes().len(), 9901);
+    assert_close(d.mean(), 5050.0);
+    assert_close(d.outcomes().iter().map(|(_, p)| p).sum(), 1.0);
+
+    let d = distribution("rpdice(3d6, 3)").unwrap();
+    assert_eq!(d.outcomes().len(), 46);
+    assert_close(d.mean(), 31.5);
+
+    // 可能的结果过多时不计算
+    let err = "Too many possible results to compute statistics.";
+    assert_eq!(distribution("1d1000000").unwrap_err(), err);
+    assert_eq!(distribution("1000d100").unwrap_err(), err);
+    assert_eq!(distribution("100d100 * 100d100").unwrap_err(), err);
+    assert!(distribution("1d{1, 100000} + 1d{1, 100000}").is_ok());
+}
+
+#[test]
+fn test_stats_unsupported() {
+    assert!(distribution("[1d6, 2]").is_err());
+    assert!(distribution("4d6!kh3").is_err());
+    assert!(distribution("1d6!mi2").is_err());
+    assert!(distribution("3d6u").is_err());
+    assert!(distribution("1d0").is_err());
+    assert!(distribution("[1d6, 2d6][0]").is_err());
+}
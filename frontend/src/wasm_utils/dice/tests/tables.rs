@@ -0,0 +1,245 @@
+use dice_roller::eval::{
+    DiceRng, RollOutput, RollValue, SplitMix64, TableRoll, evaluate_expr, evaluate_expr_with_tables,
+};
+use dice_roller::grammar::{Expr, parse_dice};
+use dice_roller::number::Num;
+use dice_roller::stats::distribution_of;
+use dice_roller::tables::TableRegistry;
+use dice_roller::typecheck::{Type, typecheck_expr};
+
+const TABLES_JSON: &str = r#"{
+    "tables": [
+        {
+            "name": "surge",
+            "roll": "1d4",
+            "entries": [
+                { "range": [1, 2], "text": "Fireball centered on you" },
+                { "range": 3, "text": "You turn blue" },
+                { "range": 4, "text": "Roll on treasure", "expr": "table(\"treasure\")" }
+            ]
+        },
+        {
+            "name": "treasure",
+            "entries": [
+                { "weight": 3, "text": "Copper", "expr": "2d6" },
+                { "text": "Gold", "expr": "1d4 * 10" }
+            ]
+        }
+    ]
+}"#;
+
+const TABLES_TOML: &str = r#"
+[[tables]]
+name = "surge"
+roll = "1d4"
+entries = [
+    { range = [1, 2], text = "Fireball centered on you" },
+    { range = 3, text = "You turn blue" },
+    { range = 4, text = "Roll on treasure", expr = 'table("treasure")' },
+]
+
+[[tables]]
+name = "treasure"
+
+[[tables.entries]]
+weight = 3
+text = "Copper"
+expr = "2d6"
+
+[[tables.entries]]
+text = "Gold"
+expr = "1d4 * 10"
+"#;
+
+// 按给定序列返回骰面下标的随机数来源
+struct SequenceRng {
+    indices: Vec<usize>,
+    pos: usize,
+}
+
+impl DiceRng for SequenceRng {
+    fn next_index(&mut self, n: usize) -> usize {
+        let index = self.indices[self.pos % self.indices.len()];
+        self.pos += 1;
+        assert!(index < n);
+        index
+    }
+}
+
+fn roll(tables: &TableRegistry, input: &str, indices: &[usize]) -> RollOutput {
+    let expr = parse_dice(input).expect("Parse error");
+    let mut rng = SequenceRng {
+        indices: indices.to_vec(),
+        pos: 0,
+    };
+    evaluate_expr_with_tables(&expr, &mut rng, tables).expect("Evaluation error")
+}
+
+fn load_error(source: &str) -> String {
+    TableRegistry::new().load_json(source).unwrap_err()
+}
+
+#[test]
+fn test_table_expr() {
+    let expr = parse_dice("table(\"wild_magic\") + 1").unwrap();
+    assert_eq!(
+        expr,
+        Expr::Binary {
+            lhs: Box::new(Expr::Table("wild_magic".to_string())),
+            op: dice_roller::grammar::BinOp::Add,
+            rhs: Box::new(Expr::Number(Num::int(1))),
+        }
+    );
+    assert_eq!(expr.to_string(), "table(\"wild_magic\") + 1");
+    assert_eq!(typecheck_expr(&expr), Type::unknown_var());
+    assert!(distribution_of(&expr).is_err());
+    // 表名必须加引号，否则是对名为 table 的函数的调用
+    let expr = parse_dice("table(wild_magic)").unwrap();
+    assert!(matches!(typecheck_expr(&expr), Type::Invalid(_)));
+}
+
+#[test]
+fn test_load_tables() {
+    let mut json = TableRegistry::new();
+    assert_eq!(json.load_json(TABLES_JSON), Ok(2));
+    let mut toml = TableRegistry::new();
+    assert_eq!(toml.load_toml(TABLES_TOML), Ok(2));
+    assert_eq!(json.names(), vec!["surge", "treasure"]);
+    for name in json.names() {
+        assert_eq!(json.get(name).unwrap().def, toml.get(name).unwrap().def);
+    }
+
+    // 权重表的驱动掷骰为 1d权重总和
+    let treasure = json.get("treasure").unwrap();
+    assert_eq!(treasure.roll, parse_dice("1d4").unwrap());
+    assert_eq!(treasure.lookup(Num::int(3)), Some(0));
+    assert_eq!(treasure.lookup(Num::int(4)), Some(1));
+    assert_eq!(treasure.lookup(Num::int(5)), None);
+
+    assert!(json.check(&parse_dice("table(\"surge\")").unwrap()).is_ok());
+    assert_eq!(
+        json.check(&parse_dice("1 + table(\"loot\")").unwrap()),
+        Err("Unknown table: loot".to_string())
+    );
+}
+
+#[test]
+fn test_roll_tables() {
+    let mut tables = TableRegistry::new();
+    tables.load_json(TABLES_JSON).unwrap();
+
+    // 只有文本的表项，结果为驱动掷骰的值
+    let output = roll(&tables, "table(\"surge\") + 1", &[2]);
+    assert_eq!(output.result, RollValue::Number(Num::int(4)));
+    assert_eq!(
+        output.tables,
+        vec![TableRoll {
+            table: "surge".to_string(),
+            roll: Num::int(3),
+            entry: 1,
+            text: Some("You turn blue".to_string()),
+            value: Num::int(3),
+        }]
+    );
+
+    // 嵌套表：外层表排在前面，表内的骰子同样记录在骰池中
+    let output = roll(&tables, "table(\"surge\")", &[3, 3, 0]);
+    assert_eq!(output.result, RollValue::Number(Num::int(10)));
+    assert_eq!(output.groups.len(), 3);
+    assert_eq!(output.tables.len(), 2);
+    assert_eq!(output.tables[0].table, "surge");
+    assert_eq!(output.tables[0].entry, 2);
+    assert_eq!(output.tables[0].value, Num::int(10));
+    assert_eq!(output.tables[1].table, "treasure");
+    assert_eq!(output.tables[1].roll, Num::int(4));
+    assert_eq!(output.tables[1].text, Some("Gold".to_string()));
+
+    // 表内的骰子不受 rpdice 影响
+    let output = roll(&tables, "rpdice(table(\"treasure\"))", &[0, 2, 3]);
+    assert_eq!(output.result, RollValue::Number(Num::int(7)));
+
+    let mut rng = SplitMix64::new(7);
+    let expr = parse_dice("table(\"surge\")").unwrap();
+    assert_eq!(
+        evaluate_expr(&expr, &mut rng).unwrap_err(),
+        "Unknown table: surge"
+    );
+}
+
+#[test]
+fn test_single_entry_tables() {
+    // 只有一项的表，驱动掷骰为常数 1
+    let mut tables = TableRegistry::new();
+    assert_eq!(
+        tables.load_json(
+            r#"{ "tables": [
+                { "name": "one", "entries": [{ "weight": 1, "text": "Only" }] },
+                { "name": "range", "entries": [{ "range": 1, "expr": "1d4" }] }
+            ] }"#
+        ),
+        Ok(2)
+    );
+    assert_eq!(tables.check(&parse_dice("table(\"one\")").unwrap()), Ok(()));
+    let output = roll(&tables, "table(\"one\")", &[0]);
+    assert_eq!(output.result, RollValue::Number(Num::ONE));
+    assert_eq!(output.tables[0].text, Some("Only".to_string()));
+    let output = roll(&tables, "table(\"range\")", &[3]);
+    assert_eq!(output.result, RollValue::Number(Num::int(4)));
+}
+
+#[test]
+fn test_load_table_errors() {
+    for source in [
+        r#"{ "tables": [{ "name": "t", "entries": [] }] }"#,
+        r#"{ "tables": [{ "name": "t", "entries": [{ "range": 1 }] }] }"#,
+        r#"{ "tables": [{ "name": "t", "entries": [{ "range": 1, "text": "a" }, { "weight": 2, "text": "b" }] }] }"#,
+        r#"{ "tables": [{ "name": "t", "entries": [{ "range": [2, 1], "text": "a" }] }] }"#,
+        r#"{ "tables": [{ "name": "t", "roll": "1d4", "entries": [{ "range": [1, 3], "text": "a" }, { "range": [3, 4], "text": "b" }] }] }"#,
+        r#"{ "tables": [{ "name": "t", "roll": "1d6", "entries": [{ "range": [1, 4], "text": "a" }] }] }"#,
+        r#"{ "tables": [{ "name": "t", "roll": "1d6", "entries": [{ "weight": 1, "text": "a" }] }] }"#,
+        r#"{ "tables": [{ "name": "t", "entries": [{ "weight": 0, "text": "a" }] }] }"#,
+        r#"{ "tables": [{ "name": "t", "entries": [{ "text": "a", "expr": "[1, 2]" }] }] }"#,
+        r#"{ "tables": [{ "name": "t", "entries": [{ "text": "a", "expr": "1d" }] }] }"#,
+        r#"{ "tables": [{ "name": "t", "entries": [{ "expr": "table(\"u\")" }] }] }"#,
+        r#"{ "tables": [{ "name": "a\"b", "entries": [{ "text": "a" }] }] }"#,
+        r#"{ "tables": [{ "name": "t" }] }"#,
+        r#"[]"#,
+    ] {
+        assert!(
+            TableRegistry::new().load_json(source).is_err(),
+            "{}",
+            source
+        );
+    }
+
+    assert_eq!(
+        load_error(
+            r#"{ "tables": [{ "name": "t", "roll": "1d6", "entries": [{ "range": [1, 4], "text": "a" }] }] }"#
+        ),
+        "Roll result 5 of table t has no entry."
+    );
+    assert_eq!(
+        load_error(
+            r#"{ "tables": [
+                { "name": "a", "entries": [{ "expr": "table(\"b\")" }] },
+                { "name": "b", "entries": [{ "expr": "table(\"a\")" }] }
+            ] }"#
+        ),
+        "Recursive table: a -> b -> a"
+    );
+
+    // 载入失败时保持原有的表
+    let mut tables = TableRegistry::new();
+    tables.load_json(TABLES_JSON).unwrap();
+    assert!(
+        tables
+            .load_json(r#"{ "tables": [{ "name": "surge", "entries": [] }] }"#)
+            .is_err()
+    );
+    assert_eq!(tables.get("surge").unwrap().def.entries.len(), 3);
+    assert!(
+        TableRegistry::new()
+            .load_toml("[[tables]]\nname = 1")
+            .is_err()
+    );
+}
@@ -0,0 +1,143 @@
+use dice_roller::tokens::{TokenKind, tokenize};
+
+// 把记号整理为 (原文, 分类) 列表，便于比较
+fn kinds(input: &str) -> Vec<(String, TokenKind)> {
+    tokenize(input)
+        .into_iter()
+        .map(|t| (t.text, t.kind))
+        .collect()
+}
+
+fn tokens(expected: &[(&str, TokenKind)]) -> Vec<(String, TokenKind)> {
+    expected
+        .iter()
+        .map(|(text, kind)| (text.to_string(), *kind))
+        .collect()
+}
+
+#[test]
+fn test_tokenize_kinds() {
+    use TokenKind::*;
+    assert_eq!(
+        kinds("4d6dl1 + 2"),
+        tokens(&[
+            ("4", Number),
+            ("d", Dice),
+            ("6", Number),
+            ("dl", Modifier),
+            ("1", Number),
+            ("+", Operator),
+            ("2", Number)
+        ])
+    );
+    assert_eq!(
+        kinds("1d6!!l3 + 4dFmi0 + d%"),
+        tokens(&[
+            ("1", Number),
+            ("d", Dice),
+            ("6", Number),
+            ("!!", Modifier),
+            ("l", Modifier),
+            ("3", Number),
+            ("+", Operator),
+            ("4", Number),
+            ("d", Dice),
+            ("F", Dice),
+            ("mi", Modifier),
+            ("0", Number),
+            ("+", Operator),
+            ("d", Dice),
+            ("%", Dice)
+        ])
+    );
+    assert_eq!(
+        kinds("10d10>=8f1"),
+        tokens(&[
+            ("10", Number),
+            ("d", Dice),
+            ("10", Number),
+            (">=", Operator),
+            ("8", Number),
+            ("f", Modifier),
+            ("1", Number)
+        ])
+    );
+    // 标签、自定义函数、随机表与注释
+    assert_eq!(
+        kinds("2d6 [Sneak Attack] + smite(3) + table(\"surge\") # hit"),
+        tokens(&[
+            ("2", Number),
+            ("d", Dice),
+            ("6", Number),
+            ("[Sneak Attack]", Label),
+            ("+", Operator),
+            ("smite", Macro),
+            ("(", Bracket),
+            ("3", Number),
+            (")", Bracket),
+            ("+", Operator),
+            ("table", Function),
+            ("(", Bracket),
+            ("\"surge\"", String),
+            (")", Bracket),
+            ("# hit", Comment)
+        ])
+    );
+    // 下标与切片不是标签
+    assert_eq!(
+        kinds("xs[1:3] // 2"),
+        tokens(&[
+            ("xs", Variable),
+            ("[", Bracket),
+            ("1", Number),
+            (":", Separator),
+            ("3", Number),
+            ("]", Bracket),
+            ("//", Operator),
+            ("2", Number)
+        ])
+    );
+    assert_eq!(
+        kinds("(1d4)d6 % 3")[4..],
+        tokens(&[
+            (")", Bracket),
+            ("d", Dice),
+            ("6", Number),
+            ("%", Operator),
+            ("3", Number)
+        ])
+    );
+}
+
+#[test]
+fn test_tokenize_errors() {
+    use TokenKind::*;
+    // 不配对的括号
+    assert_eq!(kinds("(1d20 + 5")[0], ("(".to_string(), Error));
+    assert_eq!(kinds("1d20 + 5)")[5], (")".to_string(), Error));
+    assert_eq!(kinds("[1, 2)")[0], ("[".to_string(), Error));
+    // 无法识别的修饰符与解析出错的位置
+    assert_eq!(kinds("1d20 advantage")[4], ("antage".to_string(), Error));
+    assert_eq!(kinds("1d20 + * 3")[4], ("*".to_string(), Error));
+    assert_eq!(kinds("1d20 $")[3], ("$".to_string(), Error));
+    // 不完整的输入仍然可以分类
+    assert_eq!(
+        kinds("max(1d"),
+        tokens(&[("max", Function), ("(", Error), ("1", Number), ("d", Dice)])
+    );
+}
+
+#[test]
+fn test_tokenize_brackets() {
+    let tokens = tokenize("max([1d6] * 4, 2)");
+    let pairs: Vec<_> = tokens.iter().map(|t| t.pair).collect();
+    assert_eq!(pairs[1], Some(11));
+    assert_eq!(pairs[11], Some(1));
+    assert_eq!(pairs[2], Some(6));
+    assert_eq!(pairs[6], Some(2));
+
+    // 位置按 UTF-16 计算
+    let tokens = tokenize("1d6 [火焰] + 2");
+    let last = tokens.last().unwrap();
+    assert_eq!((last.span.start, last.span.end), (11, 12));
+}
@@ -0,0 +1,229 @@
+use dice_roller::advantage::{RollMode, apply_roll_mode};
+use dice_roller::eval::{DiceRng, RollValue, evaluate_expr_with_trace};
+use dice_roller::functions::FunctionRegistry;
+use dice_roller::grammar::{Expr, parse_dice_with_spans};
+use dice_roller::number::Num;
+use dice_roller::tables::TableRegistry;
+use dice_roller::trace::{SourceSpans, TraceNode};
+
+// 按给定序列返回骰面下标的随机数来源
+struct SequenceRng {
+    indices: Vec<usize>,
+    pos: usize,
+}
+
+impl DiceRng for SequenceRng {
+    fn next_index(&mut self, n: usize) -> usize {
+        let index = self.indices[self.pos % self.indices.len()];
+        self.pos += 1;
+        assert!(index < n);
+        index
+    }
+}
+
+// 以骰面下标序列掷骰并返回标注了位置的记录，普通骰子的下标 i 对应骰面 i + 1
+fn trace(input: &str, indices: &[usize]) -> TraceNode {
+    trace_rewritten(input, indices, |expr| expr.clone())
+}
+
+// 求值前先改写表达式 (展开函数调用、加上优势/劣势)
+fn trace_rewritten(input: &str, indices: &[usize], rewrite: impl Fn(&Expr) -> Expr) -> TraceNode {
+    let (parsed, tree) = parse_dice_with_spans(input).expect("Parse error");
+    let expr = rewrite(&parsed);
+    let spans = SourceSpans::new(input, &parsed, &tree, &expr);
+    let mut rng = SequenceRng {
+        indices: indices.to_vec(),
+        pos: 0,
+    };
+    let output = evaluate_expr_with_trace(&expr, &mut rng, &TableRegistry::new(), &spans)
+        .expect("Evaluation error");
+    *output.trace.expect("Missing trace")
+}
+
+// 把记录整理为 (位置对应的原文, 结果) 的先序列表，便于比较
+fn flatten<'a>(node: &TraceNode, input: &'a str, out: &mut Vec<(Option<&'a str>, String)>) {
+    let text = node.span.map(|s| &input[s.start..s.end]);
+    let value = match &node.value {
+        RollValue::Number(n) => n.to_string(),
+        v => format!("{:?}", v),
+    };
+    out.push((text, value));
+    for child in &node.children {
+        flatten(child, input, out);
+    }
+}
+
+fn nodes(input: &str, indices: &[usize]) -> Vec<(Option<&'static str>, String)> {
+    nodes_rewritten(input, indices, |expr| expr.clone())
+}
+
+fn nodes_rewritten(
+    input: &str,
+    indices: &[usize],
+    rewrite: impl Fn(&Expr) -> Expr,
+) -> Vec<(Option<&'static str>, String)> {
+    let input: &'static str = Box::leak(input.to_string().into_boxed_str());
+    let mut out = Vec::new();
+    flatten(&trace_rewritten(input, indices, rewrite), input, &mut out);
+    out
+}
+
+fn node(text: &'static str, value: &str) -> (Option<&'static str>, String) {
+    (Some(text), value.to_string())
+}
+
+#[test]
+fn test_trace_tree() {
+    assert_eq!(
+        nodes("4d6dl1 + 2", &[5, 4, 1, 2]),
+        vec![
+            node("4d6dl1 + 2", "16"),
+            node("4d6dl1", "14"),
+            node("2", "2")
+        ]
+    );
+    // Pratt Parser 组合出的二元运算与括号也有对应的位置
+    assert_eq!(
+        nodes("1d20 + 5 + 2", &[10]),
+        vec![
+            node("1d20 + 5 + 2", "18"),
+            node("1d20 + 5", "16"),
+            node("1d20", "11"),
+            node("5", "5"),
+            node("2", "2")
+        ]
+    );
+    assert_eq!(
+        nodes("(1d4 + 1) * 2", &[2]),
+        vec![
+            node("(1d4 + 1) * 2", "8"),
+            node("(1d4 + 1)", "4"),
+            node("1d4", "3"),
+            node("1", "1"),
+            node("2", "2")
+        ]
+    );
+    // 骰组、标签与注释
+    assert_eq!(
+        nodes("{1d20 + 5, 1d20 + 3}kh1 # atk", &[9, 17]),
+        vec![
+            node("{1d20 + 5, 1d20 + 3}kh1 # atk", "21"),
+            node("{1d20 + 5, 1d20 + 3}kh1", "21"),
+            node("1d20 + 5", "15"),
+            node("1d20", "10"),
+            node("5", "5"),
+            node("1d20 + 3", "21"),
+            node("1d20", "18"),
+            node("3", "3")
+        ]
+    );
+    assert_eq!(
+        nodes("2d6 [fire] + 1d4 [cold]", &[0, 1, 3]),
+        vec![
+            node("2d6 [fire] + 1d4 [cold]", "7"),
+            node("2d6 [fire]", "3"),
+            node("2d6", "3"),
+            node("1d4 [cold]", "4"),
+            node("1d4", "4")
+        ]
+    );
+    // 负号展开的 0 - x 中的 0 没有位置
+    assert_eq!(
+        nodes("-1d4 + 3", &[1]),
+        vec![
+            node("-1d4 + 3", "1"),
+            node("-1d4", "-2"),
+            (None, "0".to_string()),
+            node("1d4", "2"),
+            node("3", "3")
+        ]
+    );
+}
+
+#[test]
+fn test_trace_details() {
+    // 写法相同的子表达式按顺序对应
+    let t = trace("1d6 + 1d6", &[0, 1]);
+    let starts: Vec<_> = t.children.iter().map(|c| c.span.unwrap().start).collect();
+    assert_eq!(starts, vec![0, 6]);
+
+    // 多次求值的列表每次都有一个节点，位置相同
+    let t = trace("max([1d6] * 3)", &[0, 3, 5]);
+    let repeat = &t.children[0];
+    assert_eq!(
+        repeat.value,
+        RollValue::List(vec![Num::int(1), Num::int(4), Num::int(6)])
+    );
+    assert_eq!(repeat.children.len(), 3);
+    assert!(
+        repeat
+            .children
+            .iter()
+            .all(|c| c.span == repeat.children[0].span)
+    );
+    assert_eq!(repeat.dice.len(), 3);
+
+    // 节点包含子表达式中掷出的所有骰子，包括丢弃的骰子
+    let t = trace("4d6dl1 + 1d4", &[5, 4, 1, 2, 3]);
+    assert_eq!(t.dice.len(), 5);
+    assert_eq!(t.children[0].dice.len(), 4);
+    assert!(t.children[0].dice[2].dropped);
+
+    // 位置按 UTF-16 计算
+    let t = trace("1d6 [火焰] + 1d4", &[0, 1]);
+    let span = t.children[1].span.unwrap();
+    assert_eq!((span.start, span.end), (11, 14));
+}
+
+#[test]
+fn test_trace_spans_from_parser() {
+    // 写法相同的子表达式各自对应自己的位置
+    let t = trace("(1d6 + 1) * (1d6 + 1)", &[0, 1]);
+    let spans: Vec<_> = t
+        .children
+        .iter()
+        .map(|c| c.span.map(|s| (s.start, s.end)))
+        .collect();
+    assert_eq!(spans, vec![Some((0, 9)), Some((12, 21))]);
+
+    // 很长的加法链也能直接标注，每一项都有自己的位置
+    let input = vec!["1d6"; 150].join(" + ");
+    let mut t = trace(&input, &[0]);
+    let mut last = Vec::new();
+    while let [lhs, rhs] = t.children.as_slice() {
+        last.push(rhs.span.unwrap().start);
+        t = lhs.clone();
+    }
+    last.push(t.span.unwrap().start);
+    last.reverse();
+    assert_eq!(last, (0..150).map(|i| i * 6).collect::<Vec<_>>());
+
+    // 展开的优势取原来写法的位置，其中的子节点没有位置
+    assert_eq!(
+        nodes_rewritten("(1d20ro1) + 5", &[2, 9], |e| apply_roll_mode(
+            e,
+            RollMode::Advantage
+        )),
+        vec![
+            node("(1d20ro1) + 5", "15"),
+            node("(1d20ro1)", "10"),
+            (None, "3".to_string()),
+            (None, "10".to_string()),
+            node("5", "5")
+        ]
+    );
+
+    // 展开的函数调用取调用的位置，函数体中的节点没有位置
+    let mut functions = FunctionRegistry::new();
+    functions.load("def f(x) = x + 1").unwrap();
+    assert_eq!(
+        nodes_rewritten("f(2) * 1d4", &[3], |e| functions.expand(e).unwrap()),
+        vec![
+            node("f(2) * 1d4", "12"),
+            node("f(2)", "3"),
+            (None, "2".to_string()),
+            (None, "1".to_string()),
+            node("1d4", "4")
+        ]
+    );
+}
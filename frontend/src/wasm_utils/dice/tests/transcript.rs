@@ -0,0 +1,102 @@
+use dice_roller::eval::{DiceRng, evaluate_expr};
+use dice_roller::grammar::parse_dice;
+use dice_roller::transcript::{TranscriptFormat, render_transcript};
+
+// 按给定序列返回骰面下标的随机数来源
+struct SequenceRng {
+    indices: Vec<usize>,
+    pos: usize,
+}
+
+impl DiceRng for SequenceRng {
+    fn next_index(&mut self, n: usize) -> usize {
+        let index = self.indices[self.pos % self.indices.len()];
+        self.pos += 1;
+        assert!(index < n);
+        index
+    }
+}
+
+// 以骰面下标序列掷骰并渲染，普通骰子的下标 i 对应骰面 i + 1
+fn render(input: &str, indices: &[usize], format: TranscriptFormat) -> String {
+    let expr = parse_dice(input).expect("Parse error");
+    let mut rng = SequenceRng {
+        indices: indices.to_vec(),
+        pos: 0,
+    };
+    let output = evaluate_expr(&expr, &mut rng).expect("Evaluation error");
+    render_transcript(&expr, &output, format)
+}
+
+#[test]
+fn test_transcript_markdown() {
+    use TranscriptFormat::Markdown;
+    assert_eq!(
+        render("4d6dl1", &[5, 4, 1, 2], Markdown),
+        "4d6dl1 → [**6**, 5, ~~2~~, 3] = 14"
+    );
+    assert_eq!(
+        render("1d20 + 5", &[19], Markdown),
+        "1d20 → [**20**] = 20  \n1d20 + 5 = 25"
+    );
+    // 爆骰与复合爆骰
+    assert_eq!(
+        render("2d6!", &[5, 2, 0], Markdown),
+        "2d6! → [**6!**, *1*, 3] = 10"
+    );
+    assert_eq!(
+        render("1d6!!", &[5, 5, 1], Markdown),
+        "1d6!! → [**6!+6!+2**] = 14"
+    );
+    assert_eq!(
+        render("1d20ro1", &[0, 11], Markdown),
+        "1d20ro=1 → [~~*1*~~, 12] = 12"
+    );
+    // 需要转义的字符与注释
+    assert_eq!(
+        render("2d4 * 2 # fire", &[1, 2], Markdown),
+        "2d4 → [2, 3] = 5  \n2d4 \\* 2 = 10 *fire*"
+    );
+}
+
+#[test]
+fn test_transcript_plain() {
+    use TranscriptFormat::Plain;
+    assert_eq!(
+        render("4d6dl1", &[5, 4, 1, 2], Plain),
+        "4d6dl1 → [6, 5, (2), 3] = 14"
+    );
+    assert_eq!(
+        render("2d6 [fire] + 1d4 [cold]", &[0, 1, 3], Plain),
+        "2d6 [fire] → [1, 2] = 3\n1d4 [cold] → [4] = 4\n2d6 [fire] + 1d4 [cold] = 7"
+    );
+    assert_eq!(
+        render("{1d20 + 5, 1d20 + 3}kh1", &[9, 17], Plain),
+        "1d20 → [10] = 10\n1d20 → [18] = 18\n\
+         {1d20 + 5, 1d20 + 3}kh1 → [(15), 21] = 21\n\
+         {1d20 + 5, 1d20 + 3}kh1 = 21"
+    );
+    assert_eq!(
+        render("[1d4] * 2 # stealth", &[0, 3], Plain),
+        "1d4 → [1] = 1\n1d4 → [4] = 4\n[1d4] * 2 = [1, 4] # stealth"
+    );
+    assert_eq!(render("1 + 2", &[0], Plain), "1 + 2 = 3");
+}
+
+#[test]
+fn test_transcript_html() {
+    use TranscriptFormat::Html;
+    assert_eq!(
+        render("2d20kh1", &[19, 0], Html),
+        "<div class=\"dice-transcript\"><span class=\"dice-notation\">2d20kh1</span> → [\
+         <span class=\"die\"><strong class=\"critical-success\">20</strong></span>, \
+         <span class=\"die dropped\"><s><em class=\"critical-failure\">1</em></s></span>\
+         ] = <span class=\"dice-total\">20</span></div>"
+    );
+    assert_eq!(
+        render("1d4 # <b>", &[2], Html),
+        "<div class=\"dice-transcript\"><span class=\"dice-notation\">1d4</span> → [\
+         <span class=\"die\">3</span>] = <span class=\"dice-total\">3</span> \
+         <span class=\"dice-comment\">&lt;b&gt;</span></div>"
+    );
+}
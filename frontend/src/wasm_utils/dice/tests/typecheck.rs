@@ -1,5 +1,5 @@
 use dice_roller::grammar::parse_dice;
-use dice_roller::typecheck::{DiceItem, Type, typecheck_expr};
+use dice_roller::typecheck::{DiceItem, DiceSide, ListType, Type, typecheck_expr};
 
 fn typecheck(input: &str) -> Result<Type, String> {
     let parsed_expr = parse_dice(input).map_err(|e| format!("Parse error: {}", e))?;
@@ -129,7 +129,7 @@ fn test_typecheck_dice_pool_variable() {
         result.unwrap(),
         Type::raw_dice_pool(DiceItem {
             min_count: 6,
-            side: 6
+            side: DiceSide::Number(6)
         })
     );
 
@@ -138,7 +138,7 @@ fn test_typecheck_dice_pool_variable() {
         result.unwrap(),
         Type::raw_dice_pool(DiceItem {
             min_count: 6,
-            side: 6
+            side: DiceSide::Number(6)
         })
     );
 
@@ -242,6 +242,206 @@ fn test_typecheck_function_calls() {
     assert_eq!(result.unwrap(), Type::unknown_var());
 }
 
+#[test]
+fn test_typecheck_math_functions() {
+    let result = typecheck("2 ^ 10");
+    assert_eq!(result.unwrap(), Type::constant(1024.0));
+
+    let result = typecheck("1d6 ^ 2");
+    assert_eq!(result.unwrap(), Type::unknown_var());
+
+    let result = typecheck("pow(4, 0.5)");
+    assert_eq!(result.unwrap(), Type::constant(2.0));
+
+    let result = typecheck("sqrt(16)");
+    assert_eq!(result.unwrap(), Type::constant(4.0));
+
+    let result = typecheck("clamp(20, 1, 10)");
+    assert_eq!(result.unwrap(), Type::constant(10.0));
+
+    let result = typecheck("clamp(1d20, 1d4, 10)");
+    assert_eq!(result.unwrap(), Type::unknown_var());
+
+    let result = typecheck("avg(1, 2, 6)");
+    assert_eq!(result.unwrap(), Type::constant(3.0));
+
+    let result = typecheck("median([5, 1, 3, 2])");
+    assert_eq!(result.unwrap(), Type::constant(2.5));
+
+    let result = typecheck("median([1d6, 1d8, 1d10])");
+    assert_eq!(result.unwrap(), Type::unknown_var());
+
+    // 列表的长度是静态的
+    let result = typecheck("len([1d6] * 6)");
+    assert_eq!(result.unwrap(), Type::constant(6.0));
+
+    let result = typecheck("count([1, 2, 2, 3], 2)");
+    assert_eq!(result.unwrap(), Type::constant(2.0));
+
+    let result = typecheck("count([1d6] * 4, 6)");
+    assert_eq!(result.unwrap(), Type::unknown_var());
+
+    for input in [
+        "0 ^ -1",
+        "(-8) ^ (1 / 3)",
+        "pow(2)",
+        "sqrt(-1)",
+        "sqrt([4])",
+        "clamp(5, 10, 1)",
+        "clamp([1], 1, 2)",
+        "avg([])",
+        "len(5)",
+        "count([1, 2])",
+    ] {
+        let result = typecheck(input);
+        assert!(matches!(result.unwrap(), Type::Invalid(_)), "{}", input);
+    }
+}
+
+#[test]
+fn test_typecheck_list_operations() {
+    let result = typecheck("[1, 2, 3][-1]");
+    assert_eq!(result.unwrap(), Type::constant(3.0));
+
+    let result = typecheck("([1d6] * 4)[1]");
+    assert_eq!(result.unwrap(), Type::unknown_var());
+
+    let result = typecheck("[5, 3, 4, 1][1:3]");
+    assert_eq!(result.unwrap(), Type::const_list(vec![3.0, 4.0]));
+
+    let result = typecheck("([1d6] * 4)[:-1]");
+    assert_eq!(result.unwrap(), Type::var_list(3));
+
+    let result = typecheck("sort([3, 1, 2])");
+    assert_eq!(result.unwrap(), Type::const_list(vec![1.0, 2.0, 3.0]));
+
+    let result = typecheck("filter([1, 5, 10], >=5)");
+    assert_eq!(result.unwrap(), Type::const_list(vec![5.0, 10.0]));
+
+    // 过滤骰子列表后只知道长度上限
+    let result = typecheck("filter([1d20] * 4, >=10)");
+    assert_eq!(result.unwrap(), Type::bounded_list(4));
+
+    let result = typecheck("len(filter([1d20] * 4, >=10))");
+    assert_eq!(result.unwrap(), Type::unknown_var());
+
+    let result = typecheck("[[4d6dl1] * 6] * 2");
+    assert_eq!(
+        result.unwrap(),
+        Type::nested_list(vec![ListType::VariableList(6); 2])
+    );
+
+    let result = typecheck("max([[4d6dl1] * 6] * 3, 2)");
+    assert_eq!(
+        result.unwrap(),
+        Type::nested_list(vec![ListType::VariableList(6); 2])
+    );
+
+    for input in [
+        "[1, 2, 3][3]",
+        "[1, 2, 3][-4]",
+        "[1, 2][1d2]",
+        "[1, 2][0.5]",
+        "5[0]",
+        "[1, [2]]",
+        "[[1]] + [2]",
+        "sum([[1], [2]])",
+        "filter([[1]], >0)",
+    ] {
+        let result = typecheck(input);
+        assert!(matches!(result.unwrap(), Type::Invalid(_)), "{}", input);
+    }
+}
+
+#[test]
+fn test_typecheck_groups() {
+    assert_eq!(typecheck("{1d20 + 5, 1d20 + 3}").unwrap(), Type::group(2));
+    assert_eq!(
+        typecheck("{1d20 + 5, 1d20 + 3}kh1").unwrap(),
+        Type::group(1)
+    );
+    assert_eq!(typecheck("{1, 2, 3}dl1").unwrap(), Type::group(2));
+    assert_eq!(
+        typecheck("{1d20, 1d20}kh1 + 5").unwrap(),
+        Type::unknown_var()
+    );
+    assert_eq!(typecheck("{3d6, 4d6}>=10").unwrap(), Type::unknown_var());
+    assert_eq!(typecheck("{3d6, 4d6}cnt>=10").unwrap(), Type::unknown_var());
+    // 单个元素的花括号仍然是骰池
+    assert_eq!(typecheck("{4d6}kh3").unwrap(), typecheck("4d6kh3").unwrap());
+
+    for input in [
+        "{1d20, 1d20}kh3",
+        "{1d20, 1d20}dl2",
+        "{1d20, [1, 2]}",
+        "{1d20, 1d20}!",
+        "{1d20, 1d20}r1",
+        "{1d20, 1d20}kh1kh2",
+        "{1d20, 1d20}>=(1d6)",
+        "{1d20, 1d0}",
+    ] {
+        assert!(
+            matches!(typecheck(input).unwrap(), Type::Invalid(_)),
+            "{}",
+            input
+        );
+    }
+}
+
+#[test]
+fn test_typecheck_limits() {
+    // 骰子数量、列表重复次数与 rpdice 重复次数都有上限
+    assert!(matches!(typecheck("1000d6").unwrap(), Type::Number(_)));
+    assert_eq!(
+        typecheck("1001d6").unwrap(),
+        Type::Invalid("Too many dice: count = 1001, the limit is 1000.".to_string())
+    );
+    assert!(matches!(
+        typecheck("1000000000dF").unwrap(),
+        Type::Invalid(_)
+    ));
+    assert!(matches!(
+        typecheck("1000000000d{1, 2}").unwrap(),
+        Type::Invalid(_)
+    ));
+    // 骰面数量不受限制，求值时直接取随机数
+    assert!(matches!(
+        typecheck("1d1000000000").unwrap(),
+        Type::Number(_)
+    ));
+
+    assert_eq!(
+        typecheck("[1d6] * 100000000").unwrap(),
+        Type::Invalid("List repeat count 100000000 exceeds the limit of 100.".to_string())
+    );
+    assert_eq!(
+        typecheck("([1, 2] * 100) * 10").unwrap(),
+        Type::Invalid("List is too long: the limit is 1000 items.".to_string())
+    );
+    assert!(matches!(
+        typecheck("[] * 100000000").unwrap(),
+        Type::Invalid(_)
+    ));
+    assert_eq!(
+        typecheck("rpdice(1d6, 1000)").unwrap(),
+        Type::Invalid("In rpdice, the repeat count must not exceed 100.".to_string())
+    );
+}
+
+#[test]
+fn test_typecheck_labels() {
+    // 标签与注释不影响类型
+    for (labelled, plain) in [
+        ("2d6 [fire] + 3", "2d6 + 3"),
+        ("(4d6 [str])kh3", "4d6kh3"),
+        ("1d20 + 5 # stealth", "1d20 + 5"),
+        ("1 [one] + 2 # sum", "1 + 2"),
+        ("[1, 2] [list] + 1", "[1, 2] + 1"),
+    ] {
+        assert_eq!(typecheck(labelled), typecheck(plain), "{}", labelled);
+    }
+}
+
 #[test]
 fn test_modifier() {
     let rusult = typecheck("2d20kh1");
@@ -249,7 +449,7 @@ fn test_modifier() {
         rusult.unwrap(),
         Type::raw_dice_pool(DiceItem {
             min_count: 1,
-            side: 20
+            side: DiceSide::Number(20)
         })
     );
 
@@ -258,7 +458,7 @@ fn test_modifier() {
         rusult.unwrap(),
         Type::raw_dice_pool(DiceItem {
             min_count: 2,
-            side: 20
+            side: DiceSide::Number(20)
         })
     );
 
@@ -267,7 +467,7 @@ fn test_modifier() {
         rusult.unwrap(),
         Type::raw_dice_pool(DiceItem {
             min_count: 1,
-            side: 20
+            side: DiceSide::Number(20)
         })
     );
 
@@ -276,16 +476,16 @@ fn test_modifier() {
         rusult.unwrap(),
         Type::raw_dice_pool(DiceItem {
             min_count: 1,
-            side: 20
+            side: DiceSide::Number(20)
         })
     );
 
     let rusult = typecheck("2d20!");
     assert_eq!(
         rusult.unwrap(),
-        Type::raw_dice_pool(DiceItem {
+        Type::limitable_dice_pool(DiceItem {
             min_count: 2,
-            side: 20
+            side: DiceSide::Number(20)
         })
     );
 
@@ -294,7 +494,7 @@ fn test_modifier() {
         rusult.unwrap(),
         Type::limitable_dice_pool(DiceItem {
             min_count: 2,
-            side: 20
+            side: DiceSide::Number(20)
         })
     );
 
@@ -303,7 +503,7 @@ fn test_modifier() {
         rusult.unwrap(),
         Type::limitable_dice_pool(DiceItem {
             min_count: 2,
-            side: 20
+            side: DiceSide::Number(20)
         })
     );
 
@@ -312,7 +512,7 @@ fn test_modifier() {
         rusult.unwrap(),
         Type::limitable_dice_pool(DiceItem {
             min_count: 2,
-            side: 20
+            side: DiceSide::Number(20)
         })
     );
 
@@ -321,7 +521,7 @@ fn test_modifier() {
         rusult.unwrap(),
         Type::limitable_dice_pool(DiceItem {
             min_count: 1,
-            side: 20
+            side: DiceSide::Number(20)
         })
     );
 
@@ -330,7 +530,7 @@ fn test_modifier() {
         rusult.unwrap(),
         Type::limitable_dice_pool(DiceItem {
             min_count: 2,
-            side: 20
+            side: DiceSide::Number(20)
         })
     );
 
@@ -339,7 +539,7 @@ fn test_modifier() {
         rusult.unwrap(),
         Type::raw_dice_pool(DiceItem {
             min_count: 2,
-            side: 20
+            side: DiceSide::Number(20)
         })
     );
 
@@ -348,7 +548,7 @@ fn test_modifier() {
         rusult.unwrap(),
         Type::raw_dice_pool(DiceItem {
             min_count: 1,
-            side: 20
+            side: DiceSide::Number(20)
         })
     );
 
@@ -357,7 +557,7 @@ fn test_modifier() {
         rusult.unwrap(),
         Type::raw_dice_pool(DiceItem {
             min_count: 3,
-            side: 20
+            side: DiceSide::Number(20)
         })
     );
 
@@ -366,7 +566,7 @@ fn test_modifier() {
         rusult.unwrap(),
         Type::raw_dice_pool(DiceItem {
             min_count: 3,
-            side: 20
+            side: DiceSide::Number(20)
         })
     );
 
@@ -375,7 +575,7 @@ fn test_modifier() {
         rusult.unwrap(),
         Type::raw_dice_pool(DiceItem {
             min_count: 3,
-            side: 20
+            side: DiceSide::Number(20)
         })
     );
 
@@ -384,16 +584,16 @@ fn test_modifier() {
         rusult.unwrap(),
         Type::raw_dice_pool(DiceItem {
             min_count: 3,
-            side: 20
+            side: DiceSide::Number(20)
         })
     );
 
     let rusult = typecheck("3d20!!!");
     assert_eq!(
         rusult.unwrap(),
-        Type::raw_dice_pool(DiceItem {
+        Type::limitable_dice_pool(DiceItem {
             min_count: 3,
-            side: 20
+            side: DiceSide::Number(20)
         })
     );
 
@@ -402,7 +602,7 @@ fn test_modifier() {
         rusult.unwrap(),
         Type::limitable_dice_pool(DiceItem {
             min_count: 3,
-            side: 20
+            side: DiceSide::Number(20)
         })
     );
 
@@ -411,7 +611,7 @@ fn test_modifier() {
         rusult.unwrap(),
         Type::raw_dice_pool(DiceItem {
             min_count: 3,
-            side: 20
+            side: DiceSide::Number(20)
         })
     );
 
@@ -420,10 +620,94 @@ fn test_modifier() {
         rusult.unwrap(),
         Type::limitable_dice_pool(DiceItem {
             min_count: 2,
-            side: 20
+            side: DiceSide::Number(20)
         })
     );
 
+    let rusult = typecheck("2d20!l2");
+    assert_eq!(
+        rusult.unwrap(),
+        Type::raw_dice_pool(DiceItem {
+            min_count: 2,
+            side: DiceSide::Number(20)
+        })
+    );
+
+    let rusult = typecheck("2d20!p");
+    assert_eq!(
+        rusult.unwrap(),
+        Type::limitable_dice_pool(DiceItem {
+            min_count: 2,
+            side: DiceSide::Number(20)
+        })
+    );
+
+    let rusult = typecheck("2d20!p>=19l(1 + 1)kh1");
+    assert_eq!(
+        rusult.unwrap(),
+        Type::raw_dice_pool(DiceItem {
+            min_count: 1,
+            side: DiceSide::Number(20)
+        })
+    );
+
+    let rusult = typecheck("1d20mi10");
+    assert_eq!(
+        rusult.unwrap(),
+        Type::raw_dice_pool(DiceItem {
+            min_count: 1,
+            side: DiceSide::Number(20)
+        })
+    );
+
+    let rusult = typecheck("4dFmi(-0)ma1kh3");
+    assert_eq!(
+        rusult.unwrap(),
+        Type::raw_dice_pool(DiceItem {
+            min_count: 3,
+            side: DiceSide::Fate
+        })
+    );
+
+    let rusult = typecheck("4d6sdkh3");
+    assert_eq!(
+        rusult.unwrap(),
+        Type::raw_dice_pool(DiceItem {
+            min_count: 3,
+            side: DiceSide::Number(6)
+        })
+    );
+
+    let rusult = typecheck("6d6cnt>=5");
+    assert_eq!(rusult.unwrap(), Type::unknown_var());
+
+    let rusult = typecheck("6d6u");
+    assert_eq!(
+        rusult.unwrap(),
+        Type::raw_dice_pool(DiceItem {
+            min_count: 6,
+            side: DiceSide::Number(6)
+        })
+    );
+    // 骰面不够时无法各不相同
+    assert!(matches!(typecheck("7d6u").unwrap(), Type::Invalid(_)));
+    assert!(matches!(
+        typecheck("3d{1, 1, 2}u").unwrap(),
+        Type::Invalid(_)
+    ));
+
+    let rusult = typecheck("2d20cs>=19cfkh1");
+    assert_eq!(
+        rusult.unwrap(),
+        Type::raw_dice_pool(DiceItem {
+            min_count: 1,
+            side: DiceSide::Number(20)
+        })
+    );
+
+    let rusult = typecheck("10d10>=8f1ds10");
+    assert_eq!(rusult.unwrap(), Type::unknown_var());
+
     let rusult = typecheck("2d20kh1 + 5");
     assert_eq!(rusult.unwrap(), Type::unknown_var());
 
@@ -437,6 +721,87 @@ fn test_modifier() {
     assert_eq!(rusult.unwrap(), Type::unknown_var());
 }
 
+#[test]
+fn test_special_dice() {
+    let result = typecheck("4dF");
+    assert_eq!(
+        result.unwrap(),
+        Type::raw_dice_pool(DiceItem {
+            min_count: 4,
+            side: DiceSide::Fate
+        })
+    );
+
+    let result = typecheck("(1 + 1)d%");
+    assert_eq!(
+        result.unwrap(),
+        Type::raw_dice_pool(DiceItem {
+            min_count: 2,
+            side: DiceSide::Percentile
+        })
+    );
+
+    let result = typecheck("4dFkh2");
+    assert_eq!(
+        result.unwrap(),
+        Type::raw_dice_pool(DiceItem {
+            min_count: 2,
+            side: DiceSide::Fate
+        })
+    );
+
+    let result = typecheck("4dF + 1");
+    assert_eq!(result.unwrap(), Type::unknown_var());
+
+    let result = typecheck("0dF");
+    assert!(matches!(result.unwrap(), Type::Invalid(_)));
+    let result = typecheck("(1d4)d%");
+    assert!(matches!(result.unwrap(), Type::Invalid(_)));
+    let result = typecheck("[2]dF");
+    assert!(matches!(result.unwrap(), Type::Invalid(_)));
+
+    assert_eq!(DiceSide::Fate.faces(), vec![-1, 0, 1]);
+    assert_eq!(DiceSide::Percentile.max_face(), 100);
+}
+
+#[test]
+fn test_custom_dice() {
+    let result = typecheck("2d{1, 1, 2, 2, 3, 4}");
+    assert_eq!(
+        result.unwrap(),
+        Type::raw_dice_pool(DiceItem {
+            min_count: 2,
+            side: DiceSide::Custom(vec![1, 1, 2, 2, 3, 4])
+        })
+    );
+
+    let result = typecheck("3d{0, -1, 1 + 1}kh2!");
+    assert_eq!(
+        result.unwrap(),
+        Type::limitable_dice_pool(DiceItem {
+            min_count: 2,
+            side: DiceSide::Custom(vec![0, -1, 2])
+        })
+    );
+
+    let side = DiceSide::Custom(vec![3, 1, 2, 1]);
+    assert_eq!(side.faces(), vec![1, 1, 2, 3]);
+    assert_eq!(side.min_face(), 1);
+    assert_eq!(side.max_face(), 3);
+
+    // 至少两个骰面，且必须是常整数
+    let result = typecheck("1d[6]");
+    assert!(matches!(result.unwrap(), Type::Invalid(_)));
+    let result = typecheck("1d[]");
+    assert!(matches!(result.unwrap(), Type::Invalid(_)));
+    let result = typecheck("1d{1, 1.5}");
+    assert!(matches!(result.unwrap(), Type::Invalid(_)));
+    let result = typecheck("1d{1, 1d6}");
+    assert!(matches!(result.unwrap(), Type::Invalid(_)));
+    let result = typecheck("0d{1, 2}");
+    assert!(matches!(result.unwrap(), Type::Invalid(_)));
+}
+
 #[test]
 fn test_success_check() {
     let rusult = typecheck("2d20 < 3");
@@ -684,6 +1049,74 @@ fn test_typecheck_invalid_expressions() {
     assert!(matches!(rusult.unwrap(), Type::Invalid(_)));
     let rusult = typecheck("3d20l3");
     assert!(matches!(rusult.unwrap(), Type::Invalid(_)));
+    let rusult = typecheck("3d20!l3l3");
+    assert!(matches!(rusult.unwrap(), Type::Invalid(_)));
+    let rusult = typecheck("3d20!pkh2l3");
+    assert!(matches!(rusult.unwrap(), Type::Invalid(_)));
+    let rusult = typecheck("1d20mi(1d6)");
+    assert!(matches!(rusult.unwrap(), Type::Invalid(_)));
+    let rusult = typecheck("1d20ma2.5");
+    assert!(matches!(rusult.unwrap(), Type::Invalid(_)));
+    let rusult = typecheck("1d6!mi2l1");
+    assert!(matches!(rusult.unwrap(), Type::Invalid(_)));
+    let rusult = typecheck("1d20cs>(1d6)");
+    assert!(matches!(rusult.unwrap(), Type::Invalid(_)));
+    let rusult = typecheck("1d6!csl2");
+    assert!(matches!(rusult.unwrap(), Type::Invalid(_)));
+    let rusult = typecheck("10d10>=8f(1d4)");
+    assert!(matches!(rusult.unwrap(), Type::Invalid(_)));
+    let rusult = typecheck("10d10>=8ds[10]");
+    assert!(matches!(rusult.unwrap(), Type::Invalid(_)));
+    let rusult = typecheck("6d6cnt>=5kh1");
+    assert!(matches!(rusult.unwrap(), Type::Invalid(_)));
+    let rusult = typecheck("6d6cnt>=(1d6)");
+    assert!(matches!(rusult.unwrap(), Type::Invalid(_)));
+    let rusult = typecheck("(1 + 2)sa");
+    assert!(matches!(rusult.unwrap(), Type::Invalid(_)));
+    let rusult = typecheck("3d20!p<(1d6)");
+    assert!(matches!(rusult.unwrap(), Type::Invalid(_)));
     let rusult = typecheck("3d20!!=3l(1d6)");
     assert!(matches!(rusult.unwrap(), Type::Invalid(_)));
 }
+
+#[test]
+fn test_typecheck_export() {
+    use dice_roller::{ParseResult, TypeResult, parse_dice_expression, typecheck_dice_expression};
+    use serde_json::{json, to_value};
+
+    // 导出给前端的 JSON 结构
+    let TypeResult::Typed(ty) = typecheck_dice_expression("2d20kh1".to_string()) else {
+        panic!("2d20kh1 should typecheck");
+    };
+    assert_eq!(
+        to_value(&ty).unwrap(),
+        json!({"Number": {"Variable": {"DicePool": {"RawDicePool": {
+            "min_count": 1,
+            "side": {"Number": 20}
+        }}}}})
+    );
+    let TypeResult::Typed(ty) = typecheck_dice_expression("[1d6, 2] * 2".to_string()) else {
+        panic!("list should typecheck");
+    };
+    assert_eq!(to_value(&ty).unwrap(), json!({"List": {"VariableList": 4}}));
+    assert!(matches!(
+        typecheck_dice_expression("1d20 + [1]".to_string()),
+        TypeResult::Error(_)
+    ));
+
+    let ParseResult::Parsed(ast) = parse_dice_expression("1d20 + 5".to_string()) else {
+        panic!("1d20 + 5 should parse");
+    };
+    assert_eq!(
+        to_value(&ast).unwrap(),
+        json!({"Binary": {
+            "lhs": {"Dice": {"count": {"Number": 1.0}, "side": {"Number": 20.0}}},
+            "op": "Add",
+            "rhs": {"Number": 5.0}
+        }})
+    );
+    assert!(matches!(
+        parse_dice_expression("1d20 +".to_string()),
+        ParseResult::Error(_)
+    ));
+}